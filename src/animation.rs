@@ -0,0 +1,581 @@
+use crate::linalg::{cross, dot, Matrix4D, Quaternion, Vector3D};
+use crate::renderer::Scene;
+
+/// Translation, rotation and scale, applied to a point in the order scale, rotation,
+/// translation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vector3D,
+    pub rotation: Quaternion,
+    pub scale: Vector3D,
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform {
+            translation: Vector3D::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::identity(),
+            scale: Vector3D::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Transform {
+    /// Splits a matrix made of a scale, rotation and translation back into them. Shears,
+    /// and the sign of a scale that mirrors the whole thing, can't be recovered.
+    pub fn from_matrix(matrix: &Matrix4D) -> Transform {
+        let row = |i: usize| {
+            let r = matrix.row(i);
+            Vector3D::new(r[0], r[1], r[2])
+        };
+        let (x, y, z) = (row(0), row(1), row(2));
+        let mut scale = Vector3D::new(x.magnitude(), y.magnitude(), z.magnitude());
+
+        // a negative determinant means an odd number of axes are mirrored, flip one back
+        if dot(&cross(&x, &y), &z) < 0.0 {
+            scale.x = -scale.x;
+        }
+
+        let axis = |v: &Vector3D, s: f64| {
+            if s == 0.0 {
+                *v
+            } else {
+                v.scale(1.0 / s)
+            }
+        };
+        let (x, y, z) = (axis(&x, scale.x), axis(&y, scale.y), axis(&z, scale.z));
+        let rotation = Matrix4D::new([
+            [x.x, x.y, x.z, 0.0],
+            [y.x, y.y, y.z, 0.0],
+            [z.x, z.y, z.z, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+
+        Transform {
+            translation: row(3),
+            rotation: Quaternion::from_rotation_matrix(&rotation),
+            scale,
+        }
+    }
+
+    pub fn to_matrix(&self) -> Matrix4D {
+        &(&Matrix4D::new_scale(&self.scale) * &self.rotation.to_matrix())
+            * &Matrix4D::new_translation(&self.translation)
+    }
+}
+
+/// How values between keyframes are found.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    /// Holds each keyframe's value until the next one.
+    Step,
+    /// Straight lines between keyframes, rotations along the shortest arc.
+    Linear,
+    /// Hermite curves through the keyframes, shaped by their tangents.
+    CubicSpline,
+}
+
+/// A value that can be keyframed.
+pub trait Animatable: Copy {
+    /// `a` times `weight_a` plus `b` times `weight_b`.
+    fn blend(a: &Self, weight_a: f64, b: &Self, weight_b: f64) -> Self;
+
+    fn zero() -> Self;
+
+    fn lerp(a: &Self, b: &Self, t: f64) -> Self {
+        Self::blend(a, 1.0 - t, b, t)
+    }
+
+    /// Brings a blended value back to a valid one, needed for rotations.
+    fn normalized(&self) -> Self {
+        *self
+    }
+}
+
+impl Animatable for Vector3D {
+    fn blend(a: &Vector3D, weight_a: f64, b: &Vector3D, weight_b: f64) -> Vector3D {
+        a.scale(weight_a).add(&b.scale(weight_b))
+    }
+
+    fn zero() -> Vector3D {
+        Vector3D::new(0.0, 0.0, 0.0)
+    }
+}
+
+impl Animatable for Quaternion {
+    fn blend(a: &Quaternion, weight_a: f64, b: &Quaternion, weight_b: f64) -> Quaternion {
+        a.scale(weight_a).add(&b.scale(weight_b))
+    }
+
+    fn zero() -> Quaternion {
+        Quaternion::new(0.0, 0.0, 0.0, 0.0)
+    }
+
+    fn lerp(a: &Quaternion, b: &Quaternion, t: f64) -> Quaternion {
+        a.slerp(b, t)
+    }
+
+    fn normalized(&self) -> Quaternion {
+        self.normalize()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyframe<T: Animatable> {
+    /// Seconds from the start of the clip.
+    pub time: f64,
+    pub value: T,
+    /// Rate of change arriving at and leaving the keyframe, per second. Only cubic
+    /// splines use them.
+    pub in_tangent: T,
+    pub out_tangent: T,
+}
+
+impl<T: Animatable> Keyframe<T> {
+    /// A keyframe with flat tangents, easing in and out of it on a cubic spline.
+    pub fn new(time: f64, value: T) -> Keyframe<T> {
+        Keyframe {
+            time,
+            value,
+            in_tangent: T::zero(),
+            out_tangent: T::zero(),
+        }
+    }
+
+    pub fn with_tangents(mut self, in_tangent: T, out_tangent: T) -> Keyframe<T> {
+        self.in_tangent = in_tangent;
+        self.out_tangent = out_tangent;
+        self
+    }
+}
+
+/// Keyframes for one property, kept in time order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Track<T: Animatable> {
+    pub keyframes: Vec<Keyframe<T>>,
+    pub interpolation: Interpolation,
+}
+
+impl<T: Animatable> Track<T> {
+    pub fn new(interpolation: Interpolation) -> Track<T> {
+        Track {
+            keyframes: vec![],
+            interpolation,
+        }
+    }
+
+    pub fn with_keyframe(mut self, keyframe: Keyframe<T>) -> Track<T> {
+        self.insert(keyframe);
+        self
+    }
+
+    pub fn with_key(self, time: f64, value: T) -> Track<T> {
+        self.with_keyframe(Keyframe::new(time, value))
+    }
+
+    /// Adds a keyframe in time order, after any already at the same time.
+    pub fn insert(&mut self, keyframe: Keyframe<T>) {
+        let index = self.keyframes.partition_point(|k| k.time <= keyframe.time);
+        self.keyframes.insert(index, keyframe);
+    }
+
+    /// Time of the last keyframe.
+    pub fn duration(&self) -> f64 {
+        self.keyframes.last().map_or(0.0, |k| k.time)
+    }
+
+    /// The value at `time`, held at the first and last keyframes outside them. None for a
+    /// track without keyframes.
+    pub fn sample(&self, time: f64) -> Option<T> {
+        let first = self.keyframes.first()?;
+        let last = self.keyframes.last()?;
+        if time <= first.time {
+            return Some(first.value);
+        }
+        if time >= last.time {
+            return Some(last.value);
+        }
+
+        // the keyframes either side of `time`
+        let next = self.keyframes.partition_point(|k| k.time <= time);
+        let (a, b) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        let span = b.time - a.time;
+        let t = if span > 0.0 {
+            (time - a.time) / span
+        } else {
+            1.0
+        };
+
+        Some(match self.interpolation {
+            Interpolation::Step => a.value,
+            Interpolation::Linear => T::lerp(&a.value, &b.value, t),
+            Interpolation::CubicSpline => {
+                let (t2, t3) = (t * t, t * t * t);
+                let start = T::blend(
+                    &a.value,
+                    2.0 * t3 - 3.0 * t2 + 1.0,
+                    &a.out_tangent,
+                    (t3 - 2.0 * t2 + t) * span,
+                );
+                let end = T::blend(
+                    &b.value,
+                    -2.0 * t3 + 3.0 * t2,
+                    &b.in_tangent,
+                    (t3 - t2) * span,
+                );
+                T::blend(&start, 1.0, &end, 1.0).normalized()
+            }
+        })
+    }
+}
+
+/// The tracks animating one object or skeleton joint, each overriding that part of `rest`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Channel {
+    /// Index into `Scene::objects`, or into `Skeleton::joints` for clips posing a skeleton.
+    pub target: usize,
+    pub rest: Transform,
+    pub translation: Option<Track<Vector3D>>,
+    pub rotation: Option<Track<Quaternion>>,
+    pub scale: Option<Track<Vector3D>>,
+}
+
+impl Channel {
+    pub fn new(target: usize, rest: Transform) -> Channel {
+        Channel {
+            target,
+            rest,
+            translation: None,
+            rotation: None,
+            scale: None,
+        }
+    }
+
+    /// A channel resting wherever the scene's object is now.
+    pub fn for_object(scene: &Scene, object: usize) -> Channel {
+        let rest = scene.objects.get(object).map_or(Transform::default(), |o| {
+            Transform::from_matrix(&o.transform)
+        });
+        Channel::new(object, rest)
+    }
+
+    pub fn with_translation(mut self, track: Track<Vector3D>) -> Channel {
+        self.translation = Some(track);
+        self
+    }
+
+    pub fn with_rotation(mut self, track: Track<Quaternion>) -> Channel {
+        self.rotation = Some(track);
+        self
+    }
+
+    pub fn with_scale(mut self, track: Track<Vector3D>) -> Channel {
+        self.scale = Some(track);
+        self
+    }
+
+    pub fn duration(&self) -> f64 {
+        let durations = [
+            self.translation.as_ref().map(Track::duration),
+            self.rotation.as_ref().map(Track::duration),
+            self.scale.as_ref().map(Track::duration),
+        ];
+        durations.into_iter().flatten().fold(0.0, f64::max)
+    }
+
+    pub fn sample(&self, time: f64) -> Transform {
+        let sample = |track: &Option<Track<Vector3D>>, rest: Vector3D| {
+            track.as_ref().and_then(|t| t.sample(time)).unwrap_or(rest)
+        };
+        Transform {
+            translation: sample(&self.translation, self.rest.translation),
+            rotation: self
+                .rotation
+                .as_ref()
+                .and_then(|t| t.sample(time))
+                .unwrap_or(self.rest.rotation),
+            scale: sample(&self.scale, self.rest.scale),
+        }
+    }
+}
+
+/// A named set of channels played together.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnimationClip {
+    pub name: String,
+    pub channels: Vec<Channel>,
+}
+
+impl AnimationClip {
+    pub fn new(name: &str) -> AnimationClip {
+        AnimationClip {
+            name: name.to_string(),
+            channels: vec![],
+        }
+    }
+
+    pub fn with_channel(mut self, channel: Channel) -> AnimationClip {
+        self.channels.push(channel);
+        self
+    }
+
+    /// Time of the last keyframe on any track.
+    pub fn duration(&self) -> f64 {
+        self.channels
+            .iter()
+            .map(Channel::duration)
+            .fold(0.0, f64::max)
+    }
+
+    /// Sets the transform of every animated object to its pose at `time`, skipping channels
+    /// for objects the scene doesn't have.
+    pub fn apply(&self, scene: &mut Scene, time: f64) {
+        for channel in &self.channels {
+            if let Some(object) = scene.objects.get_mut(channel.target) {
+                object.transform = channel.sample(time).to_matrix();
+            }
+        }
+    }
+
+    /// Indices of the objects or joints the clip moves.
+    pub fn targets(&self) -> impl Iterator<Item = usize> + '_ {
+        self.channels.iter().map(|c| c.target)
+    }
+}
+
+/// What happens when playback reaches the end of a clip.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlaybackMode {
+    /// Stops on the last frame.
+    Once,
+    /// Jumps back to the start.
+    Loop,
+    /// Plays backwards to the start, then forwards again.
+    PingPong,
+}
+
+/// Plays a clip along a timeline advanced by the frame's delta time.
+pub struct AnimationPlayer {
+    pub clip: AnimationClip,
+    pub mode: PlaybackMode,
+    /// 1 is real time, negative plays backwards.
+    pub speed: f64,
+    pub playing: bool,
+    /// Seconds since the start, growing past the clip's end; `time` maps it into the clip.
+    elapsed: f64,
+}
+
+impl AnimationPlayer {
+    pub fn new(clip: AnimationClip, mode: PlaybackMode) -> AnimationPlayer {
+        AnimationPlayer {
+            clip,
+            mode,
+            speed: 1.0,
+            playing: true,
+            elapsed: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, delta_time: f64) {
+        if !self.playing {
+            return;
+        }
+        self.elapsed += delta_time * self.speed;
+
+        let duration = self.clip.duration();
+        if self.mode == PlaybackMode::Once && (self.elapsed >= duration || self.elapsed <= 0.0) {
+            self.elapsed = self.elapsed.clamp(0.0, duration);
+            self.playing = false;
+        }
+    }
+
+    /// Moves the timeline to `time` seconds from the start.
+    pub fn seek(&mut self, time: f64) {
+        self.elapsed = time;
+    }
+
+    /// The current position within the clip, 0 to its duration.
+    pub fn time(&self) -> f64 {
+        let duration = self.clip.duration();
+        if duration <= 0.0 {
+            return 0.0;
+        }
+
+        match self.mode {
+            PlaybackMode::Once => self.elapsed.clamp(0.0, duration),
+            PlaybackMode::Loop => self.elapsed.rem_euclid(duration),
+            PlaybackMode::PingPong => {
+                let t = self.elapsed.rem_euclid(2.0 * duration);
+                if t > duration {
+                    2.0 * duration - t
+                } else {
+                    t
+                }
+            }
+        }
+    }
+
+    pub fn apply(&self, scene: &mut Scene) {
+        self.clip.apply(scene, self.time());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::{Color, Mesh, Object};
+
+    fn close(a: &Vector3D, b: &Vector3D) -> bool {
+        a.sub(b).magnitude() < 1e-9
+    }
+
+    fn v(x: f64) -> Vector3D {
+        Vector3D::new(x, 0.0, 0.0)
+    }
+
+    fn track(interpolation: Interpolation) -> Track<Vector3D> {
+        Track::new(interpolation)
+            .with_key(2.0, v(4.0))
+            .with_key(0.0, v(0.0))
+            .with_key(1.0, v(2.0))
+    }
+
+    #[test]
+    fn step_tracks_hold_each_key() {
+        let track = track(Interpolation::Step);
+        assert_eq!(track.duration(), 2.0);
+        for (time, x) in [
+            (-1.0, 0.0),
+            (0.0, 0.0),
+            (0.5, 0.0),
+            (1.0, 2.0),
+            (1.9, 2.0),
+            (3.0, 4.0),
+        ] {
+            assert_eq!(track.sample(time), Some(v(x)));
+        }
+        assert_eq!(
+            Track::<Vector3D>::new(Interpolation::Step).sample(0.0),
+            None
+        );
+    }
+
+    #[test]
+    fn linear_tracks_run_straight_between_keys() {
+        let track = track(Interpolation::Linear);
+        for (time, x) in [(0.0, 0.0), (0.25, 0.5), (1.0, 2.0), (1.5, 3.0), (2.0, 4.0)] {
+            assert!(close(&track.sample(time).unwrap(), &v(x)));
+        }
+    }
+
+    #[test]
+    fn cubic_spline_tracks_follow_their_tangents() {
+        // flat tangents ease in and out, passing the middle halfway
+        let flat = Track::new(Interpolation::CubicSpline)
+            .with_key(0.0, v(0.0))
+            .with_key(2.0, v(1.0));
+        assert!(close(&flat.sample(0.0).unwrap(), &v(0.0)));
+        assert!(close(&flat.sample(1.0).unwrap(), &v(0.5)));
+        assert!(close(&flat.sample(0.5).unwrap(), &v(0.15625)));
+        assert!(close(&flat.sample(2.0).unwrap(), &v(1.0)));
+
+        // leaving at 2 per second over a 1 second span bulges by a quarter at the middle
+        let leaving = Track::new(Interpolation::CubicSpline)
+            .with_keyframe(Keyframe::new(0.0, v(0.0)).with_tangents(v(0.0), v(2.0)))
+            .with_key(1.0, v(0.0));
+        assert!(close(&leaving.sample(0.5).unwrap(), &v(0.25)));
+    }
+
+    #[test]
+    fn rotations_take_the_short_way_round() {
+        let up = Vector3D::new(0.0, 1.0, 0.0);
+        let track = Track::new(Interpolation::Linear)
+            .with_key(0.0, Quaternion::identity())
+            .with_key(
+                1.0,
+                Quaternion::from_axis_angle(&up, 350.0_f64.to_radians()),
+            );
+
+        let halfway = track.sample(0.5).unwrap();
+        let expected = Quaternion::from_axis_angle(&up, -5.0_f64.to_radians());
+        let x = Vector3D::new(1.0, 0.0, 0.0);
+        assert!(close(&halfway.rotate(&x), &expected.rotate(&x)));
+        assert!((halfway.magnitude() - 1.0).abs() < 1e-9);
+    }
+
+    fn player(mode: PlaybackMode) -> AnimationPlayer {
+        let clip = AnimationClip::new("slide").with_channel(
+            Channel::new(0, Transform::default()).with_translation(track(Interpolation::Linear)),
+        );
+        AnimationPlayer::new(clip, mode)
+    }
+
+    #[test]
+    fn playback_modes_map_time_past_the_end() {
+        let mut looping = player(PlaybackMode::Loop);
+        looping.update(2.5);
+        assert!((looping.time() - 0.5).abs() < 1e-9);
+        looping.seek(5.0);
+        assert!((looping.time() - 1.0).abs() < 1e-9);
+
+        let mut ping_pong = player(PlaybackMode::PingPong);
+        ping_pong.update(2.5);
+        assert!((ping_pong.time() - 1.5).abs() < 1e-9);
+        ping_pong.update(2.0);
+        assert!((ping_pong.time() - 0.5).abs() < 1e-9);
+        assert!(ping_pong.playing);
+    }
+
+    #[test]
+    fn once_stops_on_the_last_frame() {
+        let mut once = player(PlaybackMode::Once);
+        once.update(1.0);
+        assert!(once.playing);
+        once.update(5.0);
+        assert!(!once.playing);
+        assert_eq!(once.time(), 2.0);
+        once.update(1.0);
+        assert_eq!(once.time(), 2.0);
+
+        let mut scene = Scene::new();
+        let white = Color::new(255, 255, 255, 255);
+        let cube = Mesh::cuboid(&Vector3D::new(1.0, 1.0, 1.0), &white);
+        scene.objects.push(Object::new(cube, Matrix4D::identity()));
+        once.apply(&mut scene);
+        assert!(close(
+            &Transform::from_matrix(&scene.objects[0].transform).translation,
+            &v(4.0)
+        ));
+    }
+
+    #[test]
+    fn transforms_survive_a_trip_through_a_matrix() {
+        let axis = Vector3D::new(1.0, 2.0, -0.5).normalize();
+        for scale in [Vector3D::new(2.0, 0.5, 3.0), Vector3D::new(-2.0, 0.5, 3.0)] {
+            let transform = Transform {
+                translation: Vector3D::new(1.0, -2.0, 3.5),
+                rotation: Quaternion::from_axis_angle(&axis, 1.2),
+                scale,
+            };
+            let matrix = transform.to_matrix();
+            let back = Transform::from_matrix(&matrix);
+
+            for i in 0..4 {
+                for (a, b) in matrix.row(i).iter().zip(back.to_matrix().row(i)) {
+                    assert!((a - b).abs() < 1e-9);
+                }
+            }
+            assert!(close(&back.translation, &transform.translation));
+        }
+
+        // without a mirror every part comes back as it was
+        let transform = Transform {
+            translation: Vector3D::new(1.0, -2.0, 3.5),
+            rotation: Quaternion::from_axis_angle(&axis, 1.2),
+            scale: Vector3D::new(2.0, 0.5, 3.0),
+        };
+        let back = Transform::from_matrix(&transform.to_matrix());
+        assert!(close(&back.scale, &transform.scale));
+        assert!(back.rotation.dot(&transform.rotation).abs() > 1.0 - 1e-9);
+    }
+}
@@ -0,0 +1,165 @@
+use crate::linalg::Vector3D;
+use crate::renderer::{Color, Mesh, Triangle};
+
+/// One row of a `BiomeTable`, covering a band of heights and slopes.
+#[derive(Clone)]
+pub struct BiomeRule {
+    pub name: String,
+    pub color: Color,
+    pub min_height: f64,
+    pub max_height: f64,
+    /// Slopes in radians from flat, 0 to PI / 2.
+    pub min_slope: f64,
+    pub max_slope: f64,
+    /// Height over which the rule fades in and out at its band's edges.
+    pub height_blend: f64,
+    /// Slope in radians over which the rule fades in and out at its band's edges.
+    pub slope_blend: f64,
+}
+
+impl BiomeRule {
+    /// A rule for heights from `min_height` to `max_height` on any slope.
+    pub fn new(name: &str, color: Color, min_height: f64, max_height: f64) -> BiomeRule {
+        BiomeRule {
+            name: name.to_string(),
+            color,
+            min_height,
+            max_height,
+            min_slope: 0.0,
+            max_slope: f64::MAX,
+            height_blend: 0.0,
+            slope_blend: 0.0,
+        }
+    }
+
+    pub fn with_slopes(mut self, min_slope: f64, max_slope: f64) -> BiomeRule {
+        self.min_slope = min_slope;
+        self.max_slope = max_slope;
+        self
+    }
+
+    pub fn with_blend(mut self, height_blend: f64, slope_blend: f64) -> BiomeRule {
+        self.height_blend = height_blend;
+        self.slope_blend = slope_blend;
+        self
+    }
+
+    /// How strongly the rule applies, 0 outside its bands and 1 well inside them.
+    pub fn weight(&self, height: f64, slope: f64) -> f64 {
+        band(height, self.min_height, self.max_height, self.height_blend)
+            * band(slope, self.min_slope, self.max_slope, self.slope_blend)
+    }
+}
+
+fn smoothstep(edge0: f64, edge1: f64, x: f64) -> f64 {
+    // open ended bands at f64::MIN or MAX have no room to blend in
+    if edge1 <= edge0 {
+        return if x >= edge0 { 1.0 } else { 0.0 };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// 1 between min and max, easing to 0 over `blend` centered on each edge
+fn band(x: f64, min: f64, max: f64, blend: f64) -> f64 {
+    if blend <= 0.0 {
+        return if x >= min && x <= max { 1.0 } else { 0.0 };
+    }
+
+    let half = blend / 2.0;
+    smoothstep(min - half, min + half, x) * (1.0 - smoothstep(max - half, max + half, x))
+}
+
+/// Colors terrain by height and slope. Rules are painted in order, each one over the
+/// result of those before it by its weight, so later rules win where they apply fully.
+#[derive(Clone)]
+pub struct BiomeTable {
+    /// Color wherever no rule applies.
+    pub base_color: Color,
+    pub rules: Vec<BiomeRule>,
+}
+
+impl BiomeTable {
+    pub fn new(base_color: Color) -> BiomeTable {
+        BiomeTable {
+            base_color,
+            rules: vec![],
+        }
+    }
+
+    pub fn with_rule(mut self, rule: BiomeRule) -> BiomeTable {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Sand, grass, rock and snow spread over heights from `low` to `high`, with rock on
+    /// every steep slope below the snow line.
+    pub fn terrain(low: f64, high: f64) -> BiomeTable {
+        let at = |fraction: f64| low + (high - low) * fraction;
+        let blend = (high - low) * 0.06;
+
+        BiomeTable::new(Color::new(110, 110, 110, 255))
+            .with_rule(
+                BiomeRule::new("sand", Color::new(194, 178, 128, 255), f64::MIN, at(0.3))
+                    .with_blend(blend, 0.0),
+            )
+            .with_rule(
+                BiomeRule::new("grass", Color::new(86, 125, 70, 255), at(0.3), at(0.65))
+                    .with_blend(blend, 0.0),
+            )
+            .with_rule(
+                BiomeRule::new("rock", Color::new(120, 112, 104, 255), at(0.65), f64::MAX)
+                    .with_blend(blend, 0.0),
+            )
+            .with_rule(
+                BiomeRule::new("cliff", Color::new(100, 94, 88, 255), f64::MIN, at(0.8))
+                    .with_slopes(40_f64.to_radians(), f64::MAX)
+                    .with_blend(blend, 10_f64.to_radians()),
+            )
+            .with_rule(
+                BiomeRule::new("snow", Color::new(240, 244, 250, 255), at(0.8), f64::MAX)
+                    .with_slopes(0.0, 55_f64.to_radians())
+                    .with_blend(blend, 10_f64.to_radians()),
+            )
+    }
+
+    pub fn color_at(&self, height: f64, slope: f64) -> Color {
+        self.rules
+            .iter()
+            .fold(self.base_color.clone(), |color, rule| {
+                let weight = rule.weight(height, slope);
+                if weight > 0.0 {
+                    color.lerp(&rule.color, weight)
+                } else {
+                    color
+                }
+            })
+    }
+
+    /// The mesh with every vertex colored by its y and the slope of its normal, or of its
+    /// face for triangles without vertex normals.
+    pub fn paint(&self, mesh: &Mesh) -> Mesh {
+        let slope = |normal: &Vector3D| normal.normalize().y.clamp(-1.0, 1.0).acos();
+
+        let triangles = mesh
+            .triangles
+            .iter()
+            .map(|t| {
+                let face_slope = slope(&t.face_normal());
+                let colors = (0..3)
+                    .map(|i| {
+                        let s = t.normals.get(i).map(slope).unwrap_or(face_slope);
+                        self.color_at(t.vertices[i].y, s)
+                    })
+                    .collect();
+
+                Triangle {
+                    colors,
+                    ..t.clone()
+                }
+            })
+            .collect();
+
+        Mesh { triangles }
+    }
+}
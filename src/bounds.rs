@@ -0,0 +1,195 @@
+use crate::linalg::{multiply_matrix_direction, multiply_matrix_vector, Matrix4D, Vector3D};
+use crate::material::Material;
+use crate::renderer::{Mesh, MeshSource, Object};
+
+/// Axis aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3D,
+    pub max: Vector3D,
+}
+
+impl Aabb {
+    pub fn new(min: Vector3D, max: Vector3D) -> Aabb {
+        Aabb { min, max }
+    }
+
+    /// A box containing nothing, growing it by any point gives a box around just that point.
+    pub fn empty() -> Aabb {
+        Aabb::new(
+            Vector3D::new(f64::MAX, f64::MAX, f64::MAX),
+            Vector3D::new(f64::MIN, f64::MIN, f64::MIN),
+        )
+    }
+
+    pub fn from_points<'a, I: IntoIterator<Item = &'a Vector3D>>(points: I) -> Aabb {
+        let mut aabb = Aabb::empty();
+        for point in points {
+            aabb.grow(point);
+        }
+        aabb
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn grow(&mut self, point: &Vector3D) {
+        self.min = Vector3D::new(
+            self.min.x.min(point.x),
+            self.min.y.min(point.y),
+            self.min.z.min(point.z),
+        );
+        self.max = Vector3D::new(
+            self.max.x.max(point.x),
+            self.max.y.max(point.y),
+            self.max.z.max(point.z),
+        );
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        if other.is_empty() {
+            return *self;
+        }
+        let mut union = *self;
+        union.grow(&other.min);
+        union.grow(&other.max);
+        union
+    }
+
+    pub fn center(&self) -> Vector3D {
+        self.min.add(&self.max).scale(0.5)
+    }
+
+    pub fn size(&self) -> Vector3D {
+        self.max.sub(&self.min)
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let size = self.size();
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x
+            && self.max.x >= other.min.x
+            && self.min.y <= other.max.y
+            && self.max.y >= other.min.y
+            && self.min.z <= other.max.z
+            && self.max.z >= other.min.z
+    }
+
+    pub fn corners(&self) -> [Vector3D; 8] {
+        let (a, b) = (&self.min, &self.max);
+        [
+            Vector3D::new(a.x, a.y, a.z),
+            Vector3D::new(b.x, a.y, a.z),
+            Vector3D::new(a.x, b.y, a.z),
+            Vector3D::new(b.x, b.y, a.z),
+            Vector3D::new(a.x, a.y, b.z),
+            Vector3D::new(b.x, a.y, b.z),
+            Vector3D::new(a.x, b.y, b.z),
+            Vector3D::new(b.x, b.y, b.z),
+        ]
+    }
+
+    /// The box around this one's corners after `transform`, loose if it rotates.
+    pub fn transform(&self, transform: &Matrix4D) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+
+        let corners = self
+            .corners()
+            .map(|corner| multiply_matrix_vector(&corner, transform));
+        Aabb::from_points(&corners)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vector3D,
+    pub radius: f64,
+}
+
+impl BoundingSphere {
+    pub fn new(center: Vector3D, radius: f64) -> BoundingSphere {
+        BoundingSphere { center, radius }
+    }
+
+    /// A sphere centered on the points' bounding box, tighter than one around the box itself.
+    pub fn from_points(points: &[Vector3D]) -> BoundingSphere {
+        let center = Aabb::from_points(points).center();
+        let radius = points
+            .iter()
+            .map(|p| p.sub(&center).magnitude())
+            .fold(0.0, f64::max);
+        BoundingSphere::new(center, radius)
+    }
+
+    /// The sphere after `transform`, its radius grown by the transform's largest scale.
+    pub fn transform(&self, transform: &Matrix4D) -> BoundingSphere {
+        let scale = [
+            Vector3D::new(1.0, 0.0, 0.0),
+            Vector3D::new(0.0, 1.0, 0.0),
+            Vector3D::new(0.0, 0.0, 1.0),
+        ]
+        .iter()
+        .map(|axis| multiply_matrix_direction(axis, transform).magnitude())
+        .fold(0.0, f64::max);
+
+        BoundingSphere::new(
+            multiply_matrix_vector(&self.center, transform),
+            self.radius * scale,
+        )
+    }
+}
+
+impl Mesh {
+    pub fn aabb(&self) -> Aabb {
+        Aabb::from_points(self.triangles.iter().flat_map(|t| &t.vertices))
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        let points: Vec<Vector3D> = self
+            .triangles
+            .iter()
+            .flat_map(|t| t.vertices.iter().copied())
+            .collect();
+        BoundingSphere::from_points(&points)
+    }
+}
+
+impl Object {
+    /// An object with its mesh's bounds worked out once up front.
+    pub fn new(mesh: Mesh, transform: Matrix4D) -> Object {
+        let mut object = Object {
+            mesh,
+            transform,
+            material: Material::default(),
+            source: MeshSource::Inline,
+            skin: None,
+            aabb: Aabb::empty(),
+            sphere: BoundingSphere::new(Vector3D::new(0.0, 0.0, 0.0), 0.0),
+        };
+        object.update_bounds();
+        object
+    }
+
+    /// Recomputes the mesh space bounds, needed after editing `mesh` but not `transform`.
+    pub fn update_bounds(&mut self) {
+        self.aabb = self.mesh.aabb();
+        self.sphere = self.mesh.bounding_sphere();
+    }
+
+    pub fn world_aabb(&self) -> Aabb {
+        self.aabb.transform(&self.transform)
+    }
+
+    pub fn world_sphere(&self) -> BoundingSphere {
+        self.sphere.transform(&self.transform)
+    }
+}
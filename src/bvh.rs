@@ -0,0 +1,602 @@
+use crate::bounds::Aabb;
+use crate::frustum::Frustum;
+use crate::linalg::{Matrix4D, Vector3D};
+use crate::ray::{Ray, TriangleHit};
+use crate::renderer::{Mesh, Renderer, Scene};
+
+// centroid bins tried along each axis when looking for a split
+const BINS: usize = 12;
+// cost of visiting a node relative to testing one primitive
+const TRAVERSAL_COST: f64 = 1.0;
+// leaves bigger than this are split even when the heuristic says not to
+const MAX_LEAF_SIZE: usize = 16;
+const NO_PARENT: usize = usize::MAX;
+
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    aabb: Aabb,
+    parent: usize,
+    left: usize,
+    right: usize,
+    // leaves own `order[start..start + count]`, inner nodes have a count of 0
+    start: usize,
+    count: usize,
+}
+
+impl Node {
+    fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+}
+
+/// Bounding volume hierarchy over a list of boxes, split with the surface area heuristic.
+///
+/// The tree only deals in indices into that list, what each box stands for is up to the
+/// caller, see `Bvh::from_mesh` and `SceneBvh`.
+#[derive(Clone, Debug, Default)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    // primitive indices, reordered so each leaf's are contiguous
+    order: Vec<usize>,
+    boxes: Vec<Aabb>,
+    // the leaf each primitive ended up in, for refitting them one at a time
+    leaves: Vec<usize>,
+}
+
+fn axis(v: &Vector3D, axis: usize) -> f64 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+impl Bvh {
+    pub fn build(boxes: Vec<Aabb>) -> Bvh {
+        let count = boxes.len();
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(count * 2),
+            order: (0..count).collect(),
+            boxes,
+            leaves: vec![0; count],
+        };
+        if count > 0 {
+            bvh.build_node(0, count, NO_PARENT);
+        }
+        bvh
+    }
+
+    /// A tree over the mesh's triangles, indexed like `mesh.triangles`.
+    pub fn from_mesh(mesh: &Mesh) -> Bvh {
+        Bvh::build(
+            mesh.triangles
+                .iter()
+                .map(|t| Aabb::from_points(&t.vertices))
+                .collect(),
+        )
+    }
+
+    pub fn len(&self) -> usize {
+        self.boxes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.boxes.is_empty()
+    }
+
+    /// The box around everything in the tree.
+    pub fn aabb(&self) -> Aabb {
+        self.nodes
+            .first()
+            .map(|node| node.aabb)
+            .unwrap_or_else(Aabb::empty)
+    }
+
+    fn build_node(&mut self, start: usize, end: usize, parent: usize) -> usize {
+        let index = self.nodes.len();
+        let aabb = self.range_bounds(start, end);
+        self.nodes.push(Node {
+            aabb,
+            parent,
+            left: 0,
+            right: 0,
+            start,
+            count: end - start,
+        });
+
+        match self.split(start, end, &aabb) {
+            Some(middle) => {
+                let left = self.build_node(start, middle, index);
+                let right = self.build_node(middle, end, index);
+                let node = &mut self.nodes[index];
+                node.left = left;
+                node.right = right;
+                node.count = 0;
+            }
+            None => {
+                for &primitive in &self.order[start..end] {
+                    self.leaves[primitive] = index;
+                }
+            }
+        }
+
+        index
+    }
+
+    fn range_bounds(&self, start: usize, end: usize) -> Aabb {
+        self.order[start..end]
+            .iter()
+            .fold(Aabb::empty(), |aabb, &i| aabb.union(&self.boxes[i]))
+    }
+
+    // partitions the range at the cheapest binned split and returns where the right half
+    // starts, or None when a leaf is cheaper
+    fn split(&mut self, start: usize, end: usize, aabb: &Aabb) -> Option<usize> {
+        let count = end - start;
+        if count <= 1 {
+            return None;
+        }
+
+        let centers: Vec<Vector3D> = self.order[start..end]
+            .iter()
+            .map(|&i| self.boxes[i].center())
+            .collect();
+        let center_bounds = Aabb::from_points(&centers);
+        // flat boxes have no area, any split of them beats a leaf
+        let area = aabb.surface_area().max(f64::MIN_POSITIVE);
+
+        let bin_of = |value: f64, low: f64, high: f64| {
+            (((value - low) / (high - low) * BINS as f64) as usize).min(BINS - 1)
+        };
+
+        let mut best: Option<(f64, usize, usize)> = None;
+        for a in 0..3 {
+            let (low, high) = (axis(&center_bounds.min, a), axis(&center_bounds.max, a));
+            if high <= low {
+                continue;
+            }
+
+            let mut bins = [(Aabb::empty(), 0_usize); BINS];
+            for (&i, center) in self.order[start..end].iter().zip(&centers) {
+                let bin = &mut bins[bin_of(axis(center, a), low, high)];
+                bin.0 = bin.0.union(&self.boxes[i]);
+                bin.1 += 1;
+            }
+
+            // sweep from the right for the area and count right of each split
+            let mut right = [(0.0, 0_usize); BINS];
+            let (mut right_box, mut right_count) = (Aabb::empty(), 0);
+            for k in (1..BINS).rev() {
+                right_box = right_box.union(&bins[k].0);
+                right_count += bins[k].1;
+                right[k - 1] = (right_box.surface_area(), right_count);
+            }
+
+            let (mut left_box, mut left_count) = (Aabb::empty(), 0);
+            for k in 0..BINS - 1 {
+                left_box = left_box.union(&bins[k].0);
+                left_count += bins[k].1;
+                let (right_area, right_count) = right[k];
+                if left_count == 0 || right_count == 0 {
+                    continue;
+                }
+
+                let cost = TRAVERSAL_COST
+                    + (left_box.surface_area() * left_count as f64
+                        + right_area * right_count as f64)
+                        / area;
+                if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                    best = Some((cost, a, k));
+                }
+            }
+        }
+
+        let (cost, a, split) = best?;
+        if cost >= count as f64 && count <= MAX_LEAF_SIZE {
+            return None;
+        }
+
+        let (low, high) = (axis(&center_bounds.min, a), axis(&center_bounds.max, a));
+        let mut middle = start;
+        for i in start..end {
+            if bin_of(axis(&centers[i - start], a), low, high) <= split {
+                self.order.swap(i, middle);
+                middle += 1;
+            }
+        }
+
+        Some(middle)
+    }
+
+    /// Indices of the boxes overlapping `aabb`.
+    pub fn query_aabb(&self, aabb: &Aabb) -> Vec<usize> {
+        self.collect(|b| b.intersects(aabb))
+    }
+
+    /// Indices of the boxes at least partly inside `frustum`.
+    pub fn query_frustum(&self, frustum: &Frustum) -> Vec<usize> {
+        self.collect(|b| frustum.intersects_aabb(b))
+    }
+
+    // every primitive whose box passes `test`, skipping subtrees whose box doesn't
+    fn collect<F: Fn(&Aabb) -> bool>(&self, test: F) -> Vec<usize> {
+        let mut found = vec![];
+        let mut stack = if self.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !test(&node.aabb) {
+                continue;
+            }
+
+            if node.is_leaf() {
+                let primitives = &self.order[node.start..node.start + node.count];
+                found.extend(primitives.iter().filter(|&&i| test(&self.boxes[i])));
+            } else {
+                stack.push(node.right);
+                stack.push(node.left);
+            }
+        }
+
+        found
+    }
+
+    /// The closest primitive along `ray` within `max_distance`. `hit` is called for each
+    /// primitive whose box the ray reaches, with the closest distance found so far, and
+    /// returns its hit distance along with anything else the caller wants back.
+    pub fn intersect_ray<T, F: FnMut(usize, f64) -> Option<(f64, T)>>(
+        &self,
+        ray: &Ray,
+        max_distance: f64,
+        mut hit: F,
+    ) -> Option<(usize, f64, T)> {
+        let mut closest = None;
+        let mut max_distance = max_distance;
+        let mut stack = vec![];
+        if let Some(entry) = self
+            .nodes
+            .first()
+            .and_then(|root| ray.intersect_aabb(&root.aabb, max_distance))
+        {
+            stack.push((0, entry));
+        }
+
+        while let Some((index, entry)) = stack.pop() {
+            // something closer was found since this node was queued
+            if entry > max_distance {
+                continue;
+            }
+
+            let node = &self.nodes[index];
+            if node.is_leaf() {
+                for &i in &self.order[node.start..node.start + node.count] {
+                    if let Some((distance, value)) = hit(i, max_distance) {
+                        if distance <= max_distance {
+                            max_distance = distance;
+                            closest = Some((i, distance, value));
+                        }
+                    }
+                }
+                continue;
+            }
+
+            let left = ray.intersect_aabb(&self.nodes[node.left].aabb, max_distance);
+            let right = ray.intersect_aabb(&self.nodes[node.right].aabb, max_distance);
+            // the nearer child goes on top so the further one is more often skipped
+            match (left, right) {
+                (Some(l), Some(r)) if l <= r => {
+                    stack.push((node.right, r));
+                    stack.push((node.left, l));
+                }
+                (Some(l), Some(r)) => {
+                    stack.push((node.left, l));
+                    stack.push((node.right, r));
+                }
+                (Some(l), None) => stack.push((node.left, l)),
+                (None, Some(r)) => stack.push((node.right, r)),
+                (None, None) => {}
+            }
+        }
+
+        closest
+    }
+
+    /// The closest triangle of `mesh` along `ray`, for a tree made by `from_mesh`.
+    pub fn intersect_mesh(
+        &self,
+        mesh: &Mesh,
+        ray: &Ray,
+        max_distance: f64,
+    ) -> Option<(usize, TriangleHit)> {
+        self.intersect_ray(ray, max_distance, |i, max_distance| {
+            let v = &mesh.triangles[i].vertices;
+            ray.intersect_triangle(&v[0], &v[1], &v[2], max_distance)
+                .map(|hit| (hit.distance, hit))
+        })
+        .map(|(i, _, hit)| (i, hit))
+    }
+
+    /// Moves primitive `index` to `aabb` and updates the boxes above it. The tree keeps its
+    /// shape, so queries slow down as primitives move far from where they were at `build`.
+    pub fn refit(&mut self, index: usize, aabb: Aabb) {
+        self.boxes[index] = aabb;
+
+        let mut node = self.leaves[index];
+        while node != NO_PARENT {
+            let bounds = self.node_bounds(node);
+            // nothing above can change either
+            if bounds == self.nodes[node].aabb {
+                break;
+            }
+            self.nodes[node].aabb = bounds;
+            node = self.nodes[node].parent;
+        }
+    }
+
+    /// Replaces every box at once, cheaper than `refit` on each when most have moved.
+    pub fn refit_all(&mut self, boxes: Vec<Aabb>) {
+        assert_eq!(
+            boxes.len(),
+            self.boxes.len(),
+            "refit can't add or remove boxes"
+        );
+        self.boxes = boxes;
+
+        // children are always stored after their parent
+        for node in (0..self.nodes.len()).rev() {
+            self.nodes[node].aabb = self.node_bounds(node);
+        }
+    }
+
+    fn node_bounds(&self, index: usize) -> Aabb {
+        let node = &self.nodes[index];
+        if node.is_leaf() {
+            self.range_bounds(node.start, node.start + node.count)
+        } else {
+            self.nodes[node.left]
+                .aabb
+                .union(&self.nodes[node.right].aabb)
+        }
+    }
+}
+
+/// Where a ray hit a scene.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SceneHit {
+    /// Index into `Scene::objects`.
+    pub object: usize,
+    /// Index into the object's `mesh.triangles`.
+    pub triangle: usize,
+    pub distance: f64,
+    pub barycentric: [f64; 3],
+}
+
+/// Two level hierarchy for a scene. The top tree holds the objects' world space boxes, and
+/// each mesh has a tree of its triangles in mesh space, so moving an object only refits
+/// the top tree.
+pub struct SceneBvh {
+    pub objects: Bvh,
+    /// One per object, in the same order.
+    pub meshes: Vec<Bvh>,
+    // world to mesh space for each object, None for transforms that flatten it
+    inverses: Vec<Option<Matrix4D>>,
+}
+
+impl SceneBvh {
+    pub fn build(scene: &Scene) -> SceneBvh {
+        SceneBvh {
+            objects: Bvh::build(scene.objects.iter().map(|o| o.world_aabb()).collect()),
+            meshes: scene
+                .objects
+                .iter()
+                .map(|o| Bvh::from_mesh(&o.mesh))
+                .collect(),
+            inverses: scene
+                .objects
+                .iter()
+                .map(|o| o.transform.inverse())
+                .collect(),
+        }
+    }
+
+    /// Call after changing the transform of object `index`.
+    pub fn refit_object(&mut self, scene: &Scene, index: usize) {
+        let object = &scene.objects[index];
+        self.inverses[index] = object.transform.inverse();
+        self.objects.refit(index, object.world_aabb());
+    }
+
+    /// Call after editing the mesh of object `index`, once its bounds are updated.
+    pub fn rebuild_mesh(&mut self, scene: &Scene, index: usize) {
+        self.meshes[index] = Bvh::from_mesh(&scene.objects[index].mesh);
+        self.refit_object(scene, index);
+    }
+
+    /// The closest triangle in the scene along `ray`.
+    pub fn intersect_ray(&self, scene: &Scene, ray: &Ray, max_distance: f64) -> Option<SceneHit> {
+        self.objects
+            .intersect_ray(ray, max_distance, |object, max_distance| {
+                // distances along the mesh space ray are the same as along the world one
+                let local = ray.transform(self.inverses[object].as_ref()?);
+                let mesh = &scene.objects[object].mesh;
+                self.meshes[object]
+                    .intersect_mesh(mesh, &local, max_distance)
+                    .map(|(triangle, hit)| (hit.distance, (triangle, hit)))
+            })
+            .map(|(object, distance, (triangle, hit))| SceneHit {
+                object,
+                triangle,
+                distance,
+                barycentric: hit.barycentric,
+            })
+    }
+
+    /// Indices of the objects whose world box overlaps `aabb`.
+    pub fn query_aabb(&self, aabb: &Aabb) -> Vec<usize> {
+        self.objects.query_aabb(aabb)
+    }
+
+    pub fn query_frustum(&self, frustum: &Frustum) -> Vec<usize> {
+        self.objects.query_frustum(frustum)
+    }
+}
+
+impl Renderer {
+    /// Like `render_scene`, but finds the visible objects through `bvh` instead of testing
+    /// each one.
+    pub fn render_scene_bvh(&mut self, scene: &Scene, bvh: &SceneBvh, proj_mat: &Matrix4D) {
+        let visible = bvh.query_frustum(&self.frustum(proj_mat));
+        self.stats.objects_submitted += scene.objects.len();
+        self.stats.objects_culled += scene.objects.len() - visible.len();
+
+        for index in visible {
+            self.render_object(&scene.objects[index], proj_mat);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+    use crate::renderer::{CameraSetup, Color, Object, Triangle};
+
+    fn random_point(rng: &mut Rng, size: f64) -> Vector3D {
+        Vector3D::new(
+            rng.range(-size, size),
+            rng.range(-size, size),
+            rng.range(-size, size),
+        )
+    }
+
+    // small triangles scattered through a 40 unit cube
+    fn soup(rng: &mut Rng, count: usize) -> Mesh {
+        let white = Color::new(255, 255, 255, 255);
+        let triangles = (0..count)
+            .map(|_| {
+                let center = random_point(rng, 20.0);
+                let corner = |rng: &mut Rng| center.add(&random_point(rng, 2.0));
+                Triangle::new(corner(rng), corner(rng), corner(rng), &white)
+            })
+            .collect();
+        Mesh { triangles }
+    }
+
+    fn sorted(mut indices: Vec<usize>) -> Vec<usize> {
+        indices.sort();
+        indices
+    }
+
+    #[test]
+    fn rays_hit_the_same_triangle_as_testing_every_one() {
+        let mut rng = Rng::new(7);
+        let mesh = soup(&mut rng, 500);
+        let bvh = Bvh::from_mesh(&mesh);
+        assert_eq!(bvh.len(), 500);
+
+        let mut hits = 0;
+        for _ in 0..200 {
+            let origin = random_point(&mut rng, 30.0);
+            let target = random_point(&mut rng, 10.0);
+            let ray = Ray::new(origin, target.sub(&origin).normalize());
+
+            let brute = mesh
+                .triangles
+                .iter()
+                .enumerate()
+                .filter_map(|(i, t)| {
+                    let v = &t.vertices;
+                    ray.intersect_triangle(&v[0], &v[1], &v[2], f64::MAX)
+                        .map(|hit| (i, hit.distance))
+                })
+                .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+            let found = bvh
+                .intersect_mesh(&mesh, &ray, f64::MAX)
+                .map(|(i, hit)| (i, hit.distance));
+
+            assert_eq!(found, brute);
+            hits += found.is_some() as usize;
+        }
+        assert!(hits > 50);
+    }
+
+    #[test]
+    fn box_and_frustum_queries_match_a_linear_scan() {
+        let mut rng = Rng::new(11);
+        let mesh = soup(&mut rng, 300);
+        let boxes: Vec<Aabb> = mesh
+            .triangles
+            .iter()
+            .map(|t| Aabb::from_points(&t.vertices))
+            .collect();
+        let bvh = Bvh::build(boxes.clone());
+
+        for _ in 0..50 {
+            let corner = random_point(&mut rng, 20.0);
+            let query = Aabb::new(corner, corner.add(&Vector3D::new(8.0, 6.0, 10.0)));
+            let linear: Vec<usize> = (0..boxes.len())
+                .filter(|&i| boxes[i].intersects(&query))
+                .collect();
+            assert_eq!(sorted(bvh.query_aabb(&query)), linear);
+        }
+
+        for yaw in [0.0, 70.0, 160.0, 250.0] {
+            let setup = CameraSetup {
+                position: Vector3D::new(0.0, 3.0, -25.0),
+                yaw,
+                far: 30.0,
+                ..CameraSetup::default()
+            };
+            let camera = setup.camera(1.0);
+            let proj = camera.get_proj_matrix(1.0, setup.fov, setup.near, setup.far);
+            let to_camera = Matrix4D::new_translation(&camera.position.scale(-1.0));
+            let frustum =
+                Frustum::from_matrix(&(&(&to_camera * &camera.create_view_matrix()) * &proj));
+
+            let linear: Vec<usize> = (0..boxes.len())
+                .filter(|&i| frustum.intersects_aabb(&boxes[i]))
+                .collect();
+            assert_eq!(sorted(bvh.query_frustum(&frustum)), linear);
+        }
+    }
+
+    #[test]
+    fn refitting_a_moved_object_finds_it_where_it_went() {
+        let white = Color::new(255, 255, 255, 255);
+        let mut scene = Scene::new();
+        for x in [-10.0, 0.0, 10.0] {
+            scene.objects.push(Object::new(
+                Mesh::cuboid(&Vector3D::new(2.0, 2.0, 2.0), &white),
+                Matrix4D::new_translation(&Vector3D::new(x, 0.0, 0.0)),
+            ));
+        }
+        let mut bvh = SceneBvh::build(&scene);
+
+        let down = |x: f64| Ray::new(Vector3D::new(x, 10.0, 0.0), Vector3D::new(0.0, -1.0, 0.0));
+        assert_eq!(
+            bvh.intersect_ray(&scene, &down(-10.0), f64::MAX)
+                .unwrap()
+                .object,
+            0
+        );
+
+        scene.objects[0].transform = Matrix4D::new_translation(&Vector3D::new(30.0, 5.0, 0.0));
+        bvh.refit_object(&scene, 0);
+
+        assert!(bvh.intersect_ray(&scene, &down(-10.0), f64::MAX).is_none());
+        let hit = bvh.intersect_ray(&scene, &down(30.0), f64::MAX).unwrap();
+        assert_eq!(hit.object, 0);
+        assert!((hit.distance - 4.0).abs() < 1e-9);
+
+        let around = Aabb::new(
+            Vector3D::new(28.0, 3.0, -2.0),
+            Vector3D::new(32.0, 7.0, 2.0),
+        );
+        assert_eq!(bvh.query_aabb(&around), vec![0]);
+        assert!(bvh.objects.aabb().max.x >= 31.0);
+    }
+}
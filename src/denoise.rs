@@ -0,0 +1,356 @@
+use crate::linalg::{dot, Vector3D};
+use crate::pathtracer::PathTracer;
+use crate::postprocess::{luma, pack};
+use crate::renderer::{FrameBuffer, FAR_DEPTH};
+use std::thread;
+
+/// What a pixel's camera ray first hit, used to keep the denoiser from blurring across
+/// edges. Pixels that hit nothing keep the default.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Guide {
+    /// Surface color, linear 0 to 1.
+    pub albedo: [f64; 3],
+    /// Unit world space normal, zero where nothing was hit.
+    pub normal: Vector3D,
+    /// View depth like the depth buffer's, `FAR_DEPTH` where nothing was hit.
+    pub depth: f64,
+}
+
+impl Default for Guide {
+    fn default() -> Guide {
+        Guide {
+            albedo: [1.0; 3],
+            normal: Vector3D::new(0.0, 0.0, 0.0),
+            depth: FAR_DEPTH,
+        }
+    }
+}
+
+// b3 spline, the 5 tap kernel of the wavelet transform
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Edge avoiding à-trous wavelet filter for noisy path traced images.
+///
+/// Each iteration blurs with the same 5x5 kernel spread twice as far apart as the last,
+/// weighting every tap by how alike its color, normal and depth are to the center's.
+/// Lighting is filtered with the albedo divided out, so textures stay sharp.
+pub struct AtrousDenoiser {
+    pub iterations: u32,
+    /// Difference in brightness at which a tap's weight falls to 1 / e, halved every
+    /// iteration as the noise goes down.
+    pub sigma_color: f64,
+    /// Power the cosine between normals is raised to, higher keeps creases sharper.
+    pub normal_power: i32,
+    /// Depth difference beyond what the surface's slope accounts for, relative to the
+    /// depth, at which a tap's weight falls to 1 / e.
+    pub sigma_depth: f64,
+    pub threads: usize,
+}
+
+impl Default for AtrousDenoiser {
+    fn default() -> AtrousDenoiser {
+        AtrousDenoiser {
+            iterations: 5,
+            sigma_color: 0.3,
+            normal_power: 64,
+            sigma_depth: 0.05,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+}
+
+// one iteration's input, what every pixel's filter reads
+struct Level<'a> {
+    input: &'a [[f64; 3]],
+    guides: &'a [Guide],
+    width: usize,
+    height: usize,
+    step: isize,
+    sigma_color: f64,
+}
+
+// lighting without the surface color, which is put back after filtering
+fn demodulate(color: &[f64; 3], albedo: &[f64; 3]) -> [f64; 3] {
+    [0, 1, 2].map(|k| color[k] / albedo[k].max(0.01))
+}
+
+// how fast depth changes across and down the screen at a pixel, taking the gentler side
+// along each axis so a pixel next to an edge keeps the slope of its own surface
+fn depth_slope(guides: &[Guide], width: usize, height: usize, x: usize, y: usize) -> (f64, f64) {
+    let depth = guides[y * width + x].depth;
+    let slope = |neighbours: [Option<usize>; 2]| {
+        neighbours
+            .iter()
+            .flatten()
+            .map(|&q| guides[q].depth)
+            .filter(|&d| d < FAR_DEPTH)
+            .map(|d| (d - depth).abs())
+            .fold(f64::INFINITY, f64::min)
+    };
+    let across = slope([
+        x.checked_sub(1).map(|x| y * width + x),
+        (x + 1 < width).then(|| y * width + x + 1),
+    ]);
+    let down = slope([
+        y.checked_sub(1).map(|y| y * width + x),
+        (y + 1 < height).then(|| (y + 1) * width + x),
+    ]);
+    let finite = |slope: f64| if slope.is_finite() { slope } else { 0.0 };
+    (finite(across), finite(down))
+}
+
+impl AtrousDenoiser {
+    pub fn new() -> AtrousDenoiser {
+        AtrousDenoiser::default()
+    }
+
+    /// Filters a `width` by `height` linear color image, `guides` holding one entry for
+    /// every pixel.
+    pub fn denoise(
+        &self,
+        color: &[[f64; 3]],
+        guides: &[Guide],
+        width: usize,
+        height: usize,
+    ) -> Vec<[f64; 3]> {
+        assert_eq!(color.len(), width * height);
+        assert_eq!(guides.len(), width * height);
+
+        let mut current: Vec<[f64; 3]> = color
+            .iter()
+            .zip(guides)
+            .map(|(c, g)| demodulate(c, &g.albedo))
+            .collect();
+        let mut next = vec![[0.0; 3]; width * height];
+        let rows_per_thread = height.div_ceil(self.threads.max(1)).max(1);
+
+        for iteration in 0..self.iterations {
+            let level = &Level {
+                input: &current,
+                guides,
+                width,
+                height,
+                step: 1 << iteration,
+                sigma_color: self.sigma_color / (1 << iteration) as f64,
+            };
+
+            thread::scope(|s| {
+                for (chunk, rows) in next.chunks_mut(rows_per_thread * width).enumerate() {
+                    s.spawn(move || {
+                        let first = chunk * rows_per_thread * width;
+                        for (i, out) in rows.iter_mut().enumerate() {
+                            *out = self.filter_pixel(level, first + i);
+                        }
+                    });
+                }
+            });
+
+            std::mem::swap(&mut current, &mut next);
+        }
+
+        current
+            .iter()
+            .zip(guides)
+            .map(|(c, g)| [0, 1, 2].map(|k| c[k] * g.albedo[k].max(0.01)))
+            .collect()
+    }
+
+    fn filter_pixel(&self, level: &Level, p: usize) -> [f64; 3] {
+        let Level {
+            input,
+            guides,
+            width,
+            height,
+            step,
+            sigma_color,
+        } = *level;
+        let (px, py) = ((p % width) as isize, (p / width) as isize);
+        let center = &input[p];
+        let center_guide = &guides[p];
+        let center_luma = luma(*center);
+        let background = center_guide.depth >= FAR_DEPTH;
+        let (slope_x, slope_y) = if background {
+            (0.0, 0.0)
+        } else {
+            depth_slope(guides, width, height, px as usize, py as usize)
+        };
+
+        let mut sum = [0.0; 3];
+        let mut total = 0.0;
+
+        for (j, ky) in KERNEL.iter().enumerate() {
+            let qy = py + (j as isize - 2) * step;
+            if qy < 0 || qy >= height as isize {
+                continue;
+            }
+            for (i, kx) in KERNEL.iter().enumerate() {
+                let qx = px + (i as isize - 2) * step;
+                if qx < 0 || qx >= width as isize {
+                    continue;
+                }
+
+                let q = qy as usize * width + qx as usize;
+                let sample = &input[q];
+                let guide = &guides[q];
+
+                // the sky only blurs with sky, surfaces only with surfaces
+                if (guide.depth >= FAR_DEPTH) != background {
+                    continue;
+                }
+
+                let mut weight = kx * ky;
+                let color_difference = luma(*sample) - center_luma;
+                weight *=
+                    (-color_difference * color_difference / (sigma_color * sigma_color)).exp();
+
+                if !background {
+                    let cos = dot(&center_guide.normal, &guide.normal).max(0.0);
+                    weight *= cos.powi(self.normal_power);
+
+                    // a sloping surface is expected to change depth by its slope times the
+                    // distance to the tap, anything more is another surface
+                    let (dx, dy) = (qx - px, qy - py);
+                    let expected = slope_x * dx.abs() as f64 + slope_y * dy.abs() as f64;
+                    let depth_difference = (guide.depth - center_guide.depth).abs();
+                    let tolerance = self.sigma_depth * center_guide.depth + expected;
+                    weight *= (-depth_difference / tolerance).exp();
+                }
+
+                for k in 0..3 {
+                    sum[k] += sample[k] * weight;
+                }
+                total += weight;
+            }
+        }
+
+        if total > 0.0 {
+            sum.map(|c| c / total)
+        } else {
+            *center
+        }
+    }
+}
+
+impl PathTracer {
+    /// Writes the denoised image so far into the color buffer, in place of the noisy one
+    /// `render_pass` wrote, and returns it in linear color. None before the first pass.
+    pub fn write_denoised(
+        &self,
+        denoiser: &AtrousDenoiser,
+        framebuffer: &mut FrameBuffer,
+    ) -> Option<Vec<[f64; 3]>> {
+        let (width, height) = (framebuffer.width, framebuffer.height);
+        if self.guides().len() != width * height {
+            return None;
+        }
+
+        let image = denoiser.denoise(&self.image(), self.guides(), width, height);
+        for (pixel, color) in framebuffer.color_buffer.iter_mut().zip(&image) {
+            *pixel = pack(*color);
+        }
+        Some(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+
+    const WIDTH: usize = 32;
+    const HEIGHT: usize = 12;
+
+    fn surface(normal: Vector3D, depth: f64) -> Guide {
+        Guide {
+            albedo: [1.0; 3],
+            normal,
+            depth,
+        }
+    }
+
+    // noisy lighting, around 0.2 left of the middle and 0.8 right of it
+    fn halves() -> Vec<[f64; 3]> {
+        let mut rng = Rng::new(3);
+        (0..WIDTH * HEIGHT)
+            .map(|p| {
+                let level = if p % WIDTH < WIDTH / 2 { 0.2 } else { 0.8 };
+                [level + rng.range(-0.1, 0.1); 3]
+            })
+            .collect()
+    }
+
+    fn spread(values: impl Iterator<Item = f64>) -> (f64, f64) {
+        values.fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), v| {
+            (low.min(v), high.max(v))
+        })
+    }
+
+    #[test]
+    fn a_constant_image_stays_constant() {
+        // a floor sloping away with sky above it, the albedo halving on a checkerboard
+        let guides: Vec<Guide> = (0..WIDTH * HEIGHT)
+            .map(|p| {
+                let (x, y) = (p % WIDTH, p / WIDTH);
+                if y < 3 {
+                    return Guide::default();
+                }
+                let albedo = if (x / 4 + y / 4) % 2 == 0 { 1.0 } else { 0.5 };
+                Guide {
+                    albedo: [albedo; 3],
+                    ..surface(Vector3D::new(0.0, 1.0, 0.0), 40.0 / y as f64)
+                }
+            })
+            .collect();
+        let color: Vec<[f64; 3]> = guides.iter().map(|g| g.albedo.map(|a| a * 0.6)).collect();
+
+        let denoised = AtrousDenoiser::new().denoise(&color, &guides, WIDTH, HEIGHT);
+        for (before, after) in color.iter().zip(&denoised) {
+            for k in 0..3 {
+                assert!((before[k] - after[k]).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn edges_in_normal_or_depth_are_not_blurred_across() {
+        // only the guides can keep the halves apart
+        let denoiser = AtrousDenoiser {
+            sigma_color: 1e6,
+            ..AtrousDenoiser::new()
+        };
+        let color = halves();
+        let facing = Vector3D::new(0.0, 0.0, -1.0);
+        let crease: Vec<Guide> = (0..WIDTH * HEIGHT)
+            .map(|p| {
+                let normal = if p % WIDTH < WIDTH / 2 {
+                    facing
+                } else {
+                    Vector3D::new(1.0, 0.0, 0.0)
+                };
+                surface(normal, 5.0)
+            })
+            .collect();
+        let step: Vec<Guide> = (0..WIDTH * HEIGHT)
+            .map(|p| {
+                let depth = if p % WIDTH < WIDTH / 2 { 5.0 } else { 10.0 };
+                surface(facing, depth)
+            })
+            .collect();
+
+        for guides in [crease, step] {
+            let denoised = denoiser.denoise(&color, &guides, WIDTH, HEIGHT);
+            let side = |left: bool| {
+                spread(
+                    (0..WIDTH * HEIGHT)
+                        .filter(|p| (p % WIDTH < WIDTH / 2) == left)
+                        .map(|p| denoised[p][0]),
+                )
+            };
+            // each half is smoothed but stays clear of the other
+            let (low, high) = side(true);
+            assert!(low > 0.15 && high < 0.25, "left {} to {}", low, high);
+            let (low, high) = side(false);
+            assert!(low > 0.75 && high < 0.85, "right {} to {}", low, high);
+        }
+    }
+}
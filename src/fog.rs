@@ -0,0 +1,49 @@
+use crate::renderer::{Color, FrameBuffer, FAR_DEPTH};
+
+/// Exponential distance fog, blended over a finished frame by its depth buffer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fog {
+    pub color: Color,
+    /// How quickly the fog thickens per world unit past `start`.
+    pub density: f64,
+    /// View depth before which there is no fog.
+    pub start: f64,
+}
+
+impl Fog {
+    pub fn new(color: Color, density: f64) -> Fog {
+        Fog {
+            color,
+            density,
+            start: 0.0,
+        }
+    }
+
+    pub fn with_start(mut self, start: f64) -> Fog {
+        self.start = start;
+        self
+    }
+
+    /// Fraction of the fog color covering something `depth` away, 0 to 1.
+    pub fn amount(&self, depth: f64) -> f64 {
+        let distance = (depth - self.start).max(0.0);
+        1.0 - (-self.density * distance).exp()
+    }
+
+    /// Fogs every drawn pixel of `framebuffer`, leaving the background as it was cleared.
+    pub fn apply(&self, framebuffer: &mut FrameBuffer) {
+        for (color, depth) in framebuffer
+            .color_buffer
+            .iter_mut()
+            .zip(&framebuffer.depth_buffer)
+        {
+            if *depth >= FAR_DEPTH {
+                continue;
+            }
+            let amount = self.amount(*depth);
+            if amount > 0.0 {
+                *color = Color::from_u32(*color).lerp(&self.color, amount).to_u32();
+            }
+        }
+    }
+}
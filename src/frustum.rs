@@ -0,0 +1,101 @@
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::linalg::{Matrix4D, Plane, Vector3D};
+use crate::renderer::{Mesh, Renderer, Scene};
+
+/// The volume a camera can see, as six inward facing planes.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near and far.
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a world to clip space matrix. With vectors multiplied as rows
+    /// each clip coordinate is a dot product with one of its columns, and a point is inside
+    /// while -w <= x <= w, -w <= y <= w and 0 <= z <= w.
+    pub fn from_matrix(view_proj: &Matrix4D) -> Frustum {
+        let [x, y, z, w] = [0, 1, 2, 3].map(|j| view_proj.column(j));
+        let combine = |a: [f64; 4], b: [f64; 4], sign: f64| {
+            let p = [0, 1, 2, 3].map(|i| a[i] + sign * b[i]);
+            let normal = Vector3D::new(p[0], p[1], p[2]);
+            let length = normal.magnitude();
+            Plane::new(normal.scale(1.0 / length), p[3] / length)
+        };
+
+        Frustum {
+            planes: [
+                combine(w, x, 1.0),
+                combine(w, x, -1.0),
+                combine(w, y, 1.0),
+                combine(w, y, -1.0),
+                combine(z, z, 0.0),
+                combine(w, z, -1.0),
+            ],
+        }
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.signed_distance(&sphere.center) >= -sphere.radius)
+    }
+
+    /// False only when the box is entirely behind one of the planes, so a few boxes near
+    /// the frustum's corners pass without being visible.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // the corner furthest along the plane's normal
+            let n = &plane.normal;
+            let corner = Vector3D::new(
+                if n.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                if n.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                if n.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+            );
+            plane.signed_distance(&corner) >= 0.0
+        })
+    }
+}
+
+impl Renderer {
+    /// World to clip space for the current camera, the translation to the camera followed
+    /// by its view rotation and `proj_mat`.
+    pub fn view_projection(&self, proj_mat: &Matrix4D) -> Matrix4D {
+        let to_camera = Matrix4D::new_translation(&self.camera.position.scale(-1.0));
+        &(&to_camera * &self.camera.create_view_matrix()) * proj_mat
+    }
+
+    pub fn frustum(&self, proj_mat: &Matrix4D) -> Frustum {
+        Frustum::from_matrix(&self.view_projection(proj_mat))
+    }
+
+    /// Draws every object at least partly inside the view frustum. The cheap sphere test
+    /// runs first, the box only for objects it can't rule out.
+    pub fn render_scene(&mut self, scene: &Scene, proj_mat: &Matrix4D) {
+        let frustum = self.frustum(proj_mat);
+
+        for object in &scene.objects {
+            self.stats.objects_submitted += 1;
+
+            if !frustum.intersects_sphere(&object.world_sphere())
+                || !frustum.intersects_aabb(&object.world_aabb())
+            {
+                self.stats.objects_culled += 1;
+                continue;
+            }
+
+            self.render_object(object, proj_mat);
+        }
+    }
+
+    /// Draws `mesh`, already in world space, unless its bounding box is outside `frustum`.
+    pub fn render_mesh_culled(&mut self, mesh: &Mesh, frustum: &Frustum, proj_mat: &Matrix4D) {
+        self.stats.objects_submitted += 1;
+
+        if !frustum.intersects_aabb(&mesh.aabb()) {
+            self.stats.objects_culled += 1;
+            return;
+        }
+
+        self.render_mesh(mesh, proj_mat);
+    }
+}
@@ -0,0 +1,1504 @@
+//! glTF 2.0 loading, from a `.gltf` with its buffers in separate `.bin` files or embedded
+//! as base64, or from a single binary `.glb`.
+//!
+//! Every node with a mesh becomes one object per mesh primitive, with positions, normals,
+//! the first texture coordinates and vertex colors, and joints and weights for skinned
+//! meshes. Materials keep their base color, base color texture, emission, and a
+//! reflectivity and transparency approximating the metallic roughness model. Morph
+//! targets, cameras and tangents are left out, and files requiring an extension we don't
+//! support are refused rather than loaded wrongly.
+
+use crate::animation::{AnimationClip, Channel, Interpolation, Keyframe, Track, Transform};
+use crate::image::{Image, ImageError};
+use crate::json::{Json, JsonError};
+use crate::linalg::{Matrix4D, Quaternion, Vector2D, Vector3D};
+use crate::material::Material;
+use crate::renderer::{Color, Mesh, Object, Scene, Triangle};
+use crate::skinning::{Joint, Skeleton, SkinningMethod};
+use crate::texture::Texture;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Extensions a file can require and still be loaded.
+pub const SUPPORTED_EXTENSIONS: [&str; 4] = [
+    "KHR_materials_emissive_strength",
+    "KHR_materials_ior",
+    "KHR_materials_transmission",
+    "KHR_mesh_quantization",
+];
+
+#[derive(Debug)]
+pub enum GltfError {
+    Io(std::io::Error),
+    Json(JsonError),
+    /// The file breaks the glTF spec.
+    Malformed(String),
+    /// A valid file using something we don't load, like an extension it requires.
+    Unsupported(String),
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GltfError::Io(e) => write!(f, "{}", e),
+            GltfError::Json(e) => write!(f, "malformed gltf json, {}", e),
+            GltfError::Malformed(msg) => write!(f, "malformed gltf: {}", msg),
+            GltfError::Unsupported(msg) => write!(f, "unsupported gltf: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for GltfError {}
+
+impl From<std::io::Error> for GltfError {
+    fn from(e: std::io::Error) -> GltfError {
+        GltfError::Io(e)
+    }
+}
+
+impl From<JsonError> for GltfError {
+    fn from(e: JsonError) -> GltfError {
+        GltfError::Json(e)
+    }
+}
+
+fn malformed(message: String) -> GltfError {
+    GltfError::Malformed(message)
+}
+
+// a skinned object and, for each joint of its skeleton, the node moving that joint
+struct SkinnedObject {
+    object: usize,
+    joint_nodes: Vec<usize>,
+}
+
+/// A loaded glTF scene along with the node hierarchy its objects hang from.
+///
+/// The hierarchy is kept as a `Skeleton`, joint i being node i resting in the transform the
+/// file gives it, so node animations are played by sampling a pose from one of
+/// `animations` and handing it to `Gltf::pose`.
+pub struct Gltf {
+    pub scene: Scene,
+    pub nodes: Skeleton,
+    /// Indices into `scene.objects` of the objects made from each node's mesh.
+    pub node_objects: Vec<Vec<usize>>,
+    /// Clips whose channels target `nodes`.
+    pub animations: Vec<AnimationClip>,
+    skinned: Vec<SkinnedObject>,
+}
+
+impl Gltf {
+    /// Loads a `.gltf` or `.glb`, telling them apart by their first bytes. Buffers and
+    /// images in other files are found relative to it.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Gltf, GltfError> {
+        let path = path.as_ref();
+        Gltf::parse(&fs::read(path)?, path.parent().unwrap_or(Path::new("")))
+    }
+
+    /// Loads the file's default scene from its bytes, reading external files from
+    /// `base_dir`.
+    pub fn parse(bytes: &[u8], base_dir: &Path) -> Result<Gltf, GltfError> {
+        let (text, binary) = if bytes.starts_with(b"glTF") {
+            read_glb(bytes)?
+        } else {
+            (bytes, None)
+        };
+        let text = std::str::from_utf8(text)
+            .map_err(|_| malformed("the json isn't valid utf8".to_string()))?;
+        let root = Json::parse(text.trim_start_matches('\u{feff}'))?;
+
+        check_version(&root)?;
+        for extension in list(&root, "extensionsRequired") {
+            let name = extension.as_str().unwrap_or("");
+            if !SUPPORTED_EXTENSIONS.contains(&name) {
+                return Err(GltfError::Unsupported(format!(
+                    "the file requires extension `{}`, which isn't supported",
+                    name
+                )));
+            }
+        }
+
+        let mut buffers = vec![];
+        for (i, buffer) in list(&root, "buffers").iter().enumerate() {
+            buffers.push(load_buffer(i, buffer, binary, base_dir)?);
+        }
+        let document = Document {
+            root: &root,
+            buffers,
+            base_dir,
+        };
+        document.build()
+    }
+
+    /// Moves every object to where `pose`, one transform per node, puts it and poses the
+    /// skinned ones.
+    pub fn pose(&mut self, pose: &[Transform]) {
+        let world = self.nodes.world_matrices(pose);
+        for (node, objects) in self.node_objects.iter().enumerate() {
+            for &object in objects {
+                self.scene.objects[object].transform = world[node];
+            }
+        }
+
+        // skinned vertices land in the scene's space, whatever node holds the mesh
+        for skinned in &self.skinned {
+            let object = &mut self.scene.objects[skinned.object];
+            let skin_pose = match &object.skin {
+                Some(skin) => skin_pose(
+                    &self.nodes,
+                    &skin.skeleton,
+                    &skinned.joint_nodes,
+                    pose,
+                    &world,
+                ),
+                None => continue,
+            };
+            object.transform = Matrix4D::identity();
+            object.set_pose(skin_pose);
+        }
+    }
+
+    /// Poses the scene as `clip` has it at `time`.
+    pub fn apply(&mut self, clip: &AnimationClip, time: f64) {
+        let pose = clip.sample_pose(&self.nodes, time);
+        self.pose(&pose);
+    }
+}
+
+// each skeleton joint's transform relative to its parent joint, which isn't always the
+// parent of its node
+fn skin_pose(
+    nodes: &Skeleton,
+    skeleton: &Skeleton,
+    joint_nodes: &[usize],
+    pose: &[Transform],
+    world: &[Matrix4D],
+) -> Vec<Transform> {
+    skeleton
+        .joints
+        .iter()
+        .zip(joint_nodes)
+        .map(
+            |(joint, &node)| match joint.parent.map(|p| joint_nodes[p]) {
+                Some(parent) if nodes.joints[node].parent == Some(parent) => pose[node],
+                Some(parent) => {
+                    let parent_inverse = world[parent].inverse().unwrap_or(Matrix4D::identity());
+                    Transform::from_matrix(&(&world[node] * &parent_inverse))
+                }
+                None => Transform::from_matrix(&world[node]),
+            },
+        )
+        .collect()
+}
+
+// the json and binary chunks of a glb
+fn read_glb(bytes: &[u8]) -> Result<(&[u8], Option<&[u8]>), GltfError> {
+    let word = |offset: usize| {
+        bytes
+            .get(offset..offset + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+    };
+
+    let version = word(4).ok_or_else(|| malformed("glb header is cut short".to_string()))?;
+    if version != 2 {
+        return Err(GltfError::Unsupported(format!(
+            "glb version {}, only 2 can be read",
+            version
+        )));
+    }
+    let length = word(8).unwrap_or(0);
+    if length > bytes.len() {
+        return Err(malformed(format!(
+            "glb says it's {} bytes long but is {}",
+            length,
+            bytes.len()
+        )));
+    }
+
+    let mut chunks = vec![];
+    let mut offset = 12;
+    while offset + 8 <= length {
+        let (chunk_length, kind) = (word(offset).unwrap_or(0), word(offset + 4).unwrap_or(0));
+        let start = offset + 8;
+        let data = bytes
+            .get(start..start + chunk_length)
+            .filter(|_| start + chunk_length <= length)
+            .ok_or_else(|| malformed(format!("glb chunk at byte {} runs past the end", offset)))?;
+        chunks.push((kind, data));
+        // chunks are padded to four bytes
+        offset = start + chunk_length.div_ceil(4) * 4;
+    }
+
+    const JSON: usize = 0x4e4f534a;
+    const BIN: usize = 0x004e4942;
+    match chunks.first() {
+        Some((JSON, json)) => {
+            let binary = chunks.get(1).filter(|(kind, _)| *kind == BIN);
+            Ok((json, binary.map(|(_, data)| *data)))
+        }
+        _ => Err(malformed("glb doesn't start with a json chunk".to_string())),
+    }
+}
+
+fn check_version(root: &Json) -> Result<(), GltfError> {
+    let asset = root
+        .get("asset")
+        .ok_or_else(|| malformed("no `asset` describing the file".to_string()))?;
+    let version = asset.get("version").and_then(Json::as_str).unwrap_or("");
+    let required = asset
+        .get("minVersion")
+        .and_then(Json::as_str)
+        .unwrap_or("2.0");
+    if !version.starts_with("2.") || required != "2.0" {
+        return Err(GltfError::Unsupported(format!(
+            "gltf version `{}`, only 2.0 can be read",
+            version
+        )));
+    }
+    Ok(())
+}
+
+// the contents of a data uri, or of a file relative to the gltf
+fn read_uri(uri: &str, base_dir: &Path) -> Result<Vec<u8>, GltfError> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (_, encoded) = data
+            .split_once(";base64,")
+            .ok_or_else(|| GltfError::Unsupported("data uris have to be base64".to_string()))?;
+        return decode_base64(encoded);
+    }
+
+    let path = base_dir.join(decode_percents(uri));
+    fs::read(&path).map_err(|e| {
+        GltfError::Io(std::io::Error::new(
+            e.kind(),
+            format!("{}: {}", path.display(), e),
+        ))
+    })
+}
+
+fn decode_base64(text: &str) -> Result<Vec<u8>, GltfError> {
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+    let (mut bits, mut count) = (0u32, 0);
+    for c in text.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            _ => return Err(malformed(format!("`{}` in base64 data", c as char))),
+        };
+        bits = bits << 6 | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+// uris can escape characters as %xx, like spaces in file names
+fn decode_percents(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn load_buffer(
+    index: usize,
+    buffer: &Json,
+    binary: Option<&[u8]>,
+    base_dir: &Path,
+) -> Result<Vec<u8>, GltfError> {
+    let data = match buffer.get("uri").and_then(Json::as_str) {
+        Some(uri) => read_uri(uri, base_dir)?,
+        // a buffer without a uri is the glb's binary chunk
+        None => binary
+            .ok_or_else(|| {
+                malformed(format!(
+                    "buffer {} has no uri and there's no glb binary chunk",
+                    index
+                ))
+            })?
+            .to_vec(),
+    };
+
+    let length = buffer
+        .get("byteLength")
+        .and_then(Json::as_usize)
+        .unwrap_or(0);
+    if data.len() < length {
+        return Err(malformed(format!(
+            "buffer {} should be {} bytes but is {}",
+            index,
+            length,
+            data.len()
+        )));
+    }
+    Ok(data)
+}
+
+// the array called `key`, empty when the file leaves it out
+fn list<'a>(json: &'a Json, key: &str) -> &'a [Json] {
+    json.get(key).and_then(Json::as_array).unwrap_or(&[])
+}
+
+// the numbers in the array called `key`, None when it's missing or not all numbers
+fn numbers(json: &Json, key: &str) -> Option<Vec<f64>> {
+    json.get(key)?
+        .as_array()?
+        .iter()
+        .map(Json::as_f64)
+        .collect()
+}
+
+fn number(json: &Json, key: &str, default: f64) -> f64 {
+    json.get(key).and_then(Json::as_f64).unwrap_or(default)
+}
+
+// an index the file may leave out, which has to be a whole number when it doesn't
+fn optional_index(json: &Json, key: &str, what: &str) -> Result<Option<usize>, GltfError> {
+    match json.get(key) {
+        None => Ok(None),
+        Some(value) => value
+            .as_usize()
+            .map(Some)
+            .ok_or_else(|| malformed(format!("{} `{}` isn't an index", what, key))),
+    }
+}
+
+fn required_index(json: &Json, key: &str, what: &str) -> Result<usize, GltfError> {
+    optional_index(json, key, what)?.ok_or_else(|| malformed(format!("{} has no `{}`", what, key)))
+}
+
+// bytes per component of each accessor component type
+fn component_size(component_type: usize) -> Option<usize> {
+    match component_type {
+        5120 | 5121 => Some(1),
+        5122 | 5123 => Some(2),
+        5125 | 5126 => Some(4),
+        _ => None,
+    }
+}
+
+// a normalized integer maps to 0 to 1, or -1 to 1 when signed
+fn read_component(bytes: &[u8], component_type: usize, normalized: bool) -> f64 {
+    let value = match component_type {
+        5120 => bytes[0] as i8 as f64,
+        5121 => bytes[0] as f64,
+        5122 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+        5123 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+        5125 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+        _ => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+    };
+    if !normalized {
+        return value;
+    }
+    match component_type {
+        5120 => (value / 127.0).max(-1.0),
+        5121 => value / 255.0,
+        5122 => (value / 32767.0).max(-1.0),
+        5123 => value / 65535.0,
+        _ => value,
+    }
+}
+
+// `count` elements of `components` numbers each, `stride` bytes apart starting at
+// `offset`, or None when they don't all fit in `bytes`. The size is checked before
+// anything is allocated, so a huge count can't exhaust memory.
+fn read_elements(
+    count: usize,
+    components: usize,
+    bytes: &[u8],
+    offset: usize,
+    stride: usize,
+    component_type: usize,
+    normalized: bool,
+) -> Option<Vec<Vec<f64>>> {
+    let size = component_size(component_type).unwrap_or(4);
+    if count > 0 {
+        let end = (count - 1)
+            .checked_mul(stride)?
+            .checked_add(offset)?
+            .checked_add(size.checked_mul(components)?)?;
+        if end > bytes.len() {
+            return None;
+        }
+    }
+
+    let element = |i: usize| {
+        (0..components)
+            .map(|c| {
+                let start = offset + i * stride + c * size;
+                read_component(&bytes[start..start + size], component_type, normalized)
+            })
+            .collect()
+    };
+    Some((0..count).map(element).collect())
+}
+
+// the most elements an accessor without a bufferView may have
+const MAX_ZEROED_ELEMENTS: usize = 1 << 22;
+
+struct Document<'a> {
+    root: &'a Json,
+    buffers: Vec<Vec<u8>>,
+    base_dir: &'a Path,
+}
+
+impl<'a> Document<'a> {
+    // entry `index` of the top level array `kind`, like accessors or nodes
+    fn item(&self, kind: &str, index: usize) -> Result<&'a Json, GltfError> {
+        list(self.root, kind)
+            .get(index)
+            .ok_or_else(|| malformed(format!("{} {} doesn't exist", kind, index)))
+    }
+
+    // a buffer view's bytes and the stride it gives, if any
+    fn view(&self, index: usize) -> Result<(&[u8], Option<usize>), GltfError> {
+        let view = self.item("bufferViews", index)?;
+        let what = format!("bufferView {}", index);
+        let buffer = required_index(view, "buffer", &what)?;
+        let data = self
+            .buffers
+            .get(buffer)
+            .ok_or_else(|| malformed(format!("{} uses missing buffer {}", what, buffer)))?;
+        let offset = optional_index(view, "byteOffset", &what)?.unwrap_or(0);
+        let length = required_index(view, "byteLength", &what)?;
+        let bytes = offset
+            .checked_add(length)
+            .and_then(|end| data.get(offset..end))
+            .ok_or_else(|| malformed(format!("{} runs past the end of its buffer", what)))?;
+        Ok((bytes, optional_index(view, "byteStride", &what)?))
+    }
+
+    /// The accessor's elements, each `components` numbers long, normalized integers
+    /// already mapped into 0 to 1 or -1 to 1.
+    fn accessor(&self, accessor_index: usize) -> Result<Vec<Vec<f64>>, GltfError> {
+        let accessor = self.item("accessors", accessor_index)?;
+        let what = format!("accessor {}", accessor_index);
+        let count = required_index(accessor, "count", &what)?;
+        let components = match accessor.get("type").and_then(Json::as_str) {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") | Some("MAT2") => 4,
+            Some("MAT3") => 9,
+            Some("MAT4") => 16,
+            _ => return Err(malformed(format!("{} has an unknown type", what))),
+        };
+        let component_type = required_index(accessor, "componentType", &what)?;
+        let size = component_size(component_type).ok_or_else(|| {
+            malformed(format!(
+                "{} has unknown component type {}",
+                what, component_type
+            ))
+        })?;
+        let normalized = accessor
+            .get("normalized")
+            .and_then(Json::as_bool)
+            .unwrap_or(false);
+
+        let mut values = match optional_index(accessor, "bufferView", &what)? {
+            Some(view) => {
+                let (bytes, stride) = self.view(view)?;
+                if stride.is_some_and(|stride| stride < size * components) {
+                    return Err(malformed(format!(
+                        "{} has elements wider than the byteStride of its bufferView",
+                        what
+                    )));
+                }
+                let offset = optional_index(accessor, "byteOffset", &what)?.unwrap_or(0);
+                read_elements(
+                    count,
+                    components,
+                    bytes,
+                    offset,
+                    stride.unwrap_or(size * components),
+                    component_type,
+                    normalized,
+                )
+                .ok_or_else(|| malformed(format!("{} runs past the end of its bufferView", what)))?
+            }
+            // an accessor without a view is all zeros, usually overwritten by sparse
+            // values, and takes no room in the file however big its count
+            None if count > MAX_ZEROED_ELEMENTS => {
+                return Err(GltfError::Unsupported(format!(
+                    "{} has {} elements without a bufferView, more than {} aren't loaded",
+                    what, count, MAX_ZEROED_ELEMENTS
+                )))
+            }
+            None => vec![vec![0.0; components]; count],
+        };
+
+        if let Some(sparse) = accessor.get("sparse") {
+            self.apply_sparse(&mut values, sparse, component_type, normalized, &what)?;
+        }
+        Ok(values)
+    }
+
+    // an accessor that has to be one of `types`, like VEC3 for positions
+    fn typed_accessor(
+        &self,
+        index: usize,
+        types: &[&str],
+        what: &str,
+    ) -> Result<Vec<Vec<f64>>, GltfError> {
+        let accessor = self.item("accessors", index)?;
+        let kind = accessor
+            .get("type")
+            .and_then(Json::as_str)
+            .unwrap_or("no type");
+        if !types.contains(&kind) {
+            return Err(malformed(format!(
+                "{} uses accessor {} of type {}, not {}",
+                what,
+                index,
+                kind,
+                types.join(" or ")
+            )));
+        }
+        self.accessor(index)
+    }
+
+    // replaces the elements a sparse accessor lists with its own values
+    fn apply_sparse(
+        &self,
+        values: &mut [Vec<f64>],
+        sparse: &Json,
+        component_type: usize,
+        normalized: bool,
+        what: &str,
+    ) -> Result<(), GltfError> {
+        let count = required_index(sparse, "count", what)?;
+        let (indices, replacements) = match (sparse.get("indices"), sparse.get("values")) {
+            (Some(indices), Some(replacements)) => (indices, replacements),
+            _ => {
+                return Err(malformed(format!(
+                    "{} is sparse without indices and values",
+                    what
+                )))
+            }
+        };
+
+        if count > values.len() {
+            return Err(malformed(format!(
+                "{} replaces more sparse elements than it has",
+                what
+            )));
+        }
+
+        let index_type = required_index(indices, "componentType", what)?;
+        let index_size = component_size(index_type)
+            .ok_or_else(|| malformed(format!("{} has unknown sparse index type", what)))?;
+        let (bytes, _) = self.view(required_index(indices, "bufferView", what)?)?;
+        let offset = optional_index(indices, "byteOffset", what)?.unwrap_or(0);
+        let targets = read_elements(count, 1, bytes, offset, index_size, index_type, false)
+            .ok_or_else(|| malformed(format!("{} sparse indices run past their view", what)))?;
+
+        let components = values.first().map_or(1, Vec::len);
+        let size = component_size(component_type).unwrap_or(4);
+        let (bytes, _) = self.view(required_index(replacements, "bufferView", what)?)?;
+        let offset = optional_index(replacements, "byteOffset", what)?.unwrap_or(0);
+        let sparse_values = read_elements(
+            count,
+            components,
+            bytes,
+            offset,
+            size * components,
+            component_type,
+            normalized,
+        )
+        .ok_or_else(|| malformed(format!("{} sparse values run past their view", what)))?;
+
+        for (target, value) in targets.iter().zip(sparse_values) {
+            let element = values.get_mut(target[0] as usize).ok_or_else(|| {
+                malformed(format!(
+                    "{} sparse index {} is out of range",
+                    what, target[0]
+                ))
+            })?;
+            *element = value;
+        }
+        Ok(())
+    }
+
+    fn texture(&self, index: usize) -> Result<Arc<Texture>, GltfError> {
+        let texture = self.item("textures", index)?;
+        let what = format!("texture {}", index);
+        let source = optional_index(texture, "source", &what)?.ok_or_else(|| {
+            GltfError::Unsupported(format!("{} has no png image, only an extension's", what))
+        })?;
+
+        let image = self.item("images", source)?;
+        let (bytes, path) = match image.get("uri").and_then(Json::as_str) {
+            Some(uri) if uri.starts_with("data:") => (read_uri(uri, self.base_dir)?, None),
+            Some(uri) => (read_uri(uri, self.base_dir)?, Some(decode_percents(uri))),
+            None => {
+                let view = required_index(image, "bufferView", &format!("image {}", source))?;
+                (self.view(view)?.0.to_vec(), None)
+            }
+        };
+
+        let image = Image::decode(&bytes).map_err(|e| match e {
+            ImageError::Unsupported(message) => {
+                GltfError::Unsupported(format!("image {}: {}", source, message))
+            }
+            e => malformed(format!("image {}: {}", source, e)),
+        })?;
+        Ok(Arc::new(Texture { path, image }))
+    }
+
+    fn material(
+        &self,
+        index: usize,
+        textures: &mut Vec<Option<Arc<Texture>>>,
+    ) -> Result<Material, GltfError> {
+        let material = self.item("materials", index)?;
+        let what = format!("material {}", index);
+        let pbr = material.get("pbrMetallicRoughness");
+        let factor = |key: &str, default: f64| pbr.map_or(default, |pbr| number(pbr, key, default));
+
+        let base_color = pbr
+            .and_then(|pbr| numbers(pbr, "baseColorFactor"))
+            .filter(|c| c.len() == 4)
+            .unwrap_or(vec![1.0; 4]);
+        let emissive = numbers(material, "emissiveFactor")
+            .filter(|e| e.len() == 3)
+            .unwrap_or(vec![0.0; 3]);
+        let extension = |name: &str| material.get("extensions").and_then(|e| e.get(name));
+        let strength = extension("KHR_materials_emissive_strength")
+            .map_or(1.0, |e| number(e, "emissiveStrength", 1.0));
+
+        // a smooth metal mirrors, and blended alpha or transmission lets light through
+        let mut result = Material::default()
+            .with_albedo([base_color[0], base_color[1], base_color[2]])
+            .with_emission([0, 1, 2].map(|i| emissive[i] * strength))
+            .with_reflectivity(
+                factor("metallicFactor", 1.0) * (1.0 - factor("roughnessFactor", 1.0)),
+            );
+        if material.get("alphaMode").and_then(Json::as_str) == Some("BLEND") {
+            result = result.with_transparency(1.0 - base_color[3], 1.0);
+        }
+        if let Some(transmission) = extension("KHR_materials_transmission") {
+            let ior = extension("KHR_materials_ior").map_or(1.5, |e| number(e, "ior", 1.5));
+            result = result.with_transparency(number(transmission, "transmissionFactor", 0.0), ior);
+        }
+
+        let texture = pbr
+            .and_then(|pbr| pbr.get("baseColorTexture"))
+            .map(|info| required_index(info, "index", &what))
+            .transpose()?;
+        if let Some(texture) = texture {
+            if textures.len() <= texture {
+                textures.resize(texture + 1, None);
+            }
+            if textures[texture].is_none() {
+                textures[texture] = Some(self.texture(texture)?);
+            }
+            result.texture = textures[texture].clone();
+        }
+        Ok(result)
+    }
+
+    // one mesh and material per primitive of the mesh
+    fn mesh(
+        &self,
+        index: usize,
+        materials: &[Material],
+    ) -> Result<Vec<(Mesh, Material)>, GltfError> {
+        let mesh = self.item("meshes", index)?;
+        let mut primitives = vec![];
+        for (p, primitive) in list(mesh, "primitives").iter().enumerate() {
+            let what = format!("mesh {} primitive {}", index, p);
+            let material = match optional_index(primitive, "material", &what)? {
+                Some(m) => materials
+                    .get(m)
+                    .cloned()
+                    .ok_or_else(|| malformed(format!("{} uses missing material {}", what, m)))?,
+                None => Material::default(),
+            };
+            primitives.push((self.primitive(primitive, &what)?, material));
+        }
+        Ok(primitives)
+    }
+
+    fn primitive(&self, primitive: &Json, what: &str) -> Result<Mesh, GltfError> {
+        let attributes = primitive
+            .get("attributes")
+            .ok_or_else(|| malformed(format!("{} has no attributes", what)))?;
+        let attribute = |name: &str, types: &[&str]| -> Result<Option<Vec<Vec<f64>>>, GltfError> {
+            match optional_index(attributes, name, what)? {
+                Some(accessor) => {
+                    let what = format!("{} {}", what, name);
+                    Ok(Some(self.typed_accessor(accessor, types, &what)?))
+                }
+                None => Ok(None),
+            }
+        };
+
+        let positions = attribute("POSITION", &["VEC3"])?
+            .ok_or_else(|| malformed(format!("{} has no POSITION", what)))?;
+        let count = positions.len();
+        let normals = attribute("NORMAL", &["VEC3"])?;
+        let uvs = attribute("TEXCOORD_0", &["VEC2"])?;
+        let colors = attribute("COLOR_0", &["VEC3", "VEC4"])?;
+        let joints = attribute("JOINTS_0", &["VEC4"])?;
+        let weights = attribute("WEIGHTS_0", &["VEC4"])?;
+        let lengths = [&normals, &uvs, &colors, &joints, &weights];
+        if lengths
+            .iter()
+            .any(|a| a.as_ref().is_some_and(|a| a.len() != count))
+        {
+            return Err(malformed(format!(
+                "{} has attributes of different lengths",
+                what
+            )));
+        }
+
+        let indices: Vec<usize> = match optional_index(primitive, "indices", what)? {
+            Some(accessor) => self
+                .typed_accessor(accessor, &["SCALAR"], &format!("{} indices", what))?
+                .iter()
+                .map(|i| i[0] as usize)
+                .collect(),
+            None => (0..count).collect(),
+        };
+        if let Some(bad) = indices.iter().find(|&&i| i >= count) {
+            return Err(malformed(format!("{} index {} is out of range", what, bad)));
+        }
+
+        // strips flip every other triangle back to the same winding as the first
+        let corners: Vec<[usize; 3]> = match number(primitive, "mode", 4.0) as usize {
+            4 => indices
+                .chunks_exact(3)
+                .map(|c| [c[0], c[1], c[2]])
+                .collect(),
+            5 => (0..indices.len().saturating_sub(2))
+                .map(|i| match i % 2 {
+                    0 => [indices[i], indices[i + 1], indices[i + 2]],
+                    _ => [indices[i + 1], indices[i], indices[i + 2]],
+                })
+                .collect(),
+            6 => (1..indices.len().saturating_sub(1))
+                .map(|i| [indices[0], indices[i], indices[i + 1]])
+                .collect(),
+            mode => {
+                return Err(GltfError::Unsupported(format!(
+                    "{} draws points or lines (mode {}), only triangles are supported",
+                    what, mode
+                )))
+            }
+        };
+
+        let vector3 = |v: &[f64]| Vector3D::new(v[0], v[1], v[2]);
+        let channel = |v: f64| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+        let color = |c: &[f64]| {
+            Color::new(
+                channel(c[0]),
+                channel(c[1]),
+                channel(c[2]),
+                c.get(3).map_or(255, |a| channel(*a)),
+            )
+        };
+
+        let white = Color::new(255, 255, 255, 255);
+        let triangles = corners
+            .iter()
+            .map(|&[a, b, c]| {
+                let mut triangle = Triangle::new(
+                    vector3(&positions[a]),
+                    vector3(&positions[b]),
+                    vector3(&positions[c]),
+                    &white,
+                );
+                if let Some(n) = &normals {
+                    triangle = triangle.with_normals(
+                        vector3(&n[a]).normalize(),
+                        vector3(&n[b]).normalize(),
+                        vector3(&n[c]).normalize(),
+                    );
+                }
+                if let Some(uv) = &uvs {
+                    let uv = |i: usize| Vector2D::new(uv[i][0], uv[i][1]);
+                    triangle = triangle.with_uvs(uv(a), uv(b), uv(c));
+                }
+                if let Some(colors) = &colors {
+                    triangle = triangle.with_colors(
+                        color(&colors[a]),
+                        color(&colors[b]),
+                        color(&colors[c]),
+                    );
+                }
+                if let (Some(j), Some(w)) = (&joints, &weights) {
+                    let joint = |i: usize| [0, 1, 2, 3].map(|k| j[i][k] as usize);
+                    let weight = |i: usize| [0, 1, 2, 3].map(|k| w[i][k]);
+                    triangle = triangle.with_skin_weights(
+                        [joint(a), joint(b), joint(c)],
+                        [weight(a), weight(b), weight(c)],
+                    );
+                }
+                triangle
+            })
+            .collect();
+        Ok(Mesh { triangles })
+    }
+
+    // a node's transform relative to its parent, from its matrix or its parts
+    fn node_transform(&self, node: &Json, what: &str) -> Result<Transform, GltfError> {
+        // gltf matrices are column major for column vectors, which read row by row is the
+        // row vector matrix used here
+        if let Some(m) = numbers(node, "matrix") {
+            if m.len() != 16 {
+                return Err(malformed(format!("{} matrix isn't 16 numbers", what)));
+            }
+            return Ok(Transform::from_matrix(&matrix(&m)));
+        }
+
+        let vector = |key: &str, default: [f64; 3]| match numbers(node, key) {
+            Some(v) if v.len() == 3 => Ok(Vector3D::new(v[0], v[1], v[2])),
+            None => Ok(Vector3D::new(default[0], default[1], default[2])),
+            Some(_) => Err(malformed(format!("{} {} isn't 3 numbers", what, key))),
+        };
+        let rotation = match numbers(node, "rotation") {
+            Some(r) if r.len() == 4 => Quaternion::new(r[0], r[1], r[2], r[3]).normalize(),
+            None => Quaternion::identity(),
+            Some(_) => return Err(malformed(format!("{} rotation isn't 4 numbers", what))),
+        };
+        Ok(Transform {
+            translation: vector("translation", [0.0; 3])?,
+            rotation,
+            scale: vector("scale", [1.0; 3])?,
+        })
+    }
+
+    fn build(&self) -> Result<Gltf, GltfError> {
+        let node_list = list(self.root, "nodes");
+
+        // the hierarchy, checking every node has at most one parent
+        let mut parents: Vec<Option<usize>> = vec![None; node_list.len()];
+        for (i, node) in node_list.iter().enumerate() {
+            for child in list(node, "children") {
+                let child = child
+                    .as_usize()
+                    .filter(|&c| c < node_list.len())
+                    .ok_or_else(|| malformed(format!("node {} has a missing child", i)))?;
+                if parents[child].is_some() || child == i {
+                    return Err(malformed(format!(
+                        "node {} has more than one parent",
+                        child
+                    )));
+                }
+                parents[child] = Some(i);
+            }
+        }
+        let mut nodes = Skeleton::new();
+        for (i, node) in node_list.iter().enumerate() {
+            let what = format!("node {}", i);
+            let name = node
+                .get("name")
+                .and_then(Json::as_str)
+                .unwrap_or(&what)
+                .to_string();
+            let transform = self.node_transform(node, &what)?;
+            nodes = nodes.with_joint(Joint::new(&name, parents[i], transform));
+        }
+
+        let mut textures = vec![];
+        let materials = (0..list(self.root, "materials").len())
+            .map(|i| self.material(i, &mut textures))
+            .collect::<Result<Vec<Material>, GltfError>>()?;
+
+        // the nodes of the default scene, or every node when the file has no scenes
+        let roots: Vec<usize> = match optional_index(self.root, "scene", "file")?.unwrap_or(0) {
+            scene if scene < list(self.root, "scenes").len() => {
+                list(self.item("scenes", scene)?, "nodes")
+                    .iter()
+                    .filter_map(Json::as_usize)
+                    .collect()
+            }
+            _ => (0..node_list.len())
+                .filter(|&i| parents[i].is_none())
+                .collect(),
+        };
+        let mut in_scene = vec![false; node_list.len()];
+        let mut stack = roots;
+        while let Some(node) = stack.pop() {
+            if node >= node_list.len() || in_scene[node] {
+                continue;
+            }
+            in_scene[node] = true;
+            stack.extend(
+                list(&node_list[node], "children")
+                    .iter()
+                    .filter_map(Json::as_usize),
+            );
+        }
+
+        let mut meshes: Vec<Option<Vec<(Mesh, Material)>>> =
+            vec![None; list(self.root, "meshes").len()];
+        let mut scene = Scene::new();
+        let mut node_objects = vec![vec![]; node_list.len()];
+        let mut skinned = vec![];
+        for (i, node) in node_list.iter().enumerate() {
+            let what = format!("node {}", i);
+            let mesh = match optional_index(node, "mesh", &what)? {
+                Some(mesh) if in_scene[i] => mesh,
+                _ => continue,
+            };
+            if mesh >= meshes.len() {
+                return Err(malformed(format!("{} uses missing mesh {}", what, mesh)));
+            }
+            if meshes[mesh].is_none() {
+                meshes[mesh] = Some(self.mesh(mesh, &materials)?);
+            }
+            let skin = optional_index(node, "skin", &what)?
+                .map(|skin| self.skin(skin, &nodes))
+                .transpose()?;
+
+            for (primitive, material) in meshes[mesh].iter().flatten() {
+                let mut object = Object::new(primitive.clone(), Matrix4D::identity())
+                    .with_material(material.clone());
+                if let Some((skeleton, joint_nodes)) = &skin {
+                    object = object.with_skin(skeleton.clone(), SkinningMethod::Linear);
+                    skinned.push(SkinnedObject {
+                        object: scene.objects.len(),
+                        joint_nodes: joint_nodes.clone(),
+                    });
+                }
+                node_objects[i].push(scene.objects.len());
+                scene.objects.push(object);
+            }
+        }
+
+        let animations = list(self.root, "animations")
+            .iter()
+            .enumerate()
+            .map(|(i, animation)| self.animation(i, animation, &nodes))
+            .collect::<Result<Vec<AnimationClip>, GltfError>>()?;
+
+        let mut gltf = Gltf {
+            scene,
+            nodes,
+            node_objects,
+            animations,
+            skinned,
+        };
+        let rest = gltf.nodes.rest_pose();
+        gltf.pose(&rest);
+        Ok(gltf)
+    }
+
+    // the skin's skeleton, its joints in the skin's order, and the node of each joint
+    fn skin(&self, index: usize, nodes: &Skeleton) -> Result<(Skeleton, Vec<usize>), GltfError> {
+        let skin = self.item("skins", index)?;
+        let what = format!("skin {}", index);
+        let joint_nodes = list(skin, "joints")
+            .iter()
+            .map(|j| j.as_usize().filter(|&j| j < nodes.joints.len()))
+            .collect::<Option<Vec<usize>>>()
+            .ok_or_else(|| malformed(format!("{} has a missing joint node", what)))?;
+        let inverse_binds = match optional_index(skin, "inverseBindMatrices", &what)? {
+            Some(accessor) => {
+                self.typed_accessor(accessor, &["MAT4"], &format!("{} inverse binds", what))?
+            }
+            None => vec![],
+        };
+        if !inverse_binds.is_empty() && inverse_binds.len() < joint_nodes.len() {
+            return Err(malformed(format!(
+                "{} has fewer inverse bind matrices than joints",
+                what
+            )));
+        }
+
+        let mut skeleton = Skeleton::new();
+        for (j, &node) in joint_nodes.iter().enumerate() {
+            // the closest ancestor that's also one of the skin's joints
+            let mut parent = nodes.joints[node].parent;
+            let mut depth = 0;
+            while let Some(p) = parent {
+                if joint_nodes.contains(&p) || depth > nodes.joints.len() {
+                    break;
+                }
+                parent = nodes.joints[p].parent;
+                depth += 1;
+            }
+            let parent = parent.and_then(|p| joint_nodes.iter().position(|&n| n == p));
+
+            let mut joint = Joint::new(&nodes.joints[node].name, parent, nodes.joints[node].rest);
+            if let Some(m) = inverse_binds.get(j) {
+                joint.inverse_bind = matrix(m);
+            }
+            skeleton = skeleton.with_joint(joint);
+        }
+        Ok((skeleton, joint_nodes))
+    }
+
+    fn animation(
+        &self,
+        index: usize,
+        animation: &Json,
+        nodes: &Skeleton,
+    ) -> Result<AnimationClip, GltfError> {
+        let what = format!("animation {}", index);
+        let name = animation
+            .get("name")
+            .and_then(Json::as_str)
+            .unwrap_or(&what);
+        let samplers = list(animation, "samplers");
+        let mut clip = AnimationClip::new(name);
+
+        for (c, channel) in list(animation, "channels").iter().enumerate() {
+            let what = format!("{} channel {}", what, c);
+            let target = channel
+                .get("target")
+                .ok_or_else(|| malformed(format!("{} has no target", what)))?;
+            // channels for nodes outside the file's core, like extension targets, are skipped
+            let node = match optional_index(target, "node", &what)? {
+                Some(node) if node < nodes.joints.len() => node,
+                Some(node) => {
+                    return Err(malformed(format!("{} targets missing node {}", what, node)))
+                }
+                None => continue,
+            };
+            let sampler = required_index(channel, "sampler", &what)?;
+            let sampler = samplers
+                .get(sampler)
+                .ok_or_else(|| malformed(format!("{} uses missing sampler {}", what, sampler)))?;
+            let path = target.get("path").and_then(Json::as_str);
+            let value_type = match path {
+                Some("translation") | Some("scale") => "VEC3",
+                Some("rotation") => "VEC4",
+                // morph target weights aren't loaded, so neither is their animation
+                Some("weights") => continue,
+                _ => return Err(malformed(format!("{} has an unknown target path", what))),
+            };
+            let input = required_index(sampler, "input", &what)?;
+            let times = self.typed_accessor(input, &["SCALAR"], &format!("{} input", what))?;
+            let output = required_index(sampler, "output", &what)?;
+            let values = self.typed_accessor(output, &[value_type], &format!("{} output", what))?;
+            let interpolation = match sampler.get("interpolation").and_then(Json::as_str) {
+                Some("STEP") => Interpolation::Step,
+                Some("LINEAR") | None => Interpolation::Linear,
+                Some("CUBICSPLINE") => Interpolation::CubicSpline,
+                Some(other) => {
+                    return Err(malformed(format!(
+                        "{} has unknown interpolation `{}`",
+                        what, other
+                    )))
+                }
+            };
+
+            // cubic spline keys are stored as in tangent, value, out tangent
+            let stride = if interpolation == Interpolation::CubicSpline {
+                3
+            } else {
+                1
+            };
+            if values.len() < times.len() * stride {
+                return Err(malformed(format!(
+                    "{} has fewer values than key times",
+                    what
+                )));
+            }
+            let keys = times.iter().enumerate().map(|(k, time)| {
+                let key = &values[k * stride..(k + 1) * stride];
+                (time[0], key)
+            });
+
+            let position = clip.channels.iter().position(|ch| ch.target == node);
+            let mut animated = match position {
+                Some(p) => clip.channels.remove(p),
+                None => Channel::new(node, nodes.joints[node].rest),
+            };
+            let vector = |v: &[f64]| Vector3D::new(v[0], v[1], v[2]);
+            let quaternion = |v: &[f64]| Quaternion::new(v[0], v[1], v[2], v[3]);
+            match path {
+                Some("translation") => {
+                    animated.translation = Some(track(interpolation, keys, vector))
+                }
+                Some("rotation") => {
+                    animated.rotation = Some(track(interpolation, keys, quaternion))
+                }
+                _ => animated.scale = Some(track(interpolation, keys, vector)),
+            }
+            clip.channels.push(animated);
+        }
+        Ok(clip)
+    }
+}
+
+fn matrix(m: &[f64]) -> Matrix4D {
+    Matrix4D::new([0, 1, 2, 3].map(|row| [0, 1, 2, 3].map(|column| m[row * 4 + column])))
+}
+
+fn track<'k, T, I, F>(interpolation: Interpolation, keys: I, value: F) -> Track<T>
+where
+    T: crate::animation::Animatable,
+    I: Iterator<Item = (f64, &'k [Vec<f64>])>,
+    F: Fn(&[f64]) -> T,
+{
+    keys.fold(Track::new(interpolation), |track, (time, key)| {
+        let keyframe = match key {
+            [in_tangent, v, out_tangent] => {
+                Keyframe::new(time, value(v)).with_tangents(value(in_tangent), value(out_tangent))
+            }
+            _ => Keyframe::new(time, value(&key[0])),
+        };
+        track.with_keyframe(keyframe)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linalg::multiply_matrix_vector;
+
+    // a glb whose single buffer holds a triangle's positions followed by zeros, every
+    // accessor reading from the start of it
+    fn glb(accessors: &[&str], rest: &str) -> Vec<u8> {
+        let mut bin = vec![0u8; 256];
+        for (i, v) in [1.0f32, 0.0, 0.0, 0.0, 1.0, 0.0].iter().enumerate() {
+            bin[12 + i * 4..16 + i * 4].copy_from_slice(&v.to_le_bytes());
+        }
+        let accessors: Vec<String> = accessors
+            .iter()
+            .map(|a| format!(r#"{{"bufferView":0,"componentType":5126,{}}}"#, a))
+            .collect();
+        let mut json = format!(
+            r#"{{"asset":{{"version":"2.0"}},"buffers":[{{"byteLength":256}}],
+            "bufferViews":[{{"buffer":0,"byteLength":256}}],"accessors":[{}]{}}}"#,
+            accessors.join(","),
+            rest
+        )
+        .into_bytes();
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+
+        let mut bytes = b"glTF".to_vec();
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        bytes.extend_from_slice(&((28 + json.len() + bin.len()) as u32).to_le_bytes());
+        bytes.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(b"JSON");
+        bytes.extend_from_slice(&json);
+        bytes.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(b"BIN\0");
+        bytes.extend_from_slice(&bin);
+        bytes
+    }
+
+    const MESH: &str = r#","meshes":[{"primitives":[{"attributes":{"POSITION":0}}]}],
+        "nodes":[{"mesh":0}],"scenes":[{"nodes":[0]}]"#;
+
+    fn parse(bytes: &[u8]) -> Result<Gltf, GltfError> {
+        Gltf::parse(bytes, Path::new(""))
+    }
+
+    fn assert_malformed(result: Result<Gltf, GltfError>, expected: &str) {
+        match result {
+            Err(GltfError::Malformed(message)) => {
+                assert!(message.contains(expected), "{}", message)
+            }
+            Err(e) => panic!("expected a malformed error, got {}", e),
+            Ok(_) => panic!("expected a malformed error"),
+        }
+    }
+
+    #[test]
+    fn loads_a_triangle_from_a_glb() {
+        let gltf = parse(&glb(&[r#""count":3,"type":"VEC3""#], MESH)).unwrap();
+        assert_eq!(gltf.scene.objects.len(), 1);
+        let triangle = &gltf.scene.objects[0].mesh.triangles[0];
+        assert_eq!(triangle.vertices[1].x, 1.0);
+        assert_eq!(triangle.vertices[2].y, 1.0);
+    }
+
+    #[test]
+    fn rejects_counts_past_the_end_of_the_view_before_allocating() {
+        let bytes = glb(&[r#""count":100000000000000,"type":"VEC3""#], MESH);
+        assert_malformed(
+            parse(&bytes),
+            "accessor 0 runs past the end of its bufferView",
+        );
+
+        let bytes = glb(&[r#""count":4,"type":"VEC3","byteOffset":220"#], MESH);
+        assert_malformed(
+            parse(&bytes),
+            "accessor 0 runs past the end of its bufferView",
+        );
+    }
+
+    #[test]
+    fn rejects_offsets_that_overflow() {
+        let bytes = glb(
+            &[r#""count":3,"type":"VEC3","byteOffset":18446744073709549568"#],
+            MESH,
+        );
+        assert_malformed(
+            parse(&bytes),
+            "accessor 0 runs past the end of its bufferView",
+        );
+    }
+
+    #[test]
+    fn rejects_huge_sparse_counts() {
+        let accessor = r#""count":3,"type":"VEC3","sparse":{"count":100000000000000,
+            "indices":{"bufferView":0,"componentType":5125},"values":{"bufferView":0}}"#;
+        let bytes = glb(&[accessor], MESH);
+        assert_malformed(parse(&bytes), "replaces more sparse elements than it has");
+    }
+
+    #[test]
+    fn rejects_a_scalar_position() {
+        let bytes = glb(&[r#""count":3,"type":"SCALAR""#], MESH);
+        assert_malformed(parse(&bytes), "POSITION uses accessor 0 of type SCALAR");
+    }
+
+    #[test]
+    fn rejects_joints_and_weights_narrower_than_vec4() {
+        let mesh = r#","meshes":[{"primitives":[{"attributes":
+            {"POSITION":0,"JOINTS_0":1,"WEIGHTS_0":2}}]}],
+            "nodes":[{"mesh":0}],"scenes":[{"nodes":[0]}]"#;
+        let joints = glb(
+            &[
+                r#""count":3,"type":"VEC3""#,
+                r#""count":3,"type":"VEC2""#,
+                r#""count":3,"type":"VEC4""#,
+            ],
+            mesh,
+        );
+        assert_malformed(parse(&joints), "JOINTS_0 uses accessor 1 of type VEC2");
+
+        let weights = glb(
+            &[
+                r#""count":3,"type":"VEC3""#,
+                r#""count":3,"type":"VEC4""#,
+                r#""count":3,"type":"SCALAR""#,
+            ],
+            mesh,
+        );
+        assert_malformed(parse(&weights), "WEIGHTS_0 uses accessor 2 of type SCALAR");
+    }
+
+    #[test]
+    fn rejects_inverse_binds_that_arent_mat4() {
+        let rest = r#","meshes":[{"primitives":[{"attributes":{"POSITION":0}}]}],
+            "skins":[{"joints":[1],"inverseBindMatrices":1}],
+            "nodes":[{"mesh":0,"skin":0},{}],"scenes":[{"nodes":[0,1]}]"#;
+        let bytes = glb(
+            &[r#""count":3,"type":"VEC3""#, r#""count":1,"type":"MAT3""#],
+            rest,
+        );
+        assert_malformed(
+            parse(&bytes),
+            "skin 0 inverse binds uses accessor 1 of type MAT3",
+        );
+    }
+
+    #[test]
+    fn rejects_rotations_narrower_than_vec4() {
+        let rest = r#","nodes":[{}],"scenes":[{"nodes":[0]}],"animations":[{
+            "samplers":[{"input":0,"output":1}],
+            "channels":[{"sampler":0,"target":{"node":0,"path":"rotation"}}]}]"#;
+        let bytes = glb(
+            &[r#""count":2,"type":"SCALAR""#, r#""count":2,"type":"VEC3""#],
+            rest,
+        );
+        assert_malformed(
+            parse(&bytes),
+            "output uses accessor 1 of type VEC3, not VEC4",
+        );
+    }
+
+    // a triangle's positions, then key times 0 and 2, then translations to match
+    fn fixture_buffer() -> Vec<u8> {
+        [
+            0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, // positions
+            0.0, 2.0, // times
+            0.0, 0.0, 0.0, 4.0, 0.0, 0.0, // translations
+        ]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect()
+    }
+
+    fn base64(bytes: &[u8]) -> String {
+        const DIGITS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut text = String::new();
+        for chunk in bytes.chunks(3) {
+            let bits = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |bits, (i, &b)| bits | (b as u32) << (16 - 8 * i));
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    text.push(DIGITS[(bits >> (18 - 6 * i) & 63) as usize] as char);
+                } else {
+                    text.push('=');
+                }
+            }
+        }
+        text
+    }
+
+    // a .gltf reading `fixture_buffer` from `uri`
+    fn document(uri: &str, rest: &str) -> String {
+        format!(
+            r#"{{"asset":{{"version":"2.0"}},"buffers":[{{"uri":"{}","byteLength":68}}],
+            "bufferViews":[{{"buffer":0,"byteLength":68}}],
+            "accessors":[
+                {{"bufferView":0,"componentType":5126,"count":3,"type":"VEC3"}},
+                {{"bufferView":0,"byteOffset":36,"componentType":5126,"count":2,"type":"SCALAR"}},
+                {{"bufferView":0,"byteOffset":44,"componentType":5126,"count":2,"type":"VEC3"}}
+            ]{}}}"#,
+            uri, rest
+        )
+    }
+
+    fn embedded(rest: &str) -> Gltf {
+        let uri = format!(
+            "data:application/octet-stream;base64,{}",
+            base64(&fixture_buffer())
+        );
+        parse(document(&uri, rest).as_bytes()).unwrap()
+    }
+
+    fn world_vertices(gltf: &Gltf, object: usize) -> Vec<Vector3D> {
+        let object = &gltf.scene.objects[object];
+        object.mesh.triangles[0]
+            .vertices
+            .iter()
+            .map(|v| multiply_matrix_vector(v, &object.transform))
+            .collect()
+    }
+
+    fn close(a: &Vector3D, b: &Vector3D) -> bool {
+        a.sub(b).magnitude() < 1e-6
+    }
+
+    #[test]
+    fn reads_buffers_embedded_as_base64() {
+        let gltf = embedded(MESH);
+        let vertices = &gltf.scene.objects[0].mesh.triangles[0].vertices;
+        assert_eq!(vertices[1], Vector3D::new(1.0, 0.0, 0.0));
+        assert_eq!(vertices[2], Vector3D::new(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn reads_buffers_from_files_beside_it() {
+        let dir = std::env::temp_dir().join("atlas_gltf_external_buffer");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("tri angle.bin"), fixture_buffer()).unwrap();
+        fs::write(dir.join("scene.gltf"), document("tri%20angle.bin", MESH)).unwrap();
+        fs::write(dir.join("missing.gltf"), document("nowhere.bin", MESH)).unwrap();
+
+        let gltf = Gltf::load(dir.join("scene.gltf")).unwrap();
+        let vertices = &gltf.scene.objects[0].mesh.triangles[0].vertices;
+        assert_eq!(vertices[1], Vector3D::new(1.0, 0.0, 0.0));
+        assert!(matches!(
+            Gltf::load(dir.join("missing.gltf")),
+            Err(GltfError::Io(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn places_children_inside_their_parents() {
+        // the parent turns a quarter about y and moves 5 along z, the child doubles in
+        // size, a third node is placed by a column major matrix
+        let half = std::f64::consts::FRAC_1_SQRT_2;
+        let rest = format!(
+            r#","meshes":[{{"primitives":[{{"attributes":{{"POSITION":0}}}}]}}],
+            "nodes":[
+                {{"children":[1],"rotation":[0,{},0,{}],"translation":[0,0,5]}},
+                {{"mesh":0,"scale":[2,2,2]}},
+                {{"mesh":0,"matrix":[1,0,0,0, 0,1,0,0, 0,0,1,0, 3,4,5,1]}}
+            ],"scenes":[{{"nodes":[0,2]}}]"#,
+            half, half
+        );
+        let gltf = embedded(&rest);
+        assert_eq!(gltf.nodes.joints[1].parent, Some(0));
+        assert_eq!(gltf.node_objects, vec![vec![], vec![0], vec![1]]);
+
+        let child = world_vertices(&gltf, 0);
+        assert!(close(&child[0], &Vector3D::new(0.0, 0.0, 5.0)));
+        assert!(close(&child[1], &Vector3D::new(0.0, 0.0, 3.0)));
+        assert!(close(&child[2], &Vector3D::new(0.0, 2.0, 5.0)));
+
+        let moved = world_vertices(&gltf, 1);
+        assert!(close(&moved[1], &Vector3D::new(4.0, 4.0, 5.0)));
+    }
+
+    #[test]
+    fn refuses_files_requiring_unsupported_extensions() {
+        let uri = format!(
+            "data:application/octet-stream;base64,{}",
+            base64(&fixture_buffer())
+        );
+        let requiring = |extension: &str| {
+            let rest = format!(r#"{},"extensionsRequired":["{}"]"#, MESH, extension);
+            parse(document(&uri, &rest).as_bytes())
+        };
+        assert!(requiring("KHR_mesh_quantization").is_ok());
+
+        match requiring("KHR_draco_mesh_compression") {
+            Err(GltfError::Unsupported(message)) => {
+                assert!(
+                    message.contains("KHR_draco_mesh_compression"),
+                    "{}",
+                    message
+                )
+            }
+            _ => panic!("expected the draco extension to be refused"),
+        }
+    }
+
+    #[test]
+    fn imports_node_animations() {
+        let rest = format!(
+            r#"{},"animations":[{{"name":"slide",
+            "samplers":[{{"input":1,"output":2}}],
+            "channels":[{{"sampler":0,"target":{{"node":0,"path":"translation"}}}}]}}]"#,
+            MESH
+        );
+        let mut gltf = embedded(&rest);
+        assert_eq!(gltf.animations.len(), 1);
+        let clip = gltf.animations[0].clone();
+        assert_eq!(clip.name, "slide");
+        assert_eq!(clip.channels[0].target, 0);
+        assert_eq!(clip.duration(), 2.0);
+
+        gltf.apply(&clip, 0.5);
+        assert!(close(
+            &world_vertices(&gltf, 0)[0],
+            &Vector3D::new(1.0, 0.0, 0.0)
+        ));
+        gltf.apply(&clip, 2.0);
+        assert!(close(
+            &world_vertices(&gltf, 0)[2],
+            &Vector3D::new(4.0, 1.0, 0.0)
+        ));
+    }
+}
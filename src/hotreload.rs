@@ -0,0 +1,252 @@
+use crate::renderer::{Mesh, MeshSource, Scene};
+use crate::scenefile::SceneError;
+use crate::texture::Texture;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Dependency {
+    Scene,
+    /// Path as written in the scene file.
+    Mesh(String),
+    Texture(String),
+}
+
+struct WatchedFile {
+    path: PathBuf,
+    dependency: Dependency,
+    /// None while the file is missing.
+    modified: Option<SystemTime>,
+    /// Why the file last failed to load, until it loads again.
+    error: Option<String>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Watches a scene file and the meshes and textures it uses, reloading whichever change.
+///
+/// Files are polled for their modification times every `interval` seconds. A changed scene
+/// file replaces the whole scene, a changed mesh or texture is swapped into every object
+/// using it and leaves everything else alone. When something fails to load the scene is
+/// kept as it was and `error` says why until that file loads again.
+pub struct SceneWatcher {
+    pub path: PathBuf,
+    /// Seconds between checks.
+    pub interval: f64,
+    files: Vec<WatchedFile>,
+    elapsed: f64,
+}
+
+impl SceneWatcher {
+    /// Loads the scene at `path` and starts watching it and its dependencies.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<(SceneWatcher, Scene), SceneError> {
+        let path = path.as_ref().to_path_buf();
+        let scene = Scene::load(&path)?;
+        let mut watcher = SceneWatcher {
+            path,
+            interval: 0.5,
+            files: vec![],
+            elapsed: 0.0,
+        };
+        watcher.watch(&scene);
+        Ok((watcher, scene))
+    }
+
+    /// Why a file failed to reload, the scene file's error first. None once every file
+    /// that failed has loaded again.
+    pub fn error(&self) -> Option<&str> {
+        self.files.iter().find_map(|file| file.error.as_deref())
+    }
+
+    fn base_dir(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new(""))
+    }
+
+    // every file the scene was loaded from, each once
+    fn watch(&mut self, scene: &Scene) {
+        let mut dependencies = vec![Dependency::Scene];
+        for object in &scene.objects {
+            if let MeshSource::File { path, .. } = &object.source {
+                dependencies.push(Dependency::Mesh(path.clone()));
+            }
+            if let Some(path) = object
+                .material
+                .texture
+                .as_ref()
+                .and_then(|t| t.path.clone())
+            {
+                dependencies.push(Dependency::Texture(path));
+            }
+        }
+
+        self.files.clear();
+        for dependency in dependencies {
+            if self.files.iter().any(|file| file.dependency == dependency) {
+                continue;
+            }
+            let path = match &dependency {
+                Dependency::Scene => self.path.clone(),
+                Dependency::Mesh(path) | Dependency::Texture(path) => self.base_dir().join(path),
+            };
+            self.files.push(WatchedFile {
+                modified: modified(&path),
+                path,
+                dependency,
+                error: None,
+            });
+        }
+    }
+
+    /// Advances the poll timer by `delta_time` and reloads anything changed since the last
+    /// check. True when `scene` was changed, so anything built from it is out of date.
+    pub fn update(&mut self, scene: &mut Scene, delta_time: f64) -> bool {
+        self.elapsed += delta_time;
+        if self.elapsed < self.interval {
+            return false;
+        }
+        self.elapsed = 0.0;
+
+        let mut changed = vec![];
+        for (index, file) in self.files.iter_mut().enumerate() {
+            let modified = modified(&file.path);
+            if modified != file.modified {
+                file.modified = modified;
+                changed.push(index);
+            }
+        }
+
+        let mut reloaded = false;
+        for index in changed {
+            let result = match &self.files[index].dependency {
+                // a new scene file reloads its meshes and textures anyway
+                Dependency::Scene => match Scene::load(&self.path) {
+                    Ok(new_scene) => {
+                        *scene = new_scene;
+                        self.watch(scene);
+                        return true;
+                    }
+                    Err(e) => Err(format!("{}: {}", self.path.display(), e)),
+                },
+                Dependency::Mesh(path) => self.reload_mesh(scene, path),
+                Dependency::Texture(path) => self.reload_texture(scene, path),
+            };
+            reloaded |= result.is_ok();
+            self.files[index].error = result.err();
+        }
+        reloaded
+    }
+
+    fn reload_mesh(&self, scene: &mut Scene, path: &str) -> Result<(), String> {
+        let full_path = self.base_dir().join(path);
+        for object in &mut scene.objects {
+            if let MeshSource::File {
+                path: source,
+                color,
+            } = &object.source
+            {
+                if source == path {
+                    object.mesh = Mesh::load_file(&full_path, color)
+                        .map_err(|e| format!("{}: {}", full_path.display(), e))?;
+                    object.update_bounds();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn reload_texture(&self, scene: &mut Scene, path: &str) -> Result<(), String> {
+        let full_path = self.base_dir().join(path);
+        let mut texture = Texture::load(&full_path.to_string_lossy())
+            .map_err(|e| format!("{}: {}", full_path.display(), e))?;
+        texture.path = Some(path.to_string());
+        let texture = Arc::new(texture);
+
+        for object in &mut scene.objects {
+            let material = &mut object.material;
+            if material.texture.as_ref().and_then(|t| t.path.as_deref()) == Some(path) {
+                material.texture = Some(texture.clone());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const QUAD: &str = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
+
+    // writes a file and moves its modification time on, so the change shows even on file
+    // systems that only keep whole seconds
+    fn write(path: &Path, contents: &str, age: u64) {
+        fs::write(path, contents).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(age))
+            .unwrap();
+    }
+
+    fn scene_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(name);
+        fs::create_dir_all(&dir).unwrap();
+        write(&dir.join("quad.obj"), QUAD, 0);
+        write(
+            &dir.join("scene.txt"),
+            "object { mesh \"quad.obj\" }\nobject { mesh \"quad.obj\" translate 2 0 0 }",
+            0,
+        );
+        dir
+    }
+
+    #[test]
+    fn changed_scene_files_are_reloaded() {
+        let dir = scene_dir("atlas_hotreload_scene");
+        let (mut watcher, mut scene) = SceneWatcher::load(dir.join("scene.txt")).unwrap();
+        assert_eq!(scene.objects.len(), 2);
+        assert!(!watcher.update(&mut scene, 1.0));
+
+        write(&dir.join("scene.txt"), "object { mesh \"quad.obj\" }", 10);
+        assert!(!watcher.update(&mut scene, 0.1), "waits for the interval");
+        assert!(watcher.update(&mut scene, 1.0));
+        assert_eq!(scene.objects.len(), 1);
+        assert_eq!(watcher.error(), None);
+
+        // the mesh is watched through the new scene
+        write(&dir.join("quad.obj"), &format!("{}f 1 3 2\n", QUAD), 20);
+        assert!(watcher.update(&mut scene, 1.0));
+        assert_eq!(scene.objects[0].mesh.triangles.len(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn broken_scene_files_keep_the_old_scene_and_their_error() {
+        let dir = scene_dir("atlas_hotreload_broken");
+        let (mut watcher, mut scene) = SceneWatcher::load(dir.join("scene.txt")).unwrap();
+
+        write(&dir.join("scene.txt"), "object { mesh", 10);
+        assert!(!watcher.update(&mut scene, 1.0));
+        assert_eq!(scene.objects.len(), 2);
+        let error = watcher.error().unwrap().to_string();
+        assert!(error.contains("scene.txt"), "{}", error);
+
+        // a mesh loading fine says nothing about the scene file
+        write(&dir.join("quad.obj"), &format!("{}f 1 3 2\n", QUAD), 20);
+        assert!(watcher.update(&mut scene, 1.0));
+        assert_eq!(scene.objects[1].mesh.triangles.len(), 2);
+        assert_eq!(watcher.error(), Some(error.as_str()));
+
+        write(&dir.join("scene.txt"), "object { mesh \"quad.obj\" }", 30);
+        assert!(watcher.update(&mut scene, 1.0));
+        assert_eq!(scene.objects.len(), 1);
+        assert_eq!(watcher.error(), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+use crate::font::TextStyle;
+use crate::renderer::{Color, Renderer};
+use std::collections::VecDeque;
+use std::time::Instant;
+
+// number of frames the fps and frame time are averaged over
+const HISTORY: usize = 60;
+
+/// On screen debug readout of frame timing, triangle counts and the camera.
+pub struct Hud {
+    pub visible: bool,
+    pub style: TextStyle,
+    frame_times: VecDeque<f64>,
+    last_frame: Instant,
+}
+
+impl Default for Hud {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hud {
+    pub fn new() -> Hud {
+        let mut style = TextStyle::new(&Color::new(255, 255, 255, 255));
+        style.scale = 2;
+        style.background = Some(Color::new(0, 0, 0, 160));
+
+        Hud {
+            visible: true,
+            style,
+            frame_times: VecDeque::with_capacity(HISTORY),
+            last_frame: Instant::now(),
+        }
+    }
+
+    /// Call once per frame, returns the seconds since the previous call.
+    pub fn tick(&mut self) -> f64 {
+        let now = Instant::now();
+        let delta = now.duration_since(self.last_frame).as_secs_f64();
+        self.last_frame = now;
+
+        if self.frame_times.len() == HISTORY {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(delta);
+        delta
+    }
+
+    /// Average frame time in seconds over the recent history.
+    pub fn frame_time(&self) -> f64 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+        self.frame_times.iter().sum::<f64>() / self.frame_times.len() as f64
+    }
+
+    pub fn fps(&self) -> f64 {
+        let frame_time = self.frame_time();
+        if frame_time > 0.0 {
+            1.0 / frame_time
+        } else {
+            0.0
+        }
+    }
+
+    /// Draws the readout in the top left corner, followed by any `extra` lines.
+    pub fn draw(&self, renderer: &mut Renderer, extra: &[String]) {
+        if !self.visible {
+            return;
+        }
+
+        let camera = &renderer.camera;
+        let stats = &renderer.stats;
+
+        let mut lines = vec![
+            format!("FPS {:.1}", self.fps()),
+            format!("frame {:.2} ms", self.frame_time() * 1000.0),
+            format!(
+                "triangles {} / {}",
+                stats.triangles_drawn, stats.triangles_submitted
+            ),
+            format!(
+                "objects culled {} / {}",
+                stats.objects_culled, stats.objects_submitted
+            ),
+            format!(
+                "pos {:.2} {:.2} {:.2}",
+                camera.position.x, camera.position.y, camera.position.z
+            ),
+            format!(
+                "yaw {:.1} pitch {:.1}",
+                camera.yaw.to_degrees(),
+                camera.pitch.to_degrees()
+            ),
+            format!("mode {:?}", renderer.mode),
+        ];
+        lines.extend(extra.iter().cloned());
+
+        // one call so the background is a single box behind every line
+        let margin = 8;
+        renderer
+            .framebuffer
+            .draw_text(margin, margin, &lines.join("\n"), &self.style);
+    }
+}
@@ -0,0 +1,298 @@
+use crate::png;
+use crate::renderer::Color;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io(std::io::Error),
+    /// The file isn't a well formed image of the format it claims to be.
+    Malformed(String),
+    /// A valid image using a feature we don't decode.
+    Unsupported(String),
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageError::Io(e) => write!(f, "{}", e),
+            ImageError::Malformed(msg) => write!(f, "malformed image: {}", msg),
+            ImageError::Unsupported(msg) => write!(f, "unsupported image: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+impl From<std::io::Error> for ImageError {
+    fn from(e: std::io::Error) -> ImageError {
+        ImageError::Io(e)
+    }
+}
+
+/// A decoded image, `channels` samples per pixel stored row by row from the top left.
+///
+/// Samples keep the file's precision, running from 0 to `max_value`. One channel is gray,
+/// two gray and alpha, three rgb and four rgba.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub channels: usize,
+    pub max_value: u16,
+    pub samples: Vec<u16>,
+}
+
+/// The widest or tallest image that will be decoded.
+pub const MAX_DIMENSION: usize = 1 << 16;
+
+impl Image {
+    pub fn new(width: usize, height: usize, channels: usize, max_value: u16) -> Image {
+        let count = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(channels))
+            .expect("image sample count overflows usize");
+        Image {
+            width,
+            height,
+            channels,
+            max_value,
+            samples: vec![0; count],
+        }
+    }
+
+    /// The number of pixels in an image read from a file, refusing empty or oversized ones
+    /// before anything is allocated for them.
+    pub(crate) fn checked_pixels(width: usize, height: usize) -> Result<usize, ImageError> {
+        if width == 0 || height == 0 {
+            return Err(ImageError::Malformed(format!(
+                "{}x{} image has no pixels",
+                width, height
+            )));
+        }
+        if width > MAX_DIMENSION || height > MAX_DIMENSION {
+            return Err(ImageError::Unsupported(format!(
+                "{}x{} image is larger than {} pixels across",
+                width, height, MAX_DIMENSION
+            )));
+        }
+        Ok(width * height)
+    }
+
+    /// Loads a png or pgm, telling them apart by their first bytes rather than the extension.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Image, ImageError> {
+        Image::decode(&fs::read(path)?)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Image, ImageError> {
+        if bytes.starts_with(&png::SIGNATURE) {
+            png::decode(bytes)
+        } else if bytes.starts_with(b"P5") || bytes.starts_with(b"P2") {
+            Image::from_pgm(bytes)
+        } else {
+            Err(ImageError::Unsupported(
+                "only png and pgm images can be read".to_string(),
+            ))
+        }
+    }
+
+    /// Reads binary (P5) or plain text (P2) pgm, 8 or 16 bits per sample.
+    pub fn from_pgm(bytes: &[u8]) -> Result<Image, ImageError> {
+        let mut pos = 2;
+        let mut header = [0usize; 3];
+
+        for value in header.iter_mut() {
+            *value = pgm_number(bytes, &mut pos)?;
+        }
+        let [width, height, max_value] = header;
+
+        if max_value == 0 || max_value > u16::MAX as usize {
+            return Err(ImageError::Malformed(format!(
+                "pgm max value {} out of range",
+                max_value
+            )));
+        }
+
+        let count = Image::checked_pixels(width, height)?;
+        // every sample takes at least one byte, so a short file can't claim a huge image
+        if count > bytes.len().saturating_sub(pos) {
+            return Err(ImageError::Malformed("pgm raster is truncated".to_string()));
+        }
+        let mut image = Image::new(width, height, 1, max_value as u16);
+
+        if bytes.starts_with(b"P2") {
+            for sample in image.samples.iter_mut() {
+                *sample = pgm_number(bytes, &mut pos)?.min(max_value) as u16;
+            }
+            return Ok(image);
+        }
+
+        // a single whitespace byte separates the header from the raster
+        pos += 1;
+        let wide = max_value > 255;
+        let size = if wide { count * 2 } else { count };
+        let raster = bytes
+            .get(pos..pos + size)
+            .ok_or_else(|| ImageError::Malformed("pgm raster is truncated".to_string()))?;
+
+        if wide {
+            for (sample, pair) in image.samples.iter_mut().zip(raster.chunks_exact(2)) {
+                *sample = u16::from_be_bytes([pair[0], pair[1]]);
+            }
+        } else {
+            for (sample, byte) in image.samples.iter_mut().zip(raster) {
+                *sample = *byte as u16;
+            }
+        }
+
+        Ok(image)
+    }
+
+    /// Binary pgm holding the luminance of every pixel, 16 bits if `max_value` needs it.
+    pub fn to_pgm(&self) -> Vec<u8> {
+        let mut bytes =
+            format!("P5\n{} {}\n{}\n", self.width, self.height, self.max_value).into_bytes();
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let value = (self.luminance(x, y) * self.max_value as f64).round() as u16;
+                if self.max_value > 255 {
+                    bytes.extend_from_slice(&value.to_be_bytes());
+                } else {
+                    bytes.push(value as u8);
+                }
+            }
+        }
+
+        bytes
+    }
+
+    pub fn save_pgm<P: AsRef<Path>>(&self, path: P) -> Result<(), ImageError> {
+        fs::write(path, self.to_pgm())?;
+        Ok(())
+    }
+
+    /// Sample `channel` of the pixel at (`x`, `y`) scaled to [0, 1].
+    pub fn sample(&self, x: usize, y: usize, channel: usize) -> f64 {
+        self.samples[(y * self.width + x) * self.channels + channel] as f64 / self.max_value as f64
+    }
+
+    /// Brightness of a pixel in [0, 1], gray images give their value back exactly.
+    pub fn luminance(&self, x: usize, y: usize) -> f64 {
+        if self.channels < 3 {
+            self.sample(x, y, 0)
+        } else {
+            0.2126 * self.sample(x, y, 0)
+                + 0.7152 * self.sample(x, y, 1)
+                + 0.0722 * self.sample(x, y, 2)
+        }
+    }
+
+    pub fn color(&self, x: usize, y: usize) -> Color {
+        let byte = |channel: usize| (self.sample(x, y, channel) * 255.0).round() as u8;
+        match self.channels {
+            1 => Color::new(byte(0), byte(0), byte(0), 255),
+            2 => Color::new(byte(0), byte(0), byte(0), byte(1)),
+            3 => Color::new(byte(0), byte(1), byte(2), 255),
+            _ => Color::new(byte(0), byte(1), byte(2), byte(3)),
+        }
+    }
+}
+
+// next whitespace separated number in a pgm header, skipping # comments
+fn pgm_number(bytes: &[u8], pos: &mut usize) -> Result<usize, ImageError> {
+    loop {
+        match bytes.get(*pos) {
+            Some(b'#') => {
+                while *pos < bytes.len() && bytes[*pos] != b'\n' {
+                    *pos += 1;
+                }
+            }
+            Some(c) if c.is_ascii_whitespace() => *pos += 1,
+            _ => break,
+        }
+    }
+
+    let start = *pos;
+    while *pos < bytes.len() && bytes[*pos].is_ascii_digit() {
+        *pos += 1;
+    }
+
+    std::str::from_utf8(&bytes[start..*pos])
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| ImageError::Malformed(format!("expected a number at byte {}", start)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn malformed_message(bytes: &[u8]) -> String {
+        match Image::decode(bytes) {
+            Err(ImageError::Malformed(message)) => message,
+            Err(e) => panic!("expected a malformed error, got {}", e),
+            Ok(_) => panic!("expected a malformed error"),
+        }
+    }
+
+    #[test]
+    fn reads_binary_and_plain_pgm() {
+        let image = Image::decode(b"P5\n# a comment\n3 1\n255\n\x00\x80\xff").unwrap();
+        assert_eq!((image.width, image.height, image.max_value), (3, 1, 255));
+        assert_eq!(image.samples, [0, 128, 255]);
+
+        let image = Image::decode(b"P5 2 1 65535\n\x12\x34\xff\xff").unwrap();
+        assert_eq!(image.samples, [0x1234, 0xffff]);
+
+        let image = Image::decode(b"P2\n2 2\n15\n0 5\n15 99\n").unwrap();
+        assert_eq!(image.samples, [0, 5, 15, 15]);
+    }
+
+    #[test]
+    fn pgm_round_trips() {
+        let image = Image::decode(b"P5 2 2 1000\n\x00\x00\x01\xf4\x03\xe8\x00\x01").unwrap();
+        assert_eq!(Image::decode(&image.to_pgm()).unwrap(), image);
+    }
+
+    #[test]
+    fn rejects_malformed_pgm() {
+        assert_eq!(
+            malformed_message(b"P5 2 2 255\n\x00\x00\x00"),
+            "pgm raster is truncated"
+        );
+        assert_eq!(
+            malformed_message(b"P5 2 2 0\n\x00\x00\x00\x00"),
+            "pgm max value 0 out of range"
+        );
+        assert_eq!(
+            malformed_message(b"P2 2 x 255\n"),
+            "expected a number at byte 5"
+        );
+        assert_eq!(
+            malformed_message(b"P2 2 1 255\n7"),
+            "expected a number at byte 12"
+        );
+        assert!(matches!(
+            Image::decode(b"GIF89a"),
+            Err(ImageError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_empty_and_oversized_pgm_before_allocating() {
+        assert_eq!(
+            malformed_message(b"P5 0 0 255\n"),
+            "0x0 image has no pixels"
+        );
+        assert!(matches!(
+            Image::from_pgm(b"P5 4294967296 4294967296 255\n"),
+            Err(ImageError::Unsupported(_))
+        ));
+        assert_eq!(
+            malformed_message(b"P2 60000 60000 255\n1 2 3"),
+            "pgm raster is truncated"
+        );
+    }
+}
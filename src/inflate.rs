@@ -0,0 +1,401 @@
+// DEFLATE (RFC 1951) and zlib (RFC 1950) decompression, enough for png and gltf buffers.
+
+const MAX_BITS: usize = 15;
+
+// base lengths and extra bits for length symbols 257..285
+const LENGTH_BASE: [usize; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u32; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+// base distances and extra bits for distance symbols 0..29
+const DIST_BASE: [usize; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u32; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+// order the code length code lengths are stored in a dynamic block header
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit_buffer: u32,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            data,
+            pos: 0,
+            bit_buffer: 0,
+            bit_count: 0,
+        }
+    }
+
+    // deflate packs bits starting from the least significant bit of each byte
+    fn bits(&mut self, count: u32) -> Result<u32, String> {
+        while self.bit_count < count {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or("deflate stream ended unexpectedly")?;
+            self.bit_buffer |= (byte as u32) << self.bit_count;
+            self.pos += 1;
+            self.bit_count += 8;
+        }
+
+        let value = self.bit_buffer & ((1u64 << count) - 1) as u32;
+        self.bit_buffer >>= count;
+        self.bit_count -= count;
+        Ok(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + count)
+            .ok_or("deflate stream ended unexpectedly")?;
+        self.pos += count;
+        Ok(bytes)
+    }
+}
+
+/// Canonical huffman code, decoded one bit at a time.
+struct Huffman {
+    // number of codes of each length
+    counts: [u16; MAX_BITS + 1],
+    // symbols ordered by code
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman, String> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        // reject codes that use more bit patterns than exist
+        let mut left: i32 = 1;
+        for &count in counts.iter().skip(1) {
+            left = left * 2 - count as i32;
+            if left < 0 {
+                return Err("over-subscribed huffman code".to_string());
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<usize, String> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+
+        for len in 1..=MAX_BITS {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize] as usize);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err("invalid huffman code".to_string())
+    }
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+
+    // both are complete codes, they can't fail to build
+    let literals = Huffman::new(&lengths).unwrap();
+    let distances = Huffman::new(&[5; 30]).unwrap();
+    (literals, distances)
+}
+
+fn dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &index in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        code_lengths[index] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths)?;
+
+    // literal and distance lengths are one sequence, repeats may run across the boundary
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let symbol = code_length_code.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or("length repeat with no previous length")?;
+                (previous, 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat));
+    }
+
+    if lengths.len() > literal_count + distance_count {
+        return Err("code lengths overflow the header's counts".to_string());
+    }
+    if lengths[256] == 0 {
+        return Err("missing end of block code".to_string());
+    }
+
+    let literals = Huffman::new(&lengths[..literal_count])?;
+    let distances = Huffman::new(&lengths[literal_count..])?;
+    Ok((literals, distances))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    max_len: usize,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(reader)?;
+        match symbol {
+            0..=255 if out.len() >= max_len => return Err(too_long(max_len)),
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let index = symbol - 257;
+                if index >= LENGTH_BASE.len() {
+                    return Err(format!("invalid length symbol {}", symbol));
+                }
+                let length = LENGTH_BASE[index] + reader.bits(LENGTH_EXTRA[index])? as usize;
+
+                let index = distances.decode(reader)?;
+                if index >= DIST_BASE.len() {
+                    return Err(format!("invalid distance symbol {}", index));
+                }
+                let distance = DIST_BASE[index] + reader.bits(DIST_EXTRA[index])? as usize;
+                if distance > out.len() {
+                    return Err("distance reaches before the start of the output".to_string());
+                }
+                if out.len() + length > max_len {
+                    return Err(too_long(max_len));
+                }
+
+                // copied a byte at a time since the match may overlap what it produces
+                let start = out.len() - distance;
+                for i in 0..length {
+                    out.push(out[start + i]);
+                }
+            }
+        }
+    }
+}
+
+fn too_long(max_len: usize) -> String {
+    format!("decompresses to more than the expected {} bytes", max_len)
+}
+
+/// Decompresses a raw DEFLATE stream, failing rather than producing more than `max_len`
+/// bytes.
+pub fn inflate(data: &[u8], max_len: usize) -> Result<Vec<u8>, String> {
+    let mut reader = BitReader::new(data);
+    let mut out = vec![];
+
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align_to_byte();
+                let header = reader.bytes(4)?;
+                let length = u16::from_le_bytes([header[0], header[1]]);
+                let inverse = u16::from_le_bytes([header[2], header[3]]);
+                if length != !inverse {
+                    return Err("stored block length check failed".to_string());
+                }
+                if out.len() + length as usize > max_len {
+                    return Err(too_long(max_len));
+                }
+                out.extend_from_slice(reader.bytes(length as usize)?);
+            }
+            1 => {
+                let (literals, distances) = fixed_tables();
+                inflate_block(&mut reader, &mut out, max_len, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_tables(&mut reader)?;
+                inflate_block(&mut reader, &mut out, max_len, &literals, &distances)?;
+            }
+            _ => return Err("invalid block type".to_string()),
+        }
+
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+/// Decompresses a zlib stream of at most `max_len` bytes, checking its header and adler-32
+/// checksum.
+pub fn zlib_decompress(data: &[u8], max_len: usize) -> Result<Vec<u8>, String> {
+    if data.len() < 6 {
+        return Err("zlib stream too short".to_string());
+    }
+
+    let (cmf, flg) = (data[0], data[1]);
+    if cmf & 0x0f != 8 {
+        return Err(format!(
+            "unsupported zlib compression method {}",
+            cmf & 0x0f
+        ));
+    }
+    if !(cmf as u16 * 256 + flg as u16).is_multiple_of(31) {
+        return Err("zlib header check failed".to_string());
+    }
+    if flg & 0x20 != 0 {
+        return Err("zlib preset dictionaries are not supported".to_string());
+    }
+
+    let out = inflate(&data[2..], max_len)?;
+
+    let tail = &data[data.len() - 4..];
+    let expected = u32::from_be_bytes([tail[0], tail[1], tail[2], tail[3]]);
+    if adler32(&out) != expected {
+        return Err("zlib checksum mismatch".to_string());
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "a quick brown fox jumps over the lazy dog" twice, compressed with fixed codes by zlib
+    const FOX: [u8; 54] = [
+        0x78, 0xda, 0x4b, 0x54, 0x28, 0x2c, 0xcd, 0x4c, 0xce, 0x56, 0x48, 0x2a, 0xca, 0x2f, 0xcf,
+        0x53, 0x48, 0xcb, 0xaf, 0x50, 0xc8, 0x2a, 0xcd, 0x2d, 0x28, 0x56, 0xc8, 0x2f, 0x4b, 0x2d,
+        0x52, 0x28, 0xc9, 0x48, 0x55, 0xc8, 0x49, 0xac, 0xaa, 0x54, 0x48, 0xc9, 0x4f, 0xd7, 0x51,
+        0x48, 0x24, 0x56, 0x29, 0x00, 0x0e, 0x1a, 0x1e, 0x7f,
+    ];
+
+    // 100000 zero bytes, compressed with dynamic codes by zlib
+    fn zeros() -> Vec<u8> {
+        let mut bytes = vec![
+            0x78, 0xda, 0xed, 0xc1, 0x31, 0x01, 0x00, 0x00, 0x00, 0xc2, 0xa0, 0xf5, 0x4f, 0x6d,
+            0x0d, 0x0f, 0xa0,
+        ];
+        bytes.extend_from_slice(&[0; 96]);
+        bytes.extend_from_slice(&[0x80, 0x57, 0x03, 0x86, 0xaf, 0x00, 0x01]);
+        bytes
+    }
+
+    #[test]
+    fn decompresses_fixed_code_blocks() {
+        let text = "a quick brown fox jumps over the lazy dog";
+        let expected = format!("{}, {}", text, text);
+        assert_eq!(zlib_decompress(&FOX, 1000).unwrap(), expected.as_bytes());
+    }
+
+    #[test]
+    fn decompresses_dynamic_code_blocks() {
+        assert_eq!(
+            zlib_decompress(&zeros(), 100_000).unwrap(),
+            vec![0; 100_000]
+        );
+    }
+
+    #[test]
+    fn decompresses_stored_blocks() {
+        let stored = [
+            0x78, 0x01, 0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c', 0x02, 0x4d, 0x01, 0x27,
+        ];
+        assert_eq!(zlib_decompress(&stored, 3).unwrap(), b"abc");
+        assert!(zlib_decompress(&stored, 2).is_err());
+    }
+
+    #[test]
+    fn stops_at_the_expected_length() {
+        let error = zlib_decompress(&zeros(), 1000).unwrap_err();
+        assert_eq!(error, "decompresses to more than the expected 1000 bytes");
+    }
+
+    #[test]
+    fn rejects_malformed_streams() {
+        let mut checksum = FOX;
+        checksum[53] ^= 1;
+        assert_eq!(
+            zlib_decompress(&checksum, 1000).unwrap_err(),
+            "zlib checksum mismatch"
+        );
+
+        let mut header = FOX;
+        header[1] ^= 1;
+        assert_eq!(
+            zlib_decompress(&header, 1000).unwrap_err(),
+            "zlib header check failed"
+        );
+
+        assert!(zlib_decompress(&FOX[..20], 1000).is_err());
+        assert!(zlib_decompress(&[0x78, 0xda, 0x07, 0, 0, 0, 0], 1000).is_err());
+        // a stored block whose length and its complement disagree
+        assert_eq!(
+            inflate(&[0x01, 0x03, 0x00, 0x00, 0x00], 10).unwrap_err(),
+            "stored block length check failed"
+        );
+    }
+}
@@ -0,0 +1,41 @@
+pub mod animation;
+pub mod biome;
+pub mod bounds;
+pub mod bvh;
+pub mod denoise;
+pub mod fog;
+pub mod font;
+pub mod frustum;
+pub mod gltf;
+pub mod hotreload;
+pub mod hud;
+pub mod image;
+pub mod inflate;
+pub mod json;
+pub mod light;
+pub mod linalg;
+pub mod line;
+pub mod lod;
+pub mod material;
+pub mod noise;
+pub mod obj;
+pub mod particles;
+pub mod pathtracer;
+pub mod picking;
+pub mod png;
+pub mod postprocess;
+pub mod primitives;
+pub mod random;
+pub mod ray;
+pub mod raytracer;
+pub mod renderer;
+pub mod scenefile;
+pub mod skinning;
+pub mod stl;
+pub mod streaming;
+pub mod terrain;
+pub mod texture;
+pub mod vector;
+pub mod walk;
+pub mod water;
+pub mod zbuf;
@@ -0,0 +1,50 @@
+use crate::linalg::{orthonormal_basis, Vector3D};
+use crate::random::Rng;
+
+/// A spherical light. The rasterizer ignores lights, the ray and path tracers shade with them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Light {
+    pub position: Vector3D,
+    /// Linear rgb, 0 to 1 per channel.
+    pub color: [f64; 3],
+    /// Brightness one unit away, falling off with the square of the distance.
+    pub intensity: f64,
+    /// 0 is a point light with hard shadows, bigger lights cast softer ones.
+    pub radius: f64,
+}
+
+impl Light {
+    pub fn new(position: Vector3D, color: [f64; 3], intensity: f64) -> Light {
+        Light {
+            position,
+            color,
+            intensity,
+            radius: 0.0,
+        }
+    }
+
+    pub fn with_radius(mut self, radius: f64) -> Light {
+        self.radius = radius;
+        self
+    }
+
+    /// Light arriving from one unit away, the color scaled by the intensity.
+    pub fn radiance(&self) -> [f64; 3] {
+        self.color.map(|c| c * self.intensity)
+    }
+
+    /// A uniformly random point on the disc of the light as seen from `from`.
+    pub fn sample_point(&self, from: &Vector3D, rng: &mut Rng) -> Vector3D {
+        if self.radius <= 0.0 {
+            return self.position;
+        }
+
+        let (u, v) = orthonormal_basis(&self.position.sub(from).normalize());
+
+        let r = self.radius * rng.next_f64().sqrt();
+        let angle = rng.range(0.0, std::f64::consts::TAU);
+        self.position
+            .add(&u.scale(r * angle.cos()))
+            .add(&v.scale(r * angle.sin()))
+    }
+}
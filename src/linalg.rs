@@ -1,5 +1,6 @@
 use std::ops;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Vector2D {
     pub x: f64,
     pub y: f64,
@@ -48,7 +49,7 @@ impl ops::Mul<f64> for Vector2D {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Vector3D {
     pub x: f64,
     pub y: f64,
@@ -98,11 +99,7 @@ impl Vector3D {
     }
 
     pub fn is_zero(&self) -> bool {
-        if self.x == 0.0 && self.y == 0.0 && self.z == 0.0 {
-            true
-        } else {
-            false
-        }
+        self.x == 0.0 && self.y == 0.0 && self.z == 0.0
     }
 }
 
@@ -146,6 +143,7 @@ impl ops::Mul<f64> for Vector3D {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Matrix4D {
     m: [[f64; 4]; 4],
 }
@@ -155,28 +153,95 @@ impl Matrix4D {
         Matrix4D { m }
     }
 
+    pub fn identity() -> Matrix4D {
+        Matrix4D::new([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    // vectors are rows multiplied on the left, so the translation goes in the last row
     pub fn new_translation(translation: &Vector3D) -> Matrix4D {
         Matrix4D::new([
-            [1.0, 0.0, 0.0, translation.x],
-            [0.0, 1.0, 0.0, translation.y],
-            [0.0, 0.0, 1.0, translation.z],
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [translation.x, translation.y, translation.z, 1.0],
+        ])
+    }
+
+    pub fn new_scale(scale: &Vector3D) -> Matrix4D {
+        Matrix4D::new([
+            [scale.x, 0.0, 0.0, 0.0],
+            [0.0, scale.y, 0.0, 0.0],
+            [0.0, 0.0, scale.z, 0.0],
             [0.0, 0.0, 0.0, 1.0],
         ])
     }
 
+    pub fn row(&self, i: usize) -> [f64; 4] {
+        self.m[i]
+    }
+
+    pub fn column(&self, j: usize) -> [f64; 4] {
+        [self.m[0][j], self.m[1][j], self.m[2][j], self.m[3][j]]
+    }
+
     pub fn multiply(&self, other: &Matrix4D) -> Matrix4D {
         let mut result = [[0.0; 4]; 4];
 
-        for i in 0..4 {
-            for j in 0..4 {
+        for (i, row) in result.iter_mut().enumerate() {
+            for (j, cell) in row.iter_mut().enumerate() {
                 for k in 0..4 {
-                    result[i][j] += self.m[i][k] * other.m[k][j];
+                    *cell += self.m[i][k] * other.m[k][j];
                 }
             }
         }
 
         Matrix4D::new(result)
     }
+
+    pub fn transpose(&self) -> Matrix4D {
+        Matrix4D::new([0, 1, 2, 3].map(|j| self.column(j)))
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting, None if the matrix is singular.
+    pub fn inverse(&self) -> Option<Matrix4D> {
+        let mut a = self.m;
+        let mut inv = Matrix4D::identity().m;
+
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = 1.0 / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= scale;
+                inv[col][j] *= scale;
+            }
+
+            for row in 0..4 {
+                if row == col {
+                    continue;
+                }
+                let factor = a[row][col];
+                for j in 0..4 {
+                    a[row][j] -= factor * a[col][j];
+                    inv[row][j] -= factor * inv[col][j];
+                }
+            }
+        }
+
+        Some(Matrix4D::new(inv))
+    }
 }
 
 impl ops::Mul<&Matrix4D> for &Matrix4D {
@@ -187,6 +252,33 @@ impl ops::Mul<&Matrix4D> for &Matrix4D {
     }
 }
 
+pub fn get_z_rotation_matrix(theta: f64) -> Matrix4D {
+    Matrix4D::new([
+        [theta.cos(), theta.sin(), 0.0, 0.0],
+        [-theta.sin(), theta.cos(), 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ])
+}
+
+pub fn get_x_rotation_matrix(theta: f64) -> Matrix4D {
+    Matrix4D::new([
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, theta.cos(), theta.sin(), 0.0],
+        [0.0, -theta.sin(), theta.cos(), 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ])
+}
+
+pub fn get_y_rotation_matrix(theta: f64) -> Matrix4D {
+    Matrix4D::new([
+        [theta.cos(), 0.0, -theta.sin(), 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [theta.sin(), 0.0, theta.cos(), 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ])
+}
+
 pub fn multiply_matrix_vector_perspective_div(v: &Vector3D, mat: &Matrix4D) -> Option<Vector3D> {
     let mut out = Vector3D {
         x: v.x * mat.m[0][0] + v.y * mat.m[1][0] + v.z * mat.m[2][0] + mat.m[3][0],
@@ -209,6 +301,15 @@ pub fn multiply_matrix_vector_perspective_div(v: &Vector3D, mat: &Matrix4D) -> O
     Some(out)
 }
 
+/// Transforms a direction, ignoring the translation part of the matrix.
+pub fn multiply_matrix_direction(v: &Vector3D, mat: &Matrix4D) -> Vector3D {
+    Vector3D {
+        x: v.x * mat.m[0][0] + v.y * mat.m[1][0] + v.z * mat.m[2][0],
+        y: v.x * mat.m[0][1] + v.y * mat.m[1][1] + v.z * mat.m[2][1],
+        z: v.x * mat.m[0][2] + v.y * mat.m[1][2] + v.z * mat.m[2][2],
+    }
+}
+
 pub fn multiply_matrix_vector(v: &Vector3D, mat: &Matrix4D) -> Vector3D {
     Vector3D {
         x: v.x * mat.m[0][0] + v.y * mat.m[1][0] + v.z * mat.m[2][0] + mat.m[3][0],
@@ -217,6 +318,11 @@ pub fn multiply_matrix_vector(v: &Vector3D, mat: &Matrix4D) -> Vector3D {
     }
 }
 
+/// Transforms a homogeneous point, keeping the w component.
+pub fn multiply_matrix_vector4(v: &[f64; 4], mat: &Matrix4D) -> [f64; 4] {
+    [0, 1, 2, 3].map(|j| (0..4).map(|i| v[i] * mat.m[i][j]).sum())
+}
+
 impl ops::Mul<Vector3D> for Matrix4D {
     type Output = Vector3D;
     fn mul(self, rhs: Vector3D) -> Self::Output {
@@ -233,13 +339,257 @@ impl ops::Mul<Matrix4D> for Vector3D {
 }
 
 pub fn dot(u: &Vector3D, v: &Vector3D) -> f64 {
-    return u.x * v.x + u.y * v.y + u.z * v.z;
+    u.x * v.x + u.y * v.y + u.z * v.z
 }
 
 pub fn cross(u: &Vector3D, v: &Vector3D) -> Vector3D {
     Vector3D {
         x: u.y * v.z - u.z * v.y,
         y: -(u.x * v.z - u.z * v.x),
-        z: u.x * v.y - u.y * u.x,
+        z: u.x * v.y - u.y * v.x,
+    }
+}
+
+/// Two unit vectors perpendicular to unit `normal` and to each other.
+pub fn orthonormal_basis(normal: &Vector3D) -> (Vector3D, Vector3D) {
+    // any axis not too close to the normal works to start from
+    let helper = if normal.x.abs() < 0.9 {
+        Vector3D::new(1.0, 0.0, 0.0)
+    } else {
+        Vector3D::new(0.0, 1.0, 0.0)
+    };
+    let u = cross(normal, &helper).normalize();
+    let v = cross(normal, &u);
+    (u, v)
+}
+
+/// A rotation, `w` the cosine of half the angle and `x`, `y`, `z` the axis scaled by its sine.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quaternion {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub w: f64,
+}
+
+impl Quaternion {
+    pub fn new(x: f64, y: f64, z: f64, w: f64) -> Quaternion {
+        Quaternion { x, y, z, w }
+    }
+
+    pub fn identity() -> Quaternion {
+        Quaternion::new(0.0, 0.0, 0.0, 1.0)
+    }
+
+    /// Rotation by `angle` radians around unit `axis`, the same way round as
+    /// `get_x_rotation_matrix` and friends turn around theirs.
+    pub fn from_axis_angle(axis: &Vector3D, angle: f64) -> Quaternion {
+        let (sin, cos) = (angle / 2.0).sin_cos();
+        Quaternion::new(axis.x * sin, axis.y * sin, axis.z * sin, cos)
+    }
+
+    /// The rotation in the upper 3x3 of `matrix`, which must be a pure rotation.
+    pub fn from_rotation_matrix(matrix: &Matrix4D) -> Quaternion {
+        // rows of `matrix` are the columns of the usual column vector rotation `r`
+        let r = |i: usize, j: usize| matrix.m[j][i];
+        let trace = r(0, 0) + r(1, 1) + r(2, 2);
+
+        // divides by the largest of the four to stay accurate
+        let q = if trace > 0.0 {
+            let s = (trace + 1.0).sqrt() * 2.0;
+            Quaternion::new(
+                (r(2, 1) - r(1, 2)) / s,
+                (r(0, 2) - r(2, 0)) / s,
+                (r(1, 0) - r(0, 1)) / s,
+                s / 4.0,
+            )
+        } else if r(0, 0) > r(1, 1) && r(0, 0) > r(2, 2) {
+            let s = (1.0 + r(0, 0) - r(1, 1) - r(2, 2)).sqrt() * 2.0;
+            Quaternion::new(
+                s / 4.0,
+                (r(0, 1) + r(1, 0)) / s,
+                (r(0, 2) + r(2, 0)) / s,
+                (r(2, 1) - r(1, 2)) / s,
+            )
+        } else if r(1, 1) > r(2, 2) {
+            let s = (1.0 + r(1, 1) - r(0, 0) - r(2, 2)).sqrt() * 2.0;
+            Quaternion::new(
+                (r(0, 1) + r(1, 0)) / s,
+                s / 4.0,
+                (r(1, 2) + r(2, 1)) / s,
+                (r(0, 2) - r(2, 0)) / s,
+            )
+        } else {
+            let s = (1.0 + r(2, 2) - r(0, 0) - r(1, 1)).sqrt() * 2.0;
+            Quaternion::new(
+                (r(0, 2) + r(2, 0)) / s,
+                (r(1, 2) + r(2, 1)) / s,
+                s / 4.0,
+                (r(1, 0) - r(0, 1)) / s,
+            )
+        };
+        q.normalize()
+    }
+
+    pub fn dot(&self, other: &Quaternion) -> f64 {
+        self.x * other.x + self.y * other.y + self.z * other.z + self.w * other.w
+    }
+
+    pub fn magnitude(&self) -> f64 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn normalize(&self) -> Quaternion {
+        let length = self.magnitude();
+        if length == 0.0 {
+            return Quaternion::identity();
+        }
+        self.scale(1.0 / length)
+    }
+
+    pub fn scale(&self, scalar: f64) -> Quaternion {
+        Quaternion::new(
+            self.x * scalar,
+            self.y * scalar,
+            self.z * scalar,
+            self.w * scalar,
+        )
+    }
+
+    pub fn add(&self, other: &Quaternion) -> Quaternion {
+        Quaternion::new(
+            self.x + other.x,
+            self.y + other.y,
+            self.z + other.z,
+            self.w + other.w,
+        )
+    }
+
+    /// The opposite rotation, for unit quaternions.
+    pub fn conjugate(&self) -> Quaternion {
+        Quaternion::new(-self.x, -self.y, -self.z, self.w)
+    }
+
+    /// Rotates by `other` and then by `self`.
+    pub fn multiply(&self, other: &Quaternion) -> Quaternion {
+        let (a, b) = (self, other);
+        Quaternion::new(
+            a.w * b.x + a.x * b.w + a.y * b.z - a.z * b.y,
+            a.w * b.y - a.x * b.z + a.y * b.w + a.z * b.x,
+            a.w * b.z + a.x * b.y - a.y * b.x + a.z * b.w,
+            a.w * b.w - a.x * b.x - a.y * b.y - a.z * b.z,
+        )
+    }
+
+    pub fn rotate(&self, v: &Vector3D) -> Vector3D {
+        // v + 2w (u x v) + 2 u x (u x v), u the vector part
+        let u = Vector3D::new(self.x, self.y, self.z);
+        let t = cross(&u, v).scale(2.0);
+        v.add(&t.scale(self.w)).add(&cross(&u, &t))
+    }
+
+    /// The rotation as a matrix for row vectors, like the rest of this module's.
+    pub fn to_matrix(&self) -> Matrix4D {
+        let Quaternion { x, y, z, w } = *self;
+        Matrix4D::new([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y + w * z),
+                2.0 * (x * z - w * y),
+                0.0,
+            ],
+            [
+                2.0 * (x * y - w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z + w * x),
+                0.0,
+            ],
+            [
+                2.0 * (x * z + w * y),
+                2.0 * (y * z - w * x),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// Constant speed rotation from `self` at 0 to `other` at 1, the short way round.
+    pub fn slerp(&self, other: &Quaternion, t: f64) -> Quaternion {
+        let mut cos = self.dot(other);
+        // q and -q are the same rotation, pick the one on this side
+        let other = if cos < 0.0 {
+            cos = -cos;
+            other.scale(-1.0)
+        } else {
+            *other
+        };
+
+        // nearly parallel, where the sine below goes to 0
+        if cos > 0.9995 {
+            return self.scale(1.0 - t).add(&other.scale(t)).normalize();
+        }
+
+        let angle = cos.acos();
+        let sin = angle.sin();
+        let a = ((1.0 - t) * angle).sin() / sin;
+        let b = (t * angle).sin() / sin;
+        self.scale(a).add(&other.scale(b))
+    }
+}
+
+impl ops::Mul<Quaternion> for Quaternion {
+    type Output = Quaternion;
+
+    fn mul(self, rhs: Quaternion) -> Quaternion {
+        self.multiply(&rhs)
+    }
+}
+
+/// The points `p` with `dot(normal, p) + distance == 0`, `normal` pointing to the front side.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Plane {
+    pub normal: Vector3D,
+    pub distance: f64,
+}
+
+impl Plane {
+    pub fn new(normal: Vector3D, distance: f64) -> Plane {
+        Plane { normal, distance }
+    }
+
+    /// The plane through `point` facing along `normal`.
+    pub fn from_point_normal(point: &Vector3D, normal: &Vector3D) -> Plane {
+        let normal = normal.normalize();
+        Plane::new(normal, -dot(&normal, point))
+    }
+
+    /// Distance from the plane, negative behind it. Scaled by the normal's length if it
+    /// isn't unit length.
+    pub fn signed_distance(&self, point: &Vector3D) -> f64 {
+        dot(&self.normal, point) + self.distance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cross_follows_the_right_hand_rule() {
+        let x = Vector3D::new(1.0, 0.0, 0.0);
+        let y = Vector3D::new(0.0, 1.0, 0.0);
+        let z = cross(&x, &y);
+        assert_eq!((z.x, z.y, z.z), (0.0, 0.0, 1.0));
+
+        let c = cross(&Vector3D::new(1.0, 2.0, 3.0), &Vector3D::new(4.0, 5.0, 6.0));
+        assert_eq!((c.x, c.y, c.z), (-3.0, 6.0, -3.0));
+    }
+
+    #[test]
+    fn new_translation_moves_row_vectors() {
+        let m = Matrix4D::new_translation(&Vector3D::new(1.0, -2.0, 3.0));
+        let p = multiply_matrix_vector(&Vector3D::new(4.0, 5.0, 6.0), &m);
+        assert_eq!((p.x, p.y, p.z), (5.0, 3.0, 9.0));
     }
 }
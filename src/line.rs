@@ -0,0 +1,401 @@
+use crate::linalg::{
+    multiply_matrix_vector, multiply_matrix_vector_perspective_div, Matrix4D, Vector3D,
+};
+use crate::renderer::{Color, Renderer, Triangle};
+
+/// A world space line segment with a color at each end.
+pub struct Line3D {
+    pub start: Vector3D,
+    pub end: Vector3D,
+    pub start_color: Color,
+    pub end_color: Color,
+}
+
+impl Line3D {
+    pub fn new(start: Vector3D, end: Vector3D, color: &Color) -> Line3D {
+        Line3D {
+            start,
+            end,
+            start_color: color.clone(),
+            end_color: color.clone(),
+        }
+    }
+
+    pub fn with_gradient(
+        start: Vector3D,
+        end: Vector3D,
+        start_color: &Color,
+        end_color: &Color,
+    ) -> Line3D {
+        Line3D {
+            start,
+            end,
+            start_color: start_color.clone(),
+            end_color: end_color.clone(),
+        }
+    }
+}
+
+/// Lengths in screen pixels of the drawn and skipped parts of a dashed line.
+#[derive(Clone, Copy)]
+pub struct DashPattern {
+    pub on: f64,
+    pub off: f64,
+}
+
+impl DashPattern {
+    /// Whether the pattern repeats over some finite length. One that doesn't, like
+    /// `on` and `off` both 0, draws the line solid.
+    pub fn has_length(&self) -> bool {
+        let period = self.on + self.off;
+        period > 0.0 && period.is_finite()
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct LineStyle {
+    /// Width in pixels.
+    pub thickness: f64,
+    /// Shades pixels by how much of each the line covers instead of drawing them fully or
+    /// not at all. Lines 1 pixel wide use Xiaolin Wu's algorithm, splitting each column
+    /// between the two pixels nearest the line and fading the ends by how far into their
+    /// pixel they reach. Wider lines shade the pixels along their edges by how much of each
+    /// the width overlaps.
+    pub antialiased: bool,
+    pub dash: Option<DashPattern>,
+    pub depth_test: bool,
+    /// Pulls the line toward the camera so it doesn't fight with the surface it lies on.
+    pub depth_bias: f64,
+}
+
+impl Default for LineStyle {
+    fn default() -> Self {
+        LineStyle {
+            thickness: 1.0,
+            antialiased: true,
+            dash: None,
+            depth_test: true,
+            depth_bias: 0.01,
+        }
+    }
+}
+
+// projected end point, x and y in screen pixels and z the view depth
+struct ScreenPoint {
+    x: f64,
+    y: f64,
+    z: f64,
+}
+
+// Liang-Barsky, returns the parameter range of the segment inside the rectangle
+fn clip_to_rect(
+    a: &ScreenPoint,
+    b: &ScreenPoint,
+    max_x: f64,
+    max_y: f64,
+    margin: f64,
+) -> Option<(f64, f64)> {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let mut t0: f64 = 0.0;
+    let mut t1: f64 = 1.0;
+
+    for (p, q) in [
+        (-dx, a.x + margin),
+        (dx, max_x + margin - a.x),
+        (-dy, a.y + margin),
+        (dy, max_y + margin - a.y),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else {
+            let r = q / p;
+            if p < 0.0 {
+                t0 = t0.max(r);
+            } else {
+                t1 = t1.min(r);
+            }
+        }
+    }
+
+    if t0 > t1 {
+        None
+    } else {
+        Some((t0, t1))
+    }
+}
+
+impl Renderer {
+    /// Draws a world space line through the same view and projection as `render_mesh`.
+    pub fn draw_line_3d(&mut self, line: &Line3D, style: &LineStyle, proj_mat: &Matrix4D) {
+        let view_matrix = self.camera.create_view_matrix();
+        let camera_offset = self.camera.position.scale(-1.0);
+        let near = self.camera.near_clip;
+
+        let mut a = multiply_matrix_vector(&line.start.add(&camera_offset), &view_matrix);
+        let mut b = multiply_matrix_vector(&line.end.add(&camera_offset), &view_matrix);
+        let mut color_a = line.start_color.clone();
+        let mut color_b = line.end_color.clone();
+
+        // clip against the near plane in view space before the perspective divide
+        if a.z < near && b.z < near {
+            return;
+        }
+        if a.z < near || b.z < near {
+            let t = (near - a.z) / (b.z - a.z);
+            let point = a.add(&b.sub(&a).scale(t));
+            let color = color_a.lerp(&color_b, t);
+            if a.z < near {
+                a = point;
+                color_a = color;
+            } else {
+                b = point;
+                color_b = color;
+            }
+        }
+
+        let (Some(pa), Some(pb)) = (
+            multiply_matrix_vector_perspective_div(&a, proj_mat),
+            multiply_matrix_vector_perspective_div(&b, proj_mat),
+        ) else {
+            return;
+        };
+
+        let (pa, pb) = (self.screen_point(&pa), self.screen_point(&pb));
+        self.draw_screen_line(&pa, &pb, &color_a, &color_b, style);
+    }
+
+    /// Outlines a triangle projected by `project_triangle`, hidden wherever something nearer
+    /// is already drawn.
+    pub(crate) fn draw_projected_edges(&mut self, triangle: &Triangle, color: &Color) {
+        for (i, j) in [(0, 1), (1, 2), (2, 0)] {
+            let (a, b) = (&triangle.vertices[i], &triangle.vertices[j]);
+            let style = LineStyle {
+                antialiased: false,
+                // the edge shares its depth with the face it bounds, so it needs pulling
+                // further forward the further away it is
+                depth_bias: a.z.min(b.z) * 0.01,
+                ..LineStyle::default()
+            };
+            let (a, b) = (self.screen_point(a), self.screen_point(b));
+            self.draw_screen_line(&a, &b, color, color, &style);
+        }
+    }
+
+    // clip space x and y to screen pixels, keeping the view depth
+    fn screen_point(&self, p: &Vector3D) -> ScreenPoint {
+        ScreenPoint {
+            x: (p.x + 1.0) * self.framebuffer.width as f64 / 2.0,
+            y: (1.0 - p.y) * self.framebuffer.height as f64 / 2.0,
+            z: p.z,
+        }
+    }
+
+    fn draw_screen_line(
+        &mut self,
+        a: &ScreenPoint,
+        b: &ScreenPoint,
+        color_a: &Color,
+        color_b: &Color,
+        style: &LineStyle,
+    ) {
+        let (width, height) = (self.framebuffer.width, self.framebuffer.height);
+        let half = style.thickness.max(1.0) / 2.0;
+
+        let Some((t_min, t_max)) = clip_to_rect(a, b, width as f64, height as f64, half + 1.0)
+        else {
+            return;
+        };
+
+        let (dx, dy) = (b.x - a.x, b.y - a.y);
+        let length = (dx * dx + dy * dy).sqrt();
+        let steep = dy.abs() > dx.abs();
+        let wu = style.antialiased && style.thickness <= 1.0;
+
+        // walk the major axis one pixel at a time, covering a span of the minor axis
+        let (major_a, major_d, minor_a, minor_d) = if steep {
+            (a.y, dy, a.x, dx)
+        } else {
+            (a.x, dx, a.y, dy)
+        };
+
+        // half width of the line measured along the minor axis
+        let span = if major_d.abs() > f64::EPSILON {
+            half * length / major_d.abs()
+        } else {
+            half
+        };
+
+        let start = major_a + major_d * t_min;
+        let end = major_a + major_d * t_max;
+        let (low_end, high_end) = (start.min(end), start.max(end));
+        let (first, last) = (low_end.floor() as i64, high_end.floor() as i64);
+
+        for major in first..=last {
+            let (t, reach) = if major_d.abs() > f64::EPSILON {
+                let t = (major as f64 + 0.5 - major_a) / major_d;
+                // how much of this column lies between the line's ends
+                let reach = high_end.min(major as f64 + 1.0) - low_end.max(major as f64);
+                (t, reach.clamp(0.0, 1.0))
+            } else {
+                (t_min, 1.0)
+            };
+            let clamped = t.clamp(t_min, t_max);
+
+            if let Some(dash) = style.dash.filter(DashPattern::has_length) {
+                if (clamped * length) % (dash.on + dash.off) >= dash.on {
+                    continue;
+                }
+            }
+
+            // perspective correct depth and color, 1/z is what's linear on screen
+            let inv_z = (1.0 - clamped) / a.z + clamped / b.z;
+            let depth = 1.0 / inv_z;
+            let u = (clamped / b.z) / inv_z;
+            let color = color_a.lerp(color_b, u);
+
+            let mut pixels = vec![];
+            if wu {
+                // the two pixels whose centers straddle the line share the column by how
+                // close each is to it
+                let offset = minor_a + minor_d * t - 0.5;
+                let below = offset.floor();
+                let fraction = offset - below;
+                pixels.push((below as i64, (1.0 - fraction) * reach));
+                pixels.push((below as i64 + 1, fraction * reach));
+            } else {
+                let center = minor_a + minor_d * clamped;
+                let (low, high) = (center - span, center + span);
+
+                for minor in low.floor() as i64..=high.floor() as i64 {
+                    // how much of this pixel the span overlaps
+                    let overlap =
+                        (high.min(minor as f64 + 1.0) - low.max(minor as f64)).clamp(0.0, 1.0);
+                    let coverage = if style.antialiased {
+                        overlap
+                    } else if overlap >= 0.5 {
+                        1.0
+                    } else {
+                        0.0
+                    };
+                    pixels.push((minor, coverage));
+                }
+            }
+
+            for (minor, coverage) in pixels {
+                let (x, y) = if steep {
+                    (minor, major)
+                } else {
+                    (major, minor)
+                };
+                if coverage > 0.0
+                    && x >= 0
+                    && y >= 0
+                    && (x as usize) < width
+                    && (y as usize) < height
+                {
+                    self.blend_line_pixel(x as usize, y as usize, &color, coverage, depth, style);
+                }
+            }
+        }
+    }
+
+    fn blend_line_pixel(
+        &mut self,
+        x: usize,
+        y: usize,
+        color: &Color,
+        coverage: f64,
+        depth: f64,
+        style: &LineStyle,
+    ) {
+        let i = y * self.framebuffer.width + x;
+        let biased = depth - style.depth_bias;
+
+        if style.depth_test && biased >= self.framebuffer.depth_buffer[i] {
+            return;
+        }
+
+        let behind = Color::from_u32(self.framebuffer.color_buffer[i]);
+        self.framebuffer.color_buffer[i] = behind.lerp(color, coverage).to_u32();
+        self.framebuffer.overdraw_buffer[i] += 1;
+
+        // faint antialiased fringes shouldn't hide what's drawn behind them later
+        if coverage >= 0.5 {
+            self.framebuffer.depth_buffer[i] = biased;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::{CameraSetup, FrameBuffer};
+
+    #[test]
+    fn empty_dash_patterns_draw_solid() {
+        let dash = |on, off| DashPattern { on, off }.has_length();
+        assert!(dash(4.0, 2.0));
+        assert!(dash(0.0, 3.0));
+        assert!(!dash(0.0, 0.0));
+        assert!(!dash(-1.0, 1.0));
+        assert!(!dash(f64::INFINITY, 1.0));
+        assert!(!dash(f64::NAN, 1.0));
+    }
+
+    // coverage of each pixel in every column of a white line drawn over black
+    fn thin_line_columns(from: (f64, f64), to: (f64, f64)) -> Vec<Vec<f64>> {
+        let camera = CameraSetup::default().camera(1.0);
+        let mut framebuffer = FrameBuffer::new(24, 24);
+        framebuffer.clear();
+        let mut renderer = Renderer::new(camera, framebuffer);
+
+        let point = |(x, y)| ScreenPoint { x, y, z: 1.0 };
+        let white = Color::new(255, 255, 255, 255);
+        let style = LineStyle {
+            depth_test: false,
+            ..LineStyle::default()
+        };
+        renderer.draw_screen_line(&point(from), &point(to), &white, &white, &style);
+
+        let fb = &renderer.framebuffer;
+        (0..fb.width)
+            .map(|x| {
+                (0..fb.height)
+                    .map(|y| Color::from_u32(fb.color_buffer[y * fb.width + x]).r as f64 / 255.0)
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn thin_lines_split_each_column_between_two_pixels() {
+        let columns = thin_line_columns((2.5, 3.2), (17.5, 12.7));
+        for (x, column) in columns.iter().enumerate() {
+            let lit = column.iter().filter(|c| **c > 0.0).count();
+            let total: f64 = column.iter().sum();
+            assert!(lit <= 2);
+
+            // the ends only reach halfway into their columns
+            let expected = match x {
+                2 | 17 => 0.5,
+                3..=16 => 1.0,
+                _ => 0.0,
+            };
+            assert!(
+                (total - expected).abs() < 0.01,
+                "column {} has {}",
+                x,
+                total
+            );
+        }
+
+        // a diagonal through pixel centers lights only the pixel it passes through
+        let columns = thin_line_columns((2.5, 2.5), (12.5, 12.5));
+        for (x, column) in columns.iter().enumerate().take(12).skip(3) {
+            assert_eq!(column[x], 1.0);
+            assert_eq!(column.iter().sum::<f64>(), 1.0);
+        }
+    }
+}
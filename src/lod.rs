@@ -0,0 +1,568 @@
+use crate::biome::BiomeTable;
+use crate::linalg::Vector3D;
+use crate::renderer::{Color, Mesh, Triangle};
+use crate::terrain::{GridRegion, Heightmap};
+use std::collections::HashMap;
+
+pub struct LodSettings {
+    /// Cells along each side of every chunk, whatever its level.
+    pub chunk_resolution: usize,
+    /// Largest on screen error in pixels a chunk may have before it is split.
+    pub max_screen_error: f64,
+    /// Pixels covered by one world unit one unit in front of the camera.
+    pub projection_scale: f64,
+    /// How much further the skirts hanging off chunk edges reach than the gap a crack next
+    /// to them could open, which grows with the errors of the chunk and its neighbours.
+    pub skirt_depth: f64,
+}
+
+impl LodSettings {
+    /// Settings matching a camera using `Camera::get_proj_matrix` with this fov.
+    pub fn new(chunk_resolution: usize, screen_height: usize, fov: f64) -> LodSettings {
+        LodSettings {
+            chunk_resolution,
+            max_screen_error: 4.0,
+            projection_scale: screen_height as f64 / 2.0 * fov.to_radians(),
+            skirt_depth: 2.0,
+        }
+    }
+}
+
+struct LodNode {
+    region: GridRegion,
+    /// Distance between the grid points this node's mesh uses.
+    step: usize,
+    min_height: f64,
+    max_height: f64,
+    /// Largest height difference between this node's mesh and the full resolution surface.
+    error: f64,
+    /// Largest error of any node that can be selected next to this one, its own for the root.
+    neighbour_error: f64,
+    children: Option<[usize; 4]>,
+}
+
+/// A terrain split into a quadtree of chunks, drawn at finer resolution near the camera.
+///
+/// Every chunk has the same number of cells, so a chunk twice the size has half the
+/// detail. Neighbouring chunks are kept within a level of each other, and the cracks where
+/// they meet are hidden by skirts, strips hanging straight down from each chunk's edges.
+pub struct TerrainLod {
+    pub heightmap: Heightmap,
+    pub spacing: f64,
+    /// World position of grid point (0, 0).
+    pub origin: Vector3D,
+    pub settings: LodSettings,
+    pub color: Color,
+    biomes: Option<BiomeTable>,
+    nodes: Vec<LodNode>,
+    selected: Vec<usize>,
+    meshes: HashMap<usize, Mesh>,
+}
+
+impl TerrainLod {
+    /// A heightmap that isn't square with `chunk_resolution * 2^n + 1` points a side is
+    /// resampled to the next size that is, covering the same ground with the shorter side
+    /// padded out by its edge heights.
+    pub fn new(
+        heightmap: Heightmap,
+        spacing: f64,
+        origin: Vector3D,
+        settings: LodSettings,
+        color: &Color,
+    ) -> TerrainLod {
+        assert!(
+            settings.chunk_resolution > 0,
+            "lod chunks must have at least one cell a side"
+        );
+        let (heightmap, spacing) = fit_heightmap(heightmap, spacing, settings.chunk_resolution);
+        let cells = heightmap.width - 1;
+
+        let mut lod = TerrainLod {
+            heightmap,
+            spacing,
+            origin,
+            settings,
+            color: color.clone(),
+            biomes: None,
+            nodes: vec![],
+            selected: vec![],
+            meshes: HashMap::new(),
+        };
+
+        let root = GridRegion {
+            x: 0,
+            z: 0,
+            cells_x: cells,
+            cells_z: cells,
+        };
+        lod.build_node(root);
+        lod.bound_neighbour_errors();
+        lod.selected.push(0);
+        lod
+    }
+
+    // children are built before the parent's error is known, the parent is pushed first
+    // so the root is always node 0
+    fn build_node(&mut self, region: GridRegion) -> usize {
+        let index = self.nodes.len();
+        let step = region.cells_x / self.settings.chunk_resolution;
+        let (min_height, max_height) = self.height_range(&region);
+
+        self.nodes.push(LodNode {
+            region,
+            step,
+            min_height,
+            max_height,
+            error: 0.0,
+            neighbour_error: 0.0,
+            children: None,
+        });
+
+        let mut error = self.decimation_error(&region, step);
+
+        if step > 1 {
+            let half = region.cells_x / 2;
+            let mut children = [0; 4];
+            for (i, (dx, dz)) in [(0, 0), (half, 0), (0, half), (half, half)]
+                .iter()
+                .enumerate()
+            {
+                let child = GridRegion {
+                    x: region.x + dx,
+                    z: region.z + dz,
+                    cells_x: half,
+                    cells_z: half,
+                };
+                children[i] = self.build_node(child);
+                // a parent is never more accurate than its children
+                error = error.max(self.nodes[children[i]].error);
+            }
+            self.nodes[index].children = Some(children);
+        }
+
+        self.nodes[index].error = error;
+        if index == 0 {
+            self.nodes[index].neighbour_error = error;
+        }
+        index
+    }
+
+    // once selections are restricted, a node's neighbours are children of its parent or of
+    // the parent's neighbours, or those nodes themselves. a parent is never more accurate
+    // than its children, so the largest error among them bounds every neighbour
+    fn bound_neighbour_errors(&mut self) {
+        let by_region: HashMap<(usize, usize, usize), usize> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, n)| ((n.region.cells_x, n.region.x, n.region.z), i))
+            .collect();
+
+        for parent in 0..self.nodes.len() {
+            let Some(children) = self.nodes[parent].children else {
+                continue;
+            };
+            let r = self.nodes[parent].region;
+            let size = r.cells_x;
+            let sides = [
+                r.x.checked_sub(size).map(|x| (x, r.z)),
+                Some((r.x + size, r.z)),
+                r.z.checked_sub(size).map(|z| (r.x, z)),
+                Some((r.x, r.z + size)),
+            ];
+
+            let mut error = self.nodes[parent].error;
+            for (x, z) in sides.into_iter().flatten() {
+                if let Some(&side) = by_region.get(&(size, x, z)) {
+                    error = error.max(self.nodes[side].error);
+                }
+            }
+            for child in children {
+                self.nodes[child].neighbour_error = error;
+            }
+        }
+    }
+
+    fn height_range(&self, region: &GridRegion) -> (f64, f64) {
+        let mut range = (f64::MAX, f64::MIN);
+        for z in region.z..=region.z + region.cells_z {
+            for x in region.x..=region.x + region.cells_x {
+                let h = self.heightmap.get(x, z);
+                range = (range.0.min(h), range.1.max(h));
+            }
+        }
+        range
+    }
+
+    // how far the full resolution surface strays from the one made of every `step`th point
+    fn decimation_error(&self, region: &GridRegion, step: usize) -> f64 {
+        if step <= 1 {
+            return 0.0;
+        }
+
+        let mut error: f64 = 0.0;
+        for z in region.z..=region.z + region.cells_z {
+            for x in region.x..=region.x + region.cells_x {
+                let coarse = self.heightmap.sample_coarse(x, z, step);
+                error = error.max((self.heightmap.get(x, z) - coarse).abs());
+            }
+        }
+        error
+    }
+
+    /// Picks the chunks to draw for a camera at `camera_position`, splitting any chunk whose
+    /// error would cover more than `max_screen_error` pixels from there.
+    pub fn update(&mut self, camera_position: &Vector3D) {
+        self.selected.clear();
+        let mut stack = vec![0];
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let distance = self.distance_to_node(node, camera_position).max(1e-3);
+            let screen_error = node.error * self.settings.projection_scale / distance;
+
+            match node.children {
+                Some(children) if screen_error > self.settings.max_screen_error => {
+                    stack.extend(children)
+                }
+                _ => self.selected.push(index),
+            }
+        }
+
+        self.restrict();
+    }
+
+    // splits selected nodes more than a level coarser than a selected neighbour, until
+    // every pair of neighbours is within a level of each other
+    fn restrict(&mut self) {
+        let mut selected = vec![false; self.nodes.len()];
+        for &index in &self.selected {
+            selected[index] = true;
+        }
+
+        let cells = self.heightmap.width - 1;
+        let mut queue = self.selected.clone();
+        while let Some(index) = queue.pop() {
+            if !selected[index] {
+                continue;
+            }
+
+            // a coarser neighbour is bigger than this node, so any cell along the side
+            // finds it
+            let r = self.nodes[index].region;
+            let sides = [
+                r.x.checked_sub(1).map(|x| (x, r.z)),
+                Some((r.x + r.cells_x, r.z)).filter(|&(x, _)| x < cells),
+                r.z.checked_sub(1).map(|z| (r.x, z)),
+                Some((r.x, r.z + r.cells_z)).filter(|&(_, z)| z < cells),
+            ];
+
+            for (x, z) in sides.into_iter().flatten() {
+                let neighbour = self.selected_at(x, z, &selected);
+                if self.nodes[neighbour].step <= 2 * self.nodes[index].step {
+                    continue;
+                }
+                if let Some(children) = self.nodes[neighbour].children {
+                    selected[neighbour] = false;
+                    for child in children {
+                        selected[child] = true;
+                        queue.push(child);
+                    }
+                    // the child now beside this node may still be too coarse
+                    queue.push(index);
+                    break;
+                }
+            }
+        }
+
+        self.selected = (0..self.nodes.len()).filter(|&i| selected[i]).collect();
+    }
+
+    // the selected node holding grid cell (`x`, `z`)
+    fn selected_at(&self, x: usize, z: usize, selected: &[bool]) -> usize {
+        let mut index = 0;
+        while !selected[index] {
+            let node = &self.nodes[index];
+            let Some(children) = node.children else {
+                break;
+            };
+            let half = node.region.cells_x / 2;
+            let east = x >= node.region.x + half;
+            let south = z >= node.region.z + half;
+            index = children[east as usize + 2 * south as usize];
+        }
+        index
+    }
+
+    fn distance_to_node(&self, node: &LodNode, point: &Vector3D) -> f64 {
+        let r = &node.region;
+        let min = Vector3D::new(
+            self.origin.x + r.x as f64 * self.spacing,
+            self.origin.y + node.min_height,
+            self.origin.z + r.z as f64 * self.spacing,
+        );
+        let max = Vector3D::new(
+            self.origin.x + (r.x + r.cells_x) as f64 * self.spacing,
+            self.origin.y + node.max_height,
+            self.origin.z + (r.z + r.cells_z) as f64 * self.spacing,
+        );
+
+        let dx = (min.x - point.x).max(point.x - max.x).max(0.0);
+        let dy = (min.y - point.y).max(point.y - max.y).max(0.0);
+        let dz = (min.z - point.z).max(point.z - max.z).max(0.0);
+        (dx * dx + dy * dy + dz * dz).sqrt()
+    }
+
+    /// World height of the full resolution surface at world `x`, `z`, None off the map.
+    pub fn height_at(&self, x: f64, z: f64) -> Option<f64> {
+        let grid_x = (x - self.origin.x) / self.spacing;
+        let grid_z = (z - self.origin.z) / self.spacing;
+        let last = (self.heightmap.width - 1) as f64;
+
+        if !(0.0..=last).contains(&grid_x) || !(0.0..=last).contains(&grid_z) {
+            return None;
+        }
+        Some(self.origin.y + self.heightmap.sample(grid_x, grid_z))
+    }
+
+    /// Colors chunks by height and slope instead of the flat `color`, heights being
+    /// relative to `origin`. Chunks already built are rebuilt when next drawn.
+    pub fn set_biomes(&mut self, biomes: Option<BiomeTable>) {
+        self.biomes = biomes;
+        self.meshes.clear();
+    }
+
+    /// Number of chunks picked by the last `update`.
+    pub fn selected_count(&self) -> usize {
+        self.selected.len()
+    }
+
+    /// World space meshes of the chunks picked by the last `update`, building any that
+    /// haven't been needed before.
+    pub fn selected_meshes(&mut self) -> Vec<&Mesh> {
+        for index in self.selected.clone() {
+            if !self.meshes.contains_key(&index) {
+                let mesh = self.build_chunk_mesh(&self.nodes[index]);
+                self.meshes.insert(index, mesh);
+            }
+        }
+
+        self.selected.iter().map(|i| &self.meshes[i]).collect()
+    }
+
+    fn build_chunk_mesh(&self, node: &LodNode) -> Mesh {
+        let mut mesh =
+            self.heightmap
+                .region_mesh(&node.region, node.step, self.spacing, &self.color);
+        mesh.triangles.extend(self.skirt(node));
+        if let Some(biomes) = &self.biomes {
+            mesh = biomes.paint(&mesh);
+        }
+        mesh.translate(&self.origin)
+    }
+
+    fn skirt(&self, node: &LodNode) -> Vec<Triangle> {
+        let r = &node.region;
+        let (x1, z1) = (r.x + r.cells_x, r.z + r.cells_z);
+        let center = Vector3D::new(
+            (r.x + x1) as f64 / 2.0 * self.spacing,
+            0.0,
+            (r.z + z1) as f64 / 2.0 * self.spacing,
+        );
+
+        let mut edge_points = vec![];
+        for x in (r.x..x1).step_by(node.step) {
+            edge_points.push((x, r.z));
+        }
+        for z in (r.z..z1).step_by(node.step) {
+            edge_points.push((x1, z));
+        }
+        for x in (r.x + node.step..=x1).rev().step_by(node.step) {
+            edge_points.push((x, z1));
+        }
+        for z in (r.z + node.step..=z1).rev().step_by(node.step) {
+            edge_points.push((r.x, z));
+        }
+
+        let top = |(x, z): (usize, usize)| {
+            Vector3D::new(
+                x as f64 * self.spacing,
+                self.heightmap.get(x, z),
+                z as f64 * self.spacing,
+            )
+        };
+        let drop = Vector3D::new(0.0, -self.skirt_depth(node), 0.0);
+
+        let mut triangles = vec![];
+        for i in 0..edge_points.len() {
+            let p = top(edge_points[i]);
+            let q = top(edge_points[(i + 1) % edge_points.len()]);
+            let (p_low, q_low) = (p.add(&drop), q.add(&drop));
+
+            // skirts face away from the chunk's center
+            let outward = p.add(&q).scale(0.5).sub(&center);
+            for (a, b, c) in [(p, q, q_low), (p, q_low, p_low)] {
+                let triangle = Triangle::new(a, b, c, &self.color);
+                let facing = triangle.face_normal();
+                if facing.x * outward.x + facing.z * outward.z < 0.0 {
+                    triangles.push(Triangle::new(a, c, b, &self.color));
+                } else {
+                    triangles.push(triangle);
+                }
+            }
+        }
+
+        triangles
+    }
+
+    // this chunk's edge strays from the full resolution surface by at most its error, and a
+    // neighbour's by at most `neighbour_error`, so the crack between them is no deeper
+    fn skirt_depth(&self, node: &LodNode) -> f64 {
+        node.error + node.neighbour_error + self.settings.skirt_depth
+    }
+}
+
+// resamples to the smallest square of `chunk * 2^n + 1` points covering the map, returning
+// the spacing that keeps its longer side the same length
+fn fit_heightmap(heightmap: Heightmap, spacing: f64, chunk: usize) -> (Heightmap, f64) {
+    let cells = (heightmap.width - 1).max(heightmap.depth - 1);
+    let side = chunk * cells.div_ceil(chunk).max(1).next_power_of_two();
+    if heightmap.width == heightmap.depth && cells == side {
+        return (heightmap, spacing);
+    }
+
+    let scale = if cells > 0 {
+        cells as f64 / side as f64
+    } else {
+        1.0
+    };
+    let mut fitted = Heightmap::new(side + 1, side + 1);
+    for z in 0..=side {
+        for x in 0..=side {
+            fitted.set(x, z, heightmap.sample(x as f64 * scale, z as f64 * scale));
+        }
+    }
+    (fitted, spacing * scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lod(size: usize, chunk: usize) -> TerrainLod {
+        let mut heightmap = Heightmap::new(size, size);
+        for z in 0..size {
+            for x in 0..size {
+                heightmap.set(x, z, ((x * 7 + z * 13) % 5) as f64);
+            }
+        }
+        let settings = LodSettings::new(chunk, 720, 90.0);
+        TerrainLod::new(
+            heightmap,
+            1.0,
+            Vector3D::new(0.0, 0.0, 0.0),
+            settings,
+            &Color::new(255, 255, 255, 255),
+        )
+    }
+
+    fn lowest_skirt_point(lod: &TerrainLod, index: usize) -> f64 {
+        lod.skirt(&lod.nodes[index])
+            .iter()
+            .flat_map(|t| t.vertices.iter().map(|v| v.y))
+            .fold(f64::MAX, f64::min)
+    }
+
+    // grid points along the edge two selected nodes share, empty if they don't touch
+    fn shared_edge(a: &GridRegion, b: &GridRegion) -> Vec<(usize, usize)> {
+        let overlap = |a0: usize, a1: usize, b0: usize, b1: usize| a0.max(b0)..=a1.min(b1);
+        let (ax1, az1) = (a.x + a.cells_x, a.z + a.cells_z);
+        let (bx1, bz1) = (b.x + b.cells_x, b.z + b.cells_z);
+
+        if ax1 == b.x || bx1 == a.x {
+            let x = if ax1 == b.x { ax1 } else { a.x };
+            overlap(a.z, az1, b.z, bz1).map(|z| (x, z)).collect()
+        } else if az1 == b.z || bz1 == a.z {
+            let z = if az1 == b.z { az1 } else { a.z };
+            overlap(a.x, ax1, b.x, bx1).map(|x| (x, z)).collect()
+        } else {
+            vec![]
+        }
+    }
+
+    #[test]
+    fn coarser_chunks_hang_longer_skirts() {
+        let lod = lod(17, 4);
+        let root = &lod.nodes[0];
+        assert!(root.error > 0.0);
+        assert_eq!(root.neighbour_error, root.error);
+        let leaf = lod.nodes.iter().position(|n| n.step == 1).unwrap();
+        assert_eq!(lod.nodes[leaf].error, 0.0);
+
+        assert!(lod.skirt_depth(root) > lod.skirt_depth(&lod.nodes[leaf]));
+        // every height is between 0 and 4, so skirts end up to 4 above their drop
+        for index in [0, leaf] {
+            let drop = lod.skirt_depth(&lod.nodes[index]);
+            let lowest = lowest_skirt_point(&lod, index);
+            assert!(lowest >= -drop && lowest <= 4.0 - drop);
+        }
+    }
+
+    #[test]
+    fn skirts_cover_the_gaps_between_selected_chunks() {
+        let mut lod = lod(65, 4);
+        lod.settings.max_screen_error = 150.0;
+        lod.update(&Vector3D::new(0.0, 1.0, 0.0));
+
+        let selected = lod.selected.clone();
+        let steps: Vec<usize> = selected.iter().map(|&i| lod.nodes[i].step).collect();
+        // without the restriction pass an 8 step chunk would sit beside a 2 step one
+        assert!(steps.contains(&1) && steps.contains(&8));
+
+        let mut shared = 0;
+        for &a in &selected {
+            for &b in &selected {
+                let (na, nb) = (&lod.nodes[a], &lod.nodes[b]);
+                let edge = shared_edge(&na.region, &nb.region);
+                if a == b || edge.len() < 2 {
+                    continue;
+                }
+                shared += 1;
+                assert!(na.step <= 2 * nb.step && nb.step <= 2 * na.step);
+
+                // whichever chunk is higher hangs its skirt over the gap
+                for (x, z) in edge {
+                    let ha = lod.heightmap.sample_coarse(x, z, na.step);
+                    let hb = lod.heightmap.sample_coarse(x, z, nb.step);
+                    let gap = (ha - hb).abs();
+                    let depth = if ha > hb {
+                        lod.skirt_depth(na)
+                    } else {
+                        lod.skirt_depth(nb)
+                    };
+                    assert!(gap <= depth - lod.settings.skirt_depth + 1e-9);
+                }
+            }
+        }
+        assert!(shared > 0);
+    }
+
+    #[test]
+    fn odd_sized_heightmaps_are_resampled() {
+        let mut heightmap = Heightmap::new(10, 7);
+        heightmap.set(9, 0, 3.0);
+        let lod = TerrainLod::new(
+            heightmap,
+            2.0,
+            Vector3D::new(0.0, 0.0, 0.0),
+            LodSettings::new(4, 720, 90.0),
+            &Color::new(255, 255, 255, 255),
+        );
+
+        assert_eq!((lod.heightmap.width, lod.heightmap.depth), (17, 17));
+        // the longer side still covers 9 cells of 2 units
+        assert_eq!(lod.height_at(18.0, 0.0), Some(3.0));
+        assert_eq!(lod.height_at(0.0, 18.0), Some(0.0));
+        assert_eq!(lod.height_at(18.1, 0.0), None);
+    }
+}
@@ -1,12 +1,30 @@
-use linalg::{Matrix4D, Vector2D, Vector3D};
-use minifb::{Key, MouseButton, MouseMode, Window, WindowOptions};
-use renderer::{Camera, Color, FrameBuffer, Input, Mesh, Object, Renderer, Scene, Triangle};
-use zbuf::get_depth_func;
-
-mod linalg;
-pub mod renderer;
-pub mod vector;
-pub mod zbuf;
+use atlas::animation::{
+    AnimationClip, AnimationPlayer, Channel, Interpolation, PlaybackMode, Track, Transform,
+};
+use atlas::biome::BiomeTable;
+use atlas::bvh::SceneBvh;
+use atlas::denoise::AtrousDenoiser;
+use atlas::gltf::{Gltf, GltfError};
+use atlas::hotreload::SceneWatcher;
+use atlas::hud::Hud;
+use atlas::linalg::{Matrix4D, Quaternion, Vector2D, Vector3D};
+use atlas::line::{Line3D, LineStyle};
+use atlas::lod::{LodSettings, TerrainLod};
+use atlas::noise::{FractalKind, FractalNoise};
+use atlas::particles::ParticleEmitter;
+use atlas::pathtracer::{PathTracer, PathTracerSettings};
+use atlas::postprocess::PostProcessChain;
+use atlas::raytracer::{RayTracer, RayTracerSettings};
+use atlas::renderer::{
+    Color, FrameBuffer, Input, Mesh, MeshSource, Object, RenderMode, Renderer, Scene, Triangle,
+};
+use atlas::skinning::{Joint, Skeleton, SkinningMethod};
+use atlas::streaming::{StreamingSettings, StreamingTerrain};
+use atlas::terrain::Heightmap;
+use atlas::walk::{Ground, WalkController};
+use atlas::water::Water;
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window, WindowOptions};
+use std::f64::consts::PI;
 
 fn geometric_to_screen(vec: &Vector3D, width: usize, height: usize) -> Vector2D {
     let x_screen = (vec.x + 1.0) * (width as f64) / 2.0;
@@ -36,31 +54,163 @@ fn screen_to_geo(x: i32, y: i32, width: usize, height: usize) -> Vector2D {
     Vector2D { x: x_geo, y: y_geo }
 }
 
-fn get_z_rotation_matrix(theta: f64) -> Matrix4D {
-    Matrix4D::new([
-        [theta.cos(), theta.sin(), 0.0, 0.0],
-        [-theta.sin(), theta.cos(), 0.0, 0.0],
-        [0.0, 0.0, 1.0, 0.0],
-        [0.0, 0.0, 0.0, 1.0],
-    ])
+// index of the first object made from the named primitive
+fn find_primitive(scene: &Scene, name: &str) -> Option<usize> {
+    scene.objects.iter().position(|object| {
+        matches!(&object.source, MeshSource::Primitive { primitive, .. } if primitive.name() == name)
+    })
 }
 
-fn get_x_rotation_matrix(theta: f64) -> Matrix4D {
-    Matrix4D::new([
-        [1.0, 0.0, 0.0, 0.0],
-        [0.0, theta.cos(), theta.sin(), 0.0],
-        [0.0, -theta.sin(), theta.cos(), 0.0],
-        [0.0, 0.0, 0.0, 1.0],
-    ])
+// a few of the showcase primitives moving, found by shape so editing the scene file
+// doesn't send the animations to the wrong objects
+fn demo_animations(scene: &Scene) -> Vec<AnimationPlayer> {
+    let mut players = vec![];
+    let up = Vector3D::new(0.0, 1.0, 0.0);
+
+    if let Some(torus) = find_primitive(scene, "torus") {
+        let spin = (0..=3).fold(Track::new(Interpolation::Linear), |track, i| {
+            let angle = i as f64 * 2.0 * PI / 3.0;
+            track.with_key(i as f64, Quaternion::from_axis_angle(&up, angle))
+        });
+        let clip = AnimationClip::new("spin")
+            .with_channel(Channel::for_object(scene, torus).with_rotation(spin));
+        players.push(AnimationPlayer::new(clip, PlaybackMode::Loop));
+    }
+
+    if let Some(capsule) = find_primitive(scene, "capsule") {
+        let channel = Channel::for_object(scene, capsule);
+        let rest = channel.rest.translation;
+        let bounce = Track::new(Interpolation::CubicSpline)
+            .with_key(0.0, rest)
+            .with_key(0.8, rest.add(&up.scale(1.5)));
+        let clip = AnimationClip::new("bounce").with_channel(channel.with_translation(bounce));
+        players.push(AnimationPlayer::new(clip, PlaybackMode::PingPong));
+    }
+
+    if let Some(cone) = find_primitive(scene, "cone") {
+        let pulse = Track::new(Interpolation::Step)
+            .with_key(0.0, Vector3D::new(1.0, 1.0, 1.0))
+            .with_key(0.5, Vector3D::new(1.2, 0.8, 1.2))
+            .with_key(1.0, Vector3D::new(1.0, 1.0, 1.0));
+        let clip = AnimationClip::new("pulse")
+            .with_channel(Channel::for_object(scene, cone).with_scale(pulse));
+        players.push(AnimationPlayer::new(clip, PlaybackMode::Loop));
+    }
+
+    players
 }
 
-fn get_y_rotation_matrix(theta: f64) -> Matrix4D {
-    Matrix4D::new([
-        [theta.cos(), 0.0, -theta.sin(), 0.0],
-        [0.0, 1.0, 0.0, 0.0],
-        [theta.sin(), 0.0, theta.cos(), 0.0],
-        [0.0, 0.0, 0.0, 1.0],
-    ])
+// a tentacle of stacked cylinders skinned to a chain of three joints, added after the
+// scene file is loaded. returns its object index and a clip curling it back and forth
+fn demo_tentacle(scene: &mut Scene, method: SkinningMethod) -> (usize, AnimationPlayer) {
+    let joint_spacing = 1.0;
+    let segments = 8;
+    let segment_height = 2.0 * joint_spacing / segments as f64;
+    let color = Color::new(200, 90, 140, 255);
+
+    let mut mesh = Mesh { triangles: vec![] };
+    for i in 0..segments {
+        let center = (i as f64 + 0.5) * segment_height;
+        for t in Mesh::cylinder(0.25, segment_height, 12, &color).triangles {
+            let vertices: Vec<Vector3D> = t
+                .vertices
+                .iter()
+                .map(|v| Vector3D::new(v.x, v.y + center, v.z))
+                .collect();
+            // each vertex follows the two joints either side of it, by how close it is
+            let weights: Vec<[f64; 4]> = vertices
+                .iter()
+                .map(|v| {
+                    let along = (v.y / joint_spacing).clamp(0.0, 2.0);
+                    let weight = |joint: f64| (1.0 - (along - joint).abs()).max(0.0);
+                    [weight(0.0), weight(1.0), weight(2.0), 0.0]
+                })
+                .collect();
+            let triangle = Triangle { vertices, ..t }
+                .with_skin_weights([[0, 1, 2, 0]; 3], [weights[0], weights[1], weights[2]]);
+            mesh.triangles.push(triangle);
+        }
+    }
+
+    let rest = |y: f64| Transform {
+        translation: Vector3D::new(0.0, y, 0.0),
+        ..Transform::default()
+    };
+    let mut skeleton = Skeleton::new()
+        .with_joint(Joint::new("base", None, rest(0.0)))
+        .with_joint(Joint::new("middle", Some(0), rest(joint_spacing)))
+        .with_joint(Joint::new("tip", Some(1), rest(joint_spacing)));
+    skeleton.bind_rest_pose();
+
+    let bend = Vector3D::new(0.0, 0.0, 1.0);
+    let mut clip = AnimationClip::new("curl");
+    for joint in 1..3 {
+        let curl = Track::new(Interpolation::CubicSpline)
+            .with_key(0.0, Quaternion::from_axis_angle(&bend, -PI / 4.0))
+            .with_key(1.5, Quaternion::from_axis_angle(&bend, PI / 4.0));
+        clip =
+            clip.with_channel(Channel::new(joint, skeleton.joints[joint].rest).with_rotation(curl));
+    }
+
+    let transform = Matrix4D::new_translation(&Vector3D::new(6.0, 0.0, 6.0));
+    scene
+        .objects
+        .push(Object::new(mesh, transform).with_skin(skeleton, method));
+    (
+        scene.objects.len() - 1,
+        AnimationPlayer::new(clip, PlaybackMode::PingPong),
+    )
+}
+
+// a gltf named on the command line, its objects copied in after the scene file's and
+// moved by its first animation
+struct Imported {
+    gltf: Gltf,
+    player: Option<AnimationPlayer>,
+    first: usize,
+}
+
+impl Imported {
+    fn load(path: &str) -> Result<Imported, GltfError> {
+        let gltf = Gltf::load(path)?;
+        let player = gltf
+            .animations
+            .first()
+            .map(|clip| AnimationPlayer::new(clip.clone(), PlaybackMode::Loop));
+        Ok(Imported {
+            gltf,
+            player,
+            first: 0,
+        })
+    }
+
+    fn add_to(&mut self, scene: &mut Scene) {
+        self.first = scene.objects.len();
+        scene
+            .objects
+            .extend(self.gltf.scene.objects.iter().cloned());
+    }
+
+    fn update(&mut self, scene: &mut Scene, bvh: &mut SceneBvh, delta_time: f64) {
+        let player = match &mut self.player {
+            Some(player) => player,
+            None => return,
+        };
+        player.update(delta_time);
+        self.gltf.apply(&player.clip, player.time());
+
+        for (i, object) in self.gltf.scene.objects.iter().enumerate() {
+            let index = self.first + i;
+            scene.objects[index].transform = object.transform;
+            if object.skin.is_some() {
+                scene.objects[index].mesh = object.mesh.clone();
+                scene.objects[index].update_bounds();
+                bvh.rebuild_mesh(scene, index);
+            } else {
+                bvh.refit_object(scene, index);
+            }
+        }
+    }
 }
 
 const WIDTH: usize = 1280;
@@ -80,123 +230,72 @@ fn main() {
     let screen_coords2 = geometric_to_screen(&intermediate, WIDTH, HEIGHT);
     println!("{}, {}", screen_coords2.x, screen_coords2.y);
 
-    let white = Color {
-        r: 255,
-        g: 255,
-        b: 255,
-        a: 255,
-    };
-
-    let gray = Color::new(128, 128, 128, 255);
-
-    let cube_mesh: Mesh = Mesh {
-        triangles: vec![
-            // south
-            Triangle::new(
-                Vector3D::new(0.0, 0.0, 0.0),
-                Vector3D::new(0.0, 1.0, 0.0),
-                Vector3D::new(1.0, 1.0, 0.0),
-                &gray,
-            ),
-            Triangle::new(
-                Vector3D::new(0.0, 0.0, 0.0),
-                Vector3D::new(1.0, 1.0, 0.0),
-                Vector3D::new(1.0, 0.0, 0.0),
-                &gray,
-            ),
-            // east
-            Triangle::new(
-                Vector3D::new(1.0, 0.0, 0.0),
-                Vector3D::new(1.0, 1.0, 0.0),
-                Vector3D::new(1.0, 1.0, 1.0),
-                &gray,
-            ),
-            Triangle::new(
-                Vector3D::new(1.0, 0.0, 0.0),
-                Vector3D::new(1.0, 1.0, 1.0),
-                Vector3D::new(1.0, 0.0, 1.0),
-                &gray,
-            ),
-            // north
-            Triangle::new(
-                Vector3D::new(1.0, 0.0, 1.0),
-                Vector3D::new(1.0, 1.0, 1.0),
-                Vector3D::new(0.0, 1.0, 1.0),
-                &gray,
-            ),
-            Triangle::new(
-                Vector3D::new(1.0, 0.0, 1.0),
-                Vector3D::new(0.0, 1.0, 1.0),
-                Vector3D::new(0.0, 0.0, 1.0),
-                &gray,
-            ),
-            // west
-            Triangle::new(
-                Vector3D::new(0.0, 0.0, 1.0),
-                Vector3D::new(0.0, 1.0, 1.0),
-                Vector3D::new(0.0, 1.0, 0.0),
-                &gray,
-            ),
-            Triangle::new(
-                Vector3D::new(0.0, 0.0, 1.0),
-                Vector3D::new(0.0, 1.0, 0.0),
-                Vector3D::new(0.0, 0.0, 0.0),
-                &gray,
-            ),
-            // top
-            Triangle::new(
-                Vector3D::new(0.0, 1.0, 0.0),
-                Vector3D::new(0.0, 1.0, 1.0),
-                Vector3D::new(1.0, 1.0, 1.0),
-                &white,
-            ),
-            Triangle::new(
-                Vector3D::new(0.0, 1.0, 0.0),
-                Vector3D::new(1.0, 1.0, 1.0),
-                Vector3D::new(1.0, 1.0, 0.0),
-                &white,
-            ),
-            // bottom
-            Triangle::new(
-                Vector3D::new(1.0, 0.0, 1.0),
-                Vector3D::new(0.0, 0.0, 1.0),
-                Vector3D::new(0.0, 0.0, 0.0),
-                &white,
-            ),
-            Triangle::new(
-                Vector3D::new(1.0, 0.0, 1.0),
-                Vector3D::new(0.0, 0.0, 0.0),
-                Vector3D::new(1.0, 0.0, 0.0),
-                &white,
-            ),
-        ],
-    };
+    let scene_path = concat!(env!("CARGO_MANIFEST_DIR"), "/scenes/demo.scene");
+    // the scene and the files it uses are reloaded whenever they are saved
+    let (mut scene_watcher, mut scene) = SceneWatcher::load(scene_path).unwrap_or_else(|e| {
+        panic!("{}: {}", scene_path, e);
+    });
+    // K switches the tentacle between linear blend and dual quaternion skinning
+    let mut skinning_method = SkinningMethod::Linear;
+    let (mut tentacle, mut tentacle_player) = demo_tentacle(&mut scene, skinning_method);
+    // `cargo run -- model.glb` shows a gltf or glb alongside the demo scene
+    let mut imported = std::env::args().nth(1).map(|path| {
+        Imported::load(&path).unwrap_or_else(|e| {
+            eprintln!("couldn't load {}: {}", path, e);
+            std::process::exit(1);
+        })
+    });
+    if let Some(imported) = &mut imported {
+        imported.add_to(&mut scene);
+    }
+    let mut scene_bvh = SceneBvh::build(&scene);
+
+    let mut terrain_noise = FractalNoise::new(42);
+    terrain_noise.kind = FractalKind::Ridged;
+    let terrain_spacing = 2.0;
+    let terrain_map = Heightmap::from_noise(
+        &terrain_noise,
+        129,
+        129,
+        terrain_spacing,
+        &Vector2D::new(0.0, 0.0),
+        12.0,
+    );
 
-    let f_near = 0.1;
-    let f_far = 1000.0;
-    let f_fov = 90.0;
+    let camera_setup = scene.camera.unwrap_or_default();
+    let f_near = camera_setup.near;
+    let f_far = camera_setup.far;
+    let f_fov = camera_setup.fov;
     let f_aspect_ratio = HEIGHT as f64 / WIDTH as f64;
 
-    let mut cam = Camera {
-        position: Vector3D::new(0.0, 0.0, 0.0),
-        front: Vector3D::new(0.0, 0.0, -1.0),
-        up: Vector3D::new(0.0, 1.0, 0.0),
-        yaw: 0.0,
-        pitch: 0.0,
-        near_clip: f_near,
-        far_clip: f_far,
-        aspect_ratio: f_aspect_ratio,
+    let cam = camera_setup.camera(f_aspect_ratio);
+
+    let mut terrain = TerrainLod::new(
+        terrain_map,
+        terrain_spacing,
+        Vector3D::new(-128.0, -14.0, 10.0),
+        LodSettings::new(16, HEIGHT, f_fov),
+        &Color::new(90, 110, 80, 255),
+    );
+    // sand runs a little above the water line
+    let biomes = BiomeTable::terrain(-9.0, 12.0);
+    terrain.set_biomes(Some(biomes.clone()));
+
+    // T switches between the finite lod terrain and endless streamed terrain
+    let streaming_settings = StreamingSettings {
+        biomes: Some(biomes),
+        ..StreamingSettings::default()
     };
+    let mut streaming = StreamingTerrain::new(terrain_noise.clone(), streaming_settings);
+    let mut use_streaming = false;
 
     let proj_mat = cam.get_proj_matrix(f_aspect_ratio, f_fov, f_near, f_far);
     let inv_proj_mat = cam.get_inverse_proj_matrix(f_aspect_ratio, f_fov, f_near, f_far);
 
     let mut frame_buffer = FrameBuffer::new(WIDTH, HEIGHT);
+    frame_buffer.clear_color = scene.background.clone();
 
-    let mut renderer = Renderer {
-        camera: cam,
-        framebuffer: frame_buffer,
-    };
+    let mut renderer = Renderer::new(cam, frame_buffer);
 
     let mut window =
         Window::new("ATLAS", WIDTH, HEIGHT, WindowOptions::default()).unwrap_or_else(|e| {
@@ -205,14 +304,37 @@ fn main() {
 
     window.set_target_fps(60);
 
-    let white = Color {
-        r: 255,
-        g: 255,
-        b: 255,
-        a: 255,
-    };
+    let mut post_chain = PostProcessChain::with_builtin_passes();
+    let post_keys = [Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6];
+
+    let mut smoke = ParticleEmitter::smoke(Vector3D::new(0.5, 1.0, 3.5), 1);
+    let mut sparks = ParticleEmitter::sparks(Vector3D::new(2.5, 1.0, 3.5), 2);
+
+    let mut hud = Hud::new();
+
+    // O shows and hides the water
+    let mut water = Water::new(-17.0, 200.0, WIDTH / 2, HEIGHT / 2);
+    let mut show_water = true;
 
-    let mut theta: f64 = 0.0;
+    // G switches between flying and walking on the terrain
+    let mut walker = WalkController::new();
+
+    // left click selects the object under the mouse
+    let mut selected: Option<(usize, String)> = None;
+    let mut mouse_was_down = false;
+
+    // R ray traces the current view and shows it over the right half of the screen
+    let ray_tracer = RayTracer::new(RayTracerSettings::default());
+    let mut traced: Option<FrameBuffer> = None;
+
+    // P replaces rasterizing with a path traced image that refines while the camera is still
+    let mut path_tracer = PathTracer::new(PathTracerSettings::default());
+    let mut path_tracing = false;
+    // N smooths the path traced image with the guided denoiser
+    let denoiser = AtrousDenoiser::new();
+    let mut denoise = true;
+
+    let mut animations = demo_animations(&scene);
 
     let mut prev_mouse_x = 0.0;
     let mut prev_mouse_y = 0.0;
@@ -220,6 +342,9 @@ fn main() {
     let mut has_initialized_mouse_pos = false;
 
     while window.is_open() && !window.is_key_down(Key::Escape) {
+        // capped so a long stall, like the first frame after loading, doesn't launch the
+        // walker through the ground
+        let dt = hud.tick().min(0.1);
         let mut input = Input {
             backward: false,
             forward: false,
@@ -248,6 +373,24 @@ fn main() {
             prev_mouse_y = y;
         }
 
+        let mouse_down = window.get_mouse_down(MouseButton::Left);
+        if mouse_down && !mouse_was_down {
+            if let Some((x, y)) = window.get_mouse_pos(MouseMode::Discard) {
+                let ray =
+                    renderer
+                        .camera
+                        .screen_ray(x as f64, y as f64, WIDTH, HEIGHT, &inv_proj_mat);
+                selected = scene_bvh.pick(&scene, &ray).map(|pick| {
+                    let info = format!(
+                        "selected {} triangle {} at {:.2}",
+                        pick.index, pick.triangle, pick.distance
+                    );
+                    (pick.index, info)
+                });
+            }
+        }
+        mouse_was_down = mouse_down;
+
         if window.is_key_down(Key::A) {
             input.left = true;
         }
@@ -269,90 +412,265 @@ fn main() {
             input.down = true;
         }
 
-        renderer.camera.update(&input, 0.01);
-
-        renderer.framebuffer.clear();
-        theta += 0.03;
-
-        let mat_rot_z = Matrix4D::new([
-            [theta.cos(), theta.sin(), 0.0, 0.0],
-            [-theta.sin(), theta.cos(), 0.0, 0.0],
-            [0.0, 0.0, 1.0, 0.0],
-            [0.0, 0.0, 0.0, 1.0],
-        ]);
-
-        let mat_rot_x = Matrix4D::new([
-            [1.0, 0.0, 0.0, 0.0],
-            [0.0, (0.5 * theta).cos(), (0.5 * theta).sin(), 0.0],
-            [0.0, -(0.5 * theta).sin(), (theta * 0.5).cos(), 0.0],
-            [0.0, 0.0, 0.0, 1.0],
-        ]);
-
-        let view_matrix = renderer.camera.create_view_matrix();
-
-        for i in 0..3 {
-            let mut view_triangles = cube_mesh
-                // .apply_transformation(&mat_rot_z)
-                // .apply_transformation(&mat_rot_x)
-                // .apply_transformation(&get_x_rotation_matrix(cam_x_theta))
-                // .apply_transformation(&get_y_rotation_matrix(cam_y_theta))
-                .translate(&Vector3D {
-                    x: i as f64,
-                    y: 0.0,
-                    z: 3.0,
-                })
-                .translate(&renderer.camera.position.scale(-1.0))
-                .apply_transformation(&view_matrix);
+        for (i, key) in post_keys.iter().enumerate() {
+            if window.is_key_pressed(*key, KeyRepeat::No) {
+                post_chain.toggle(i);
+            }
+        }
 
-                view_triangles.sort_triangles();
-                view_triangles.triangles.reverse();
+        if window.is_key_pressed(Key::H, KeyRepeat::No) {
+            hud.visible = !hud.visible;
+        }
 
-                let proj_2d = view_triangles.apply_transformation_with_perspective_div(&proj_mat);
+        if window.is_key_pressed(Key::O, KeyRepeat::No) {
+            show_water = !show_water;
+        }
 
-            for triangle in proj_2d.triangles {
-                // let mut on_screen = true;
-                // renderer.fill_triangle(&triangle);
+        if window.is_key_pressed(Key::G, KeyRepeat::No) {
+            walker.toggle();
+        }
 
-                let depth = get_depth_func(&triangle);
-                renderer.framebuffer.depth_func = Box::new(depth);
+        if window.is_key_pressed(Key::T, KeyRepeat::No) {
+            use_streaming = !use_streaming;
+        }
 
-                let v1 = geometric_to_screen(&triangle.vertices[0], WIDTH, HEIGHT);
-                let v2 = geometric_to_screen(&triangle.vertices[1], WIDTH, HEIGHT);
-                let v3 = geometric_to_screen(&triangle.vertices[2], WIDTH, HEIGHT);
+        if window.is_key_pressed(Key::R, KeyRepeat::No) {
+            traced = match traced {
+                Some(_) => None,
+                None => {
+                    let mut still = FrameBuffer::new(WIDTH, HEIGHT);
+                    ray_tracer.render(&scene, &renderer.camera, &inv_proj_mat, &mut still);
+                    Some(still)
+                }
+            };
+        }
 
-                renderer.framebuffer.drawline(
-                    v1.x as i32,
-                    v1.y as i32,
-                    v2.x as i32,
-                    v2.y as i32,
-                    &white,
-                );
-                renderer.framebuffer.drawline(
-                    v2.x as i32,
-                    v2.y as i32,
-                    v3.x as i32,
-                    v3.y as i32,
-                    &white,
+        if window.is_key_pressed(Key::P, KeyRepeat::No) {
+            path_tracing = !path_tracing;
+            path_tracer.reset();
+        }
+
+        if window.is_key_pressed(Key::N, KeyRepeat::No) {
+            denoise = !denoise;
+        }
+
+        if window.is_key_pressed(Key::K, KeyRepeat::No) {
+            skinning_method = match skinning_method {
+                SkinningMethod::Linear => SkinningMethod::DualQuaternion,
+                SkinningMethod::DualQuaternion => SkinningMethod::Linear,
+            };
+            if let Some(skin) = &mut scene.objects[tentacle].skin {
+                skin.method = skinning_method;
+            }
+            scene.objects[tentacle].update_skin();
+            scene_bvh.rebuild_mesh(&scene, tentacle);
+        }
+
+        if window.is_key_pressed(Key::Tab, KeyRepeat::No) {
+            renderer.mode = renderer.mode.next();
+        }
+
+        let ground: &dyn Ground = if use_streaming { &streaming } else { &terrain };
+        walker.update(&mut renderer.camera, &input, ground, dt);
+        smoke.update(dt);
+        sparks.update(dt);
+
+        if use_streaming {
+            streaming.update(&renderer.camera.position);
+        } else {
+            terrain.update(&renderer.camera.position);
+        }
+
+        // the camera is left where it is, only the scene's contents change
+        if scene_watcher.update(&mut scene, dt) {
+            (tentacle, tentacle_player) = demo_tentacle(&mut scene, skinning_method);
+            if let Some(imported) = &mut imported {
+                imported.add_to(&mut scene);
+            }
+            scene_bvh = SceneBvh::build(&scene);
+            selected = None;
+            path_tracer.reset();
+            renderer.framebuffer.clear_color = scene.background.clone();
+            animations = demo_animations(&scene);
+        }
+
+        // held still while path tracing so the samples keep adding up
+        if !path_tracing {
+            for player in &mut animations {
+                player.update(dt);
+                player.apply(&mut scene);
+                for index in player.clip.targets() {
+                    scene_bvh.refit_object(&scene, index);
+                }
+            }
+
+            tentacle_player.update(dt);
+            if let Some(skin) = &scene.objects[tentacle].skin {
+                let pose = tentacle_player.pose(&skin.skeleton);
+                scene.objects[tentacle].set_pose(pose);
+                scene_bvh.rebuild_mesh(&scene, tentacle);
+            }
+            if let Some(imported) = &mut imported {
+                imported.update(&mut scene, &mut scene_bvh, dt);
+            }
+        }
+
+        // everything opaque, drawn once for the water's reflection and once for the frame
+        let mut draw_scene = |renderer: &mut Renderer| {
+            renderer.render_scene_bvh(&scene, &scene_bvh, &proj_mat);
+
+            // the reflection pass sees through a different camera, so cull per pass
+            let frustum = renderer.frustum(&proj_mat);
+            if use_streaming {
+                for chunk in streaming.meshes() {
+                    renderer.render_mesh_culled(chunk, &frustum, &proj_mat);
+                }
+            } else {
+                for chunk in terrain.selected_meshes() {
+                    renderer.render_mesh_culled(chunk, &frustum, &proj_mat);
+                }
+            }
+        };
+
+        // the path tracer's linear color, for the tonemap to compress
+        let mut hdr = None;
+        if path_tracing {
+            path_tracer.render_pass(
+                &scene,
+                &scene_bvh,
+                &renderer.camera,
+                &inv_proj_mat,
+                &mut renderer.framebuffer,
+            );
+            hdr = if denoise {
+                path_tracer.write_denoised(&denoiser, &mut renderer.framebuffer)
+            } else {
+                Some(path_tracer.image())
+            };
+        } else {
+            if show_water {
+                water.update(dt);
+                water.follow(&renderer.camera.position);
+                water.render_reflection(&mut renderer, &mut draw_scene);
+            }
+
+            renderer.begin_frame();
+            draw_scene(&mut renderer);
+
+            if show_water {
+                water.draw(&mut renderer, &proj_mat);
+            }
+
+            if let Some(fog) = &scene.fog {
+                if matches!(
+                    renderer.mode,
+                    RenderMode::Shaded | RenderMode::ShadedWireframe
+                ) {
+                    fog.apply(&mut renderer.framebuffer);
+                }
+            }
+
+            // world axes, fading out from the origin
+            let axis_style = LineStyle {
+                thickness: 2.0,
+                ..LineStyle::default()
+            };
+            for axis in [
+                Vector3D::new(1.0, 0.0, 0.0),
+                Vector3D::new(0.0, 1.0, 0.0),
+                Vector3D::new(0.0, 0.0, 1.0),
+            ] {
+                let color = Color::new(
+                    (axis.x * 255.0) as u8,
+                    (axis.y * 255.0) as u8,
+                    (axis.z * 255.0) as u8,
+                    255,
                 );
-                renderer.framebuffer.drawline(
-                    v3.x as i32,
-                    v3.y as i32,
-                    v1.x as i32,
-                    v1.y as i32,
-                    &white,
+                let axis_line = Line3D::with_gradient(
+                    Vector3D::new(0.0, 0.0, 0.0),
+                    axis.scale(2.0),
+                    &color,
+                    &Color::new(0, 0, 0, 255),
                 );
+                renderer.draw_line_3d(&axis_line, &axis_style, &proj_mat);
+            }
 
-                // let vertices_2d = vec![v1, v2, v3].iter().map(|v| Vector3D::new(v.x as f64, v.y as f64, 0.0)).collect();
+            if let Some((index, _)) = &selected {
+                let corners = scene.objects[*index].world_aabb().corners();
+                let highlight = Color::new(255, 220, 0, 255);
+                // corners differing in one coordinate share an edge
+                for i in 0..8 {
+                    for bit in [1, 2, 4] {
+                        if i & bit == 0 {
+                            let edge = Line3D::new(corners[i], corners[i | bit], &highlight);
+                            renderer.draw_line_3d(&edge, &LineStyle::default(), &proj_mat);
+                        }
+                    }
+                }
+            }
+
+            renderer.draw_particles(&smoke, &proj_mat);
+            renderer.draw_particles(&sparks, &proj_mat);
 
-                // let tri_2d = Triangle {
-                //     vertices: vertices_2d,
-                // };
-                println!("{}", triangle.vertices[0].z);
+            renderer.end_frame();
 
-                renderer.fill_triangle(&v1, &v2, &v3, &triangle.color);
+            if let Some(still) = &traced {
+                for y in 0..HEIGHT {
+                    let row = y * WIDTH;
+                    renderer.framebuffer.color_buffer[row + WIDTH / 2..row + WIDTH]
+                        .copy_from_slice(&still.color_buffer[row + WIDTH / 2..row + WIDTH]);
+                }
             }
         }
 
+        post_chain.apply(&mut renderer.framebuffer, hdr.as_deref());
+
+        let enabled_passes: Vec<&str> = post_chain
+            .passes()
+            .iter()
+            .filter(|p| p.enabled)
+            .map(|p| p.pass.name())
+            .collect();
+        hud.draw(
+            &mut renderer,
+            &[
+                format!("post {}", enabled_passes.join(" ")),
+                format!("camera {}", if walker.walking { "walk" } else { "fly" }),
+                if use_streaming {
+                    format!(
+                        "streamed chunks {} pending {}",
+                        streaming.loaded_count(),
+                        streaming.pending_count()
+                    )
+                } else {
+                    format!("terrain chunks {}", terrain.selected_count())
+                },
+                if path_tracing {
+                    format!(
+                        "path traced {} samples{}",
+                        path_tracer.samples(),
+                        if denoise { " denoised" } else { "" }
+                    )
+                } else {
+                    "rasterized".to_string()
+                },
+                selected
+                    .as_ref()
+                    .map_or("nothing selected".to_string(), |(_, info)| info.clone()),
+                format!(
+                    "skinning {}",
+                    match skinning_method {
+                        SkinningMethod::Linear => "linear blend",
+                        SkinningMethod::DualQuaternion => "dual quaternion",
+                    }
+                ),
+                match scene_watcher.error() {
+                    Some(error) => format!("reload failed {}", error),
+                    None => "scene loaded".to_string(),
+                },
+            ],
+        );
+
         window
             .update_with_buffer(
                 &renderer.framebuffer.color_buffer,
@@ -0,0 +1,118 @@
+use crate::linalg::Matrix4D;
+use crate::renderer::{Color, Mesh, Object, Renderer, Triangle};
+use crate::texture::Texture;
+use std::sync::Arc;
+
+/// How an object's surface responds to light. The mesh's own colors are multiplied by
+/// `albedo`; everything else only matters to the ray and path tracers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Material {
+    /// Linear rgb, 0 to 1 per channel.
+    pub albedo: [f64; 3],
+    /// Fraction of light mirrored off the surface.
+    pub reflectivity: f64,
+    /// Fraction of light passing through the surface, bent by `refractive_index`.
+    pub transparency: f64,
+    pub refractive_index: f64,
+    /// Light given off by the surface itself, in the same units as `Light::radiance`.
+    pub emission: [f64; 3],
+    /// Multiplies the colors like `albedo`, looked up by the mesh's texture coordinates.
+    pub texture: Option<Arc<Texture>>,
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material {
+            albedo: [1.0, 1.0, 1.0],
+            reflectivity: 0.0,
+            transparency: 0.0,
+            refractive_index: 1.5,
+            emission: [0.0, 0.0, 0.0],
+            texture: None,
+        }
+    }
+}
+
+impl Material {
+    pub fn with_albedo(mut self, albedo: [f64; 3]) -> Material {
+        self.albedo = albedo;
+        self
+    }
+
+    pub fn with_reflectivity(mut self, reflectivity: f64) -> Material {
+        self.reflectivity = reflectivity;
+        self
+    }
+
+    pub fn with_transparency(mut self, transparency: f64, refractive_index: f64) -> Material {
+        self.transparency = transparency;
+        self.refractive_index = refractive_index;
+        self
+    }
+
+    pub fn with_emission(mut self, emission: [f64; 3]) -> Material {
+        self.emission = emission;
+        self
+    }
+
+    pub fn with_texture(mut self, texture: Texture) -> Material {
+        self.texture = Some(Arc::new(texture));
+        self
+    }
+
+    /// Share of light scattered diffusely, what isn't reflected or transmitted.
+    pub fn diffuse(&self) -> f64 {
+        (1.0 - self.reflectivity - self.transparency).max(0.0)
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.emission.iter().any(|&e| e > 0.0)
+    }
+}
+
+impl Object {
+    pub fn with_material(mut self, material: Material) -> Object {
+        self.material = material;
+        self
+    }
+
+    /// The mesh moved by `transform` and tinted by the material, as the rasterizer draws it.
+    pub fn world_mesh(&self) -> Mesh {
+        let mesh = self.mesh.apply_transformation(&self.transform);
+        if self.material.albedo == [1.0, 1.0, 1.0] {
+            return mesh;
+        }
+
+        let [r, g, b] = self.material.albedo;
+        let scale = |a: f64, c: u8| (a.clamp(0.0, 1.0) * c as f64).round() as u8;
+        let tint = |color: &Color| {
+            Color::new(
+                scale(r, color.r),
+                scale(g, color.g),
+                scale(b, color.b),
+                color.a,
+            )
+        };
+
+        Mesh {
+            triangles: mesh
+                .triangles
+                .iter()
+                .map(|t| Triangle {
+                    color: tint(&t.color),
+                    colors: t.colors.iter().map(tint).collect(),
+                    ..t.clone()
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Renderer {
+    /// Draws an object with its transform and material applied.
+    pub fn render_object(&mut self, object: &Object, proj_mat: &Matrix4D) {
+        self.texture = object.material.texture.clone();
+        self.render_mesh(&object.world_mesh(), proj_mat);
+        self.texture = None;
+    }
+}
@@ -0,0 +1,192 @@
+use crate::random::Rng;
+use std::f64::consts::{FRAC_1_SQRT_2, SQRT_2};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NoiseKind {
+    Perlin,
+    Simplex,
+}
+
+/// Seeded 2D gradient noise in roughly [-1, 1], the same seed always gives the same field.
+#[derive(Clone)]
+pub struct Noise {
+    pub kind: NoiseKind,
+    // permutation of 0..256 repeated twice so lookups never need to wrap
+    perm: Vec<u8>,
+}
+
+// eight evenly spread unit gradient directions
+const GRADIENTS: [(f64, f64); 8] = [
+    (1.0, 0.0),
+    (-1.0, 0.0),
+    (0.0, 1.0),
+    (0.0, -1.0),
+    (FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+    (-FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+    (FRAC_1_SQRT_2, -FRAC_1_SQRT_2),
+    (-FRAC_1_SQRT_2, -FRAC_1_SQRT_2),
+];
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+impl Noise {
+    pub fn new(seed: u64, kind: NoiseKind) -> Noise {
+        let mut rng = Rng::new(seed);
+        let mut table: Vec<u8> = (0..=255).collect();
+
+        // fisher-yates
+        for i in (1..table.len()).rev() {
+            let j = (rng.next_u64() % (i as u64 + 1)) as usize;
+            table.swap(i, j);
+        }
+
+        let perm = table.iter().chain(table.iter()).copied().collect();
+        Noise { kind, perm }
+    }
+
+    pub fn sample(&self, x: f64, y: f64) -> f64 {
+        match self.kind {
+            NoiseKind::Perlin => self.perlin(x, y),
+            NoiseKind::Simplex => self.simplex(x, y),
+        }
+    }
+
+    fn hash(&self, x: i64, y: i64) -> usize {
+        let x = (x & 255) as usize;
+        let y = (y & 255) as usize;
+        self.perm[self.perm[x] as usize + y] as usize
+    }
+
+    fn gradient_dot(&self, hash: usize, x: f64, y: f64) -> f64 {
+        let (gx, gy) = GRADIENTS[hash & 7];
+        gx * x + gy * y
+    }
+
+    pub fn perlin(&self, x: f64, y: f64) -> f64 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (ix, iy) = (x0 as i64, y0 as i64);
+
+        let n00 = self.gradient_dot(self.hash(ix, iy), fx, fy);
+        let n10 = self.gradient_dot(self.hash(ix + 1, iy), fx - 1.0, fy);
+        let n01 = self.gradient_dot(self.hash(ix, iy + 1), fx, fy - 1.0);
+        let n11 = self.gradient_dot(self.hash(ix + 1, iy + 1), fx - 1.0, fy - 1.0);
+
+        let (u, v) = (fade(fx), fade(fy));
+        // the largest a 2D perlin value can reach with these gradients is about 1/sqrt(2)
+        lerp(lerp(n00, n10, u), lerp(n01, n11, u), v) * SQRT_2
+    }
+
+    pub fn simplex(&self, x: f64, y: f64) -> f64 {
+        // skew to the simplex grid and back
+        let f2 = 0.5 * (3.0_f64.sqrt() - 1.0);
+        let g2 = (3.0 - 3.0_f64.sqrt()) / 6.0;
+
+        let s = (x + y) * f2;
+        let (i, j) = ((x + s).floor(), (y + s).floor());
+        let t = (i + j) * g2;
+        let (x0, y0) = (x - (i - t), y - (j - t));
+
+        // which of the two triangles of the skewed cell the point is in
+        let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+
+        let x1 = x0 - i1 as f64 + g2;
+        let y1 = y0 - j1 as f64 + g2;
+        let x2 = x0 - 1.0 + 2.0 * g2;
+        let y2 = y0 - 1.0 + 2.0 * g2;
+
+        let (ii, jj) = (i as i64, j as i64);
+        let corners = [
+            (x0, y0, self.hash(ii, jj)),
+            (x1, y1, self.hash(ii + i1, jj + j1)),
+            (x2, y2, self.hash(ii + 1, jj + 1)),
+        ];
+
+        let mut total = 0.0;
+        for (cx, cy, hash) in corners {
+            let falloff = 0.5 - cx * cx - cy * cy;
+            if falloff > 0.0 {
+                let f2 = falloff * falloff;
+                total += f2 * f2 * self.gradient_dot(hash, cx, cy);
+            }
+        }
+
+        // scales the result to about [-1, 1]
+        70.0 * total
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FractalKind {
+    /// Plain fractal brownian motion, rolling hills.
+    Fbm,
+    /// Inverted absolute value, sharp ridges like mountain ranges.
+    Ridged,
+    /// Absolute value, rounded puffy bumps.
+    Billowed,
+}
+
+/// Several octaves of `Noise` summed at rising frequency and falling amplitude.
+#[derive(Clone)]
+pub struct FractalNoise {
+    pub noise: Noise,
+    pub kind: FractalKind,
+    pub octaves: u32,
+    /// Frequency multiplier between octaves.
+    pub lacunarity: f64,
+    /// Amplitude multiplier between octaves.
+    pub persistence: f64,
+    /// Frequency of the first octave, in cycles per world unit.
+    pub frequency: f64,
+}
+
+impl FractalNoise {
+    pub fn new(seed: u64) -> FractalNoise {
+        FractalNoise {
+            noise: Noise::new(seed, NoiseKind::Perlin),
+            kind: FractalKind::Fbm,
+            octaves: 5,
+            lacunarity: 2.0,
+            persistence: 0.5,
+            frequency: 0.02,
+        }
+    }
+
+    /// Sum of the octaves at `x`, `y`, normalized to about [-1, 1] for every kind.
+    pub fn sample(&self, x: f64, y: f64) -> f64 {
+        let mut frequency = self.frequency;
+        let mut amplitude = 1.0;
+        let mut total = 0.0;
+        let mut max_total = 0.0;
+
+        for octave in 0..self.octaves.max(1) {
+            // offset each octave so their lattice points don't line up at the origin
+            let shift = octave as f64 * 17.31;
+            let n = self
+                .noise
+                .sample(x * frequency + shift, y * frequency - shift);
+
+            total += amplitude
+                * match self.kind {
+                    FractalKind::Fbm => n,
+                    FractalKind::Ridged => {
+                        let ridge = 1.0 - n.abs();
+                        ridge * ridge * 2.0 - 1.0
+                    }
+                    FractalKind::Billowed => n.abs() * 2.0 - 1.0,
+                };
+
+            max_total += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.persistence;
+        }
+
+        total / max_total
+    }
+}
@@ -0,0 +1,189 @@
+use crate::linalg::{Vector2D, Vector3D};
+use crate::renderer::{Color, Mesh, Triangle};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(std::io::Error),
+    /// A line that couldn't be read, numbered from 1.
+    Malformed {
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjError::Io(e) => write!(f, "{}", e),
+            ObjError::Malformed { line, message } => {
+                write!(f, "malformed obj, line {}: {}", line, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+impl From<std::io::Error> for ObjError {
+    fn from(e: std::io::Error) -> ObjError {
+        ObjError::Io(e)
+    }
+}
+
+// one corner of a face, indices already resolved to zero based
+struct Corner {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+}
+
+impl Mesh {
+    pub fn load_obj<P: AsRef<Path>>(path: P, color: &Color) -> Result<Mesh, ObjError> {
+        Mesh::parse_obj(&fs::read_to_string(path)?, color)
+    }
+
+    /// Reads positions, optional vertex colors written after them, texture coordinates,
+    /// normals and faces, fanning polygons into triangles. Anything else, like groups and
+    /// materials, is skipped. Vertices without colors get `color`.
+    pub fn parse_obj(text: &str, color: &Color) -> Result<Mesh, ObjError> {
+        let mut positions: Vec<Vector3D> = vec![];
+        let mut colors: Vec<Option<Color>> = vec![];
+        let mut uvs: Vec<Vector2D> = vec![];
+        let mut normals: Vec<Vector3D> = vec![];
+        let mut triangles = vec![];
+
+        for (number, line) in text.lines().enumerate() {
+            let number = number + 1;
+            let malformed = |message: String| ObjError::Malformed {
+                line: number,
+                message,
+            };
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            let keyword = match words.next() {
+                Some(keyword) => keyword,
+                None => continue,
+            };
+            let values: Vec<&str> = words.collect();
+            let numbers = || {
+                values
+                    .iter()
+                    .map(|v| {
+                        v.parse::<f64>()
+                            .map_err(|_| malformed(format!("`{}` isn't a number", v)))
+                    })
+                    .collect::<Result<Vec<f64>, ObjError>>()
+            };
+
+            match keyword {
+                "v" => {
+                    let v = numbers()?;
+                    if v.len() < 3 {
+                        return Err(malformed("a vertex needs x, y and z".to_string()));
+                    }
+                    positions.push(Vector3D::new(v[0], v[1], v[2]));
+                    // a w, or an rgb color in 0 to 1
+                    colors.push(if v.len() >= 6 {
+                        let channel = |c: f64| (c * 255.0).round().clamp(0.0, 255.0) as u8;
+                        Some(Color::new(channel(v[3]), channel(v[4]), channel(v[5]), 255))
+                    } else {
+                        None
+                    });
+                }
+                "vt" => {
+                    let v = numbers()?;
+                    if v.is_empty() {
+                        return Err(malformed("a texture coordinate needs u".to_string()));
+                    }
+                    // obj puts v 0 at the bottom, textures here put it at the top
+                    uvs.push(Vector2D::new(v[0], 1.0 - v.get(1).copied().unwrap_or(0.0)));
+                }
+                "vn" => {
+                    let v = numbers()?;
+                    if v.len() < 3 {
+                        return Err(malformed("a normal needs x, y and z".to_string()));
+                    }
+                    normals.push(Vector3D::new(v[0], v[1], v[2]).normalize());
+                }
+                "f" => {
+                    if values.len() < 3 {
+                        return Err(malformed("a face needs at least 3 corners".to_string()));
+                    }
+                    let corners = values
+                        .iter()
+                        .map(|v| parse_corner(v, positions.len(), uvs.len(), normals.len()))
+                        .collect::<Result<Vec<Corner>, String>>()
+                        .map_err(malformed)?;
+
+                    for i in 1..corners.len() - 1 {
+                        let corner = [&corners[0], &corners[i], &corners[i + 1]];
+                        let vertices = corner.map(|c| positions[c.position]);
+                        let mut triangle =
+                            Triangle::new(vertices[0], vertices[1], vertices[2], color);
+
+                        if let [Some(a), Some(b), Some(c)] = corner.map(|c| c.normal) {
+                            triangle = triangle.with_normals(normals[a], normals[b], normals[c]);
+                        }
+                        if let [Some(a), Some(b), Some(c)] = corner.map(|c| c.uv) {
+                            triangle = triangle.with_uvs(uvs[a], uvs[b], uvs[c]);
+                        }
+                        if corner.iter().any(|c| colors[c.position].is_some()) {
+                            let vertex_color =
+                                |c: &Corner| colors[c.position].clone().unwrap_or(color.clone());
+                            triangle = triangle.with_colors(
+                                vertex_color(corner[0]),
+                                vertex_color(corner[1]),
+                                vertex_color(corner[2]),
+                            );
+                        }
+                        triangles.push(triangle);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Mesh { triangles })
+    }
+}
+
+// `v`, `v/vt`, `v//vn` or `v/vt/vn`, one based or negative to count back from the end
+fn parse_corner(
+    text: &str,
+    positions: usize,
+    uvs: usize,
+    normals: usize,
+) -> Result<Corner, String> {
+    let mut parts = text.split('/');
+    let index = |part: Option<&str>, count: usize| -> Result<Option<usize>, String> {
+        let part = match part {
+            Some(part) if !part.is_empty() => part,
+            _ => return Ok(None),
+        };
+        let index: i64 = part
+            .parse()
+            .map_err(|_| format!("`{}` isn't a valid face corner", text))?;
+        let resolved = if index < 0 {
+            count as i64 + index
+        } else {
+            index - 1
+        };
+        if resolved < 0 || resolved >= count as i64 {
+            return Err(format!("index {} in `{}` is out of range", index, text));
+        }
+        Ok(Some(resolved as usize))
+    };
+
+    let position =
+        index(parts.next(), positions)?.ok_or_else(|| format!("`{}` has no vertex index", text))?;
+    let uv = index(parts.next(), uvs)?;
+    let normal = index(parts.next(), normals)?;
+    Ok(Corner {
+        position,
+        uv,
+        normal,
+    })
+}
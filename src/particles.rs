@@ -0,0 +1,344 @@
+use crate::linalg::{
+    multiply_matrix_vector, multiply_matrix_vector_perspective_div, Matrix4D, Vector3D,
+};
+use crate::random::Rng;
+use crate::renderer::{Color, Renderer};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BlendMode {
+    /// Adds light on top of what's behind, for sparks and fire.
+    Additive,
+    /// Covers what's behind by the particle's alpha, for smoke and dust.
+    Alpha,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ParticleShape {
+    /// Camera facing quad sized in world units, with a soft round falloff.
+    Billboard,
+    /// Hard square sprite sized in screen pixels regardless of distance.
+    Point,
+}
+
+pub struct Particle {
+    pub position: Vector3D,
+    pub velocity: Vector3D,
+    pub age: f64,
+    pub lifetime: f64,
+}
+
+impl Particle {
+    /// 0 when spawned, 1 when about to die, which a particle with no lifetime always is.
+    pub fn life_fraction(&self) -> f64 {
+        if self.lifetime <= 0.0 {
+            return 1.0;
+        }
+        (self.age / self.lifetime).clamp(0.0, 1.0)
+    }
+}
+
+pub struct ParticleEmitter {
+    pub position: Vector3D,
+    /// Half size of the box around `position` particles spawn in.
+    pub spawn_extent: Vector3D,
+    /// Particles per second.
+    pub spawn_rate: f64,
+    pub lifetime: f64,
+    pub velocity: Vector3D,
+    /// Random offset added to each component of `velocity`, in either direction.
+    pub velocity_spread: f64,
+    pub gravity: Vector3D,
+    pub start_color: Color,
+    pub end_color: Color,
+    pub start_size: f64,
+    pub end_size: f64,
+    pub blend: BlendMode,
+    pub shape: ParticleShape,
+    pub max_particles: usize,
+    particles: Vec<Particle>,
+    spawn_accumulator: f64,
+    rng: Rng,
+}
+
+impl ParticleEmitter {
+    pub fn new(position: Vector3D, seed: u64) -> ParticleEmitter {
+        ParticleEmitter {
+            position,
+            spawn_extent: Vector3D::new(0.0, 0.0, 0.0),
+            spawn_rate: 20.0,
+            lifetime: 2.0,
+            velocity: Vector3D::new(0.0, 1.0, 0.0),
+            velocity_spread: 0.2,
+            gravity: Vector3D::new(0.0, 0.0, 0.0),
+            start_color: Color::new(255, 255, 255, 255),
+            end_color: Color::new(255, 255, 255, 0),
+            start_size: 0.1,
+            end_size: 0.1,
+            blend: BlendMode::Alpha,
+            shape: ParticleShape::Billboard,
+            max_particles: 2000,
+            particles: vec![],
+            spawn_accumulator: 0.0,
+            rng: Rng::new(seed),
+        }
+    }
+
+    pub fn smoke(position: Vector3D, seed: u64) -> ParticleEmitter {
+        ParticleEmitter {
+            spawn_rate: 15.0,
+            lifetime: 4.0,
+            velocity: Vector3D::new(0.0, 0.6, 0.0),
+            velocity_spread: 0.15,
+            start_color: Color::new(90, 90, 90, 160),
+            end_color: Color::new(160, 160, 160, 0),
+            start_size: 0.2,
+            end_size: 1.2,
+            ..ParticleEmitter::new(position, seed)
+        }
+    }
+
+    pub fn dust(position: Vector3D, extent: Vector3D, seed: u64) -> ParticleEmitter {
+        ParticleEmitter {
+            spawn_extent: extent,
+            spawn_rate: 30.0,
+            lifetime: 6.0,
+            velocity: Vector3D::new(0.05, 0.02, 0.0),
+            velocity_spread: 0.05,
+            start_color: Color::new(200, 180, 140, 120),
+            end_color: Color::new(200, 180, 140, 0),
+            start_size: 2.0,
+            end_size: 2.0,
+            shape: ParticleShape::Point,
+            ..ParticleEmitter::new(position, seed)
+        }
+    }
+
+    pub fn rain(position: Vector3D, extent: Vector3D, seed: u64) -> ParticleEmitter {
+        ParticleEmitter {
+            spawn_extent: extent,
+            spawn_rate: 400.0,
+            lifetime: 1.5,
+            velocity: Vector3D::new(0.0, -12.0, 0.0),
+            velocity_spread: 0.5,
+            gravity: Vector3D::new(0.0, -9.8, 0.0),
+            start_color: Color::new(170, 190, 255, 150),
+            end_color: Color::new(170, 190, 255, 150),
+            start_size: 1.0,
+            end_size: 1.0,
+            shape: ParticleShape::Point,
+            ..ParticleEmitter::new(position, seed)
+        }
+    }
+
+    pub fn sparks(position: Vector3D, seed: u64) -> ParticleEmitter {
+        ParticleEmitter {
+            spawn_rate: 120.0,
+            lifetime: 0.8,
+            velocity: Vector3D::new(0.0, 3.0, 0.0),
+            velocity_spread: 2.0,
+            gravity: Vector3D::new(0.0, -9.8, 0.0),
+            start_color: Color::new(255, 230, 120, 255),
+            end_color: Color::new(255, 60, 0, 0),
+            start_size: 0.06,
+            end_size: 0.01,
+            blend: BlendMode::Additive,
+            ..ParticleEmitter::new(position, seed)
+        }
+    }
+
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    /// Ages and moves every particle, then spawns new ones for the time that has passed.
+    pub fn update(&mut self, delta_time: f64) {
+        for p in self.particles.iter_mut() {
+            p.age += delta_time;
+            p.velocity = p.velocity.add(&self.gravity.scale(delta_time));
+            p.position = p.position.add(&p.velocity.scale(delta_time));
+        }
+        self.particles.retain(|p| p.age < p.lifetime);
+
+        // carry over fractional particles so low spawn rates still emit at high frame rates
+        self.spawn_accumulator += self.spawn_rate * delta_time;
+        while self.spawn_accumulator >= 1.0 {
+            self.spawn_accumulator -= 1.0;
+            if self.particles.len() < self.max_particles {
+                self.spawn();
+            }
+        }
+    }
+
+    fn spawn(&mut self) {
+        let e = &self.spawn_extent;
+        let offset = Vector3D::new(
+            self.rng.range(-e.x, e.x),
+            self.rng.range(-e.y, e.y),
+            self.rng.range(-e.z, e.z),
+        );
+        let s = self.velocity_spread;
+        let jitter = Vector3D::new(
+            self.rng.range(-s, s),
+            self.rng.range(-s, s),
+            self.rng.range(-s, s),
+        );
+
+        self.particles.push(Particle {
+            position: self.position.add(&offset),
+            velocity: self.velocity.add(&jitter),
+            age: 0.0,
+            lifetime: self.lifetime * self.rng.range(0.8, 1.2),
+        });
+    }
+}
+
+// screen space footprint of one particle
+#[derive(Clone, Copy)]
+struct SpriteRect {
+    cx: f64,
+    cy: f64,
+    half_w: f64,
+    half_h: f64,
+    depth: f64,
+}
+
+impl Renderer {
+    /// Draws an emitter's particles depth tested against, but without writing to, the depth buffer.
+    pub fn draw_particles(&mut self, emitter: &ParticleEmitter, proj_mat: &Matrix4D) {
+        let view_matrix = self.camera.create_view_matrix();
+        let camera_offset = self.camera.position.scale(-1.0);
+
+        let mut visible: Vec<(Vector3D, &Particle)> = emitter
+            .particles
+            .iter()
+            .map(|p| {
+                let view = multiply_matrix_vector(&p.position.add(&camera_offset), &view_matrix);
+                (view, p)
+            })
+            .filter(|(view, _)| view.z > self.camera.near_clip)
+            .collect();
+
+        // alpha blending needs back to front, additive doesn't care but it's cheap
+        visible.sort_by(|(a, _), (b, _)| b.z.partial_cmp(&a.z).unwrap());
+
+        let (width, height) = (
+            self.framebuffer.width as f64,
+            self.framebuffer.height as f64,
+        );
+
+        for (view, particle) in visible {
+            let t = particle.life_fraction();
+            let color = emitter.start_color.lerp(&emitter.end_color, t);
+            let size = emitter.start_size + (emitter.end_size - emitter.start_size) * t;
+
+            let Some(center) = multiply_matrix_vector_perspective_div(&view, proj_mat) else {
+                continue;
+            };
+            let cx = (center.x + 1.0) * width / 2.0;
+            let cy = (1.0 - center.y) * height / 2.0;
+
+            // a view aligned quad projects to a screen aligned rectangle
+            let (half_w, half_h) = match emitter.shape {
+                ParticleShape::Point => (size / 2.0, size / 2.0),
+                ParticleShape::Billboard => {
+                    let corner = view.add(&Vector3D::new(size / 2.0, size / 2.0, 0.0));
+                    let Some(corner) = multiply_matrix_vector_perspective_div(&corner, proj_mat)
+                    else {
+                        continue;
+                    };
+                    (
+                        (corner.x - center.x) * width / 2.0,
+                        (corner.y - center.y) * height / 2.0,
+                    )
+                }
+            };
+
+            let sprite = SpriteRect {
+                cx,
+                cy,
+                half_w: half_w.max(0.5),
+                half_h: half_h.max(0.5),
+                depth: view.z,
+            };
+            self.blend_sprite(&sprite, &color, emitter);
+        }
+    }
+
+    fn blend_sprite(&mut self, sprite: &SpriteRect, color: &Color, emitter: &ParticleEmitter) {
+        let SpriteRect {
+            cx,
+            cy,
+            half_w,
+            half_h,
+            depth,
+        } = *sprite;
+        let (width, height) = (self.framebuffer.width, self.framebuffer.height);
+        let min_x = (cx - half_w).floor().max(0.0) as usize;
+        let min_y = (cy - half_h).floor().max(0.0) as usize;
+        let max_x = (cx + half_w).ceil().min(width as f64);
+        let max_y = (cy + half_h).ceil().min(height as f64);
+        if max_x <= 0.0 || max_y <= 0.0 {
+            return;
+        }
+
+        let opacity = color.a as f64 / 255.0;
+
+        for y in min_y..max_y as usize {
+            for x in min_x..max_x as usize {
+                let i = y * width + x;
+                if depth >= self.framebuffer.depth_buffer[i] {
+                    continue;
+                }
+
+                let alpha = match emitter.shape {
+                    ParticleShape::Point => opacity,
+                    ParticleShape::Billboard => {
+                        let dx = (x as f64 + 0.5 - cx) / half_w;
+                        let dy = (y as f64 + 0.5 - cy) / half_h;
+                        let falloff = (1.0 - (dx * dx + dy * dy)).max(0.0);
+                        opacity * falloff * falloff
+                    }
+                };
+                if alpha <= 0.0 {
+                    continue;
+                }
+
+                let behind = Color::from_u32(self.framebuffer.color_buffer[i]);
+                let blended = match emitter.blend {
+                    BlendMode::Alpha => behind.lerp(color, alpha),
+                    BlendMode::Additive => {
+                        let add = |a: u8, b: u8| (a as f64 + b as f64 * alpha).min(255.0) as u8;
+                        Color::new(
+                            add(behind.r, color.r),
+                            add(behind.g, color.g),
+                            add(behind.b, color.b),
+                            255,
+                        )
+                    }
+                };
+
+                self.framebuffer.color_buffer[i] = blended.to_u32();
+                self.framebuffer.overdraw_buffer[i] += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn life_fraction_handles_no_lifetime() {
+        let particle = |age, lifetime| Particle {
+            position: Vector3D::new(0.0, 0.0, 0.0),
+            velocity: Vector3D::new(0.0, 0.0, 0.0),
+            age,
+            lifetime,
+        };
+        assert_eq!(particle(0.5, 2.0).life_fraction(), 0.25);
+        assert_eq!(particle(3.0, 2.0).life_fraction(), 1.0);
+        assert_eq!(particle(0.0, 0.0).life_fraction(), 1.0);
+        assert_eq!(particle(0.1, -1.0).life_fraction(), 1.0);
+    }
+}
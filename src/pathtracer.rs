@@ -0,0 +1,397 @@
+use crate::bvh::SceneBvh;
+use crate::denoise::Guide;
+use crate::linalg::{dot, orthonormal_basis, Matrix4D, Vector3D};
+use crate::postprocess::{pack, unpack};
+use crate::random::Rng;
+use crate::ray::Ray;
+use crate::raytracer::{fresnel, light_visible, reflect, refract, Surface};
+use crate::renderer::{Camera, FrameBuffer, Scene};
+use std::f64::consts::{PI, TAU};
+use std::thread;
+
+pub struct PathTracerSettings {
+    /// Longest path followed, whatever russian roulette decides.
+    pub max_bounces: u32,
+    /// Bounces before paths can be ended at random.
+    pub roulette_start: u32,
+    pub threads: usize,
+    pub seed: u64,
+}
+
+impl Default for PathTracerSettings {
+    fn default() -> PathTracerSettings {
+        PathTracerSettings {
+            max_bounces: 8,
+            roulette_start: 3,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            seed: 1,
+        }
+    }
+}
+
+/// Unidirectional path tracer that refines its image a little with every pass.
+///
+/// Each pass traces one path per pixel, sampling diffuse bounces with a cosine weighted
+/// hemisphere and lighting every diffuse hit directly from the scene's lights, and adds
+/// it to a running sum. The sum starts over whenever the camera has moved or its
+/// projection has changed since the previous pass.
+pub struct PathTracer {
+    pub settings: PathTracerSettings,
+    accumulation: Vec<[f64; 3]>,
+    guides: Vec<Guide>,
+    samples: u32,
+    // the camera and projection the accumulated samples were taken from
+    last_view: Option<(Vector3D, f64, f64, f64, Matrix4D)>,
+}
+
+/// A direction around unit `normal`, more likely the closer it is to the normal, with a
+/// density of cos / PI.
+pub fn cosine_sample_hemisphere(normal: &Vector3D, rng: &mut Rng) -> Vector3D {
+    let (u, v) = orthonormal_basis(normal);
+    let r1 = rng.next_f64();
+    let angle = rng.range(0.0, TAU);
+    let r = r1.sqrt();
+
+    u.scale(r * angle.cos())
+        .add(&v.scale(r * angle.sin()))
+        .add(&normal.scale((1.0 - r1).sqrt()))
+        .normalize()
+}
+
+impl PathTracer {
+    pub fn new(settings: PathTracerSettings) -> PathTracer {
+        PathTracer {
+            settings,
+            accumulation: vec![],
+            guides: vec![],
+            samples: 0,
+            last_view: None,
+        }
+    }
+
+    /// Samples per pixel accumulated so far.
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    /// The average of the samples so far, in linear color.
+    pub fn image(&self) -> Vec<[f64; 3]> {
+        let samples = self.samples.max(1) as f64;
+        self.accumulation
+            .iter()
+            .map(|sum| sum.map(|c| c / samples))
+            .collect()
+    }
+
+    /// What the camera rays of the first pass hit, for `AtrousDenoiser`.
+    pub fn guides(&self) -> &[Guide] {
+        &self.guides
+    }
+
+    /// Throws away the accumulated image, needed after changing the scene.
+    pub fn reset(&mut self) {
+        self.accumulation.iter_mut().for_each(|c| *c = [0.0; 3]);
+        self.samples = 0;
+    }
+
+    /// Traces one more sample for every pixel and writes the average so far to the color
+    /// buffer. `bvh` must be built from `scene`, `inv_proj_mat` is the inverse of the
+    /// rasterizer's projection.
+    pub fn render_pass(
+        &mut self,
+        scene: &Scene,
+        bvh: &SceneBvh,
+        camera: &Camera,
+        inv_proj_mat: &Matrix4D,
+        framebuffer: &mut FrameBuffer,
+    ) {
+        let (width, height) = (framebuffer.width, framebuffer.height);
+        let view = (
+            camera.position,
+            camera.yaw,
+            camera.pitch,
+            camera.near_clip,
+            *inv_proj_mat,
+        );
+        if self.last_view != Some(view) || self.accumulation.len() != width * height {
+            self.accumulation = vec![[0.0; 3]; width * height];
+            self.guides = vec![Guide::default(); width * height];
+            self.samples = 0;
+            self.last_view = Some(view);
+        }
+
+        self.samples += 1;
+        let samples = self.samples;
+        let rows_per_thread = height.div_ceil(self.settings.threads.max(1)).max(1);
+        let settings = &self.settings;
+        let forward = camera.forward();
+
+        thread::scope(|s| {
+            let sums = self.accumulation.chunks_mut(rows_per_thread * width);
+            let guides = self.guides.chunks_mut(rows_per_thread * width);
+            let colors = framebuffer.color_buffer.chunks_mut(rows_per_thread * width);
+
+            for (chunk, ((sums, guides), colors)) in sums.zip(guides).zip(colors).enumerate() {
+                s.spawn(move || {
+                    let first = chunk * rows_per_thread * width;
+                    let pixels = sums.iter_mut().zip(guides).zip(colors);
+                    for (i, ((sum, guide), color)) in pixels.enumerate() {
+                        let pixel = first + i;
+                        let (x, y) = (pixel % width, pixel / width);
+                        let mut rng =
+                            Rng::new(settings.seed ^ (pixel as u64) ^ ((samples as u64) << 40));
+
+                        // the first pass goes through pixel centers so the guides line up
+                        // with the pixels
+                        let (jx, jy) = if samples == 1 {
+                            (0.0, 0.0)
+                        } else {
+                            (rng.range(-0.5, 0.5), rng.range(-0.5, 0.5))
+                        };
+                        let ray = camera.screen_ray(
+                            x as f64 + jx,
+                            y as f64 + jy,
+                            width,
+                            height,
+                            inv_proj_mat,
+                        );
+                        let (radiance, primary) = trace_path(settings, scene, bvh, ray, &mut rng);
+
+                        if samples == 1 {
+                            *guide =
+                                primary.map_or(Guide::default(), |(surface, distance)| Guide {
+                                    albedo: surface.color,
+                                    normal: surface.normal,
+                                    depth: distance * dot(&ray.direction, &forward),
+                                });
+                        }
+
+                        for k in 0..3 {
+                            // a stray nan would stay in the sum until the next reset
+                            if radiance[k].is_finite() {
+                                sum[k] += radiance[k];
+                            }
+                        }
+                        *color = pack(sum.map(|c| c / samples as f64));
+                    }
+                });
+            }
+        });
+    }
+}
+
+fn trace_path(
+    settings: &PathTracerSettings,
+    scene: &Scene,
+    bvh: &SceneBvh,
+    mut ray: Ray,
+    rng: &mut Rng,
+) -> ([f64; 3], Option<(Surface, f64)>) {
+    let mut radiance = [0.0; 3];
+    let mut primary = None;
+    let mut throughput = [1.0; 3];
+    let add = |radiance: &mut [f64; 3], throughput: &[f64; 3], light: [f64; 3]| {
+        for k in 0..3 {
+            radiance[k] += throughput[k] * light[k];
+        }
+    };
+
+    for bounce in 0..settings.max_bounces {
+        let hit = match bvh.intersect_ray(scene, &ray, f64::MAX) {
+            Some(hit) => hit,
+            None => {
+                add(
+                    &mut radiance,
+                    &throughput,
+                    unpack(scene.background.to_u32()),
+                );
+                break;
+            }
+        };
+        let surface = Surface::at(scene, &ray, &hit);
+        let material = surface.material.clone();
+
+        // lights aren't geometry so paths never reach them, emissive surfaces are only
+        // ever found this way and nothing is counted twice
+        add(&mut radiance, &throughput, material.emission);
+
+        // pick one of the diffuse, mirror and transparent lobes in proportion to its share
+        let weights = [
+            material.diffuse(),
+            material.reflectivity,
+            material.transparency,
+        ];
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            break;
+        }
+        let pick = rng.next_f64() * total;
+
+        let direction = if pick < weights[0] {
+            // next event estimation, a lambertian brdf is color / PI, scaled by total like
+            // the bounce below since this lobe was only picked weight / total of the time
+            for light in &scene.lights {
+                let point = light.sample_point(&surface.position, rng);
+                let to_light = point.sub(&surface.position);
+                let distance_squared = dot(&to_light, &to_light);
+                let cos = dot(&surface.normal, &to_light.normalize());
+                if cos > 0.0 && light_visible(scene, bvh, &surface, &point) {
+                    let incoming = light.radiance();
+                    let direct = [0, 1, 2].map(|k| {
+                        surface.color[k] * incoming[k] * cos * total / (PI * distance_squared)
+                    });
+                    add(&mut radiance, &throughput, direct);
+                }
+            }
+
+            // brdf times cos over the sampling density leaves just the color
+            for (t, c) in throughput.iter_mut().zip(surface.color) {
+                *t *= c;
+            }
+            cosine_sample_hemisphere(&surface.normal, rng)
+        } else if pick < weights[0] + weights[1] {
+            reflect(&ray.direction, &surface.normal)
+        } else {
+            let eta = if surface.front_face {
+                1.0 / material.refractive_index
+            } else {
+                material.refractive_index
+            };
+            match refract(&ray.direction, &surface.normal, eta) {
+                Some(refracted) => {
+                    let cos = if eta <= 1.0 {
+                        -dot(&ray.direction, &surface.normal)
+                    } else {
+                        -dot(&refracted, &surface.normal)
+                    };
+                    if rng.next_f64() < fresnel(cos, material.refractive_index) {
+                        reflect(&ray.direction, &surface.normal)
+                    } else {
+                        refracted
+                    }
+                }
+                None => reflect(&ray.direction, &surface.normal),
+            }
+        };
+        // the lobe was chosen with probability weight / total
+        for t in throughput.iter_mut() {
+            *t *= total;
+        }
+
+        if bounce >= settings.roulette_start {
+            let survival = throughput.iter().cloned().fold(0.0, f64::max).min(0.95);
+            if rng.next_f64() >= survival {
+                break;
+            }
+            for t in throughput.iter_mut() {
+                *t /= survival;
+            }
+        }
+
+        ray = surface.spawn_ray(direction);
+        if bounce == 0 {
+            primary = Some((surface, hit.distance));
+        }
+    }
+
+    (radiance, primary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::light::Light;
+    use crate::material::Material;
+    use crate::renderer::{CameraSetup, Color, Mesh, Object, Triangle};
+
+    const SIZE: usize = 41;
+
+    // a wall facing the camera 5 units ahead, lit by one point light off to the side
+    fn lit_wall() -> (Scene, Light) {
+        let white = Color::new(255, 255, 255, 255);
+        let corner = |x: f64, y: f64| Vector3D::new(x, y, 5.0);
+        let mesh = Mesh {
+            triangles: vec![
+                Triangle::new(
+                    corner(-20.0, -20.0),
+                    corner(20.0, 20.0),
+                    corner(20.0, -20.0),
+                    &white,
+                ),
+                Triangle::new(
+                    corner(-20.0, -20.0),
+                    corner(-20.0, 20.0),
+                    corner(20.0, 20.0),
+                    &white,
+                ),
+            ],
+        };
+        let light = Light::new(Vector3D::new(1.0, 0.0, 3.0), [1.0, 0.5, 0.25], 6.0);
+
+        let mut scene = Scene::new();
+        scene.background = Color::new(0, 0, 0, 255);
+        scene.lights.push(light);
+        scene.objects.push(
+            Object::new(mesh, Matrix4D::identity())
+                .with_material(Material::default().with_albedo([0.8, 0.6, 0.4])),
+        );
+        (scene, light)
+    }
+
+    fn settings() -> PathTracerSettings {
+        PathTracerSettings {
+            threads: 2,
+            ..PathTracerSettings::default()
+        }
+    }
+
+    #[test]
+    fn a_diffuse_wall_converges_to_its_irradiance() {
+        let (scene, light) = lit_wall();
+        let bvh = SceneBvh::build(&scene);
+        let setup = CameraSetup::default();
+        let camera = setup.camera(1.0);
+        let inv_proj = camera.get_inverse_proj_matrix(1.0, setup.fov, setup.near, setup.far);
+        let mut framebuffer = FrameBuffer::new(SIZE, SIZE);
+        let mut tracer = PathTracer::new(settings());
+        for _ in 0..64 {
+            tracer.render_pass(&scene, &bvh, &camera, &inv_proj, &mut framebuffer);
+        }
+
+        // the middle pixel looks straight at (0, 0, 5), light arrives at cos 2 / sqrt(5)
+        // from 5 units squared away, and a lambertian surface sends albedo / PI of it on
+        let to_light = light.position.sub(&Vector3D::new(0.0, 0.0, 5.0));
+        let distance_squared = dot(&to_light, &to_light);
+        let cos = -to_light.z / distance_squared.sqrt();
+        let albedo = [0.8, 0.6, 0.4];
+        let pixel = tracer.image()[SIZE / 2 * SIZE + SIZE / 2];
+        for k in 0..3 {
+            let expected = albedo[k] / PI * light.radiance()[k] * cos / distance_squared;
+            assert!(
+                (pixel[k] - expected).abs() < expected * 0.01,
+                "channel {}: {} against {}",
+                k,
+                pixel[k],
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn changing_the_projection_starts_over() {
+        let (scene, _) = lit_wall();
+        let bvh = SceneBvh::build(&scene);
+        let camera = CameraSetup::default().camera(1.0);
+        let mut framebuffer = FrameBuffer::new(8, 8);
+        let mut tracer = PathTracer::new(settings());
+
+        let wide = camera.get_inverse_proj_matrix(1.0, 90.0, 0.1, 1000.0);
+        tracer.render_pass(&scene, &bvh, &camera, &wide, &mut framebuffer);
+        tracer.render_pass(&scene, &bvh, &camera, &wide, &mut framebuffer);
+        assert_eq!(tracer.samples(), 2);
+
+        let narrow = camera.get_inverse_proj_matrix(1.0, 45.0, 0.1, 1000.0);
+        tracer.render_pass(&scene, &bvh, &camera, &narrow, &mut framebuffer);
+        assert_eq!(tracer.samples(), 1);
+    }
+}
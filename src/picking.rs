@@ -0,0 +1,206 @@
+use crate::bvh::SceneBvh;
+use crate::linalg::{multiply_matrix_vector, multiply_matrix_vector4, Matrix4D, Vector3D};
+use crate::ray::Ray;
+use crate::renderer::{Camera, Object, Scene};
+
+/// What a pick ray hit.
+pub struct Pick<'a> {
+    pub object: &'a Object,
+    /// Index of `object` in `Scene::objects`.
+    pub index: usize,
+    /// Index into the object's `mesh.triangles`.
+    pub triangle: usize,
+    /// World units from the camera.
+    pub distance: f64,
+    /// Weights of the triangle's vertices at the hit point.
+    pub barycentric: [f64; 3],
+    pub position: Vector3D,
+}
+
+impl Camera {
+    /// Clip to world space, undoing the projection with `inv_proj_mat` from
+    /// `get_inverse_proj_matrix` and then the camera's view.
+    pub fn unproject(&self, clip: &[f64; 4], inv_proj_mat: &Matrix4D) -> Vector3D {
+        let [x, y, z, w] = multiply_matrix_vector4(clip, inv_proj_mat);
+        let view_point = Vector3D::new(x / w, y / w, z / w);
+
+        let view =
+            &Matrix4D::new_translation(&self.position.scale(-1.0)) * &self.create_view_matrix();
+        let inv_view = view.inverse().unwrap_or_else(Matrix4D::identity);
+        multiply_matrix_vector(&view_point, &inv_view)
+    }
+
+    /// The world space ray from the camera through the middle of pixel `x`, `y` on a
+    /// `width` by `height` screen, with a unit direction.
+    pub fn screen_ray(
+        &self,
+        x: f64,
+        y: f64,
+        width: usize,
+        height: usize,
+        inv_proj_mat: &Matrix4D,
+    ) -> Ray {
+        let ndc_x = (x + 0.5) / width as f64 * 2.0 - 1.0;
+        let ndc_y = 1.0 - (y + 0.5) / height as f64 * 2.0;
+
+        // on the near plane z is 0 and w is the near distance
+        let near = self.near_clip;
+        let on_near_plane = self.unproject(&[ndc_x * near, ndc_y * near, 0.0, near], inv_proj_mat);
+
+        Ray::new(self.position, on_near_plane.sub(&self.position).normalize())
+    }
+}
+
+impl Scene {
+    /// The closest object along `ray`, testing every triangle of every object whose box it
+    /// passes through. `SceneBvh::pick` is much faster for larger scenes.
+    pub fn pick(&self, ray: &Ray) -> Option<Pick<'_>> {
+        let mut closest: Option<Pick> = None;
+
+        for (index, object) in self.objects.iter().enumerate() {
+            let max_distance = closest.as_ref().map_or(f64::MAX, |pick| pick.distance);
+            if ray
+                .intersect_aabb(&object.world_aabb(), max_distance)
+                .is_none()
+            {
+                continue;
+            }
+            let local = match object.transform.inverse() {
+                Some(inverse) => ray.transform(&inverse),
+                None => continue,
+            };
+
+            for (triangle, t) in object.mesh.triangles.iter().enumerate() {
+                let max_distance = closest.as_ref().map_or(f64::MAX, |pick| pick.distance);
+                let v = &t.vertices;
+                if let Some(hit) = local.intersect_triangle(&v[0], &v[1], &v[2], max_distance) {
+                    closest = Some(Pick {
+                        object,
+                        index,
+                        triangle,
+                        distance: hit.distance,
+                        barycentric: hit.barycentric,
+                        position: ray.at(hit.distance),
+                    });
+                }
+            }
+        }
+
+        closest
+    }
+}
+
+impl SceneBvh {
+    /// The closest object along `ray`, `scene` being the one this was built from.
+    pub fn pick<'a>(&self, scene: &'a Scene, ray: &Ray) -> Option<Pick<'a>> {
+        self.intersect_ray(scene, ray, f64::MAX).map(|hit| Pick {
+            object: &scene.objects[hit.object],
+            index: hit.object,
+            triangle: hit.triangle,
+            distance: hit.distance,
+            barycentric: hit.barycentric,
+            position: ray.at(hit.distance),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::{CameraSetup, Color, Mesh};
+
+    fn close(a: &Vector3D, b: &Vector3D) -> bool {
+        a.sub(b).magnitude() < 1e-9
+    }
+
+    fn camera() -> (Camera, Matrix4D, Matrix4D) {
+        let setup = CameraSetup {
+            position: Vector3D::new(1.0, 2.0, -3.0),
+            yaw: 30.0,
+            pitch: -10.0,
+            ..CameraSetup::default()
+        };
+        let camera = setup.camera(0.75);
+        let proj = camera.get_proj_matrix(0.75, setup.fov, setup.near, setup.far);
+        let inv_proj = camera.get_inverse_proj_matrix(0.75, setup.fov, setup.near, setup.far);
+        (camera, proj, inv_proj)
+    }
+
+    // world to clip space, as the renderer does before the divide
+    fn project(camera: &Camera, point: &Vector3D, proj: &Matrix4D) -> [f64; 4] {
+        let view =
+            multiply_matrix_vector(&point.sub(&camera.position), &camera.create_view_matrix());
+        multiply_matrix_vector4(&[view.x, view.y, view.z, 1.0], proj)
+    }
+
+    #[test]
+    fn unproject_undoes_project() {
+        let (camera, proj, inv_proj) = camera();
+        let product = &proj * &inv_proj;
+        for i in 0..4 {
+            for (j, value) in product.row(i).iter().enumerate() {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((value - expected).abs() < 1e-9);
+            }
+        }
+
+        for point in [
+            Vector3D::new(0.0, 0.0, 5.0),
+            Vector3D::new(-4.0, 3.0, 20.0),
+            Vector3D::new(10.0, -1.0, 2.0),
+        ] {
+            let clip = project(&camera, &point, &proj);
+            assert!(close(&camera.unproject(&clip, &inv_proj), &point));
+        }
+    }
+
+    #[test]
+    fn screen_rays_pass_through_what_projects_to_their_pixel() {
+        let (camera, proj, inv_proj) = camera();
+        let (width, height) = (320, 240);
+        let point = Vector3D::new(-2.0, 1.0, 12.0);
+
+        let [x, y, _, w] = project(&camera, &point, &proj);
+        let screen_x = (x / w + 1.0) * width as f64 / 2.0 - 0.5;
+        let screen_y = (1.0 - y / w) * height as f64 / 2.0 - 0.5;
+        let ray = camera.screen_ray(screen_x, screen_y, width, height, &inv_proj);
+
+        assert!(close(&ray.origin, &camera.position));
+        assert!((ray.direction.magnitude() - 1.0).abs() < 1e-9);
+        let along = point.sub(&ray.origin);
+        let distance = along.magnitude();
+        assert!(close(&ray.at(distance), &point));
+    }
+
+    #[test]
+    fn picks_the_nearest_object_under_a_pixel() {
+        let white = Color::new(255, 255, 255, 255);
+        let cube = |x: f64, z: f64| {
+            Object::new(
+                Mesh::cuboid(&Vector3D::new(2.0, 2.0, 2.0), &white),
+                Matrix4D::new_translation(&Vector3D::new(x, 0.0, z)),
+            )
+        };
+        let mut scene = Scene::new();
+        scene.objects = vec![cube(0.0, 20.0), cube(0.0, 10.0), cube(5.0, 10.0)];
+        let bvh = SceneBvh::build(&scene);
+
+        let setup = CameraSetup::default();
+        let camera = setup.camera(1.0);
+        let inv_proj = camera.get_inverse_proj_matrix(1.0, setup.fov, setup.near, setup.far);
+
+        // the middle pixel of an odd sized screen looks straight down the z axis
+        let ray = camera.screen_ray(20.0, 20.0, 41, 41, &inv_proj);
+        let pick = bvh.pick(&scene, &ray).unwrap();
+        assert_eq!(pick.index, 1);
+        assert!((pick.distance - 9.0).abs() < 1e-9);
+        assert!(close(&pick.position, &Vector3D::new(0.0, 0.0, 9.0)));
+
+        let linear = scene.pick(&ray).unwrap();
+        assert_eq!((linear.index, linear.triangle), (pick.index, pick.triangle));
+
+        // nothing is above the cubes
+        let ray = camera.screen_ray(20.0, 0.0, 41, 41, &inv_proj);
+        assert!(bvh.pick(&scene, &ray).is_none());
+    }
+}
@@ -0,0 +1,395 @@
+use crate::image::{Image, ImageError};
+use crate::inflate::zlib_decompress;
+
+pub const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+fn malformed(msg: &str) -> ImageError {
+    ImageError::Malformed(format!("png {}", msg))
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+struct Header {
+    width: usize,
+    height: usize,
+    bit_depth: usize,
+    color_type: u8,
+}
+
+impl Header {
+    fn channels(&self) -> usize {
+        match self.color_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            // gray and palette indices
+            _ => 1,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth
+    }
+
+    fn row_bytes(&self) -> usize {
+        (self.width * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    // filtered image data, each row led by its filter byte
+    fn filtered_len(&self) -> usize {
+        (self.row_bytes() + 1) * self.height
+    }
+}
+
+fn parse_header(data: &[u8]) -> Result<Header, ImageError> {
+    if data.len() != 13 {
+        return Err(malformed("IHDR has the wrong length"));
+    }
+
+    let header = Header {
+        width: be_u32(&data[0..4]) as usize,
+        height: be_u32(&data[4..8]) as usize,
+        bit_depth: data[8] as usize,
+        color_type: data[9],
+    };
+
+    let allowed_depths: &[usize] = match header.color_type {
+        0 => &[1, 2, 4, 8, 16],
+        3 => &[1, 2, 4, 8],
+        2 | 4 | 6 => &[8, 16],
+        other => return Err(malformed(&format!("color type {} is invalid", other))),
+    };
+    if !allowed_depths.contains(&header.bit_depth) {
+        return Err(malformed(&format!(
+            "bit depth {} is invalid for color type {}",
+            header.bit_depth, header.color_type
+        )));
+    }
+    if data[10] != 0 || data[11] != 0 {
+        return Err(malformed("uses an unknown compression or filter method"));
+    }
+    if data[12] != 0 {
+        return Err(ImageError::Unsupported(
+            "interlaced png images are not supported".to_string(),
+        ));
+    }
+    Image::checked_pixels(header.width, header.height)?;
+
+    Ok(header)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// undoes the per row filters, leaving rows packed without their filter bytes
+fn unfilter(header: &Header, data: &[u8]) -> Result<Vec<u8>, ImageError> {
+    let stride = header.row_bytes();
+    // filters look this many bytes back, the previous whole pixel or one byte when smaller
+    let bpp = header.bits_per_pixel().div_ceil(8);
+
+    if data.len() < header.filtered_len() {
+        return Err(malformed("image data is truncated"));
+    }
+
+    let mut out = vec![0u8; stride * header.height];
+    for y in 0..header.height {
+        let filter = data[y * (stride + 1)];
+        let row = &data[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        let (before, current) = out.split_at_mut(y * stride);
+        let current = &mut current[..stride];
+        let previous = if y > 0 {
+            &before[(y - 1) * stride..]
+        } else {
+            &[][..]
+        };
+
+        for i in 0..stride {
+            let a = if i >= bpp { current[i - bpp] } else { 0 };
+            let b = previous.get(i).copied().unwrap_or(0);
+            let c = if i >= bpp {
+                previous.get(i - bpp).copied().unwrap_or(0)
+            } else {
+                0
+            };
+
+            current[i] = match filter {
+                0 => row[i],
+                1 => row[i].wrapping_add(a),
+                2 => row[i].wrapping_add(b),
+                3 => row[i].wrapping_add(((a as u16 + b as u16) / 2) as u8),
+                4 => row[i].wrapping_add(paeth(a, b, c)),
+                other => return Err(malformed(&format!("row filter {} is invalid", other))),
+            };
+        }
+    }
+
+    Ok(out)
+}
+
+/// Decodes a non interlaced png of any color type and bit depth.
+pub fn decode(bytes: &[u8]) -> Result<Image, ImageError> {
+    if !bytes.starts_with(&SIGNATURE) {
+        return Err(malformed("signature is missing"));
+    }
+
+    let mut header = None;
+    let mut palette: Vec<[u8; 4]> = vec![];
+    let mut compressed = vec![];
+    let mut pos = SIGNATURE.len();
+
+    loop {
+        let chunk_header = bytes
+            .get(pos..pos + 8)
+            .ok_or_else(|| malformed("ends before its IEND chunk"))?;
+        let length = be_u32(chunk_header) as usize;
+        let kind = &chunk_header[4..8];
+        let data = bytes
+            .get(pos + 8..pos + 8 + length)
+            .ok_or_else(|| malformed("chunk runs past the end of the file"))?;
+        let crc = bytes
+            .get(pos + 8 + length..pos + 12 + length)
+            .ok_or_else(|| malformed("chunk is missing its crc"))?;
+
+        // the crc covers the chunk type and data
+        if crc32(&bytes[pos + 4..pos + 8 + length]) != be_u32(crc) {
+            return Err(malformed(&format!(
+                "{} chunk fails its crc check",
+                String::from_utf8_lossy(kind)
+            )));
+        }
+        pos += 12 + length;
+
+        match kind {
+            b"IHDR" => header = Some(parse_header(data)?),
+            b"PLTE" => {
+                palette = data
+                    .chunks_exact(3)
+                    .map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+                    .collect()
+            }
+            b"tRNS" => {
+                for (entry, alpha) in palette.iter_mut().zip(data) {
+                    entry[3] = *alpha;
+                }
+            }
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            // chunks with a lowercase first letter are safe to skip
+            _ if kind[0].is_ascii_lowercase() => {}
+            _ => {
+                return Err(ImageError::Unsupported(format!(
+                    "critical png chunk {} is not supported",
+                    String::from_utf8_lossy(kind)
+                )))
+            }
+        }
+    }
+
+    let header = header.ok_or_else(|| malformed("has no IHDR chunk"))?;
+    let raw = zlib_decompress(&compressed, header.filtered_len()).map_err(|e| malformed(&e))?;
+    let pixels = unfilter(&header, &raw)?;
+
+    let stride = header.row_bytes();
+    let depth = header.bit_depth;
+    // samples narrower than a byte are packed from the most significant bit
+    let sample_at = |row: &[u8], index: usize| -> u16 {
+        match depth {
+            16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+            8 => row[index] as u16,
+            _ => {
+                let bit = index * depth;
+                let shift = 8 - depth - bit % 8;
+                ((row[bit / 8] >> shift) & ((1 << depth) - 1) as u8) as u16
+            }
+        }
+    };
+
+    if header.color_type == 3 {
+        if palette.is_empty() {
+            return Err(malformed("palette image has no PLTE chunk"));
+        }
+
+        let mut image = Image::new(header.width, header.height, 4, 255);
+        for y in 0..header.height {
+            let row = &pixels[y * stride..(y + 1) * stride];
+            for x in 0..header.width {
+                let entry = palette
+                    .get(sample_at(row, x) as usize)
+                    .ok_or_else(|| malformed("palette index out of range"))?;
+                let offset = (y * header.width + x) * 4;
+                for (sample, value) in image.samples[offset..offset + 4].iter_mut().zip(entry) {
+                    *sample = *value as u16;
+                }
+            }
+        }
+        return Ok(image);
+    }
+
+    let channels = header.channels();
+    let max_value = ((1u32 << depth) - 1) as u16;
+    let mut image = Image::new(header.width, header.height, channels, max_value);
+    for y in 0..header.height {
+        let row = &pixels[y * stride..(y + 1) * stride];
+        for i in 0..header.width * channels {
+            image.samples[y * header.width * channels + i] = sample_at(row, i);
+        }
+    }
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a 3x2 rgb image from zlib, its first row sub filtered and its second paeth filtered
+    const RGB: [u8; 81] = [
+        0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44,
+        0x52, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x02, 0x08, 0x02, 0x00, 0x00, 0x00, 0x12,
+        0x16, 0xf1, 0x4d, 0x00, 0x00, 0x00, 0x18, 0x49, 0x44, 0x41, 0x54, 0x78, 0xda, 0x63, 0xe4,
+        0x12, 0x91, 0x83, 0x00, 0x16, 0x56, 0x56, 0xd6, 0x05, 0x46, 0x47, 0xcc, 0x67, 0xff, 0x07,
+        0x00, 0x19, 0x0e, 0x04, 0x6c, 0x9f, 0x6e, 0xac, 0x27, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45,
+        0x4e, 0x44, 0xae, 0x42, 0x60, 0x82,
+    ];
+
+    fn chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut bytes = (data.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(kind);
+        bytes.extend_from_slice(data);
+        bytes.extend_from_slice(&crc32(&bytes[4..]).to_be_bytes());
+        bytes
+    }
+
+    // a png with `chunks` before its image data, which is `filtered` kept in a single
+    // stored deflate block
+    fn png(size: (u32, u32), depth: u8, color_type: u8, chunks: &[u8], filtered: &[u8]) -> Vec<u8> {
+        let (width, height) = size;
+        let mut ihdr = width.to_be_bytes().to_vec();
+        ihdr.extend_from_slice(&height.to_be_bytes());
+        ihdr.extend_from_slice(&[depth, color_type, 0, 0, 0]);
+
+        let length = filtered.len() as u16;
+        let mut zlib = vec![0x78, 0x01, 0x01];
+        zlib.extend_from_slice(&length.to_le_bytes());
+        zlib.extend_from_slice(&(!length).to_le_bytes());
+        zlib.extend_from_slice(filtered);
+        let (mut a, mut b) = (1u32, 0u32);
+        for &byte in filtered {
+            a = (a + byte as u32) % 65521;
+            b = (b + a) % 65521;
+        }
+        zlib.extend_from_slice(&((b << 16) | a).to_be_bytes());
+
+        let mut bytes = SIGNATURE.to_vec();
+        bytes.extend(chunk(b"IHDR", &ihdr));
+        bytes.extend_from_slice(chunks);
+        bytes.extend(chunk(b"IDAT", &zlib));
+        bytes.extend(chunk(b"IEND", &[]));
+        bytes
+    }
+
+    fn malformed_message(bytes: &[u8]) -> String {
+        match decode(bytes) {
+            Err(ImageError::Malformed(message)) => message,
+            Err(e) => panic!("expected a malformed error, got {}", e),
+            Ok(_) => panic!("expected a malformed error"),
+        }
+    }
+
+    #[test]
+    fn decodes_a_filtered_rgb_image() {
+        let image = decode(&RGB).unwrap();
+        assert_eq!((image.width, image.height, image.channels), (3, 2, 3));
+        assert_eq!(
+            image.samples,
+            [10, 20, 30, 40, 50, 60, 70, 80, 90, 15, 25, 35, 200, 100, 0, 255, 255, 255]
+        );
+    }
+
+    #[test]
+    fn decodes_packed_gray_and_palettes() {
+        // two rows of 1 bit gray, the second up filtered onto the first
+        let image = decode(&png((3, 2), 1, 0, &[], &[0, 0b1010_0000, 2, 0b0100_0000])).unwrap();
+        assert_eq!(image.max_value, 1);
+        assert_eq!(image.samples, [1, 0, 1, 1, 1, 1]);
+
+        let mut chunks = chunk(b"PLTE", &[255, 0, 0, 0, 0, 255]);
+        chunks.extend(chunk(b"tRNS", &[128]));
+        let image = decode(&png((2, 1), 2, 3, &chunks, &[0, 0b0001_0000])).unwrap();
+        assert_eq!(image.samples, [255, 0, 0, 128, 0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn decodes_16_bit_samples() {
+        let image = decode(&png((1, 1), 16, 0, &[], &[0, 0x12, 0x34])).unwrap();
+        assert_eq!((image.max_value, image.samples[0]), (65535, 0x1234));
+    }
+
+    #[test]
+    fn rejects_bad_crcs_and_truncated_files() {
+        let mut crc = RGB;
+        crc[45] ^= 1;
+        assert_eq!(
+            malformed_message(&crc),
+            "png IDAT chunk fails its crc check"
+        );
+        assert_eq!(
+            malformed_message(&RGB[..60]),
+            "png chunk runs past the end of the file"
+        );
+        assert_eq!(
+            malformed_message(&png((2, 2), 8, 0, &[], &[0, 1, 2])),
+            "png image data is truncated"
+        );
+        assert_eq!(
+            malformed_message(&png((1, 1), 8, 0, &[], &[7, 1])),
+            "png row filter 7 is invalid"
+        );
+    }
+
+    #[test]
+    fn rejects_empty_and_oversized_images_before_allocating() {
+        assert_eq!(
+            malformed_message(&png((0, 4), 8, 0, &[], &[])),
+            "0x4 image has no pixels"
+        );
+        assert!(matches!(
+            decode(&png((u32::MAX, u32::MAX), 16, 6, &[], &[0])),
+            Err(ImageError::Unsupported(_))
+        ));
+        // more data than the header's size allows is refused while decompressing
+        let error = malformed_message(&png((1, 1), 8, 0, &[], &[0, 1, 2, 3]));
+        assert!(
+            error.contains("more than the expected 2 bytes"),
+            "{}",
+            error
+        );
+    }
+}
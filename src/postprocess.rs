@@ -0,0 +1,672 @@
+use crate::renderer::FrameBuffer;
+
+/// Read-only view of a finished frame handed to each post-processing pass.
+pub struct PassInput<'a> {
+    pub color: &'a [u32],
+    /// Linear color from before it was clamped into `color`, when the frame was made that
+    /// way. Only the first enabled pass gets it, later ones see what earlier ones wrote.
+    pub hdr: Option<&'a [[f64; 3]]>,
+    pub depth: &'a [f64],
+    pub width: usize,
+    pub height: usize,
+}
+
+impl PassInput<'_> {
+    // clamps to the nearest edge pixel so kernels don't have to special case borders
+    fn sample(&self, x: isize, y: isize) -> [f64; 3] {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        unpack(self.color[y * self.width + x])
+    }
+}
+
+pub trait PostProcess {
+    fn name(&self) -> &str;
+
+    /// Reads the whole input frame and writes every pixel of `output`, which is the same size.
+    fn apply(&self, input: &PassInput, output: &mut [u32]);
+}
+
+pub struct PostPass {
+    pub pass: Box<dyn PostProcess>,
+    pub enabled: bool,
+}
+
+/// Ordered list of passes run between rasterization and presenting the frame.
+pub struct PostProcessChain {
+    passes: Vec<PostPass>,
+    scratch: Vec<u32>,
+}
+
+impl Default for PostProcessChain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PostProcessChain {
+    pub fn new() -> PostProcessChain {
+        PostProcessChain {
+            passes: vec![],
+            scratch: vec![],
+        }
+    }
+
+    /// The chain with every built-in pass in its usual order, all disabled.
+    pub fn with_builtin_passes() -> PostProcessChain {
+        let mut chain = PostProcessChain::new();
+        chain.add(Tonemap::new(TonemapOperator::Aces, 1.0), false);
+        chain.add(Bloom::new(0.7, 0.6, 4), false);
+        chain.add(Fxaa::new(), false);
+        chain.add(Sharpen::new(0.5), false);
+        chain.add(Vignette::new(0.5, 0.4), false);
+        chain.add(GammaCorrection::new(2.2), false);
+        chain
+    }
+
+    pub fn add<P: PostProcess + 'static>(&mut self, pass: P, enabled: bool) {
+        self.passes.push(PostPass {
+            pass: Box::new(pass),
+            enabled,
+        });
+    }
+
+    pub fn passes(&self) -> &[PostPass] {
+        &self.passes
+    }
+
+    /// Flips the pass at `index` and returns its new state, or `None` if there is no such pass.
+    pub fn toggle(&mut self, index: usize) -> Option<bool> {
+        let pass = self.passes.get_mut(index)?;
+        pass.enabled = !pass.enabled;
+        Some(pass.enabled)
+    }
+
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) {
+        for pass in self.passes.iter_mut().filter(|p| p.pass.name() == name) {
+            pass.enabled = enabled;
+        }
+    }
+
+    /// Runs the enabled passes over the color buffer in order. `hdr`, one linear color per
+    /// pixel, goes to the first of them.
+    pub fn apply(&mut self, framebuffer: &mut FrameBuffer, hdr: Option<&[[f64; 3]]>) {
+        let len = framebuffer.width * framebuffer.height;
+        self.scratch.resize(len, 0);
+        let mut hdr = hdr.filter(|hdr| hdr.len() == len);
+
+        for pass in self.passes.iter().filter(|p| p.enabled) {
+            let input = PassInput {
+                color: &framebuffer.color_buffer,
+                hdr: hdr.take(),
+                depth: &framebuffer.depth_buffer,
+                width: framebuffer.width,
+                height: framebuffer.height,
+            };
+            pass.pass.apply(&input, &mut self.scratch);
+
+            // the output of this pass is the input of the next one
+            std::mem::swap(&mut framebuffer.color_buffer, &mut self.scratch);
+        }
+    }
+}
+
+pub fn unpack(color: u32) -> [f64; 3] {
+    [
+        ((color >> 16) & 0xff) as f64 / 255.0,
+        ((color >> 8) & 0xff) as f64 / 255.0,
+        (color & 0xff) as f64 / 255.0,
+    ]
+}
+
+pub fn pack(rgb: [f64; 3]) -> u32 {
+    let [r, g, b] = rgb.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u32);
+    (0xff << 24) | (r << 16) | (g << 8) | b
+}
+
+pub fn luma(rgb: [f64; 3]) -> f64 {
+    0.299 * rgb[0] + 0.587 * rgb[1] + 0.114 * rgb[2]
+}
+
+fn smoothstep(edge0: f64, edge1: f64, x: f64) -> f64 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn lerp(a: [f64; 3], b: [f64; 3], t: f64) -> [f64; 3] {
+    [
+        a[0] + (b[0] - a[0]) * t,
+        a[1] + (b[1] - a[1]) * t,
+        a[2] + (b[2] - a[2]) * t,
+    ]
+}
+
+pub struct GammaCorrection {
+    pub gamma: f64,
+}
+
+impl GammaCorrection {
+    pub fn new(gamma: f64) -> GammaCorrection {
+        GammaCorrection { gamma }
+    }
+}
+
+impl PostProcess for GammaCorrection {
+    fn name(&self) -> &str {
+        "gamma"
+    }
+
+    fn apply(&self, input: &PassInput, output: &mut [u32]) {
+        let inv_gamma = 1.0 / self.gamma;
+        for (out, &c) in output.iter_mut().zip(input.color) {
+            *out = pack(unpack(c).map(|v| v.powf(inv_gamma)));
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TonemapOperator {
+    Reinhard,
+    Aces,
+}
+
+/// Compresses linear color of any brightness into 0 to 1. Only frames with `hdr` color
+/// have anything above 1 to compress, on anything else it is just a curve that darkens
+/// the highlights.
+pub struct Tonemap {
+    pub operator: TonemapOperator,
+    pub exposure: f64,
+}
+
+impl Tonemap {
+    pub fn new(operator: TonemapOperator, exposure: f64) -> Tonemap {
+        Tonemap { operator, exposure }
+    }
+
+    fn map(&self, x: f64) -> f64 {
+        let x = x * self.exposure;
+        match self.operator {
+            TonemapOperator::Reinhard => x / (1.0 + x),
+            // Narkowicz's fit of the ACES filmic curve
+            TonemapOperator::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+        }
+    }
+}
+
+impl PostProcess for Tonemap {
+    fn name(&self) -> &str {
+        "tonemap"
+    }
+
+    fn apply(&self, input: &PassInput, output: &mut [u32]) {
+        match input.hdr {
+            Some(hdr) => {
+                for (out, c) in output.iter_mut().zip(hdr) {
+                    *out = pack(c.map(|v| self.map(v)));
+                }
+            }
+            None => {
+                for (out, &c) in output.iter_mut().zip(input.color) {
+                    *out = pack(unpack(c).map(|v| self.map(v)));
+                }
+            }
+        }
+    }
+}
+
+pub struct Vignette {
+    /// How dark the corners get, 0 disables the effect.
+    pub strength: f64,
+    /// Normalized distance from the center where darkening starts.
+    pub radius: f64,
+}
+
+impl Vignette {
+    pub fn new(strength: f64, radius: f64) -> Vignette {
+        Vignette { strength, radius }
+    }
+}
+
+impl PostProcess for Vignette {
+    fn name(&self) -> &str {
+        "vignette"
+    }
+
+    fn apply(&self, input: &PassInput, output: &mut [u32]) {
+        let (cx, cy) = (input.width as f64 / 2.0, input.height as f64 / 2.0);
+        let max_dist = (cx * cx + cy * cy).sqrt();
+
+        for y in 0..input.height {
+            for x in 0..input.width {
+                let (dx, dy) = (x as f64 - cx, y as f64 - cy);
+                let dist = (dx * dx + dy * dy).sqrt() / max_dist;
+                let factor = 1.0 - self.strength * smoothstep(self.radius, 1.0, dist);
+
+                let i = y * input.width + x;
+                output[i] = pack(unpack(input.color[i]).map(|v| v * factor));
+            }
+        }
+    }
+}
+
+pub struct Bloom {
+    /// Luma above which a pixel starts to glow.
+    pub threshold: f64,
+    pub intensity: f64,
+    /// Blur radius in pixels.
+    pub radius: usize,
+}
+
+impl Bloom {
+    pub fn new(threshold: f64, intensity: f64, radius: usize) -> Bloom {
+        Bloom {
+            threshold,
+            intensity,
+            radius,
+        }
+    }
+
+    fn box_blur(
+        &self,
+        src: &[[f64; 3]],
+        width: usize,
+        height: usize,
+        horizontal: bool,
+    ) -> Vec<[f64; 3]> {
+        let r = self.radius as isize;
+        let weight = 1.0 / (2 * r + 1) as f64;
+        let mut out = vec![[0.0; 3]; src.len()];
+
+        for y in 0..height as isize {
+            for x in 0..width as isize {
+                let mut sum = [0.0; 3];
+                for k in -r..=r {
+                    let (sx, sy) = if horizontal { (x + k, y) } else { (x, y + k) };
+                    let sx = sx.clamp(0, width as isize - 1) as usize;
+                    let sy = sy.clamp(0, height as isize - 1) as usize;
+                    let s = src[sy * width + sx];
+                    sum = [sum[0] + s[0], sum[1] + s[1], sum[2] + s[2]];
+                }
+                out[y as usize * width + x as usize] = sum.map(|v| v * weight);
+            }
+        }
+
+        out
+    }
+}
+
+impl PostProcess for Bloom {
+    fn name(&self) -> &str {
+        "bloom"
+    }
+
+    fn apply(&self, input: &PassInput, output: &mut [u32]) {
+        let (w, h) = (input.width, input.height);
+
+        // bright pass, keeps only the part of each pixel above the threshold
+        let bright: Vec<[f64; 3]> = input
+            .color
+            .iter()
+            .map(|&c| {
+                let rgb = unpack(c);
+                let l = luma(rgb);
+                if l <= self.threshold {
+                    [0.0; 3]
+                } else {
+                    rgb.map(|v| v * (l - self.threshold) / l)
+                }
+            })
+            .collect();
+
+        // two separable box blurs are a cheap approximation of a gaussian
+        let mut blurred = bright;
+        for _ in 0..2 {
+            blurred = self.box_blur(&blurred, w, h, true);
+            blurred = self.box_blur(&blurred, w, h, false);
+        }
+
+        for (i, out) in output.iter_mut().enumerate() {
+            let base = unpack(input.color[i]);
+            let glow = blurred[i];
+            *out = pack([
+                base[0] + glow[0] * self.intensity,
+                base[1] + glow[1] * self.intensity,
+                base[2] + glow[2] * self.intensity,
+            ]);
+        }
+    }
+}
+
+/// A reduced FXAA: finds local luma edges and blends each edge pixel toward the
+/// neighbour across the edge, without the long end-of-edge search of the full algorithm.
+pub struct Fxaa {
+    /// Minimum relative contrast for a pixel to count as an edge.
+    pub edge_threshold: f64,
+    /// Absolute contrast below which dark areas are left alone.
+    pub edge_threshold_min: f64,
+    /// How much sub-pixel aliasing is removed, 0 to 1.
+    pub subpixel_quality: f64,
+}
+
+impl Default for Fxaa {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fxaa {
+    pub fn new() -> Fxaa {
+        Fxaa {
+            edge_threshold: 0.125,
+            edge_threshold_min: 0.0312,
+            subpixel_quality: 0.75,
+        }
+    }
+}
+
+impl PostProcess for Fxaa {
+    fn name(&self) -> &str {
+        "fxaa"
+    }
+
+    fn apply(&self, input: &PassInput, output: &mut [u32]) {
+        for y in 0..input.height as isize {
+            for x in 0..input.width as isize {
+                let i = y as usize * input.width + x as usize;
+
+                let center = input.sample(x, y);
+                let n = input.sample(x, y - 1);
+                let s = input.sample(x, y + 1);
+                let e = input.sample(x + 1, y);
+                let w = input.sample(x - 1, y);
+
+                let (lm, ln, ls, le, lw) = (luma(center), luma(n), luma(s), luma(e), luma(w));
+                let l_max = lm.max(ln).max(ls).max(le).max(lw);
+                let l_min = lm.min(ln).min(ls).min(le).min(lw);
+                let contrast = l_max - l_min;
+
+                if contrast < self.edge_threshold_min.max(l_max * self.edge_threshold) {
+                    output[i] = input.color[i];
+                    continue;
+                }
+
+                let lne = luma(input.sample(x + 1, y - 1));
+                let lnw = luma(input.sample(x - 1, y - 1));
+                let lse = luma(input.sample(x + 1, y + 1));
+                let lsw = luma(input.sample(x - 1, y + 1));
+
+                // sub-pixel blend factor from the difference to the neighbourhood average
+                let average = (2.0 * (ln + ls + le + lw) + lne + lnw + lse + lsw) / 12.0;
+                let subpixel =
+                    smoothstep(0.0, 1.0, ((average - lm).abs() / contrast).clamp(0.0, 1.0));
+                let blend = subpixel * subpixel * self.subpixel_quality;
+
+                // a horizontal edge changes luma going up and down, a vertical one going across
+                let horizontal = (lnw + lsw - 2.0 * lw).abs()
+                    + 2.0 * (ln + ls - 2.0 * lm).abs()
+                    + (lne + lse - 2.0 * le).abs();
+                let vertical = (lnw + lne - 2.0 * ln).abs()
+                    + 2.0 * (lw + le - 2.0 * lm).abs()
+                    + (lsw + lse - 2.0 * ls).abs();
+
+                // blend across the edge, toward whichever side has the steeper gradient
+                let target = if horizontal >= vertical {
+                    if (ln - lm).abs() >= (ls - lm).abs() {
+                        n
+                    } else {
+                        s
+                    }
+                } else if (lw - lm).abs() >= (le - lm).abs() {
+                    w
+                } else {
+                    e
+                };
+
+                // pixels on a detected edge always get at least a quarter blend
+                output[i] = pack(lerp(center, target, blend.max(0.25)));
+            }
+        }
+    }
+}
+
+pub struct Sharpen {
+    pub amount: f64,
+}
+
+impl Sharpen {
+    pub fn new(amount: f64) -> Sharpen {
+        Sharpen { amount }
+    }
+}
+
+impl PostProcess for Sharpen {
+    fn name(&self) -> &str {
+        "sharpen"
+    }
+
+    fn apply(&self, input: &PassInput, output: &mut [u32]) {
+        for y in 0..input.height as isize {
+            for x in 0..input.width as isize {
+                let c = input.sample(x, y);
+                let n = input.sample(x, y - 1);
+                let s = input.sample(x, y + 1);
+                let e = input.sample(x + 1, y);
+                let w = input.sample(x - 1, y);
+
+                // unsharp mask with a 4-neighbour laplacian
+                let out: [f64; 3] = std::array::from_fn(|k| {
+                    c[k] + self.amount * (4.0 * c[k] - n[k] - s[k] - e[k] - w[k])
+                });
+
+                output[y as usize * input.width + x as usize] = pack(out);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(v: f64) -> u32 {
+        pack([v; 3])
+    }
+
+    fn frame(width: usize, height: usize, color: impl Fn(usize, usize) -> u32) -> FrameBuffer {
+        let mut framebuffer = FrameBuffer::new(width, height);
+        framebuffer.clear();
+        for y in 0..height {
+            for x in 0..width {
+                framebuffer.color_buffer[y * width + x] = color(x, y);
+            }
+        }
+        framebuffer
+    }
+
+    // runs a single pass over `framebuffer`
+    fn run<P: PostProcess + 'static>(pass: P, framebuffer: &mut FrameBuffer) {
+        let mut chain = PostProcessChain::new();
+        chain.add(pass, true);
+        chain.apply(framebuffer, None);
+    }
+
+    fn level(color: u32) -> f64 {
+        unpack(color)[0]
+    }
+
+    // fills the frame with one gray, or with white when it was handed hdr color
+    struct Fill(f64);
+
+    impl PostProcess for Fill {
+        fn name(&self) -> &str {
+            "fill"
+        }
+
+        fn apply(&self, input: &PassInput, output: &mut [u32]) {
+            let v = if input.hdr.is_some() { 1.0 } else { self.0 };
+            output.iter_mut().for_each(|out| *out = gray(v));
+        }
+    }
+
+    struct Invert;
+
+    impl PostProcess for Invert {
+        fn name(&self) -> &str {
+            "invert"
+        }
+
+        fn apply(&self, input: &PassInput, output: &mut [u32]) {
+            for (out, &c) in output.iter_mut().zip(input.color) {
+                *out = pack(unpack(c).map(|v| 1.0 - v));
+            }
+        }
+    }
+
+    #[test]
+    fn passes_run_in_order_on_the_previous_output() {
+        let mut framebuffer = frame(4, 4, |_, _| gray(0.5));
+        let mut chain = PostProcessChain::new();
+        chain.add(Fill(0.2), true);
+        chain.add(Invert, true);
+        chain.apply(&mut framebuffer, None);
+        assert_eq!(framebuffer.color_buffer[0], gray(0.8));
+
+        let mut chain = PostProcessChain::new();
+        chain.add(Invert, true);
+        chain.add(Fill(0.2), true);
+        chain.apply(&mut framebuffer, None);
+        assert_eq!(framebuffer.color_buffer[0], gray(0.2));
+    }
+
+    #[test]
+    fn only_the_first_enabled_pass_sees_hdr_color() {
+        let hdr = vec![[4.0; 3]; 16];
+        let mut framebuffer = frame(4, 4, |_, _| gray(0.5));
+        let mut chain = PostProcessChain::new();
+        chain.add(Invert, false);
+        chain.add(Fill(0.2), true);
+        chain.add(Fill(0.3), true);
+        chain.apply(&mut framebuffer, Some(&hdr));
+        assert_eq!(framebuffer.color_buffer[0], gray(0.3));
+
+        chain.set_enabled("fill", false);
+        chain.toggle(1);
+        chain.apply(&mut framebuffer, Some(&hdr));
+        assert_eq!(framebuffer.color_buffer[0], gray(1.0));
+    }
+
+    #[test]
+    fn toggling_skips_disabled_passes() {
+        let mut chain = PostProcessChain::with_builtin_passes();
+        let names: Vec<&str> = chain.passes().iter().map(|p| p.pass.name()).collect();
+        assert_eq!(
+            names,
+            ["tonemap", "bloom", "fxaa", "sharpen", "vignette", "gamma"]
+        );
+        assert!(chain.passes().iter().all(|p| !p.enabled));
+
+        let mut framebuffer = frame(4, 4, |_, _| gray(0.25));
+        chain.apply(&mut framebuffer, None);
+        assert_eq!(framebuffer.color_buffer[0], gray(0.25));
+
+        assert_eq!(chain.toggle(5), Some(true));
+        assert_eq!(chain.toggle(6), None);
+        chain.apply(&mut framebuffer, None);
+        assert_ne!(framebuffer.color_buffer[0], gray(0.25));
+
+        assert_eq!(chain.toggle(5), Some(false));
+        chain.set_enabled("vignette", true);
+        assert!(chain.passes()[4].enabled);
+    }
+
+    #[test]
+    fn gamma_brightens_the_midtones() {
+        let mut framebuffer = frame(2, 2, |_, _| gray(0.25));
+        run(GammaCorrection::new(2.0), &mut framebuffer);
+        assert_eq!(framebuffer.color_buffer[0], gray(0.5));
+    }
+
+    #[test]
+    fn tonemapping_compresses_hdr_color_into_range() {
+        let hdr: Vec<[f64; 3]> = [0.5, 1.0, 3.0, 20.0].iter().map(|&v| [v; 3]).collect();
+        let mut framebuffer = frame(4, 1, |_, _| gray(1.0));
+        let mut chain = PostProcessChain::new();
+        chain.add(Tonemap::new(TonemapOperator::Reinhard, 1.0), true);
+        chain.apply(&mut framebuffer, Some(&hdr));
+        let mapped: Vec<u32> = [0.5 / 1.5, 0.5, 0.75, 20.0 / 21.0]
+            .iter()
+            .map(|&v| gray(v))
+            .collect();
+        assert_eq!(framebuffer.color_buffer, mapped);
+
+        // without hdr color the clamped buffer is all there is
+        let mut framebuffer = frame(4, 1, |_, _| gray(1.0));
+        run(
+            Tonemap::new(TonemapOperator::Reinhard, 1.0),
+            &mut framebuffer,
+        );
+        assert_eq!(framebuffer.color_buffer[0], gray(0.5));
+
+        // aces keeps the brightest values apart as well, and never leaves 0 to 1
+        let mut framebuffer = frame(4, 1, |_, _| gray(0.0));
+        let mut chain = PostProcessChain::new();
+        chain.add(Tonemap::new(TonemapOperator::Aces, 1.0), true);
+        chain.apply(&mut framebuffer, Some(&hdr));
+        let levels: Vec<f64> = framebuffer.color_buffer.iter().map(|&c| level(c)).collect();
+        assert!(levels.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn vignette_darkens_the_corners_only() {
+        let mut framebuffer = frame(21, 21, |_, _| gray(1.0));
+        run(Vignette::new(0.5, 0.4), &mut framebuffer);
+        assert_eq!(framebuffer.color_buffer[10 * 21 + 10], gray(1.0));
+        assert!((level(framebuffer.color_buffer[0]) - 0.5).abs() < 0.05);
+    }
+
+    #[test]
+    fn bloom_spreads_light_only_from_bright_pixels() {
+        let dim = |_, _| gray(0.5);
+        let mut framebuffer = frame(15, 15, dim);
+        run(Bloom::new(0.7, 1.0, 2), &mut framebuffer);
+        assert!(framebuffer.color_buffer.iter().all(|&c| c == gray(0.5)));
+
+        let mut framebuffer = frame(15, 15, |x, y| {
+            gray(if (x, y) == (7, 7) { 1.0 } else { 0.0 })
+        });
+        run(Bloom::new(0.7, 1.0, 2), &mut framebuffer);
+        assert!(level(framebuffer.color_buffer[7 * 15 + 9]) > 0.0);
+        assert_eq!(framebuffer.color_buffer[0], gray(0.0));
+    }
+
+    #[test]
+    fn fxaa_softens_edges_and_leaves_flat_areas() {
+        // a vertical edge, then the same edge turned on its side
+        for turned in [false, true] {
+            let at = |along: usize, i: usize| if turned { (i, along) } else { (along, i) };
+            let mut framebuffer = frame(8, 8, |x, y| {
+                let along = if turned { y } else { x };
+                gray(if along < 4 { 0.0 } else { 1.0 })
+            });
+            run(Fxaa::new(), &mut framebuffer);
+
+            for i in 0..8 {
+                let pixel = |along: usize| {
+                    let (x, y) = at(along, i);
+                    level(framebuffer.color_buffer[y * 8 + x])
+                };
+                assert_eq!((pixel(0), pixel(7)), (0.0, 1.0));
+                assert!(pixel(3) > 0.0 && pixel(4) < 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn sharpen_raises_local_contrast() {
+        let mut framebuffer = frame(5, 5, |x, y| gray(if (x, y) == (2, 2) { 0.6 } else { 0.4 }));
+        run(Sharpen::new(0.5), &mut framebuffer);
+        assert!(level(framebuffer.color_buffer[12]) > 0.6);
+        assert!(level(framebuffer.color_buffer[7]) < 0.4);
+        assert_eq!(framebuffer.color_buffer[0], gray(0.4));
+    }
+}
@@ -0,0 +1,850 @@
+use crate::linalg::{cross, dot, Plane, Vector2D, Vector3D};
+use crate::renderer::{Color, Mesh, Triangle};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::ops::RangeInclusive;
+
+// Every generator below centers its shape on the origin with y up, and emits
+// triangles wound like the cube in main: `Triangle::face_normal` points out of the
+// surface. Triangles collapsed to a point or line (sphere poles, cone tips) are dropped.
+
+struct Vertex {
+    position: Vector3D,
+    normal: Vector3D,
+    uv: Vector2D,
+}
+
+struct MeshBuilder {
+    vertices: Vec<Vertex>,
+    triangles: Vec<Triangle>,
+    color: Color,
+}
+
+impl MeshBuilder {
+    fn new(color: &Color) -> MeshBuilder {
+        MeshBuilder {
+            vertices: vec![],
+            triangles: vec![],
+            color: color.clone(),
+        }
+    }
+
+    fn vertex(&mut self, position: Vector3D, normal: Vector3D, uv: Vector2D) -> usize {
+        self.vertices.push(Vertex {
+            position,
+            normal,
+            uv,
+        });
+        self.vertices.len() - 1
+    }
+
+    fn triangle(&mut self, a: usize, b: usize, c: usize) {
+        let (va, vb, vc) = (&self.vertices[a], &self.vertices[b], &self.vertices[c]);
+
+        let face = cross(
+            &vb.position.sub(&va.position),
+            &vc.position.sub(&va.position),
+        );
+        if face.magnitude() < 1e-12 {
+            return;
+        }
+
+        // flip anything that came out facing against its vertex normals
+        let average_normal = va.normal.add(&vb.normal).add(&vc.normal);
+        let (vb, vc) = if dot(&face, &average_normal) < 0.0 {
+            (vc, vb)
+        } else {
+            (vb, vc)
+        };
+
+        self.triangles.push(
+            Triangle::new(va.position, vb.position, vc.position, &self.color)
+                .with_normals(va.normal, vb.normal, vc.normal)
+                .with_uvs(va.uv, vb.uv, vc.uv),
+        );
+    }
+
+    fn quad(&mut self, a: usize, b: usize, c: usize, d: usize) {
+        self.triangle(a, b, c);
+        self.triangle(a, c, d);
+    }
+
+    /// Sweeps a profile of (radius, height) points around the y axis. `normals` are the
+    /// profile's (radial, vertical) normals and `v` its texture coordinate down the profile.
+    fn lathe(
+        &mut self,
+        profile: &[(f64, f64)],
+        normals: &[(f64, f64)],
+        v: &[f64],
+        segments: usize,
+    ) {
+        let segments = segments.max(3);
+        let columns = segments + 1;
+        let first = self.vertices.len();
+
+        for i in 0..profile.len() {
+            let (r, y) = profile[i];
+            let (nr, ny) = normals[i];
+
+            for j in 0..columns {
+                let theta = 2.0 * PI * j as f64 / segments as f64;
+                let (sin, cos) = theta.sin_cos();
+
+                self.vertex(
+                    Vector3D::new(r * cos, y, r * sin),
+                    Vector3D::new(nr * cos, ny, nr * sin).normalize(),
+                    Vector2D::new(j as f64 / segments as f64, v[i]),
+                );
+            }
+        }
+
+        for i in 0..profile.len() - 1 {
+            for j in 0..segments {
+                let a = first + i * columns + j;
+                let b = a + 1;
+                let c = a + columns + 1;
+                let d = a + columns;
+                self.quad(a, b, c, d);
+            }
+        }
+    }
+
+    /// A flat disk at height `y` facing up or down.
+    fn disk(&mut self, y: f64, radius: f64, segments: usize, up: bool) {
+        let segments = segments.max(3);
+        let normal = Vector3D::new(0.0, if up { 1.0 } else { -1.0 }, 0.0);
+        let center = self.vertex(Vector3D::new(0.0, y, 0.0), normal, Vector2D::new(0.5, 0.5));
+
+        let first = self.vertices.len();
+        for j in 0..=segments {
+            let theta = 2.0 * PI * j as f64 / segments as f64;
+            let (sin, cos) = theta.sin_cos();
+            self.vertex(
+                Vector3D::new(radius * cos, y, radius * sin),
+                normal,
+                Vector2D::new(0.5 + 0.5 * cos, 0.5 + 0.5 * sin),
+            );
+        }
+
+        for j in 0..segments {
+            self.triangle(center, first + j, first + j + 1);
+        }
+    }
+
+    fn build(self) -> Mesh {
+        Mesh {
+            triangles: self.triangles,
+        }
+    }
+}
+
+impl Mesh {
+    /// An axis aligned box with the given edge lengths, each face mapped to the whole uv square.
+    pub fn cuboid(size: &Vector3D, color: &Color) -> Mesh {
+        let mut builder = MeshBuilder::new(color);
+        let half = size.scale(0.5);
+
+        // normal, then the two in-plane axes the face spans
+        let faces = [
+            (
+                Vector3D::new(1.0, 0.0, 0.0),
+                Vector3D::new(0.0, 0.0, 1.0),
+                Vector3D::new(0.0, 1.0, 0.0),
+            ),
+            (
+                Vector3D::new(-1.0, 0.0, 0.0),
+                Vector3D::new(0.0, 0.0, -1.0),
+                Vector3D::new(0.0, 1.0, 0.0),
+            ),
+            (
+                Vector3D::new(0.0, 1.0, 0.0),
+                Vector3D::new(1.0, 0.0, 0.0),
+                Vector3D::new(0.0, 0.0, 1.0),
+            ),
+            (
+                Vector3D::new(0.0, -1.0, 0.0),
+                Vector3D::new(1.0, 0.0, 0.0),
+                Vector3D::new(0.0, 0.0, -1.0),
+            ),
+            (
+                Vector3D::new(0.0, 0.0, 1.0),
+                Vector3D::new(-1.0, 0.0, 0.0),
+                Vector3D::new(0.0, 1.0, 0.0),
+            ),
+            (
+                Vector3D::new(0.0, 0.0, -1.0),
+                Vector3D::new(1.0, 0.0, 0.0),
+                Vector3D::new(0.0, 1.0, 0.0),
+            ),
+        ];
+
+        let scale = |v: &Vector3D| Vector3D::new(v.x * half.x, v.y * half.y, v.z * half.z);
+
+        for (normal, u_axis, v_axis) in faces.iter() {
+            let center = scale(normal);
+            let (u, v) = (scale(u_axis), scale(v_axis));
+
+            let corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+            let indices: Vec<usize> = corners
+                .iter()
+                .map(|(cu, cv)| {
+                    let position = center.add(&u.scale(*cu)).add(&v.scale(*cv));
+                    let uv = Vector2D::new((cu + 1.0) / 2.0, (1.0 - cv) / 2.0);
+                    builder.vertex(position, *normal, uv)
+                })
+                .collect();
+
+            builder.quad(indices[0], indices[1], indices[2], indices[3]);
+        }
+
+        builder.build()
+    }
+
+    /// A latitude/longitude sphere, `rings` is the number of bands from pole to pole.
+    pub fn uv_sphere(radius: f64, segments: usize, rings: usize, color: &Color) -> Mesh {
+        let rings = rings.max(2);
+        let mut profile = vec![];
+        let mut normals = vec![];
+        let mut v = vec![];
+
+        for i in 0..=rings {
+            let phi = PI * i as f64 / rings as f64;
+            let (sin, cos) = phi.sin_cos();
+            profile.push((radius * sin, radius * cos));
+            normals.push((sin, cos));
+            v.push(i as f64 / rings as f64);
+        }
+
+        let mut builder = MeshBuilder::new(color);
+        builder.lathe(&profile, &normals, &v, segments);
+        builder.build()
+    }
+
+    /// A sphere made by splitting every face of an icosahedron into four, `subdivisions`
+    /// times over, for evenly sized triangles. Uvs are a spherical projection.
+    pub fn icosphere(radius: f64, subdivisions: usize, color: &Color) -> Mesh {
+        let t = (1.0 + 5.0_f64.sqrt()) / 2.0;
+        let mut points: Vec<Vector3D> = [
+            (-1.0, t, 0.0),
+            (1.0, t, 0.0),
+            (-1.0, -t, 0.0),
+            (1.0, -t, 0.0),
+            (0.0, -1.0, t),
+            (0.0, 1.0, t),
+            (0.0, -1.0, -t),
+            (0.0, 1.0, -t),
+            (t, 0.0, -1.0),
+            (t, 0.0, 1.0),
+            (-t, 0.0, -1.0),
+            (-t, 0.0, 1.0),
+        ]
+        .iter()
+        .map(|(x, y, z)| Vector3D::new(*x, *y, *z).normalize())
+        .collect();
+
+        let mut faces: Vec<[usize; 3]> = vec![
+            [0, 11, 5],
+            [0, 5, 1],
+            [0, 1, 7],
+            [0, 7, 10],
+            [0, 10, 11],
+            [1, 5, 9],
+            [5, 11, 4],
+            [11, 10, 2],
+            [10, 7, 6],
+            [7, 1, 8],
+            [3, 9, 4],
+            [3, 4, 2],
+            [3, 2, 6],
+            [3, 6, 8],
+            [3, 8, 9],
+            [4, 9, 5],
+            [2, 4, 11],
+            [6, 2, 10],
+            [8, 6, 7],
+            [9, 8, 1],
+        ];
+
+        for _ in 0..subdivisions {
+            // shared edges must share their midpoint or the surface cracks
+            let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+            let mut midpoint = |a: usize, b: usize, points: &mut Vec<Vector3D>| {
+                let key = (a.min(b), a.max(b));
+                *midpoints.entry(key).or_insert_with(|| {
+                    points.push(points[a].add(&points[b]).scale(0.5).normalize());
+                    points.len() - 1
+                })
+            };
+
+            let mut next = Vec::with_capacity(faces.len() * 4);
+            for [a, b, c] in faces {
+                let ab = midpoint(a, b, &mut points);
+                let bc = midpoint(b, c, &mut points);
+                let ca = midpoint(c, a, &mut points);
+                next.extend([[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]);
+            }
+            faces = next;
+        }
+
+        let spherical_uv = |p: &Vector3D| {
+            Vector2D::new(
+                0.5 + p.z.atan2(p.x) / (2.0 * PI),
+                p.y.clamp(-1.0, 1.0).acos() / PI,
+            )
+        };
+
+        let mut builder = MeshBuilder::new(color);
+        for face in faces {
+            let mut uvs: Vec<Vector2D> = face.iter().map(|i| spherical_uv(&points[*i])).collect();
+
+            // a face straddling the seam would otherwise wrap its u across the whole texture
+            let max_u = uvs.iter().map(|uv| uv.x).fold(f64::MIN, f64::max);
+            for uv in uvs.iter_mut() {
+                if max_u - uv.x > 0.5 {
+                    uv.x += 1.0;
+                }
+            }
+
+            let indices: Vec<usize> = face
+                .iter()
+                .zip(uvs)
+                .map(|(i, uv)| builder.vertex(points[*i].scale(radius), points[*i], uv))
+                .collect();
+            builder.triangle(indices[0], indices[1], indices[2]);
+        }
+
+        // then cut those faces along the seam, which runs through z = 0 on the -x side, so
+        // the piece past it can take its u back into [0, 1]
+        let mut mesh = builder.build();
+        let front = Plane::new(Vector3D::new(0.0, 0.0, 1.0), 0.0);
+        let back = Plane::new(Vector3D::new(0.0, 0.0, -1.0), 0.0);
+        mesh.triangles = mesh
+            .triangles
+            .into_iter()
+            .flat_map(|triangle| {
+                if triangle.uvs.iter().all(|uv| uv.x <= 1.0) {
+                    return vec![triangle];
+                }
+
+                let mut pieces = triangle.clip(&front);
+                for mut piece in triangle.clip(&back) {
+                    for uv in piece.uvs.iter_mut() {
+                        uv.x -= 1.0;
+                    }
+                    pieces.push(piece);
+                }
+                for piece in pieces.iter_mut() {
+                    for uv in piece.uvs.iter_mut() {
+                        uv.x = uv.x.clamp(0.0, 1.0);
+                    }
+                }
+                // a corner lying on the seam leaves a sliver with no area
+                pieces.retain(|piece| {
+                    let v = &piece.vertices;
+                    cross(&v[1].sub(&v[0]), &v[2].sub(&v[0])).magnitude() > 1e-12
+                });
+                pieces
+            })
+            .collect();
+
+        mesh
+    }
+
+    /// A capped cylinder standing on the y axis.
+    pub fn cylinder(radius: f64, height: f64, segments: usize, color: &Color) -> Mesh {
+        let half = height / 2.0;
+        let mut builder = MeshBuilder::new(color);
+
+        builder.lathe(
+            &[(radius, half), (radius, -half)],
+            &[(1.0, 0.0), (1.0, 0.0)],
+            &[0.0, 1.0],
+            segments,
+        );
+        builder.disk(half, radius, segments, true);
+        builder.disk(-half, radius, segments, false);
+
+        builder.build()
+    }
+
+    /// A cone with its base centered at y = -height / 2 and its tip at y = height / 2.
+    pub fn cone(radius: f64, height: f64, segments: usize, color: &Color) -> Mesh {
+        let half = height / 2.0;
+        let mut builder = MeshBuilder::new(color);
+
+        // the side normal leans up by the slope of the cone
+        let slope = (height, radius);
+        builder.lathe(
+            &[(0.0, half), (radius, -half)],
+            &[slope, slope],
+            &[0.0, 1.0],
+            segments,
+        );
+        builder.disk(-half, radius, segments, false);
+
+        builder.build()
+    }
+
+    /// A ring in the xz plane, `major_radius` to the center of the tube.
+    pub fn torus(
+        major_radius: f64,
+        minor_radius: f64,
+        major_segments: usize,
+        minor_segments: usize,
+        color: &Color,
+    ) -> Mesh {
+        let minor_segments = minor_segments.max(3);
+        let mut profile = vec![];
+        let mut normals = vec![];
+        let mut v = vec![];
+
+        // the tube's cross section, swept around y like any other lathe
+        for i in 0..=minor_segments {
+            let phi = 2.0 * PI * i as f64 / minor_segments as f64;
+            let (sin, cos) = phi.sin_cos();
+            profile.push((major_radius + minor_radius * cos, minor_radius * sin));
+            normals.push((cos, sin));
+            v.push(i as f64 / minor_segments as f64);
+        }
+
+        let mut builder = MeshBuilder::new(color);
+        builder.lathe(&profile, &normals, &v, major_segments);
+        builder.build()
+    }
+
+    /// A flat grid in the xz plane facing up, split into `segments_x` by `segments_z` quads.
+    pub fn grid(
+        width: f64,
+        depth: f64,
+        segments_x: usize,
+        segments_z: usize,
+        color: &Color,
+    ) -> Mesh {
+        let (segments_x, segments_z) = (segments_x.max(1), segments_z.max(1));
+        let up = Vector3D::new(0.0, 1.0, 0.0);
+        let mut builder = MeshBuilder::new(color);
+
+        for j in 0..=segments_z {
+            for i in 0..=segments_x {
+                let u = i as f64 / segments_x as f64;
+                let v = j as f64 / segments_z as f64;
+                builder.vertex(
+                    Vector3D::new((u - 0.5) * width, 0.0, (v - 0.5) * depth),
+                    up,
+                    Vector2D::new(u, v),
+                );
+            }
+        }
+
+        let columns = segments_x + 1;
+        for j in 0..segments_z {
+            for i in 0..segments_x {
+                let a = j * columns + i;
+                builder.quad(a, a + 1, a + columns + 1, a + columns);
+            }
+        }
+
+        builder.build()
+    }
+
+    /// A single quad, the same as a one segment grid.
+    pub fn plane(width: f64, depth: f64, color: &Color) -> Mesh {
+        Mesh::grid(width, depth, 1, 1, color)
+    }
+
+    /// A cylinder of `height` with a hemisphere on each end, so it is `height + 2 * radius` tall.
+    pub fn capsule(radius: f64, height: f64, segments: usize, rings: usize, color: &Color) -> Mesh {
+        let rings = rings.max(1);
+        let half = height / 2.0;
+        let total = height + 2.0 * radius;
+
+        let mut profile = vec![];
+        let mut normals = vec![];
+
+        // top cap from the pole down to the equator, then the bottom cap, the straight
+        // side falls out of the gap between the two equators
+        for i in 0..=rings {
+            let phi = PI / 2.0 * i as f64 / rings as f64;
+            let (sin, cos) = phi.sin_cos();
+            profile.push((radius * sin, half + radius * cos));
+            normals.push((sin, cos));
+        }
+        for i in 0..=rings {
+            let phi = PI / 2.0 + PI / 2.0 * i as f64 / rings as f64;
+            let (sin, cos) = phi.sin_cos();
+            profile.push((radius * sin, -half + radius * cos));
+            normals.push((sin, cos));
+        }
+
+        let v: Vec<f64> = profile
+            .iter()
+            .map(|(_, y)| (total / 2.0 - y) / total)
+            .collect();
+
+        let mut builder = MeshBuilder::new(color);
+        builder.lathe(&profile, &normals, &v, segments);
+        builder.build()
+    }
+}
+
+/// The parameters of one of the generators above, so a mesh can be described and rebuilt
+/// rather than stored triangle by triangle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Primitive {
+    Cuboid {
+        size: Vector3D,
+    },
+    UvSphere {
+        radius: f64,
+        segments: usize,
+        rings: usize,
+    },
+    Icosphere {
+        radius: f64,
+        subdivisions: usize,
+    },
+    Cylinder {
+        radius: f64,
+        height: f64,
+        segments: usize,
+    },
+    Cone {
+        radius: f64,
+        height: f64,
+        segments: usize,
+    },
+    Torus {
+        major_radius: f64,
+        minor_radius: f64,
+        major_segments: usize,
+        minor_segments: usize,
+    },
+    Grid {
+        width: f64,
+        depth: f64,
+        segments_x: usize,
+        segments_z: usize,
+    },
+    Plane {
+        width: f64,
+        depth: f64,
+    },
+    Capsule {
+        radius: f64,
+        height: f64,
+        segments: usize,
+        rings: usize,
+    },
+}
+
+impl Primitive {
+    pub const NAMES: [&'static str; 9] = [
+        "cuboid",
+        "uv_sphere",
+        "icosphere",
+        "cylinder",
+        "cone",
+        "torus",
+        "grid",
+        "plane",
+        "capsule",
+    ];
+
+    pub fn mesh(&self, color: &Color) -> Mesh {
+        match *self {
+            Primitive::Cuboid { size } => Mesh::cuboid(&size, color),
+            Primitive::UvSphere {
+                radius,
+                segments,
+                rings,
+            } => Mesh::uv_sphere(radius, segments, rings, color),
+            Primitive::Icosphere {
+                radius,
+                subdivisions,
+            } => Mesh::icosphere(radius, subdivisions, color),
+            Primitive::Cylinder {
+                radius,
+                height,
+                segments,
+            } => Mesh::cylinder(radius, height, segments, color),
+            Primitive::Cone {
+                radius,
+                height,
+                segments,
+            } => Mesh::cone(radius, height, segments, color),
+            Primitive::Torus {
+                major_radius,
+                minor_radius,
+                major_segments,
+                minor_segments,
+            } => Mesh::torus(
+                major_radius,
+                minor_radius,
+                major_segments,
+                minor_segments,
+                color,
+            ),
+            Primitive::Grid {
+                width,
+                depth,
+                segments_x,
+                segments_z,
+            } => Mesh::grid(width, depth, segments_x, segments_z, color),
+            Primitive::Plane { width, depth } => Mesh::plane(width, depth, color),
+            Primitive::Capsule {
+                radius,
+                height,
+                segments,
+                rings,
+            } => Mesh::capsule(radius, height, segments, rings, color),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        let index = match self {
+            Primitive::Cuboid { .. } => 0,
+            Primitive::UvSphere { .. } => 1,
+            Primitive::Icosphere { .. } => 2,
+            Primitive::Cylinder { .. } => 3,
+            Primitive::Cone { .. } => 4,
+            Primitive::Torus { .. } => 5,
+            Primitive::Grid { .. } => 6,
+            Primitive::Plane { .. } => 7,
+            Primitive::Capsule { .. } => 8,
+        };
+        Primitive::NAMES[index]
+    }
+
+    /// Every parameter by name, counts as whole numbers.
+    pub fn params(&self) -> Vec<(&'static str, f64)> {
+        match *self {
+            Primitive::Cuboid { size } => {
+                vec![("width", size.x), ("height", size.y), ("depth", size.z)]
+            }
+            Primitive::UvSphere {
+                radius,
+                segments,
+                rings,
+            } => vec![
+                ("radius", radius),
+                ("segments", segments as f64),
+                ("rings", rings as f64),
+            ],
+            Primitive::Icosphere {
+                radius,
+                subdivisions,
+            } => vec![("radius", radius), ("subdivisions", subdivisions as f64)],
+            Primitive::Cylinder {
+                radius,
+                height,
+                segments,
+            }
+            | Primitive::Cone {
+                radius,
+                height,
+                segments,
+            } => vec![
+                ("radius", radius),
+                ("height", height),
+                ("segments", segments as f64),
+            ],
+            Primitive::Torus {
+                major_radius,
+                minor_radius,
+                major_segments,
+                minor_segments,
+            } => vec![
+                ("major_radius", major_radius),
+                ("minor_radius", minor_radius),
+                ("major_segments", major_segments as f64),
+                ("minor_segments", minor_segments as f64),
+            ],
+            Primitive::Grid {
+                width,
+                depth,
+                segments_x,
+                segments_z,
+            } => vec![
+                ("width", width),
+                ("depth", depth),
+                ("segments_x", segments_x as f64),
+                ("segments_z", segments_z as f64),
+            ],
+            Primitive::Plane { width, depth } => vec![("width", width), ("depth", depth)],
+            Primitive::Capsule {
+                radius,
+                height,
+                segments,
+                rings,
+            } => vec![
+                ("radius", radius),
+                ("height", height),
+                ("segments", segments as f64),
+                ("rings", rings as f64),
+            ],
+        }
+    }
+
+    /// The primitive called `name` with its parameters read through `param`, which is
+    /// given each parameter's name and default. Counts that aren't whole numbers in their
+    /// range are an error, as are unknown names. The ranges keep meshes from degenerating
+    /// or growing past a few million triangles.
+    pub fn from_params<F: FnMut(&str, f64) -> f64>(
+        name: &str,
+        mut param: F,
+    ) -> Result<Primitive, String> {
+        Ok(match name {
+            "cuboid" => Primitive::Cuboid {
+                size: Vector3D::new(
+                    param("width", 1.0),
+                    param("height", 1.0),
+                    param("depth", 1.0),
+                ),
+            },
+            "uv_sphere" => Primitive::UvSphere {
+                radius: param("radius", 0.5),
+                segments: count("segments", param("segments", 16.0), SEGMENTS)?,
+                rings: count("rings", param("rings", 8.0), 2..=MAX_SEGMENTS)?,
+            },
+            "icosphere" => Primitive::Icosphere {
+                radius: param("radius", 0.5),
+                subdivisions: count("subdivisions", param("subdivisions", 2.0), 0..=7)?,
+            },
+            "cylinder" => Primitive::Cylinder {
+                radius: param("radius", 0.5),
+                height: param("height", 1.0),
+                segments: count("segments", param("segments", 16.0), SEGMENTS)?,
+            },
+            "cone" => Primitive::Cone {
+                radius: param("radius", 0.5),
+                height: param("height", 1.0),
+                segments: count("segments", param("segments", 16.0), SEGMENTS)?,
+            },
+            "torus" => Primitive::Torus {
+                major_radius: param("major_radius", 0.4),
+                minor_radius: param("minor_radius", 0.15),
+                major_segments: count("major_segments", param("major_segments", 16.0), SEGMENTS)?,
+                minor_segments: count("minor_segments", param("minor_segments", 8.0), SEGMENTS)?,
+            },
+            "grid" => Primitive::Grid {
+                width: param("width", 10.0),
+                depth: param("depth", 10.0),
+                segments_x: count("segments_x", param("segments_x", 10.0), 1..=MAX_SEGMENTS)?,
+                segments_z: count("segments_z", param("segments_z", 10.0), 1..=MAX_SEGMENTS)?,
+            },
+            "plane" => Primitive::Plane {
+                width: param("width", 1.0),
+                depth: param("depth", 1.0),
+            },
+            "capsule" => Primitive::Capsule {
+                radius: param("radius", 0.3),
+                height: param("height", 0.5),
+                segments: count("segments", param("segments", 12.0), SEGMENTS)?,
+                rings: count("rings", param("rings", 4.0), 1..=MAX_SEGMENTS)?,
+            },
+            _ => {
+                return Err(format!(
+                    "unknown primitive `{}`, expected one of {}",
+                    name,
+                    Primitive::NAMES.join(", ")
+                ))
+            }
+        })
+    }
+}
+
+const MAX_SEGMENTS: usize = 1024;
+// anything round needs at least a triangle's worth of sides
+const SEGMENTS: RangeInclusive<usize> = 3..=MAX_SEGMENTS;
+
+fn count(name: &str, value: f64, range: RangeInclusive<usize>) -> Result<usize, String> {
+    let whole = value.fract() == 0.0;
+    if whole && value >= *range.start() as f64 && value <= *range.end() as f64 {
+        Ok(value as usize)
+    } else {
+        Err(format!(
+            "`{}` must be a whole number from {} to {}, not {}",
+            name,
+            range.start(),
+            range.end(),
+            value
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every primitive with its default settings
+    fn defaults() -> Vec<Primitive> {
+        Primitive::NAMES
+            .iter()
+            .map(|name| Primitive::from_params(name, |_, default| default).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn winding_agrees_with_the_vertex_normals() {
+        let white = Color::new(255, 255, 255, 255);
+        for primitive in defaults() {
+            let mesh = primitive.mesh(&white);
+            assert!(!mesh.triangles.is_empty(), "{}", primitive.name());
+
+            for triangle in &mesh.triangles {
+                let face = triangle.face_normal();
+                for normal in &triangle.normals {
+                    assert!(dot(&face, normal) > 0.0, "{}", primitive.name());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn uvs_stay_in_the_unit_square() {
+        let white = Color::new(255, 255, 255, 255);
+        let mut primitives = defaults();
+        primitives.push(Primitive::Icosphere {
+            radius: 1.0,
+            subdivisions: 4,
+        });
+
+        for primitive in primitives {
+            for triangle in &primitive.mesh(&white).triangles {
+                assert_eq!(triangle.uvs.len(), 3);
+                for uv in &triangle.uvs {
+                    let inside = (0.0..=1.0).contains(&uv.x) && (0.0..=1.0).contains(&uv.y);
+                    assert!(inside, "{} has uv {} {}", primitive.name(), uv.x, uv.y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn counts_out_of_range_are_refused() {
+        let with = |name: &str, key: &'static str, value: f64| {
+            Primitive::from_params(
+                name,
+                |param, default| {
+                    if param == key {
+                        value
+                    } else {
+                        default
+                    }
+                },
+            )
+        };
+
+        assert!(with("icosphere", "subdivisions", 7.0).is_ok());
+        assert_eq!(
+            with("icosphere", "subdivisions", 20.0),
+            Err("`subdivisions` must be a whole number from 0 to 7, not 20".to_string())
+        );
+        assert!(with("cylinder", "segments", 0.0).is_err());
+        assert!(with("cylinder", "segments", 2.5).is_err());
+        assert!(with("uv_sphere", "rings", 1.0).is_err());
+        assert!(with("torus", "major_segments", 1e9).is_err());
+        assert!(with("grid", "segments_x", 1.0).is_ok());
+        assert!(with("grid", "segments_z", -1.0).is_err());
+    }
+}
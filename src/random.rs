@@ -0,0 +1,59 @@
+/// Small seeded xorshift64* generator, deterministic for a given seed.
+#[derive(Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // splitmix64 spreads seeds that differ in a few bits over the whole state. The one
+        // seed it maps to zero gets a fixed state instead, zero would only produce zeros
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        Rng {
+            state: if z == 0 { 0x9e37_79b9_7f4a_7c15 } else { z },
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Uniform in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform in [min, max).
+    pub fn range(&mut self, min: f64, max: f64) -> f64 {
+        min + (max - min) * self.next_f64()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_seed_gets_stuck_on_zero() {
+        for seed in [0, 1, 0x9e37_79b9_7f4a_7c15, 0x61c8_8646_80b5_83eb, u64::MAX] {
+            let mut rng = Rng::new(seed);
+            assert!((0..4).any(|_| rng.next_u64() != 0), "seed {:#x}", seed);
+        }
+    }
+
+    #[test]
+    fn nearby_seeds_start_far_apart() {
+        // seeds differing in one bit, like a pixel index, shouldn't give similar numbers
+        let first = |seed: u64| Rng::new(seed).next_f64();
+        for bit in 0..64 {
+            let difference = (first(1) - first(1 ^ (1 << bit))).abs();
+            assert!(difference > 1e-6, "bit {}", bit);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use crate::bounds::Aabb;
+use crate::linalg::{
+    cross, dot, multiply_matrix_direction, multiply_matrix_vector, Matrix4D, Vector3D,
+};
+
+/// A half line from `origin` along `direction`. Distances along it are measured in
+/// multiples of `direction`, which is only in world units when it has unit length.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Vector3D,
+    pub direction: Vector3D,
+}
+
+/// Where a ray crosses a triangle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TriangleHit {
+    pub distance: f64,
+    /// Weights of the triangle's three vertices at the hit point.
+    pub barycentric: [f64; 3],
+}
+
+impl Ray {
+    pub fn new(origin: Vector3D, direction: Vector3D) -> Ray {
+        Ray { origin, direction }
+    }
+
+    pub fn at(&self, distance: f64) -> Vector3D {
+        self.origin.add(&self.direction.scale(distance))
+    }
+
+    /// The ray in the space `transform` maps to. The direction is not renormalized, so
+    /// distances along the transformed ray match those along this one.
+    pub fn transform(&self, transform: &Matrix4D) -> Ray {
+        Ray::new(
+            multiply_matrix_vector(&self.origin, transform),
+            multiply_matrix_direction(&self.direction, transform),
+        )
+    }
+
+    /// Distance at which the ray enters the box, 0 if it starts inside, or None if it misses
+    /// or only reaches it after `max_distance`.
+    pub fn intersect_aabb(&self, aabb: &Aabb, max_distance: f64) -> Option<f64> {
+        let mut near = 0.0_f64;
+        let mut far = max_distance;
+
+        for (origin, direction, min, max) in [
+            (self.origin.x, self.direction.x, aabb.min.x, aabb.max.x),
+            (self.origin.y, self.direction.y, aabb.min.y, aabb.max.y),
+            (self.origin.z, self.direction.z, aabb.min.z, aabb.max.z),
+        ] {
+            // a zero direction divides to infinity, which the comparisons below handle
+            let inverse = 1.0 / direction;
+            let t0 = (min - origin) * inverse;
+            let t1 = (max - origin) * inverse;
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+            if near > far {
+                return None;
+            }
+        }
+
+        Some(near)
+    }
+
+    /// Möller-Trumbore intersection with the triangle `a`, `b`, `c`, hitting either side.
+    pub fn intersect_triangle(
+        &self,
+        a: &Vector3D,
+        b: &Vector3D,
+        c: &Vector3D,
+        max_distance: f64,
+    ) -> Option<TriangleHit> {
+        const EPSILON: f64 = 1e-12;
+
+        let edge1 = b.sub(a);
+        let edge2 = c.sub(a);
+        let p = cross(&self.direction, &edge2);
+        let determinant = dot(&edge1, &p);
+        // parallel to the triangle's plane
+        if determinant.abs() < EPSILON {
+            return None;
+        }
+
+        let inverse = 1.0 / determinant;
+        let to_origin = self.origin.sub(a);
+        let u = dot(&to_origin, &p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = cross(&to_origin, &edge1);
+        let v = dot(&self.direction, &q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let distance = dot(&edge2, &q) * inverse;
+        if distance <= EPSILON || distance > max_distance {
+            return None;
+        }
+
+        Some(TriangleHit {
+            distance,
+            barycentric: [1.0 - u - v, u, v],
+        })
+    }
+}
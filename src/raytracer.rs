@@ -0,0 +1,325 @@
+use crate::bvh::{SceneBvh, SceneHit};
+use crate::light::Light;
+use crate::linalg::{dot, multiply_matrix_direction, Matrix4D, Vector3D};
+use crate::material::Material;
+use crate::postprocess::{pack, unpack};
+use crate::random::Rng;
+use crate::ray::Ray;
+use crate::renderer::{Camera, FrameBuffer, Scene, FAR_DEPTH};
+use std::f64::consts::PI;
+use std::thread;
+
+// how far secondary rays start off the surface, so they don't hit it again
+const SURFACE_OFFSET: f64 = 1e-4;
+
+/// The shading inputs at a ray's hit point, in world space.
+pub struct Surface {
+    pub position: Vector3D,
+    /// Unit normal, flipped if needed to face back along the ray.
+    pub normal: Vector3D,
+    /// Whether the ray hit the side the mesh's winding faces out of.
+    pub front_face: bool,
+    /// Vertex colors times the material's albedo, linear 0 to 1.
+    pub color: [f64; 3],
+    pub material: Material,
+}
+
+impl Surface {
+    pub fn at(scene: &Scene, ray: &Ray, hit: &SceneHit) -> Surface {
+        let object = &scene.objects[hit.object];
+        let triangle = &object.mesh.triangles[hit.triangle];
+        let b = &hit.barycentric;
+
+        let local_normal = if triangle.normals.len() == 3 {
+            let n = &triangle.normals;
+            n[0].scale(b[0])
+                .add(&n[1].scale(b[1]))
+                .add(&n[2].scale(b[2]))
+        } else {
+            triangle.face_normal()
+        };
+        // normals move by the inverse transpose so they stay perpendicular under scaling
+        let normal_matrix = object
+            .transform
+            .inverse()
+            .map_or_else(Matrix4D::identity, |inverse| inverse.transpose());
+        let normal = multiply_matrix_direction(&local_normal, &normal_matrix).normalize();
+
+        let front_face = dot(&normal, &ray.direction) < 0.0;
+        let albedo = object.material.albedo;
+        let mut vertex_color = unpack(triangle.interpolate_color(b).to_u32());
+        if let (Some(texture), Some(uv)) = (&object.material.texture, triangle.interpolate_uv(b)) {
+            let texel = texture.sample(&uv);
+            vertex_color = [0, 1, 2].map(|k| vertex_color[k] * texel[k]);
+        }
+
+        Surface {
+            position: ray.at(hit.distance),
+            normal: if front_face {
+                normal
+            } else {
+                normal.scale(-1.0)
+            },
+            front_face,
+            color: [0, 1, 2].map(|i| vertex_color[i] * albedo[i]),
+            material: object.material.clone(),
+        }
+    }
+
+    /// A ray leaving the surface along `direction`, nudged off it to the side it heads to.
+    pub fn spawn_ray(&self, direction: Vector3D) -> Ray {
+        let side = if dot(&direction, &self.normal) >= 0.0 {
+            1.0
+        } else {
+            -1.0
+        };
+        Ray::new(
+            self.position.add(&self.normal.scale(SURFACE_OFFSET * side)),
+            direction,
+        )
+    }
+}
+
+pub fn reflect(direction: &Vector3D, normal: &Vector3D) -> Vector3D {
+    direction.sub(&normal.scale(2.0 * dot(direction, normal)))
+}
+
+/// Bends a unit `direction` through a surface with unit `normal` facing against it, `eta`
+/// being the ratio of refractive indices from the incoming to the outgoing side. None on
+/// total internal reflection.
+pub fn refract(direction: &Vector3D, normal: &Vector3D, eta: f64) -> Option<Vector3D> {
+    let cos_in = -dot(direction, normal);
+    let k = 1.0 - eta * eta * (1.0 - cos_in * cos_in);
+    if k < 0.0 {
+        return None;
+    }
+    Some(
+        direction
+            .scale(eta)
+            .add(&normal.scale(eta * cos_in - k.sqrt())),
+    )
+}
+
+/// Schlick's approximation of the share of light reflected at an interface.
+pub fn fresnel(cos: f64, refractive_index: f64) -> f64 {
+    let r0 = ((1.0 - refractive_index) / (1.0 + refractive_index)).powi(2);
+    r0 + (1.0 - r0) * (1.0 - cos.clamp(0.0, 1.0)).powi(5)
+}
+
+pub fn light_visible(scene: &Scene, bvh: &SceneBvh, surface: &Surface, point: &Vector3D) -> bool {
+    let to_light = point.sub(&surface.position);
+    let distance = to_light.magnitude();
+    let ray = surface.spawn_ray(to_light.scale(1.0 / distance));
+    bvh.intersect_ray(scene, &ray, distance - SURFACE_OFFSET)
+        .is_none()
+}
+
+pub struct RayTracerSettings {
+    /// Bounces of reflection and refraction followed from each camera ray.
+    pub max_depth: u32,
+    /// Shadow rays per light that has a radius, point lights always take one.
+    pub shadow_samples: usize,
+    /// Light reaching every surface regardless of shadows, as a fraction of its color.
+    pub ambient: f64,
+    /// Jittered camera rays averaged for each pixel.
+    pub samples_per_pixel: usize,
+    pub threads: usize,
+    pub seed: u64,
+}
+
+impl Default for RayTracerSettings {
+    fn default() -> RayTracerSettings {
+        RayTracerSettings {
+            max_depth: 5,
+            shadow_samples: 16,
+            ambient: 0.05,
+            samples_per_pixel: 1,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            seed: 1,
+        }
+    }
+}
+
+/// Whitted style ray tracer for the same scenes the rasterizer draws, with shadows from
+/// each light, mirror reflection and refraction. Shadows treat transparent objects as
+/// opaque.
+pub struct RayTracer {
+    pub settings: RayTracerSettings,
+}
+
+impl RayTracer {
+    pub fn new(settings: RayTracerSettings) -> RayTracer {
+        RayTracer { settings }
+    }
+
+    /// Renders `scene` as seen by `camera`, filling both the color and the depth buffer.
+    /// `inv_proj_mat` is the inverse of the rasterizer's projection.
+    pub fn render(
+        &self,
+        scene: &Scene,
+        camera: &Camera,
+        inv_proj_mat: &Matrix4D,
+        framebuffer: &mut FrameBuffer,
+    ) {
+        let bvh = SceneBvh::build(scene);
+        let (width, height) = (framebuffer.width, framebuffer.height);
+        let rows_per_thread = height.div_ceil(self.settings.threads.max(1)).max(1);
+        let forward = camera.forward();
+
+        thread::scope(|s| {
+            let colors = framebuffer.color_buffer.chunks_mut(rows_per_thread * width);
+            let depths = framebuffer.depth_buffer.chunks_mut(rows_per_thread * width);
+
+            for (chunk, (colors, depths)) in colors.zip(depths).enumerate() {
+                let bvh = &bvh;
+                s.spawn(move || {
+                    let first_row = chunk * rows_per_thread;
+                    for (i, (color, depth)) in colors.iter_mut().zip(depths).enumerate() {
+                        let (x, y) = (i % width, first_row + i / width);
+                        let mut rng = Rng::new(self.settings.seed ^ (y * width + x) as u64);
+
+                        let samples = self.settings.samples_per_pixel.max(1);
+                        let mut sum = [0.0; 3];
+                        for sample in 0..samples {
+                            // the first sample goes through the pixel's middle
+                            let (jx, jy) = if sample == 0 {
+                                (0.0, 0.0)
+                            } else {
+                                (rng.range(-0.5, 0.5), rng.range(-0.5, 0.5))
+                            };
+                            let ray = camera.screen_ray(
+                                x as f64 + jx,
+                                y as f64 + jy,
+                                width,
+                                height,
+                                inv_proj_mat,
+                            );
+                            let c = self.trace(scene, bvh, &ray, 0, &mut rng);
+                            sum = [0, 1, 2].map(|k| sum[k] + c[k]);
+
+                            if sample == 0 {
+                                *depth = bvh
+                                    .intersect_ray(scene, &ray, f64::MAX)
+                                    .map_or(FAR_DEPTH, |hit| {
+                                        hit.distance * dot(&ray.direction, &forward)
+                                    });
+                            }
+                        }
+
+                        *color = pack(sum.map(|c| c / samples as f64));
+                    }
+                });
+            }
+        });
+    }
+
+    /// Light arriving along `ray`, `depth` being how many bounces it took to get here.
+    pub fn trace(
+        &self,
+        scene: &Scene,
+        bvh: &SceneBvh,
+        ray: &Ray,
+        depth: u32,
+        rng: &mut Rng,
+    ) -> [f64; 3] {
+        let hit = match bvh.intersect_ray(scene, ray, f64::MAX) {
+            Some(hit) => hit,
+            None => return unpack(scene.background.to_u32()),
+        };
+        let surface = Surface::at(scene, ray, &hit);
+        let material = &surface.material;
+
+        let mut color = material.emission;
+        let add = |color: &mut [f64; 3], light: [f64; 3], weight: f64| {
+            for k in 0..3 {
+                color[k] += light[k] * weight;
+            }
+        };
+
+        let diffuse = material.diffuse();
+        if diffuse > 0.0 {
+            let mut lit = surface.color.map(|c| c * self.settings.ambient);
+            for light in &scene.lights {
+                let direct = self.direct_light(scene, bvh, &surface, light, rng);
+                add(&mut lit, direct, 1.0);
+            }
+            add(&mut color, lit, diffuse);
+        }
+
+        if depth >= self.settings.max_depth {
+            return color;
+        }
+
+        let reflected = reflect(&ray.direction, &surface.normal);
+        if material.reflectivity > 0.0 {
+            let light = self.trace(scene, bvh, &surface.spawn_ray(reflected), depth + 1, rng);
+            add(&mut color, light, material.reflectivity);
+        }
+
+        if material.transparency > 0.0 {
+            let eta = if surface.front_face {
+                1.0 / material.refractive_index
+            } else {
+                material.refractive_index
+            };
+            let cos_in = -dot(&ray.direction, &surface.normal);
+
+            let (reflectance, refracted) = match refract(&ray.direction, &surface.normal, eta) {
+                // going into the denser side the incoming angle is the larger one
+                Some(refracted) => {
+                    let cos = if eta <= 1.0 {
+                        cos_in
+                    } else {
+                        -dot(&refracted, &surface.normal)
+                    };
+                    (fresnel(cos, material.refractive_index), Some(refracted))
+                }
+                None => (1.0, None),
+            };
+
+            let light = self.trace(scene, bvh, &surface.spawn_ray(reflected), depth + 1, rng);
+            add(&mut color, light, material.transparency * reflectance);
+            if let Some(refracted) = refracted {
+                let light = self.trace(scene, bvh, &surface.spawn_ray(refracted), depth + 1, rng);
+                add(
+                    &mut color,
+                    light,
+                    material.transparency * (1.0 - reflectance),
+                );
+            }
+        }
+
+        color
+    }
+
+    // lambertian light from one light, averaged over points on it for soft shadows. The
+    // 1 / PI of the brdf keeps it matching the path tracer.
+    fn direct_light(
+        &self,
+        scene: &Scene,
+        bvh: &SceneBvh,
+        surface: &Surface,
+        light: &Light,
+        rng: &mut Rng,
+    ) -> [f64; 3] {
+        let samples = if light.radius > 0.0 {
+            self.settings.shadow_samples.max(1)
+        } else {
+            1
+        };
+
+        let mut sum = 0.0;
+        for _ in 0..samples {
+            let point = light.sample_point(&surface.position, rng);
+            let to_light = point.sub(&surface.position);
+            let distance_squared = dot(&to_light, &to_light);
+            let cos = dot(&surface.normal, &to_light.normalize());
+            if cos > 0.0 && light_visible(scene, bvh, surface, &point) {
+                sum += cos / distance_squared;
+            }
+        }
+
+        let radiance = light.radiance();
+        [0, 1, 2].map(|k| surface.color[k] * radiance[k] * sum / (samples as f64 * PI))
+    }
+}
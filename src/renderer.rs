@@ -1,9 +1,20 @@
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::fog::Fog;
+use crate::light::Light;
 use crate::linalg::{
-    cross, multiply_matrix_vector, multiply_matrix_vector_perspective_div, Matrix4D, Vector2D,
-    Vector3D,
+    cross, multiply_matrix_direction, multiply_matrix_vector,
+    multiply_matrix_vector_perspective_div, Matrix4D, Plane, Vector2D, Vector3D,
 };
+use crate::material::Material;
+use crate::primitives::Primitive;
+use crate::skinning::Skin;
+use crate::texture::Texture;
 use std::cmp::{max, min};
-use std::{cmp::Ordering, f64::consts::PI};
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+/// Depth the depth buffer is cleared to, further than anything the camera can see.
+pub const FAR_DEPTH: f64 = 10000.0;
 
 fn screen_to_geo(x: i32, y: i32, width: usize, height: usize) -> Vector2D {
     let x_geo = ((x as f64 * 2.0) / width as f64) - 1.0;
@@ -12,17 +23,382 @@ fn screen_to_geo(x: i32, y: i32, width: usize, height: usize) -> Vector2D {
     Vector2D { x: x_geo, y: y_geo }
 }
 
+fn geometric_to_screen(vec: &Vector3D, width: usize, height: usize) -> Vector2D {
+    let x_screen = (vec.x + 1.0) * (width as f64) / 2.0;
+    let y_screen = (1.0 - vec.y) * (height as f64) / 2.0;
+
+    Vector2D {
+        x: x_screen,
+        y: y_screen,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RenderMode {
+    Shaded,
+    Wireframe,
+    ShadedWireframe,
+    /// World space face normal mapped from [-1, 1] to rgb.
+    Normals,
+    /// Distance from the camera, near is white, normalized to the depth range of the frame.
+    Depth,
+    /// Heatmap of how many times each pixel was written, without depth testing.
+    Overdraw,
+    UvChecker,
+}
+
+impl RenderMode {
+    pub const ALL: [RenderMode; 7] = [
+        RenderMode::Shaded,
+        RenderMode::Wireframe,
+        RenderMode::ShadedWireframe,
+        RenderMode::Normals,
+        RenderMode::Depth,
+        RenderMode::Overdraw,
+        RenderMode::UvChecker,
+    ];
+
+    pub fn next(self) -> RenderMode {
+        let i = RenderMode::ALL.iter().position(|m| *m == self).unwrap();
+        RenderMode::ALL[(i + 1) % RenderMode::ALL.len()]
+    }
+
+    fn fills(self) -> bool {
+        self != RenderMode::Wireframe
+    }
+
+    fn draws_wireframe(self) -> bool {
+        matches!(self, RenderMode::Wireframe | RenderMode::ShadedWireframe)
+    }
+}
+
+/// A pixel covered by a triangle, handed to the shading closure of `rasterize_triangle`.
+pub struct Fragment {
+    pub x: usize,
+    pub y: usize,
+    /// View space depth of the pixel.
+    pub depth: f64,
+    /// Perspective correct weights of the triangle's three vertices.
+    pub barycentric: [f64; 3],
+}
+
+/// Counters for the frame in progress, reset by `begin_frame`.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct RenderStats {
+    pub triangles_submitted: usize,
+    /// Triangles that survived clipping and were handed to the rasterizer.
+    pub triangles_drawn: usize,
+    pub objects_submitted: usize,
+    /// Objects skipped whole for being outside the view frustum.
+    pub objects_culled: usize,
+}
+
 pub struct Renderer {
     // scene: Scene,
     pub framebuffer: FrameBuffer,
     pub camera: Camera,
+    pub mode: RenderMode,
+    pub stats: RenderStats,
+    /// When set, only the parts of meshes in front of this world space plane are drawn.
+    pub clip_plane: Option<Plane>,
+    /// Multiplied into the shaded colors of meshes with texture coordinates while set.
+    pub texture: Option<Arc<Texture>>,
 }
 
 impl Renderer {
     // fn render(&mut self) {}
 
+    pub fn new(camera: Camera, framebuffer: FrameBuffer) -> Renderer {
+        Renderer {
+            framebuffer,
+            camera,
+            mode: RenderMode::ShadedWireframe,
+            stats: RenderStats::default(),
+            clip_plane: None,
+            texture: None,
+        }
+    }
+
+    pub fn begin_frame(&mut self) {
+        self.framebuffer.clear();
+        self.stats = RenderStats::default();
+    }
+
+    /// Resolves the modes that can only be colored once every triangle is drawn.
+    pub fn end_frame(&mut self) {
+        match self.mode {
+            RenderMode::Depth => self.resolve_depth(),
+            RenderMode::Overdraw => self.resolve_overdraw(),
+            _ => {}
+        }
+    }
+
+    /// Transforms a world space mesh by the camera and projection and draws it in the current mode.
+    pub fn render_mesh(&mut self, mesh: &Mesh, proj_mat: &Matrix4D) {
+        let clipped;
+        let triangles = match &self.clip_plane {
+            Some(plane) => {
+                clipped = mesh
+                    .triangles
+                    .iter()
+                    .flat_map(|t| t.clip(plane))
+                    .collect::<Vec<Triangle>>();
+                &clipped
+            }
+            None => &mesh.triangles,
+        };
+
+        let mut projected: Vec<(Triangle, Vector3D)> = triangles
+            .iter()
+            .flat_map(|t| {
+                let normal = t.face_normal();
+                self.project_clipped(t, proj_mat)
+                    .into_iter()
+                    .map(move |p| (p, normal))
+            })
+            .collect();
+
+        self.stats.triangles_submitted += mesh.triangles.len();
+        self.stats.triangles_drawn += projected.len();
+
+        // back to front, so the wireframe of near faces lands on top
+        projected.sort_by(|(a, _), (b, _)| {
+            let za = a.vertices[0].z + a.vertices[1].z + a.vertices[2].z;
+            let zb = b.vertices[0].z + b.vertices[1].z + b.vertices[2].z;
+            zb.partial_cmp(&za).unwrap()
+        });
+
+        for (triangle, normal) in &projected {
+            self.draw_projected_triangle(triangle, normal);
+        }
+    }
+
+    /// A world space triangle with its vertices in clip space x and y and view depth z, as
+    /// `rasterize_triangle` takes them. None if any vertex is behind the camera.
+    pub fn project_triangle(&self, triangle: &Triangle, proj_mat: &Matrix4D) -> Option<Triangle> {
+        let view_matrix = self.camera.create_view_matrix();
+        let camera_offset = self.camera.position.scale(-1.0);
+
+        let vertices = triangle
+            .vertices
+            .iter()
+            .map(|v| {
+                let view = multiply_matrix_vector(&v.add(&camera_offset), &view_matrix);
+                multiply_matrix_vector_perspective_div(&view, proj_mat)
+            })
+            .collect::<Option<Vec<Vector3D>>>()?;
+
+        Some(Triangle {
+            vertices,
+            ..triangle.clone()
+        })
+    }
+
+    /// Like `project_triangle`, but a triangle reaching behind the camera is cut at the near
+    /// plane in view space first, leaving up to two triangles instead of none.
+    pub fn project_clipped(&self, triangle: &Triangle, proj_mat: &Matrix4D) -> Vec<Triangle> {
+        let view_matrix = self.camera.create_view_matrix();
+        let camera_offset = self.camera.position.scale(-1.0);
+
+        let view = Triangle {
+            vertices: triangle
+                .vertices
+                .iter()
+                .map(|v| multiply_matrix_vector(&v.add(&camera_offset), &view_matrix))
+                .collect(),
+            ..triangle.clone()
+        };
+        let near = Plane::new(Vector3D::new(0.0, 0.0, 1.0), -self.camera.near_clip);
+
+        view.clip(&near)
+            .into_iter()
+            .filter_map(|t| {
+                let vertices = t
+                    .vertices
+                    .iter()
+                    .map(|v| multiply_matrix_vector_perspective_div(v, proj_mat))
+                    .collect::<Option<Vec<Vector3D>>>()?;
+                Some(Triangle { vertices, ..t })
+            })
+            .collect()
+    }
+
+    fn draw_projected_triangle(&mut self, triangle: &Triangle, normal: &Vector3D) {
+        let mode = self.mode;
+        let texture = self.texture.clone();
+
+        if mode.fills() {
+            let checker_light = Color::new(220, 220, 220, 255);
+            let checker_dark = Color::new(60, 60, 60, 255);
+
+            self.rasterize_triangle(triangle, |fragment| match mode {
+                RenderMode::Normals => Color::new(
+                    ((normal.x + 1.0) * 127.5) as u8,
+                    ((normal.y + 1.0) * 127.5) as u8,
+                    ((normal.z + 1.0) * 127.5) as u8,
+                    255,
+                ),
+                RenderMode::UvChecker => {
+                    // without texture coordinates fall back to the triangle's own parameterization
+                    let (u, v) = match triangle.interpolate_uv(&fragment.barycentric) {
+                        Some(uv) => (uv.x, uv.y),
+                        None => (fragment.barycentric[1], fragment.barycentric[2]),
+                    };
+                    let cells = 8.0;
+                    if ((u * cells).floor() + (v * cells).floor()) as i64 % 2 == 0 {
+                        checker_light.clone()
+                    } else {
+                        checker_dark.clone()
+                    }
+                }
+                _ => {
+                    let color = triangle.interpolate_color(&fragment.barycentric);
+                    match (&texture, triangle.interpolate_uv(&fragment.barycentric)) {
+                        (Some(texture), Some(uv)) => {
+                            let [r, g, b] = texture.sample(&uv);
+                            let scale = |c: u8, t: f64| (c as f64 * t).round() as u8;
+                            Color::new(
+                                scale(color.r, r),
+                                scale(color.g, g),
+                                scale(color.b, b),
+                                color.a,
+                            )
+                        }
+                        _ => color,
+                    }
+                }
+            });
+        }
+
+        if mode.draws_wireframe() {
+            self.draw_projected_edges(triangle, &Color::new(255, 255, 255, 255));
+        }
+    }
+
+    /// Fills a projected triangle (x and y in clip space, z holding view depth), calling `shade`
+    /// for every pixel that passes the depth test. Overdraw mode skips the depth test.
+    pub fn rasterize_triangle<F: Fn(&Fragment) -> Color>(&mut self, tri: &Triangle, shade: F) {
+        let (width, height) = (self.framebuffer.width, self.framebuffer.height);
+        let depth_test = self.mode != RenderMode::Overdraw;
+
+        let screen: Vec<Vector2D> = tri
+            .vertices
+            .iter()
+            .map(|v| geometric_to_screen(v, width, height))
+            .collect();
+        let inv_z: Vec<f64> = tri.vertices.iter().map(|v| 1.0 / v.z).collect();
+
+        let edge = |a: &Vector2D, b: &Vector2D, x: f64, y: f64| {
+            (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x)
+        };
+
+        let area = edge(&screen[0], &screen[1], screen[2].x, screen[2].y);
+        if area.abs() < f64::EPSILON {
+            return;
+        }
+
+        let min_x = screen.iter().map(|v| v.x).fold(f64::MAX, f64::min).max(0.0) as usize;
+        let min_y = screen.iter().map(|v| v.y).fold(f64::MAX, f64::min).max(0.0) as usize;
+        let max_x = screen
+            .iter()
+            .map(|v| v.x)
+            .fold(f64::MIN, f64::max)
+            .min(width as f64 - 1.0);
+        let max_y = screen
+            .iter()
+            .map(|v| v.y)
+            .fold(f64::MIN, f64::max)
+            .min(height as f64 - 1.0);
+
+        if max_x < 0.0 || max_y < 0.0 {
+            return;
+        }
+
+        for y in min_y..=(max_y as usize) {
+            for x in min_x..=(max_x as usize) {
+                let (px, py) = (x as f64 + 0.5, y as f64 + 0.5);
+
+                let b0 = edge(&screen[1], &screen[2], px, py) / area;
+                let b1 = edge(&screen[2], &screen[0], px, py) / area;
+                let b2 = edge(&screen[0], &screen[1], px, py) / area;
+
+                if b0 < 0.0 || b1 < 0.0 || b2 < 0.0 {
+                    continue;
+                }
+
+                // 1/z is linear in screen space, z itself is not
+                let w = [b0 * inv_z[0], b1 * inv_z[1], b2 * inv_z[2]];
+                let pixel_inv_z = w[0] + w[1] + w[2];
+                let depth = 1.0 / pixel_inv_z;
+
+                let i = y * width + x;
+                if depth_test && depth >= self.framebuffer.depth_buffer[i] {
+                    continue;
+                }
+
+                let fragment = Fragment {
+                    x,
+                    y,
+                    depth,
+                    barycentric: w.map(|b| b / pixel_inv_z),
+                };
+                let color = shade(&fragment);
+
+                self.framebuffer.depth_buffer[i] = depth;
+                self.framebuffer.color_buffer[i] = color.to_u32();
+                self.framebuffer.overdraw_buffer[i] += 1;
+            }
+        }
+    }
+
+    fn resolve_depth(&mut self) {
+        let visible = self
+            .framebuffer
+            .depth_buffer
+            .iter()
+            .filter(|d| **d < FAR_DEPTH);
+        let (near, far) =
+            visible.fold((f64::MAX, f64::MIN), |(lo, hi), d| (lo.min(*d), hi.max(*d)));
+        let range = (far - near).max(f64::EPSILON);
+
+        for (color, depth) in self
+            .framebuffer
+            .color_buffer
+            .iter_mut()
+            .zip(&self.framebuffer.depth_buffer)
+        {
+            *color = if *depth < FAR_DEPTH {
+                let shade = (255.0 * (1.0 - (depth - near) / range)) as u8;
+                Color::new(shade, shade, shade, 255).to_u32()
+            } else {
+                0
+            };
+        }
+    }
+
+    fn resolve_overdraw(&mut self) {
+        // black for untouched pixels, then blue, green, yellow, red and white for 5 or more writes
+        let ramp = [
+            Color::new(0, 0, 0, 255),
+            Color::new(0, 0, 255, 255),
+            Color::new(0, 200, 0, 255),
+            Color::new(255, 255, 0, 255),
+            Color::new(255, 0, 0, 255),
+            Color::new(255, 255, 255, 255),
+        ];
+
+        for (color, count) in self
+            .framebuffer
+            .color_buffer
+            .iter_mut()
+            .zip(&self.framebuffer.overdraw_buffer)
+        {
+            *color = ramp[(*count as usize).min(ramp.len() - 1)].to_u32();
+        }
+    }
+
     pub fn fill_triangle(&mut self, v1: &Vector2D, v2: &Vector2D, v3: &Vector2D, color: &Color) {
-        let mut vertices = vec![v1, v2, v3];
+        let mut vertices = [v1, v2, v3];
         vertices.sort_by(|a, b| a.y.partial_cmp(&b.y).unwrap());
 
         let v1 = vertices[0];
@@ -70,8 +446,12 @@ impl Renderer {
 pub struct FrameBuffer {
     pub color_buffer: Vec<u32>,
     pub depth_buffer: Vec<f64>,
+    /// Number of writes to each pixel since the last clear.
+    pub overdraw_buffer: Vec<u32>,
     pub width: usize,
     pub height: usize,
+    /// What `clear` fills the color buffer with.
+    pub clear_color: Color,
     pub depth_func: Box<dyn Fn(f64, f64) -> f64>,
 }
 
@@ -80,22 +460,26 @@ impl FrameBuffer {
         FrameBuffer {
             color_buffer: vec![0; width * height],
             depth_buffer: vec![0.0; width * height],
+            overdraw_buffer: vec![0; width * height],
             width,
             height,
-            depth_func: Box::new(|x: f64, y: f64| 0.0),
+            clear_color: Color::new(0, 0, 0, 255),
+            depth_func: Box::new(|_x: f64, _y: f64| 0.0),
         }
     }
 
     pub fn clear(&mut self) {
         let len = self.width * self.height;
+        let clear_color = self.clear_color.to_u32();
 
         for i in 0..len {
-            self.color_buffer[i] = 0;
-            self.depth_buffer[i] = 10000.0;
+            self.color_buffer[i] = clear_color;
+            self.depth_buffer[i] = FAR_DEPTH;
+            self.overdraw_buffer[i] = 0;
         }
     }
 
-    pub fn set_pixel(&mut self, x: usize, y: usize, color: &Color, depth: f32) {
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: &Color, _depth: f32) {
         if x > self.width || y > self.height {
             return;
         }
@@ -110,6 +494,7 @@ impl FrameBuffer {
         // if d < self.depth_buffer[y * self.width + x] {
         self.depth_buffer[y * self.width + x] = d;
         self.color_buffer[y * self.width + x] = color.to_u32();
+        self.overdraw_buffer[y * self.width + x] += 1;
         // }
     }
 
@@ -147,18 +532,69 @@ impl FrameBuffer {
         }
     }
 
-    pub fn draw_triangle(&mut self, tri: &Triangle) {}
+    pub fn draw_triangle(&mut self, _tri: &Triangle) {}
 }
 
 pub struct Scene {
     pub objects: Vec<Object>,
+    pub lights: Vec<Light>,
+    /// Seen wherever no object is.
+    pub background: Color,
+    /// Where the camera starts, None to leave it wherever it is.
+    pub camera: Option<CameraSetup>,
+    pub fog: Option<Fog>,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
+impl Scene {
+    pub fn new() -> Scene {
+        Scene {
+            objects: vec![],
+            lights: vec![],
+            background: Color::new(0, 0, 0, 255),
+            camera: None,
+            fog: None,
+        }
+    }
+}
+
+/// Where an object's mesh came from, so it can be saved as that rather than as triangles.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MeshSource {
+    /// Built in code, saved triangle by triangle.
+    Inline,
+    /// Loaded from an obj or stl file, path as written in the scene file. `color` is for vertices
+    /// without colors of their own.
+    File {
+        path: String,
+        color: Color,
+    },
+    Primitive {
+        primitive: Primitive,
+        color: Color,
+    },
+}
+
+#[derive(Clone)]
 pub struct Object {
     pub mesh: Mesh,
     pub transform: Matrix4D,
+    pub material: Material,
+    /// Set back to `MeshSource::Inline` after editing `mesh` so saving keeps the edits.
+    pub source: MeshSource,
+    /// When set, `mesh` is the skin's bind mesh deformed into its current pose.
+    pub skin: Option<Skin>,
+    /// Bounds of `mesh` before `transform`, see `Object::update_bounds`.
+    pub aabb: Aabb,
+    pub sphere: BoundingSphere,
 }
 
+#[derive(Clone)]
 pub struct Mesh {
     pub triangles: Vec<Triangle>,
 }
@@ -175,9 +611,16 @@ impl Mesh {
                     .map(|v| multiply_matrix_vector(v, mat))
                     .collect();
 
+                let updated_normals = t
+                    .normals
+                    .iter()
+                    .map(|n| multiply_matrix_direction(n, mat).normalize())
+                    .collect();
+
                 Triangle {
                     vertices: updated_vertices,
-                    color: t.color.clone(),
+                    normals: updated_normals,
+                    ..t.clone()
                 }
             })
             .collect();
@@ -204,7 +647,7 @@ impl Mesh {
 
                 Some(Triangle {
                     vertices: updated_vertices,
-                    color: t.color.clone(),
+                    ..t.clone()
                 })
             })
             .collect();
@@ -223,7 +666,7 @@ impl Mesh {
 
                 Triangle {
                     vertices: updated_vertices,
-                    color: t.color.clone(),
+                    ..t.clone()
                 }
             })
             .collect();
@@ -242,9 +685,20 @@ impl Mesh {
     }
 }
 
+#[derive(Clone)]
 pub struct Triangle {
     pub vertices: Vec<Vector3D>,
     pub color: Color,
+    /// Per vertex normals, empty when the triangle is flat shaded.
+    pub normals: Vec<Vector3D>,
+    /// Per vertex texture coordinates, empty when the triangle has none.
+    pub uvs: Vec<Vector2D>,
+    /// Per vertex colors blended across the face, empty to fill it with `color`.
+    pub colors: Vec<Color>,
+    /// Per vertex skeleton joints the vertex follows, empty when the triangle isn't skinned.
+    pub joints: Vec<[usize; 4]>,
+    /// How much the vertex follows each of its `joints`, adding up to 1.
+    pub weights: Vec<[f64; 4]>,
 }
 
 impl Triangle {
@@ -252,7 +706,157 @@ impl Triangle {
         Triangle {
             vertices: vec![a, b, c],
             color: color.clone(),
+            normals: vec![],
+            uvs: vec![],
+            colors: vec![],
+            joints: vec![],
+            weights: vec![],
+        }
+    }
+
+    pub fn with_normals(mut self, a: Vector3D, b: Vector3D, c: Vector3D) -> Self {
+        self.normals = vec![a, b, c];
+        self
+    }
+
+    pub fn with_uvs(mut self, a: Vector2D, b: Vector2D, c: Vector2D) -> Self {
+        self.uvs = vec![a, b, c];
+        self
+    }
+
+    pub fn with_colors(mut self, a: Color, b: Color, c: Color) -> Self {
+        self.colors = vec![a, b, c];
+        self
+    }
+
+    pub fn with_skin_weights(mut self, joints: [[usize; 4]; 3], weights: [[f64; 4]; 3]) -> Self {
+        self.joints = joints.to_vec();
+        self.weights = weights.to_vec();
+        self
+    }
+
+    /// The vertex colors blended at a point, or the flat `color` if there are none.
+    pub fn interpolate_color(&self, barycentric: &[f64; 3]) -> Color {
+        if self.colors.len() < 3 {
+            return self.color.clone();
+        }
+
+        let (mut r, mut g, mut b, mut a) = (0.0, 0.0, 0.0, 0.0);
+        for (color, weight) in self.colors.iter().zip(barycentric) {
+            r += color.r as f64 * weight;
+            g += color.g as f64 * weight;
+            b += color.b as f64 * weight;
+            a += color.a as f64 * weight;
+        }
+        let channel = |v: f64| v.round().clamp(0.0, 255.0) as u8;
+        Color::new(channel(r), channel(g), channel(b), channel(a))
+    }
+
+    pub fn interpolate_uv(&self, barycentric: &[f64; 3]) -> Option<Vector2D> {
+        if self.uvs.len() < 3 {
+            return None;
+        }
+
+        let (mut u, mut v) = (0.0, 0.0);
+        for (uv, weight) in self.uvs.iter().zip(barycentric) {
+            u += uv.x * weight;
+            v += uv.y * weight;
+        }
+        Some(Vector2D::new(u, v))
+    }
+
+    /// The parts of the triangle in front of `plane`, none, itself, or one or two triangles
+    /// with interpolated normals, uvs and colors and the same winding.
+    pub fn clip(&self, plane: &Plane) -> Vec<Triangle> {
+        let distances: Vec<f64> = self
+            .vertices
+            .iter()
+            .map(|v| plane.signed_distance(v))
+            .collect();
+
+        if distances.iter().all(|d| *d >= 0.0) {
+            return vec![self.clone()];
+        }
+        if distances.iter().all(|d| *d < 0.0) {
+            return vec![];
         }
+
+        let has_normals = self.normals.len() == 3;
+        let has_uvs = self.uvs.len() == 3;
+        let has_colors = self.colors.len() == 3;
+
+        // sutherland-hodgman, keeping each vertex's attributes alongside it
+        let mut polygon: Vec<(Vector3D, Vector3D, Vector2D, Color)> = vec![];
+        for i in 0..3 {
+            let j = (i + 1) % 3;
+            let attributes = |k: usize| {
+                (
+                    self.vertices[k],
+                    if has_normals {
+                        self.normals[k]
+                    } else {
+                        Vector3D::new(0.0, 0.0, 0.0)
+                    },
+                    if has_uvs {
+                        self.uvs[k]
+                    } else {
+                        Vector2D::new(0.0, 0.0)
+                    },
+                    if has_colors {
+                        self.colors[k].clone()
+                    } else {
+                        self.color.clone()
+                    },
+                )
+            };
+
+            if distances[i] >= 0.0 {
+                polygon.push(attributes(i));
+            }
+            if (distances[i] >= 0.0) != (distances[j] >= 0.0) {
+                let t = distances[i] / (distances[i] - distances[j]);
+                let (a, b) = (attributes(i), attributes(j));
+                polygon.push((
+                    a.0.add(&b.0.sub(&a.0).scale(t)),
+                    a.1.add(&b.1.sub(&a.1).scale(t)),
+                    Vector2D::new(a.2.x + (b.2.x - a.2.x) * t, a.2.y + (b.2.y - a.2.y) * t),
+                    a.3.lerp(&b.3, t),
+                ));
+            }
+        }
+
+        (1..polygon.len() - 1)
+            .map(|i| {
+                let (a, b, c) = (&polygon[0], &polygon[i], &polygon[i + 1]);
+                // new vertices have no joints of their own, clip after skinning
+                let mut triangle = Triangle {
+                    vertices: vec![a.0, b.0, c.0],
+                    joints: vec![],
+                    weights: vec![],
+                    ..self.clone()
+                };
+                if has_normals {
+                    triangle.normals = vec![a.1.normalize(), b.1.normalize(), c.1.normalize()];
+                }
+                if has_uvs {
+                    triangle.uvs = vec![a.2, b.2, c.2];
+                }
+                if has_colors {
+                    triangle.colors = vec![a.3.clone(), b.3.clone(), c.3.clone()];
+                }
+                triangle
+            })
+            .collect()
+    }
+
+    /// Unit normal of the triangle's plane, pointing out of the side the vertices wind
+    /// clockwise around.
+    pub fn face_normal(&self) -> Vector3D {
+        cross(
+            &self.vertices[1].sub(&self.vertices[0]),
+            &self.vertices[2].sub(&self.vertices[0]),
+        )
+        .normalize()
     }
 }
 
@@ -271,6 +875,56 @@ pub struct Input {
     pub mouse_dy: f64,
 }
 
+/// A camera placement as written in a scene file, angles in degrees.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraSetup {
+    pub position: Vector3D,
+    pub yaw: f64,
+    pub pitch: f64,
+    pub fov: f64,
+    pub near: f64,
+    pub far: f64,
+}
+
+impl Default for CameraSetup {
+    fn default() -> CameraSetup {
+        CameraSetup {
+            position: Vector3D::new(0.0, 0.0, 0.0),
+            yaw: 0.0,
+            pitch: 0.0,
+            fov: 90.0,
+            near: 0.1,
+            far: 1000.0,
+        }
+    }
+}
+
+impl CameraSetup {
+    pub fn from_camera(camera: &Camera, fov: f64) -> CameraSetup {
+        CameraSetup {
+            position: camera.position,
+            yaw: camera.yaw.to_degrees(),
+            pitch: camera.pitch.to_degrees(),
+            fov,
+            near: camera.near_clip,
+            far: camera.far_clip,
+        }
+    }
+
+    pub fn camera(&self, aspect_ratio: f64) -> Camera {
+        Camera {
+            position: self.position,
+            aspect_ratio,
+            near_clip: self.near,
+            far_clip: self.far,
+            front: Vector3D::new(0.0, 0.0, -1.0),
+            up: Vector3D::new(0.0, 1.0, 0.0),
+            yaw: self.yaw.to_radians(),
+            pitch: self.pitch.to_radians(),
+        }
+    }
+}
+
 pub struct Camera {
     pub position: Vector3D,
     pub aspect_ratio: f64,
@@ -284,11 +938,41 @@ pub struct Camera {
 
 impl Camera {
     pub fn update(&mut self, input: &Input, delta_time: f64) {
+        self.look(input);
+
+        let mut movement = self.move_direction(input);
+
+        if input.up {
+            movement = movement.add(&Vector3D::new(0.0, 1.0, 0.0));
+        }
+        if input.down {
+            movement = movement.add(&Vector3D::new(0.0, -1.0, 0.0));
+        }
+
+        let move_speed = 10.0;
+        let speed = move_speed * delta_time;
+        self.position = self.position.add(&movement.scale(speed));
+    }
+
+    /// Unit world space direction the camera looks along.
+    pub fn forward(&self) -> Vector3D {
+        Vector3D::new(
+            self.yaw.sin() * self.pitch.cos(),
+            -self.pitch.sin(),
+            self.yaw.cos() * self.pitch.cos(),
+        )
+    }
+
+    /// Turns the camera by the mouse movement in `input`.
+    pub fn look(&mut self, input: &Input) {
         let mouse_sensitivity = 0.004;
         self.yaw += input.mouse_dx * mouse_sensitivity;
         self.pitch += input.mouse_dy * mouse_sensitivity;
         self.pitch = self.pitch.clamp(-PI / 4.0, PI / 4.0);
+    }
 
+    /// Unit horizontal direction the movement keys in `input` point, zero when none are held.
+    pub fn move_direction(&self, input: &Input) -> Vector3D {
         let forward = Vector3D::new(self.yaw.sin(), 0.0, self.yaw.cos());
         let mut movement = Vector3D::new(0.0, 0.0, 0.0);
 
@@ -312,16 +996,7 @@ impl Camera {
             movement = movement.normalize();
         }
 
-        if input.up {
-            movement = movement.add(&Vector3D::new(0.0, 1.0, 0.0));
-        }
-        if input.down {
-            movement = movement.add(&Vector3D::new(0.0, -1.0, 0.0));
-        }
-
-        let move_speed = 10.0;
-        let speed = move_speed * delta_time;
-        self.position = self.position.add(&movement.scale(speed));
+        movement
     }
 
     pub fn create_view_matrix(&self) -> Matrix4D {
@@ -373,12 +1048,12 @@ impl Camera {
             [1.0 / (aspect_ratio * fov_rad), 0.0, 0.0, 0.0],
             [0.0, 1.0 / fov_rad, 0.0, 0.0],
             [0.0, 0.0, 0.0, (far - near) / -(far * near)],
-            [0.0, 0.0, 1.0, far / (far - near)],
+            [0.0, 0.0, 1.0, 1.0 / near],
         ])
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -395,4 +1070,127 @@ impl Color {
         let (r, g, b, a) = (self.r as u32, self.g as u32, self.b as u32, self.a as u32);
         (a << 24) | (r << 16) | (g << 8) | b
     }
+
+    pub fn from_u32(value: u32) -> Color {
+        Color {
+            r: (value >> 16) as u8,
+            g: (value >> 8) as u8,
+            b: value as u8,
+            a: (value >> 24) as u8,
+        }
+    }
+
+    /// Linear interpolation between two colors, `t` of 0 is `self` and 1 is `other`.
+    pub fn lerp(&self, other: &Color, t: f64) -> Color {
+        let mix = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+        Color {
+            r: mix(self.r, other.r),
+            g: mix(self.g, other.g),
+            b: mix(self.b, other.b),
+            a: mix(self.a, other.a),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 40;
+
+    fn renderer(mode: RenderMode) -> (Renderer, Matrix4D) {
+        let camera = CameraSetup::default().camera(1.0);
+        let proj_mat = camera.get_proj_matrix(1.0, 90.0, camera.near_clip, camera.far_clip);
+        let mut renderer = Renderer::new(camera, FrameBuffer::new(SIZE, SIZE));
+        renderer.mode = mode;
+        renderer.begin_frame();
+        (renderer, proj_mat)
+    }
+
+    fn pixel(renderer: &Renderer, x: usize, y: usize) -> Color {
+        Color::from_u32(renderer.framebuffer.color_buffer[y * SIZE + x])
+    }
+
+    #[test]
+    fn wireframe_edges_are_hidden_by_nearer_faces() {
+        let (mut renderer, proj_mat) = renderer(RenderMode::ShadedWireframe);
+        let red = Color::new(255, 0, 0, 255);
+        let white = Color::new(255, 255, 255, 255);
+        let corner = |x, y, z| Vector3D::new(x, y, z);
+
+        let near = Mesh {
+            triangles: vec![
+                Triangle::new(
+                    corner(-2.0, -2.0, 5.0),
+                    corner(2.0, 2.0, 5.0),
+                    corner(2.0, -2.0, 5.0),
+                    &red,
+                ),
+                Triangle::new(
+                    corner(-2.0, -2.0, 5.0),
+                    corner(-2.0, 2.0, 5.0),
+                    corner(2.0, 2.0, 5.0),
+                    &red,
+                ),
+            ],
+        };
+        // its long edge runs behind the near quad, across the quad's own diagonal
+        let far = Mesh {
+            triangles: vec![Triangle::new(
+                corner(-10.0, 10.0, 20.0),
+                corner(10.0, 10.0, 20.0),
+                corner(10.0, -10.0, 20.0),
+                &red,
+            )],
+        };
+        renderer.render_mesh(&near, &proj_mat);
+        renderer.render_mesh(&far, &proj_mat);
+
+        // the near quad covers pixels 7 to 32, only its own edges are drawn over it
+        for y in 8..32 {
+            for x in 8..32 {
+                let depth = renderer.framebuffer.depth_buffer[y * SIZE + x];
+                assert!((depth - 5.0).abs() < 0.1, "pixel {} {} at {}", x, y, depth);
+            }
+        }
+        for x in (9..18).chain(22..31) {
+            assert_eq!(pixel(&renderer, x, x), red, "pixel {} {}", x, x);
+        }
+        // outside it the far edge shows
+        assert!((4..7)
+            .chain(33..36)
+            .all(|x| pixel(&renderer, x, x) == white));
+    }
+
+    #[test]
+    fn triangles_reaching_behind_the_camera_are_clipped() {
+        let (mut renderer, proj_mat) = renderer(RenderMode::Shaded);
+        let green = Color::new(0, 255, 0, 255);
+        let ground = Triangle::new(
+            Vector3D::new(-50.0, -1.0, -10.0),
+            Vector3D::new(0.0, -1.0, 50.0),
+            Vector3D::new(50.0, -1.0, -10.0),
+            &green,
+        );
+
+        assert!(renderer.project_triangle(&ground, &proj_mat).is_none());
+        let clipped = renderer.project_clipped(&ground, &proj_mat);
+        assert!(!clipped.is_empty());
+        for triangle in &clipped {
+            assert!(triangle
+                .vertices
+                .iter()
+                .all(|v| v.z >= renderer.camera.near_clip - 1e-9));
+        }
+
+        renderer.render_mesh(
+            &Mesh {
+                triangles: vec![ground],
+            },
+            &proj_mat,
+        );
+        // the ground fills the bottom of the screen right up to the camera
+        assert_eq!(pixel(&renderer, SIZE / 2, SIZE - 1), green);
+        assert_ne!(pixel(&renderer, SIZE / 2, 0), green);
+    }
 }
@@ -0,0 +1,1041 @@
+//! A text format for scenes, one block per object, light and setting:
+//!
+//! ```text
+//! # comments run to the end of the line
+//! background 20 24 32
+//! fog { color 150 160 180 density 0.02 start 10 }
+//! camera { position 0 1 -2 yaw 0 pitch 10 fov 90 near 0.1 far 1000 }
+//! light { position 2 5 1 color 1 1 1 intensity 90 radius 0.5 }
+//! object {
+//!     primitive uv_sphere { radius 0.5 segments 16 rings 8 }
+//!     color 128 128 128
+//!     rotate 0 45 0
+//!     translate 0 0.5 6
+//!     material { reflectivity 0.8 texture "textures/marble.png" }
+//! }
+//! object { mesh "models/teapot.obj" scale 2 2 2 }
+//! ```
+//!
+//! Angles are in degrees, colors are 0 to 255 with an optional alpha, `mesh` files are obj
+//! or stl by their extension, and an object's `translate`, `rotate`, `scale` and `matrix`
+//! are applied in the order written. Meshes made in code are written as `triangle` blocks,
+//! so saving a scene and loading it again gives back the same scene. Saving refuses what the
+//! format can't hold rather than dropping it: skinned objects, since skeletons aren't part
+//! of it, textures made in code without a file to point to, and objects with no triangles.
+
+use crate::fog::Fog;
+use crate::light::Light;
+use crate::linalg::{
+    get_x_rotation_matrix, get_y_rotation_matrix, get_z_rotation_matrix, Matrix4D, Vector2D,
+    Vector3D,
+};
+use crate::material::Material;
+use crate::primitives::Primitive;
+use crate::renderer::{CameraSetup, Color, Mesh, MeshSource, Object, Scene, Triangle};
+use crate::texture::Texture;
+use std::fmt::{self, Write};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    /// Where in the scene file reading stopped, both numbered from 1.
+    Parse {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    /// Something in a scene being saved that the format can't hold.
+    Unsaveable(String),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io(e) => write!(f, "{}", e),
+            SceneError::Parse {
+                line,
+                column,
+                message,
+            } => write!(f, "line {}, column {}: {}", line, column, message),
+            SceneError::Unsaveable(message) => write!(f, "can't save the scene, {}", message),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<std::io::Error> for SceneError {
+    fn from(e: std::io::Error) -> SceneError {
+        SceneError::Io(e)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Word(String),
+    Text(String),
+    Open,
+    Close,
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match &self.kind {
+            TokenKind::Word(word) => format!("`{}`", word),
+            TokenKind::Text(text) => format!("\"{}\"", text),
+            TokenKind::Open => "`{`".to_string(),
+            TokenKind::Close => "`}`".to_string(),
+        }
+    }
+}
+
+fn error(line: usize, column: usize, message: String) -> SceneError {
+    SceneError::Parse {
+        line,
+        column,
+        message,
+    }
+}
+
+// the tokens and the position just past the last one
+fn tokenize(text: &str) -> Result<(Vec<Token>, (usize, usize)), SceneError> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    let (mut line, mut column) = (1, 1);
+
+    while let Some(&c) = chars.peek() {
+        let (start_line, start_column) = (line, column);
+        let mut advance = |c: char| {
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        };
+
+        let kind = match c {
+            _ if c.is_whitespace() => {
+                advance(c);
+                chars.next();
+                continue;
+            }
+            '#' => {
+                while let Some(&c) = chars.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    advance(c);
+                    chars.next();
+                }
+                continue;
+            }
+            '{' | '}' => {
+                advance(c);
+                chars.next();
+                if c == '{' {
+                    TokenKind::Open
+                } else {
+                    TokenKind::Close
+                }
+            }
+            '"' => {
+                advance(c);
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    let c = chars.next().ok_or_else(|| {
+                        error(start_line, start_column, "unclosed string".to_string())
+                    })?;
+                    advance(c);
+                    match c {
+                        '"' => break,
+                        '\n' => {
+                            return Err(error(
+                                start_line,
+                                start_column,
+                                "unclosed string".to_string(),
+                            ))
+                        }
+                        '\\' => {
+                            let escaped = chars.next().ok_or_else(|| {
+                                error(start_line, start_column, "unclosed string".to_string())
+                            })?;
+                            advance(escaped);
+                            text.push(escaped);
+                        }
+                        _ => text.push(c),
+                    }
+                }
+                TokenKind::Text(text)
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '{' | '}' | '"' | '#') {
+                        break;
+                    }
+                    advance(c);
+                    word.push(c);
+                    chars.next();
+                }
+                TokenKind::Word(word)
+            }
+        };
+
+        tokens.push(Token {
+            kind,
+            line: start_line,
+            column: start_column,
+        });
+    }
+
+    Ok((tokens, (line, column)))
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    end: (usize, usize),
+    base_dir: &'a Path,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self, expected: &str) -> Result<Token, SceneError> {
+        match self.tokens.get(self.position) {
+            Some(token) => {
+                self.position += 1;
+                Ok(token.clone())
+            }
+            None => Err(error(
+                self.end.0,
+                self.end.1,
+                format!("expected {}, found the end of the file", expected),
+            )),
+        }
+    }
+
+    fn unexpected(token: &Token, expected: &str) -> SceneError {
+        error(
+            token.line,
+            token.column,
+            format!("expected {}, found {}", expected, token.describe()),
+        )
+    }
+
+    fn word(&mut self, expected: &str) -> Result<(String, Token), SceneError> {
+        let token = self.next(expected)?;
+        match &token.kind {
+            TokenKind::Word(word) => Ok((word.clone(), token)),
+            _ => Err(Parser::unexpected(&token, expected)),
+        }
+    }
+
+    fn text(&mut self, expected: &str) -> Result<(String, Token), SceneError> {
+        let token = self.next(expected)?;
+        match &token.kind {
+            TokenKind::Text(text) => Ok((text.clone(), token)),
+            _ => Err(Parser::unexpected(&token, expected)),
+        }
+    }
+
+    fn open(&mut self) -> Result<(), SceneError> {
+        let token = self.next("`{`")?;
+        match token.kind {
+            TokenKind::Open => Ok(()),
+            _ => Err(Parser::unexpected(&token, "`{`")),
+        }
+    }
+
+    // the next key in a block, None once its closing brace has been read
+    fn key(&mut self) -> Result<Option<(String, Token)>, SceneError> {
+        if matches!(
+            self.peek(),
+            Some(Token {
+                kind: TokenKind::Close,
+                ..
+            })
+        ) {
+            self.position += 1;
+            return Ok(None);
+        }
+        self.word("a name or `}`").map(Some)
+    }
+
+    fn number(&mut self) -> Result<f64, SceneError> {
+        let (word, token) = self.word("a number")?;
+        word.parse()
+            .map_err(|_| Parser::unexpected(&token, "a number"))
+    }
+
+    fn next_is_number(&self) -> bool {
+        match self.peek() {
+            Some(Token {
+                kind: TokenKind::Word(word),
+                ..
+            }) => word.parse::<f64>().is_ok(),
+            _ => false,
+        }
+    }
+
+    fn vector3(&mut self) -> Result<Vector3D, SceneError> {
+        Ok(Vector3D::new(
+            self.number()?,
+            self.number()?,
+            self.number()?,
+        ))
+    }
+
+    fn vector2(&mut self) -> Result<Vector2D, SceneError> {
+        Ok(Vector2D::new(self.number()?, self.number()?))
+    }
+
+    fn rgb(&mut self) -> Result<[f64; 3], SceneError> {
+        Ok([self.number()?, self.number()?, self.number()?])
+    }
+
+    fn channel(&mut self) -> Result<u8, SceneError> {
+        let (word, token) = self.word("a color channel from 0 to 255")?;
+        word.parse()
+            .map_err(|_| Parser::unexpected(&token, "a color channel from 0 to 255"))
+    }
+
+    // red, green and blue, then alpha if another number follows
+    fn color(&mut self) -> Result<Color, SceneError> {
+        let (r, g, b) = (self.channel()?, self.channel()?, self.channel()?);
+        let a = if self.next_is_number() {
+            self.channel()?
+        } else {
+            255
+        };
+        Ok(Color::new(r, g, b, a))
+    }
+
+    fn joint_indices(&mut self) -> Result<[usize; 4], SceneError> {
+        let mut joints = [0; 4];
+        for joint in joints.iter_mut() {
+            let (word, token) = self.word("a joint index")?;
+            *joint = word
+                .parse()
+                .map_err(|_| Parser::unexpected(&token, "a joint index"))?;
+        }
+        Ok(joints)
+    }
+
+    // three colors, either all with alpha or all without
+    fn vertex_colors(&mut self, key: &Token) -> Result<Vec<Color>, SceneError> {
+        let mut channels = vec![];
+        while self.next_is_number() {
+            channels.push(self.channel()?);
+        }
+        let stride = match channels.len() {
+            9 => 3,
+            12 => 4,
+            count => {
+                return Err(error(
+                    key.line,
+                    key.column,
+                    format!("expected 9 or 12 color channels, found {}", count),
+                ))
+            }
+        };
+        Ok(channels
+            .chunks(stride)
+            .map(|c| Color::new(c[0], c[1], c[2], c.get(3).copied().unwrap_or(255)))
+            .collect())
+    }
+
+    fn unknown(name: &str, token: &Token, block: &str) -> SceneError {
+        error(
+            token.line,
+            token.column,
+            format!("unknown {} setting `{}`", block, name),
+        )
+    }
+
+    fn scene(&mut self) -> Result<Scene, SceneError> {
+        let mut scene = Scene::new();
+
+        while self.peek().is_some() {
+            let (name, token) = self.word("`object`, `light`, `camera`, `fog` or `background`")?;
+            match name.as_str() {
+                "background" => scene.background = self.color()?,
+                "fog" => scene.fog = Some(self.fog()?),
+                "camera" => scene.camera = Some(self.camera()?),
+                "light" => scene.lights.push(self.light()?),
+                "object" => scene.objects.push(self.object()?),
+                _ => {
+                    return Err(Parser::unexpected(
+                        &token,
+                        "`object`, `light`, `camera`, `fog` or `background`",
+                    ))
+                }
+            }
+        }
+
+        Ok(scene)
+    }
+
+    fn fog(&mut self) -> Result<Fog, SceneError> {
+        let mut fog = Fog::new(Color::new(0, 0, 0, 255), 0.0);
+        self.open()?;
+        while let Some((name, token)) = self.key()? {
+            match name.as_str() {
+                "color" => fog.color = self.color()?,
+                "density" => fog.density = self.number()?,
+                "start" => fog.start = self.number()?,
+                _ => return Err(Parser::unknown(&name, &token, "fog")),
+            }
+        }
+        Ok(fog)
+    }
+
+    fn camera(&mut self) -> Result<CameraSetup, SceneError> {
+        let mut camera = CameraSetup::default();
+        self.open()?;
+        while let Some((name, token)) = self.key()? {
+            match name.as_str() {
+                "position" => camera.position = self.vector3()?,
+                "yaw" => camera.yaw = self.number()?,
+                "pitch" => camera.pitch = self.number()?,
+                "fov" => camera.fov = self.number()?,
+                "near" => camera.near = self.number()?,
+                "far" => camera.far = self.number()?,
+                _ => return Err(Parser::unknown(&name, &token, "camera")),
+            }
+        }
+        Ok(camera)
+    }
+
+    fn light(&mut self) -> Result<Light, SceneError> {
+        let mut light = Light::new(Vector3D::new(0.0, 0.0, 0.0), [1.0, 1.0, 1.0], 1.0);
+        self.open()?;
+        while let Some((name, token)) = self.key()? {
+            match name.as_str() {
+                "position" => light.position = self.vector3()?,
+                "color" => light.color = self.rgb()?,
+                "intensity" => light.intensity = self.number()?,
+                "radius" => light.radius = self.number()?,
+                _ => return Err(Parser::unknown(&name, &token, "light")),
+            }
+        }
+        Ok(light)
+    }
+
+    fn object(&mut self) -> Result<Object, SceneError> {
+        let open = self.next("`{`")?;
+        if open.kind != TokenKind::Open {
+            return Err(Parser::unexpected(&open, "`{`"));
+        }
+
+        // the color can come after the mesh it paints, so meshes are made at the end
+        enum Shape {
+            File(String, Token),
+            Primitive(Primitive),
+        }
+
+        let mut transform = Matrix4D::identity();
+        let mut material = Material::default();
+        let mut color = Color::new(255, 255, 255, 255);
+        let mut shape = None;
+        let mut triangles = vec![];
+
+        while let Some((name, token)) = self.key()? {
+            let step = match name.as_str() {
+                "mesh" => {
+                    let (path, token) = self.text("a quoted obj or stl file path")?;
+                    shape = Some(Shape::File(path, token));
+                    None
+                }
+                "primitive" => {
+                    shape = Some(Shape::Primitive(self.primitive()?));
+                    None
+                }
+                "triangle" => {
+                    triangles.push(self.triangle()?);
+                    None
+                }
+                "color" => {
+                    color = self.color()?;
+                    None
+                }
+                "material" => {
+                    material = self.material()?;
+                    None
+                }
+                "translate" => Some(Matrix4D::new_translation(&self.vector3()?)),
+                "scale" => Some(Matrix4D::new_scale(&self.vector3()?)),
+                "rotate" => {
+                    let angles = self.vector3()?;
+                    let x = get_x_rotation_matrix(angles.x.to_radians());
+                    let y = get_y_rotation_matrix(angles.y.to_radians());
+                    let z = get_z_rotation_matrix(angles.z.to_radians());
+                    Some(&(&x * &y) * &z)
+                }
+                "matrix" => {
+                    let mut m = [[0.0; 4]; 4];
+                    for row in m.iter_mut() {
+                        for cell in row.iter_mut() {
+                            *cell = self.number()?;
+                        }
+                    }
+                    Some(Matrix4D::new(m))
+                }
+                _ => return Err(Parser::unknown(&name, &token, "object")),
+            };
+            if let Some(step) = step {
+                transform = &transform * &step;
+            }
+        }
+
+        let (mesh, source) = match shape {
+            Some(_) if !triangles.is_empty() => {
+                return Err(error(
+                    open.line,
+                    open.column,
+                    "an object can't have both triangles and a mesh or primitive".to_string(),
+                ))
+            }
+            Some(Shape::File(path, token)) => {
+                let mesh = Mesh::load_file(self.base_dir.join(&path), &color).map_err(|e| {
+                    error(
+                        token.line,
+                        token.column,
+                        format!("can't load `{}`: {}", path, e),
+                    )
+                })?;
+                (mesh, MeshSource::File { path, color })
+            }
+            Some(Shape::Primitive(primitive)) => (
+                primitive.mesh(&color),
+                MeshSource::Primitive { primitive, color },
+            ),
+            None if !triangles.is_empty() => (Mesh { triangles }, MeshSource::Inline),
+            None => {
+                return Err(error(
+                    open.line,
+                    open.column,
+                    "an object needs a `mesh`, a `primitive` or `triangle`s".to_string(),
+                ))
+            }
+        };
+
+        Ok(Object::new(mesh, transform)
+            .with_material(material)
+            .with_source(source))
+    }
+
+    fn primitive(&mut self) -> Result<Primitive, SceneError> {
+        let (kind, kind_token) = self.word("a primitive name")?;
+        let mut params: Vec<(String, f64, Token, bool)> = vec![];
+        self.open()?;
+        while let Some((name, token)) = self.key()? {
+            params.push((name, self.number()?, token, false));
+        }
+
+        let primitive = Primitive::from_params(&kind, |name, default| {
+            match params.iter_mut().rev().find(|param| param.0 == name) {
+                Some(param) => {
+                    param.3 = true;
+                    param.1
+                }
+                None => default,
+            }
+        })
+        .map_err(|message| error(kind_token.line, kind_token.column, message))?;
+
+        match params.iter().find(|param| !param.3) {
+            Some((name, _, token, _)) => Err(error(
+                token.line,
+                token.column,
+                format!("{} has no setting `{}`", kind, name),
+            )),
+            None => Ok(primitive),
+        }
+    }
+
+    fn triangle(&mut self) -> Result<Triangle, SceneError> {
+        let open = self.next("`{`")?;
+        if open.kind != TokenKind::Open {
+            return Err(Parser::unexpected(&open, "`{`"));
+        }
+
+        let mut vertices = None;
+        let mut triangle = Triangle::new(
+            Vector3D::new(0.0, 0.0, 0.0),
+            Vector3D::new(0.0, 0.0, 0.0),
+            Vector3D::new(0.0, 0.0, 0.0),
+            &Color::new(255, 255, 255, 255),
+        );
+        while let Some((name, token)) = self.key()? {
+            match name.as_str() {
+                "vertices" => vertices = Some([self.vector3()?, self.vector3()?, self.vector3()?]),
+                "color" => triangle.color = self.color()?,
+                "normals" => {
+                    triangle.normals = vec![self.vector3()?, self.vector3()?, self.vector3()?]
+                }
+                "uvs" => triangle.uvs = vec![self.vector2()?, self.vector2()?, self.vector2()?],
+                "colors" => triangle.colors = self.vertex_colors(&token)?,
+                "joints" => {
+                    triangle.joints = (0..3)
+                        .map(|_| self.joint_indices())
+                        .collect::<Result<_, _>>()?
+                }
+                "weights" => {
+                    triangle.weights = (0..3)
+                        .map(|_| {
+                            Ok([
+                                self.number()?,
+                                self.number()?,
+                                self.number()?,
+                                self.number()?,
+                            ])
+                        })
+                        .collect::<Result<_, SceneError>>()?
+                }
+                _ => return Err(Parser::unknown(&name, &token, "triangle")),
+            }
+        }
+
+        match vertices {
+            Some(vertices) => {
+                triangle.vertices = vertices.to_vec();
+                Ok(triangle)
+            }
+            None => Err(error(
+                open.line,
+                open.column,
+                "a triangle needs `vertices`".to_string(),
+            )),
+        }
+    }
+
+    fn material(&mut self) -> Result<Material, SceneError> {
+        let mut material = Material::default();
+        self.open()?;
+        while let Some((name, token)) = self.key()? {
+            match name.as_str() {
+                "albedo" => material.albedo = self.rgb()?,
+                "reflectivity" => material.reflectivity = self.number()?,
+                "transparency" => material.transparency = self.number()?,
+                "refractive_index" => material.refractive_index = self.number()?,
+                "emission" => material.emission = self.rgb()?,
+                "texture" => {
+                    let (path, token) = self.text("a quoted image file path")?;
+                    let full_path = self.base_dir.join(&path);
+                    let mut texture = Texture::load(&full_path.to_string_lossy()).map_err(|e| {
+                        error(
+                            token.line,
+                            token.column,
+                            format!("can't load `{}`: {}", path, e),
+                        )
+                    })?;
+                    texture.path = Some(path);
+                    material.texture = Some(Arc::new(texture));
+                }
+                _ => return Err(Parser::unknown(&name, &token, "material")),
+            }
+        }
+        Ok(material)
+    }
+}
+
+impl Object {
+    pub fn with_source(mut self, source: MeshSource) -> Object {
+        self.source = source;
+        self
+    }
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn color_text(color: &Color) -> String {
+    if color.a == 255 {
+        format!("{} {} {}", color.r, color.g, color.b)
+    } else {
+        format!("{} {} {} {}", color.r, color.g, color.b, color.a)
+    }
+}
+
+fn vector_text(v: &Vector3D) -> String {
+    format!("{} {} {}", v.x, v.y, v.z)
+}
+
+fn rgb_text(rgb: &[f64; 3]) -> String {
+    format!("{} {} {}", rgb[0], rgb[1], rgb[2])
+}
+
+fn write_transform(out: &mut String, transform: &Matrix4D) -> fmt::Result {
+    if *transform == Matrix4D::identity() {
+        return Ok(());
+    }
+
+    let translation = transform.row(3);
+    let translation = Vector3D::new(translation[0], translation[1], translation[2]);
+    if *transform == Matrix4D::new_translation(&translation) {
+        return writeln!(out, "    translate {}", vector_text(&translation));
+    }
+
+    writeln!(out, "    matrix")?;
+    for i in 0..4 {
+        let row = transform.row(i);
+        writeln!(out, "        {} {} {} {}", row[0], row[1], row[2], row[3])?;
+    }
+    Ok(())
+}
+
+// only what differs from the default, which is what a missing setting reads back as
+fn write_material(out: &mut String, material: &Material) -> fmt::Result {
+    let default = Material::default();
+    if *material == default {
+        return Ok(());
+    }
+
+    writeln!(out, "    material {{")?;
+    if material.albedo != default.albedo {
+        writeln!(out, "        albedo {}", rgb_text(&material.albedo))?;
+    }
+    if material.reflectivity != default.reflectivity {
+        writeln!(out, "        reflectivity {}", material.reflectivity)?;
+    }
+    if material.transparency != default.transparency {
+        writeln!(out, "        transparency {}", material.transparency)?;
+    }
+    if material.refractive_index != default.refractive_index {
+        writeln!(
+            out,
+            "        refractive_index {}",
+            material.refractive_index
+        )?;
+    }
+    if material.emission != default.emission {
+        writeln!(out, "        emission {}", rgb_text(&material.emission))?;
+    }
+    if let Some(path) = material.texture.as_ref().and_then(|t| t.path.as_ref()) {
+        writeln!(out, "        texture {}", quote(path))?;
+    }
+    writeln!(out, "    }}")
+}
+
+fn write_triangle(out: &mut String, triangle: &Triangle) -> fmt::Result {
+    let vectors = |v: &[Vector3D]| v.iter().map(vector_text).collect::<Vec<_>>().join("  ");
+
+    writeln!(out, "    triangle {{")?;
+    writeln!(out, "        vertices {}", vectors(&triangle.vertices))?;
+    writeln!(out, "        color {}", color_text(&triangle.color))?;
+    if !triangle.normals.is_empty() {
+        writeln!(out, "        normals {}", vectors(&triangle.normals))?;
+    }
+    if !triangle.uvs.is_empty() {
+        let uvs: Vec<String> = triangle
+            .uvs
+            .iter()
+            .map(|uv| format!("{} {}", uv.x, uv.y))
+            .collect();
+        writeln!(out, "        uvs {}", uvs.join("  "))?;
+    }
+    if !triangle.colors.is_empty() {
+        // with every alpha written, so one color's can't be mistaken for the next's red
+        let colors: Vec<String> = triangle
+            .colors
+            .iter()
+            .map(|c| format!("{} {} {} {}", c.r, c.g, c.b, c.a))
+            .collect();
+        writeln!(out, "        colors {}", colors.join("  "))?;
+    }
+    if !triangle.joints.is_empty() {
+        let joints: Vec<String> = triangle
+            .joints
+            .iter()
+            .map(|j| format!("{} {} {} {}", j[0], j[1], j[2], j[3]))
+            .collect();
+        writeln!(out, "        joints {}", joints.join("  "))?;
+    }
+    if !triangle.weights.is_empty() {
+        let weights: Vec<String> = triangle
+            .weights
+            .iter()
+            .map(|w| format!("{} {} {} {}", w[0], w[1], w[2], w[3]))
+            .collect();
+        writeln!(out, "        weights {}", weights.join("  "))?;
+    }
+    writeln!(out, "    }}")
+}
+
+// what `write_scene` would otherwise lose or write in a form `parse` rejects
+fn check_saveable(scene: &Scene) -> Result<(), SceneError> {
+    for (i, object) in scene.objects.iter().enumerate() {
+        let problem = if object.skin.is_some() {
+            "is skinned, and skeletons aren't part of the format"
+        } else if matches!(object.source, MeshSource::Inline) && object.mesh.triangles.is_empty() {
+            "has no triangles"
+        } else if object
+            .material
+            .texture
+            .as_ref()
+            .is_some_and(|t| t.path.is_none())
+        {
+            "has a texture made in code, with no file to point to"
+        } else {
+            continue;
+        };
+        return Err(SceneError::Unsaveable(format!("object {} {}", i, problem)));
+    }
+    Ok(())
+}
+
+fn write_scene(out: &mut String, scene: &Scene) -> fmt::Result {
+    writeln!(out, "background {}", color_text(&scene.background))?;
+
+    if let Some(fog) = &scene.fog {
+        writeln!(
+            out,
+            "fog {{ color {} density {} start {} }}",
+            color_text(&fog.color),
+            fog.density,
+            fog.start
+        )?;
+    }
+
+    if let Some(camera) = &scene.camera {
+        writeln!(
+            out,
+            "camera {{ position {} yaw {} pitch {} fov {} near {} far {} }}",
+            vector_text(&camera.position),
+            camera.yaw,
+            camera.pitch,
+            camera.fov,
+            camera.near,
+            camera.far
+        )?;
+    }
+
+    for light in &scene.lights {
+        writeln!(
+            out,
+            "light {{ position {} color {} intensity {} radius {} }}",
+            vector_text(&light.position),
+            rgb_text(&light.color),
+            light.intensity,
+            light.radius
+        )?;
+    }
+
+    for object in &scene.objects {
+        writeln!(out, "\nobject {{")?;
+        match &object.source {
+            MeshSource::Inline => {
+                for triangle in &object.mesh.triangles {
+                    write_triangle(out, triangle)?;
+                }
+            }
+            MeshSource::File { path, color } => {
+                writeln!(out, "    mesh {}", quote(path))?;
+                writeln!(out, "    color {}", color_text(color))?;
+            }
+            MeshSource::Primitive { primitive, color } => {
+                let params: Vec<String> = primitive
+                    .params()
+                    .iter()
+                    .map(|(name, value)| format!("{} {}", name, value))
+                    .collect();
+                writeln!(
+                    out,
+                    "    primitive {} {{ {} }}",
+                    primitive.name(),
+                    params.join(" ")
+                )?;
+                writeln!(out, "    color {}", color_text(color))?;
+            }
+        }
+        write_transform(out, &object.transform)?;
+        write_material(out, &object.material)?;
+        writeln!(out, "}}")?;
+    }
+
+    Ok(())
+}
+
+impl Scene {
+    /// Reads a scene from the text of a scene file. Mesh and texture paths in it are relative
+    /// to `base_dir`.
+    pub fn parse(text: &str, base_dir: &Path) -> Result<Scene, SceneError> {
+        let (tokens, end) = tokenize(text)?;
+        Parser {
+            tokens,
+            position: 0,
+            end,
+            base_dir,
+        }
+        .scene()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Scene, SceneError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        Scene::parse(&text, path.parent().unwrap_or(Path::new("")))
+    }
+
+    /// The scene in the text format `parse` reads, or `SceneError::Unsaveable` if it has
+    /// something the format can't hold.
+    pub fn to_text(&self) -> Result<String, SceneError> {
+        check_saveable(self)?;
+        let mut out = String::new();
+        write_scene(&mut out, self).expect("writing to a string can't fail");
+        Ok(out)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SceneError> {
+        Ok(fs::write(path, self.to_text()?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::skinning::{Joint, Skeleton, SkinningMethod};
+    use crate::texture::Texture;
+
+    const SCENE: &str = r#"
+        background 20 24 32 200
+        fog { color 150 160 180 density 0.02 start 10 }
+        camera { position 0 1 -2 yaw 12.5 pitch -10 fov 75 near 0.1 far 1000 }
+        light { position 2 5 1 color 1 0.5 0.25 intensity 90 radius 0.5 }
+        object {
+            primitive uv_sphere { radius 0.5 segments 16 rings 8 }
+            color 128 64 32
+            rotate 10 45 0
+            translate 0 0.5 6
+            material { reflectivity 0.8 emission 0.1 0.2 0.3 }
+        }
+        object {
+            triangle {
+                vertices 0 0 0  1 0 0  0 1 0.1
+                color 255 0 0
+                normals 0 0 1  0 0 1  0 0 1
+                uvs 0 0  1 0  0 1
+                colors 1 2 3 4  5 6 7 8  9 10 11 12
+                joints 0 1 2 3  0 0 0 0  3 2 1 0
+                weights 0.5 0.5 0 0  1 0 0 0  0.25 0.25 0.25 0.25
+            }
+            translate 1 2 3
+        }
+    "#;
+
+    fn parse(text: &str) -> Scene {
+        Scene::parse(text, Path::new("")).unwrap()
+    }
+
+    #[test]
+    fn load_save_load_round_trips() {
+        let scene = parse(SCENE);
+        let text = scene.to_text().unwrap();
+        let reloaded = parse(&text);
+        assert_eq!(reloaded.to_text().unwrap(), text);
+
+        assert_eq!(reloaded.background, scene.background);
+        assert_eq!(reloaded.fog, scene.fog);
+        assert_eq!(reloaded.camera, scene.camera);
+        assert_eq!(reloaded.lights, scene.lights);
+        for (a, b) in scene.objects.iter().zip(&reloaded.objects) {
+            assert_eq!(a.transform, b.transform);
+            assert_eq!(a.material, b.material);
+            assert_eq!(a.source, b.source);
+            assert_eq!(a.mesh.triangles.len(), b.mesh.triangles.len());
+        }
+
+        let (a, b) = (
+            &scene.objects[1].mesh.triangles[0],
+            &reloaded.objects[1].mesh.triangles[0],
+        );
+        assert_eq!(a.vertices, b.vertices);
+        assert_eq!(a.colors, b.colors);
+        assert_eq!(a.joints, b.joints);
+        assert_eq!(a.weights, b.weights);
+    }
+
+    #[test]
+    fn round_trips_files_it_loads_relative_to_the_scene() {
+        let dir = std::env::temp_dir().join("atlas_scenefile_round_trip");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("quad.obj"), "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        fs::write(dir.join("gray.pgm"), b"P5 1 1 255\n\x80").unwrap();
+        let text = r#"object {
+            mesh "quad.obj" color 10 20 30 scale 2 2 2
+            material { texture "gray.pgm" }
+        }"#;
+        fs::write(dir.join("scene.txt"), text).unwrap();
+
+        let scene = Scene::load(dir.join("scene.txt")).unwrap();
+        scene.save(dir.join("saved.txt")).unwrap();
+        let reloaded = Scene::load(dir.join("saved.txt")).unwrap();
+        assert_eq!(reloaded.to_text().unwrap(), scene.to_text().unwrap());
+        assert_eq!(reloaded.objects[0].source, scene.objects[0].source);
+        assert_eq!(reloaded.objects[0].material, scene.objects[0].material);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_to_save_what_the_format_cant_hold() {
+        let saveable = parse(SCENE);
+        let unsaveable = |change: &dyn Fn(&mut Object), expected: &str| {
+            let mut scene = parse(SCENE);
+            change(&mut scene.objects[1]);
+            match scene.to_text() {
+                Err(SceneError::Unsaveable(message)) => assert_eq!(message, expected),
+                _ => panic!("expected {} to be refused", expected),
+            }
+        };
+        assert!(saveable.to_text().is_ok());
+
+        unsaveable(
+            &|object| object.mesh.triangles.clear(),
+            "object 1 has no triangles",
+        );
+        unsaveable(
+            &|object| {
+                let texture = Texture {
+                    path: None,
+                    image: crate::image::Image::new(1, 1, 1, 255),
+                };
+                object.material.texture = Some(Arc::new(texture));
+            },
+            "object 1 has a texture made in code, with no file to point to",
+        );
+        unsaveable(
+            &|object| {
+                let skeleton =
+                    Skeleton::new().with_joint(Joint::new("root", None, Default::default()));
+                *object = object.clone().with_skin(skeleton, SkinningMethod::Linear);
+            },
+            "object 1 is skinned, and skeletons aren't part of the format",
+        );
+    }
+
+    #[test]
+    fn reports_where_parsing_failed() {
+        match Scene::parse("object {\n  color 1 2\n}", Path::new("")) {
+            Err(SceneError::Parse { line, .. }) => assert_eq!(line, 3),
+            _ => panic!("expected a parse error"),
+        }
+        assert!(Scene::parse("object { }", Path::new("")).is_err());
+        assert!(Scene::parse("object { wobble 1 }", Path::new("")).is_err());
+        assert!(Scene::parse("light { radius }", Path::new("")).is_err());
+    }
+}
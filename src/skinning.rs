@@ -0,0 +1,490 @@
+use crate::animation::{AnimationClip, AnimationPlayer, Transform};
+use crate::linalg::{
+    multiply_matrix_direction, multiply_matrix_vector, Matrix4D, Quaternion, Vector3D,
+};
+use crate::renderer::{Mesh, Object, Triangle};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Joint {
+    pub name: String,
+    /// Index of the parent joint, None for a root.
+    pub parent: Option<usize>,
+    /// Transform relative to the parent when no animation is playing.
+    pub rest: Transform,
+    /// Takes a bind mesh vertex into this joint's space, undoing the joint's world transform
+    /// at the time the mesh was bound to it.
+    pub inverse_bind: Matrix4D,
+}
+
+impl Joint {
+    pub fn new(name: &str, parent: Option<usize>, rest: Transform) -> Joint {
+        Joint {
+            name: name.to_string(),
+            parent,
+            rest,
+            inverse_bind: Matrix4D::identity(),
+        }
+    }
+}
+
+/// A hierarchy of joints. A pose gives every joint a transform relative to its parent, in
+/// the same order as `joints`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+}
+
+impl Default for Skeleton {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Skeleton {
+    pub fn new() -> Skeleton {
+        Skeleton { joints: vec![] }
+    }
+
+    pub fn with_joint(mut self, joint: Joint) -> Skeleton {
+        self.joints.push(joint);
+        self
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.joints.iter().position(|joint| joint.name == name)
+    }
+
+    pub fn rest_pose(&self) -> Vec<Transform> {
+        self.joints.iter().map(|joint| joint.rest).collect()
+    }
+
+    /// Binds the skeleton to a mesh in its rest pose, setting every inverse bind matrix.
+    pub fn bind_rest_pose(&mut self) {
+        let world = self.world_matrices(&self.rest_pose());
+        for (joint, matrix) in self.joints.iter_mut().zip(world) {
+            joint.inverse_bind = matrix.inverse().unwrap_or(Matrix4D::identity());
+        }
+    }
+
+    /// Each joint's transform in the skeleton's space, parents applied after children.
+    pub fn world_matrices(&self, pose: &[Transform]) -> Vec<Matrix4D> {
+        let mut world: Vec<Option<Matrix4D>> = vec![None; self.joints.len()];
+        for i in 0..self.joints.len() {
+            self.world_matrix(i, pose, &mut world, 0);
+        }
+        world
+            .into_iter()
+            .map(|m| m.unwrap_or(Matrix4D::identity()))
+            .collect()
+    }
+
+    // joints can come before their parents, so each is worked out on demand and cached
+    fn world_matrix(
+        &self,
+        index: usize,
+        pose: &[Transform],
+        world: &mut [Option<Matrix4D>],
+        depth: usize,
+    ) -> Matrix4D {
+        if let Some(matrix) = world[index] {
+            return matrix;
+        }
+
+        let joint = &self.joints[index];
+        let local = pose.get(index).unwrap_or(&joint.rest).to_matrix();
+        // a parent loop would never end, treat the joint as a root instead
+        let matrix = match joint.parent {
+            Some(parent) if parent < self.joints.len() && depth < self.joints.len() => {
+                &local * &self.world_matrix(parent, pose, world, depth + 1)
+            }
+            _ => local,
+        };
+        world[index] = Some(matrix);
+        matrix
+    }
+
+    /// The matrices taking bind mesh vertices to where `pose` puts them, one per joint.
+    pub fn joint_matrices(&self, pose: &[Transform]) -> Vec<Matrix4D> {
+        self.world_matrices(pose)
+            .iter()
+            .zip(&self.joints)
+            .map(|(world, joint)| &joint.inverse_bind * world)
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SkinningMethod {
+    /// Blends the joint matrices. Fast, but joints twisting or bending far pinch the skin
+    /// around them.
+    Linear,
+    /// Blends the joints as dual quaternions, which keeps volume at bent and twisted joints.
+    /// Only rotation and translation are blended, joint scale is ignored.
+    DualQuaternion,
+}
+
+/// A rotation and translation as a unit dual quaternion, `dual` holding half the
+/// translation rotated along.
+#[derive(Clone, Copy, Debug)]
+struct DualQuaternion {
+    real: Quaternion,
+    dual: Quaternion,
+}
+
+impl DualQuaternion {
+    fn from_matrix(matrix: &Matrix4D) -> DualQuaternion {
+        let transform = Transform::from_matrix(matrix);
+        let t = transform.translation;
+        let real = transform.rotation;
+        DualQuaternion {
+            real,
+            dual: Quaternion::new(t.x, t.y, t.z, 0.0)
+                .multiply(&real)
+                .scale(0.5),
+        }
+    }
+
+    fn transform_point(&self, point: &Vector3D) -> Vector3D {
+        let t = self.dual.multiply(&self.real.conjugate()).scale(2.0);
+        self.real.rotate(point).add(&Vector3D::new(t.x, t.y, t.z))
+    }
+}
+
+impl Mesh {
+    /// The mesh deformed by `joint_matrices`, from `Skeleton::joint_matrices`. Triangles
+    /// without joints are left where they are.
+    pub fn skin(&self, joint_matrices: &[Matrix4D], method: SkinningMethod) -> Mesh {
+        let dual_quaternions: Vec<DualQuaternion> = match method {
+            SkinningMethod::Linear => vec![],
+            SkinningMethod::DualQuaternion => joint_matrices
+                .iter()
+                .map(DualQuaternion::from_matrix)
+                .collect(),
+        };
+
+        let triangles = self
+            .triangles
+            .iter()
+            .map(|t| {
+                if t.joints.len() < 3 || t.weights.len() < 3 {
+                    return t.clone();
+                }
+
+                let mut vertices = Vec::with_capacity(3);
+                let mut normals = Vec::with_capacity(t.normals.len());
+                for i in 0..3 {
+                    let influences = t.joints[i]
+                        .iter()
+                        .zip(&t.weights[i])
+                        .filter(|(joint, weight)| **weight > 0.0 && **joint < joint_matrices.len())
+                        .map(|(joint, weight)| (*joint, *weight));
+
+                    match method {
+                        SkinningMethod::Linear => {
+                            let matrix = blend_matrices(joint_matrices, influences);
+                            vertices.push(multiply_matrix_vector(&t.vertices[i], &matrix));
+                            if let Some(normal) = t.normals.get(i) {
+                                normals
+                                    .push(multiply_matrix_direction(normal, &matrix).normalize());
+                            }
+                        }
+                        SkinningMethod::DualQuaternion => {
+                            let dq = blend_dual_quaternions(&dual_quaternions, influences);
+                            vertices.push(dq.transform_point(&t.vertices[i]));
+                            if let Some(normal) = t.normals.get(i) {
+                                normals.push(dq.real.rotate(normal).normalize());
+                            }
+                        }
+                    }
+                }
+
+                Triangle {
+                    vertices,
+                    normals,
+                    ..t.clone()
+                }
+            })
+            .collect();
+
+        Mesh { triangles }
+    }
+}
+
+// the weighted sum of the matrices, renormalized in case the weights don't add up to 1
+fn blend_matrices<I: Iterator<Item = (usize, f64)>>(
+    matrices: &[Matrix4D],
+    influences: I,
+) -> Matrix4D {
+    let mut blended = [[0.0; 4]; 4];
+    let mut total = 0.0;
+    for (joint, weight) in influences {
+        for (i, row) in blended.iter_mut().enumerate() {
+            for (cell, value) in row.iter_mut().zip(matrices[joint].row(i)) {
+                *cell += value * weight;
+            }
+        }
+        total += weight;
+    }
+
+    if total <= 0.0 {
+        return Matrix4D::identity();
+    }
+    Matrix4D::new(blended.map(|row| row.map(|cell| cell / total)))
+}
+
+fn blend_dual_quaternions<I: Iterator<Item = (usize, f64)>>(
+    dual_quaternions: &[DualQuaternion],
+    influences: I,
+) -> DualQuaternion {
+    let mut real = Quaternion::new(0.0, 0.0, 0.0, 0.0);
+    let mut dual = Quaternion::new(0.0, 0.0, 0.0, 0.0);
+    let mut first: Option<Quaternion> = None;
+
+    for (joint, weight) in influences {
+        let dq = &dual_quaternions[joint];
+        // q and -q are the same rotation, blend them all from the same side
+        let pivot = *first.get_or_insert(dq.real);
+        let weight = if pivot.dot(&dq.real) < 0.0 {
+            -weight
+        } else {
+            weight
+        };
+        real = real.add(&dq.real.scale(weight));
+        dual = dual.add(&dq.dual.scale(weight));
+    }
+
+    let length = real.magnitude();
+    if length == 0.0 {
+        return DualQuaternion {
+            real: Quaternion::identity(),
+            dual: Quaternion::new(0.0, 0.0, 0.0, 0.0),
+        };
+    }
+    DualQuaternion {
+        real: real.scale(1.0 / length),
+        dual: dual.scale(1.0 / length),
+    }
+}
+
+/// A skeleton bound to a mesh, posed by setting `pose` and calling `Object::update_skin`.
+#[derive(Clone)]
+pub struct Skin {
+    pub skeleton: Skeleton,
+    pub pose: Vec<Transform>,
+    /// The mesh as it was bound to the skeleton, with joints and weights on its triangles.
+    pub bind_mesh: Mesh,
+    pub method: SkinningMethod,
+}
+
+impl Object {
+    /// Binds the object's mesh to `skeleton`, starting in the skeleton's rest pose.
+    pub fn with_skin(mut self, skeleton: Skeleton, method: SkinningMethod) -> Object {
+        self.skin = Some(Skin {
+            pose: skeleton.rest_pose(),
+            skeleton,
+            bind_mesh: self.mesh.clone(),
+            method,
+        });
+        self.update_skin();
+        self
+    }
+
+    pub fn set_pose(&mut self, pose: Vec<Transform>) {
+        if let Some(skin) = &mut self.skin {
+            skin.pose = pose;
+        }
+        self.update_skin();
+    }
+
+    /// Deforms the bind mesh into the current pose. Everything downstream, rasterizing,
+    /// ray tracing and the bvh, sees the result as the object's mesh before any of its
+    /// own transforms, so skinning happens ahead of view and projection.
+    pub fn update_skin(&mut self) {
+        if let Some(skin) = &self.skin {
+            let matrices = skin.skeleton.joint_matrices(&skin.pose);
+            self.mesh = skin.bind_mesh.skin(&matrices, skin.method);
+            self.update_bounds();
+        }
+    }
+}
+
+impl AnimationClip {
+    /// The skeleton's pose at `time`, each channel posing the joint it targets and every
+    /// other joint at rest.
+    pub fn sample_pose(&self, skeleton: &Skeleton, time: f64) -> Vec<Transform> {
+        let mut pose = skeleton.rest_pose();
+        for channel in &self.channels {
+            if let Some(transform) = pose.get_mut(channel.target) {
+                *transform = channel.sample(time);
+            }
+        }
+        pose
+    }
+}
+
+impl AnimationPlayer {
+    pub fn pose(&self, skeleton: &Skeleton) -> Vec<Transform> {
+        self.clip.sample_pose(skeleton, self.time())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::Color;
+
+    fn close(a: &Vector3D, b: &Vector3D) -> bool {
+        a.sub(b).magnitude() < 1e-9
+    }
+
+    fn transform(translation: Vector3D, axis: Vector3D, angle: f64) -> Transform {
+        Transform {
+            translation,
+            rotation: Quaternion::from_axis_angle(&axis.normalize(), angle),
+            scale: Vector3D::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    // an arm: a shoulder off the origin and an elbow two units along it
+    fn arm() -> Skeleton {
+        let mut skeleton = Skeleton::new()
+            .with_joint(Joint::new(
+                "shoulder",
+                None,
+                transform(
+                    Vector3D::new(0.5, 1.0, 0.0),
+                    Vector3D::new(0.0, 0.0, 1.0),
+                    0.3,
+                ),
+            ))
+            .with_joint(Joint::new(
+                "elbow",
+                Some(0),
+                transform(
+                    Vector3D::new(2.0, 0.0, 0.0),
+                    Vector3D::new(0.0, 1.0, 0.0),
+                    -0.2,
+                ),
+            ));
+        skeleton.bind_rest_pose();
+        skeleton
+    }
+
+    // triangles along the arm, following the shoulder, the elbow, or a mix of both
+    fn sleeve() -> Mesh {
+        let white = Color::new(255, 255, 255, 255);
+        let triangles = [[1.0, 0.0], [0.5, 0.5], [0.2, 0.8], [0.0, 1.0]]
+            .iter()
+            .enumerate()
+            .map(|(i, &[shoulder, elbow])| {
+                let x = i as f64;
+                Triangle::new(
+                    Vector3D::new(x, 1.0, 0.0),
+                    Vector3D::new(x + 1.0, 1.2, 0.3),
+                    Vector3D::new(x, 1.5, -0.4),
+                    &white,
+                )
+                .with_normals(
+                    Vector3D::new(0.0, 0.0, 1.0),
+                    Vector3D::new(0.0, 1.0, 0.0),
+                    Vector3D::new(1.0, 0.0, 0.0),
+                )
+                .with_skin_weights([[0, 1, 0, 0]; 3], [[shoulder, elbow, 0.0, 0.0]; 3])
+            })
+            .collect();
+        Mesh { triangles }
+    }
+
+    fn same_mesh(a: &Mesh, b: &Mesh) -> bool {
+        a.triangles.iter().zip(&b.triangles).all(|(a, b)| {
+            a.vertices.iter().zip(&b.vertices).all(|(a, b)| close(a, b))
+                && a.normals.iter().zip(&b.normals).all(|(a, b)| close(a, b))
+        })
+    }
+
+    #[test]
+    fn the_rest_pose_leaves_the_bind_mesh_alone() {
+        let skeleton = arm();
+        let matrices = skeleton.joint_matrices(&skeleton.rest_pose());
+        let mesh = sleeve();
+        for method in [SkinningMethod::Linear, SkinningMethod::DualQuaternion] {
+            assert!(same_mesh(&mesh.skin(&matrices, method), &mesh));
+
+            let object =
+                Object::new(mesh.clone(), Matrix4D::identity()).with_skin(skeleton.clone(), method);
+            assert!(same_mesh(&object.mesh, &mesh));
+        }
+    }
+
+    #[test]
+    fn a_fully_weighted_joint_moves_vertices_by_its_matrix() {
+        let skeleton = arm();
+        let pose = vec![
+            transform(
+                Vector3D::new(0.0, 2.0, 1.0),
+                Vector3D::new(1.0, 1.0, 0.0),
+                1.1,
+            ),
+            transform(
+                Vector3D::new(2.0, 0.0, 0.0),
+                Vector3D::new(0.0, 0.0, 1.0),
+                1.4,
+            ),
+        ];
+        let matrices = skeleton.joint_matrices(&pose);
+        let mesh = sleeve();
+
+        for method in [SkinningMethod::Linear, SkinningMethod::DualQuaternion] {
+            let skinned = mesh.skin(&matrices, method);
+            for (joint, index) in [(0, 0), (1, 3)] {
+                let (bind, posed) = (&mesh.triangles[index], &skinned.triangles[index]);
+                for i in 0..3 {
+                    let expected = multiply_matrix_vector(&bind.vertices[i], &matrices[joint]);
+                    assert!(close(&posed.vertices[i], &expected));
+                    let normal = multiply_matrix_direction(&bind.normals[i], &matrices[joint]);
+                    assert!(close(&posed.normals[i], &normal.normalize()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dual_quaternions_transform_points_like_their_matrix() {
+        let matrix = transform(
+            Vector3D::new(3.0, -1.0, 2.0),
+            Vector3D::new(1.0, -2.0, 0.5),
+            2.5,
+        )
+        .to_matrix();
+        let dq = DualQuaternion::from_matrix(&matrix);
+        for point in [
+            Vector3D::new(0.0, 0.0, 0.0),
+            Vector3D::new(1.0, 0.0, 0.0),
+            Vector3D::new(-2.0, 0.7, 4.0),
+        ] {
+            assert!(close(
+                &dq.transform_point(&point),
+                &multiply_matrix_vector(&point, &matrix)
+            ));
+        }
+    }
+
+    #[test]
+    fn linear_and_dual_quaternion_skinning_agree_on_a_rigid_move() {
+        // the whole arm moves as one, so blending the joints changes nothing
+        let skeleton = arm();
+        let mut pose = skeleton.rest_pose();
+        pose[0] = transform(
+            Vector3D::new(-1.0, 3.0, 2.0),
+            Vector3D::new(0.3, 1.0, -0.4),
+            2.0,
+        );
+        let matrices = skeleton.joint_matrices(&pose);
+
+        let mesh = sleeve();
+        let linear = mesh.skin(&matrices, SkinningMethod::Linear);
+        let dual_quaternion = mesh.skin(&matrices, SkinningMethod::DualQuaternion);
+        assert!(same_mesh(&linear, &dual_quaternion));
+        assert!(!same_mesh(&linear, &mesh));
+    }
+}
@@ -0,0 +1,531 @@
+use crate::linalg::{cross, Vector3D};
+use crate::renderer::{Color, Mesh, Triangle};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum StlError {
+    Io(std::io::Error),
+    /// The file isn't well formed ascii or binary stl.
+    Malformed(String),
+}
+
+impl fmt::Display for StlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StlError::Io(e) => write!(f, "{}", e),
+            StlError::Malformed(msg) => write!(f, "malformed stl: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for StlError {}
+
+impl From<std::io::Error> for StlError {
+    fn from(e: std::io::Error) -> StlError {
+        StlError::Io(e)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StlFormat {
+    /// 32 bit floats, 50 bytes a triangle.
+    Binary,
+    /// Text, with every coordinate written to read back as exactly the same f64.
+    Ascii,
+}
+
+const HEADER_LENGTH: usize = 80;
+const TRIANGLE_LENGTH: usize = 50;
+
+// binary stl has no magic number and can start with `solid` too, so it's recognized by its
+// triangle count matching its length
+fn is_binary(bytes: &[u8]) -> bool {
+    if let Some(count) = bytes.get(HEADER_LENGTH..HEADER_LENGTH + 4) {
+        let count = u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize;
+        if HEADER_LENGTH + 4 + count * TRIANGLE_LENGTH == bytes.len() {
+            return true;
+        }
+    }
+    !bytes.trim_ascii_start().starts_with(b"solid")
+}
+
+// the facet normal kept as each corner's normal, unless the file leaves it zero
+fn facet(vertices: [Vector3D; 3], normal: Vector3D, color: &Color) -> Triangle {
+    let triangle = Triangle::new(vertices[0], vertices[1], vertices[2], color);
+    let usable =
+        [normal.x, normal.y, normal.z].iter().all(|c| c.is_finite()) && normal.magnitude() > 0.0;
+    if usable {
+        triangle.with_normals(normal, normal, normal)
+    } else {
+        triangle
+    }
+}
+
+impl Mesh {
+    pub fn load_stl<P: AsRef<Path>>(path: P, color: &Color) -> Result<Mesh, StlError> {
+        Mesh::parse_stl(&fs::read(path)?, color)
+    }
+
+    /// Loads an stl when the path ends in `.stl`, otherwise an obj.
+    pub fn load_file<P: AsRef<Path>>(path: P, color: &Color) -> Result<Mesh, Box<dyn Error>> {
+        let path = path.as_ref();
+        let is_stl = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("stl"));
+        if is_stl {
+            Ok(Mesh::load_stl(path, color)?)
+        } else {
+            Ok(Mesh::load_obj(path, color)?)
+        }
+    }
+
+    /// Reads ascii or binary stl, telling them apart by their contents. Every triangle gets
+    /// `color`, and the file's facet normal on all three corners when it gives one, so it
+    /// shades flat either way. Corners aren't shared, see `Mesh::weld`.
+    pub fn parse_stl(bytes: &[u8], color: &Color) -> Result<Mesh, StlError> {
+        if is_binary(bytes) {
+            parse_binary(bytes, color)
+        } else {
+            let text = std::str::from_utf8(bytes)
+                .map_err(|_| StlError::Malformed("ascii stl isn't valid utf8".to_string()))?;
+            parse_ascii(text, color)
+        }
+    }
+
+    /// The mesh as stl, reading back as the same triangles: ascii keeps every coordinate
+    /// exactly, binary keeps them as 32 bit floats. A triangle's normal is written from
+    /// its corners when they all share one, as meshes read from stl do, otherwise worked
+    /// out from its winding. Colors, texture coordinates and smooth normals have no place
+    /// in stl and are left out.
+    ///
+    /// Rewriting a loaded file gives back its triangles and facet normals but not the file
+    /// itself: the binary header and attribute bytes and the ascii solid names aren't kept,
+    /// and a facet the file left with a zero or non-finite normal gets one worked out from
+    /// its winding instead.
+    pub fn to_stl(&self, format: StlFormat) -> Vec<u8> {
+        match format {
+            StlFormat::Binary => self.to_binary_stl(),
+            StlFormat::Ascii => self.to_ascii_stl().into_bytes(),
+        }
+    }
+
+    pub fn save_stl<P: AsRef<Path>>(&self, path: P, format: StlFormat) -> Result<(), StlError> {
+        fs::write(path, self.to_stl(format))?;
+        Ok(())
+    }
+
+    fn to_binary_stl(&self) -> Vec<u8> {
+        let mut bytes =
+            Vec::with_capacity(HEADER_LENGTH + 4 + self.triangles.len() * TRIANGLE_LENGTH);
+        let mut header = b"binary stl written by atlas".to_vec();
+        header.resize(HEADER_LENGTH, 0);
+        bytes.extend_from_slice(&header);
+        bytes.extend_from_slice(&(self.triangles.len() as u32).to_le_bytes());
+
+        for triangle in &self.triangles {
+            let normal = facet_normal(triangle);
+            for v in std::iter::once(&normal).chain(&triangle.vertices) {
+                for c in [v.x, v.y, v.z] {
+                    bytes.extend_from_slice(&(c as f32).to_le_bytes());
+                }
+            }
+            // the attribute byte count, unused
+            bytes.extend_from_slice(&[0, 0]);
+        }
+        bytes
+    }
+
+    fn to_ascii_stl(&self) -> String {
+        // `{:e}` prints the shortest digits that parse back to the same f64
+        let vector = |v: &Vector3D| format!("{:e} {:e} {:e}", v.x, v.y, v.z);
+        let mut text = String::from("solid atlas\n");
+        for triangle in &self.triangles {
+            text += &format!(
+                "  facet normal {}\n    outer loop\n",
+                vector(&facet_normal(triangle))
+            );
+            for vertex in &triangle.vertices {
+                text += &format!("      vertex {}\n", vector(vertex));
+            }
+            text += "    endloop\n  endfacet\n";
+        }
+        text += "endsolid atlas\n";
+        text
+    }
+
+    /// Moves corners closer than `tolerance` onto the first of them and drops triangles
+    /// left with no area, closing the hairline cracks stl files tend to have between
+    /// triangles that should meet. A tolerance of 0 only joins corners already equal.
+    pub fn weld(&self, tolerance: f64) -> Mesh {
+        let mut welder = Welder {
+            // any cell size finds exact matches, which is all a tolerance of 0 wants
+            cell: if tolerance > 0.0 { tolerance } else { 1.0 },
+            tolerance,
+            cells: HashMap::new(),
+        };
+
+        let triangles = self
+            .triangles
+            .iter()
+            .filter_map(|t| {
+                let vertices: Vec<Vector3D> = t.vertices.iter().map(|v| welder.weld(v)).collect();
+                let collapsed = vertices[0] == vertices[1]
+                    || vertices[1] == vertices[2]
+                    || vertices[2] == vertices[0];
+                if collapsed {
+                    return None;
+                }
+                Some(Triangle {
+                    vertices,
+                    ..t.clone()
+                })
+            })
+            .collect();
+        Mesh { triangles }
+    }
+}
+
+// the normal a triangle's corners share, or the one its winding gives it
+fn facet_normal(triangle: &Triangle) -> Vector3D {
+    if let [a, b, c] = triangle.normals.as_slice() {
+        if a == b && b == c {
+            return *a;
+        }
+    }
+
+    let v = &triangle.vertices;
+    let normal = cross(&v[1].sub(&v[0]), &v[2].sub(&v[0]));
+    if normal.magnitude() > 0.0 {
+        normal.normalize()
+    } else {
+        normal
+    }
+}
+
+// corners bucketed into a grid of `cell` sized cubes, so each one only needs comparing
+// with the ones in its own and the neighbouring cells
+struct Welder {
+    cell: f64,
+    tolerance: f64,
+    cells: HashMap<[i64; 3], Vec<Vector3D>>,
+}
+
+impl Welder {
+    fn key(&self, v: &Vector3D) -> [i64; 3] {
+        [v.x, v.y, v.z].map(|c| (c / self.cell).floor() as i64)
+    }
+
+    // the first corner seen within tolerance of `v`, or `v` itself when there's none
+    fn weld(&mut self, v: &Vector3D) -> Vector3D {
+        let [x, y, z] = self.key(v);
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let cell = [
+                        x.saturating_add(dx),
+                        y.saturating_add(dy),
+                        z.saturating_add(dz),
+                    ];
+                    let neighbours = match self.cells.get(&cell) {
+                        Some(neighbours) => neighbours,
+                        None => continue,
+                    };
+                    let close = neighbours
+                        .iter()
+                        .find(|n| n == &v || n.sub(v).magnitude() <= self.tolerance);
+                    if let Some(close) = close {
+                        return *close;
+                    }
+                }
+            }
+        }
+
+        self.cells.entry([x, y, z]).or_default().push(*v);
+        *v
+    }
+}
+
+fn parse_binary(bytes: &[u8], color: &Color) -> Result<Mesh, StlError> {
+    let count = bytes
+        .get(HEADER_LENGTH..HEADER_LENGTH + 4)
+        .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]) as usize)
+        .ok_or_else(|| StlError::Malformed("binary stl is shorter than its header".to_string()))?;
+    let body = &bytes[HEADER_LENGTH + 4..];
+    if body.len() < count * TRIANGLE_LENGTH {
+        return Err(StlError::Malformed(format!(
+            "binary stl says it has {} triangles but only has room for {}",
+            count,
+            body.len() / TRIANGLE_LENGTH
+        )));
+    }
+
+    let triangles = body
+        .chunks_exact(TRIANGLE_LENGTH)
+        .take(count)
+        .map(|record| {
+            let float = |i: usize| {
+                let b = &record[i * 4..i * 4 + 4];
+                f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64
+            };
+            let vector = |i: usize| Vector3D::new(float(i), float(i + 1), float(i + 2));
+            facet([vector(3), vector(6), vector(9)], vector(0), color)
+        })
+        .collect();
+    Ok(Mesh { triangles })
+}
+
+fn parse_ascii(text: &str, color: &Color) -> Result<Mesh, StlError> {
+    // what the next line has to start with, as a facet is read
+    #[derive(PartialEq)]
+    enum Expect {
+        Solid,
+        Facet,
+        OuterLoop,
+        Vertex,
+        EndLoop,
+        EndFacet,
+    }
+
+    let mut triangles = vec![];
+    let mut expect = Expect::Solid;
+    let mut normal = Vector3D::new(0.0, 0.0, 0.0);
+    let mut vertices = vec![];
+
+    for (number, line) in text.lines().enumerate() {
+        let malformed =
+            |message: &str| StlError::Malformed(format!("line {}: {}", number + 1, message));
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let keyword = match words.first() {
+            Some(keyword) => *keyword,
+            None => continue,
+        };
+        let vector = |words: &[&str]| -> Result<Vector3D, StlError> {
+            let numbers = words
+                .iter()
+                .map(|w| w.parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .map_err(|_| malformed("expected numbers"))?;
+            match numbers.as_slice() {
+                [x, y, z] => Ok(Vector3D::new(*x, *y, *z)),
+                _ => Err(malformed("expected x, y and z")),
+            }
+        };
+
+        expect = match (&expect, keyword) {
+            // the name after `solid` and `endsolid` can be anything, spaces included
+            (Expect::Solid, "solid") => Expect::Facet,
+            (Expect::Facet, "endsolid") => Expect::Solid,
+            (Expect::Facet, "facet") => {
+                if words.get(1) != Some(&"normal") {
+                    return Err(malformed("expected `facet normal`"));
+                }
+                normal = vector(&words[2..])?;
+                Expect::OuterLoop
+            }
+            (Expect::OuterLoop, "outer") if words.get(1) == Some(&"loop") => Expect::Vertex,
+            (Expect::Vertex, "vertex") => {
+                vertices.push(vector(&words[1..])?);
+                if vertices.len() == 3 {
+                    Expect::EndLoop
+                } else {
+                    Expect::Vertex
+                }
+            }
+            (Expect::EndLoop, "endloop") => Expect::EndFacet,
+            (Expect::EndFacet, "endfacet") => {
+                triangles.push(facet(
+                    [vertices[0], vertices[1], vertices[2]],
+                    normal,
+                    color,
+                ));
+                vertices.clear();
+                Expect::Facet
+            }
+            (expected, _) => {
+                let wanted = match expected {
+                    Expect::Solid => "`solid`",
+                    Expect::Facet => "`facet normal` or `endsolid`",
+                    Expect::OuterLoop => "`outer loop`",
+                    Expect::Vertex => "`vertex`",
+                    Expect::EndLoop => "`endloop` after 3 vertices",
+                    Expect::EndFacet => "`endfacet`",
+                };
+                return Err(malformed(&format!(
+                    "expected {}, found `{}`",
+                    wanted, keyword
+                )));
+            }
+        };
+    }
+
+    if expect != Expect::Solid {
+        return Err(StlError::Malformed(
+            "ascii stl ends before `endsolid`".to_string(),
+        ));
+    }
+    Ok(Mesh { triangles })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASCII: &str = "solid part
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 0 1 0.1
+    endloop
+  endfacet
+  facet normal 0 0 0
+    outer loop
+      vertex 2 2 2
+      vertex 2 2 2
+      vertex 3 3 3
+    endloop
+  endfacet
+endsolid part
+solid second part
+  facet normal 1 0 0
+    outer loop
+      vertex 0 0 0
+      vertex 0 1 0
+      vertex 0 0 1
+    endloop
+  endfacet
+endsolid second part
+";
+
+    fn white() -> Color {
+        Color::new(255, 255, 255, 255)
+    }
+
+    fn corners(mesh: &Mesh) -> Vec<(Vec<Vector3D>, Vector3D)> {
+        mesh.triangles
+            .iter()
+            .map(|t| (t.vertices.clone(), facet_normal(t)))
+            .collect()
+    }
+
+    fn malformed_message(bytes: &[u8]) -> String {
+        match Mesh::parse_stl(bytes, &white()) {
+            Err(StlError::Malformed(message)) => message,
+            Err(e) => panic!("expected a malformed error, got {}", e),
+            Ok(_) => panic!("expected a malformed error"),
+        }
+    }
+
+    #[test]
+    fn reads_ascii_with_several_solids_and_degenerate_facets() {
+        let mesh = Mesh::parse_stl(ASCII.as_bytes(), &white()).unwrap();
+        assert_eq!(mesh.triangles.len(), 3);
+        assert_eq!(mesh.triangles[0].normals[0], Vector3D::new(0.0, 0.0, 1.0));
+        // a zero normal isn't kept, and a triangle with no area has none to work out
+        assert!(mesh.triangles[1].normals.is_empty());
+        assert_eq!(
+            facet_normal(&mesh.triangles[1]),
+            Vector3D::new(0.0, 0.0, 0.0)
+        );
+        assert_eq!(mesh.triangles[2].vertices[2], Vector3D::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn ascii_round_trips_exactly() {
+        let mesh = Mesh::parse_stl(ASCII.as_bytes(), &white()).unwrap();
+        let text = mesh.to_stl(StlFormat::Ascii);
+        let reloaded = Mesh::parse_stl(&text, &white()).unwrap();
+        assert_eq!(corners(&reloaded), corners(&mesh));
+        assert_eq!(reloaded.to_stl(StlFormat::Ascii), text);
+
+        // coordinates that need every digit of an f64
+        let exact = Mesh {
+            triangles: vec![Triangle::new(
+                Vector3D::new(0.1, 1.0 / 3.0, -2e-300),
+                Vector3D::new(std::f64::consts::PI, 1e300, 0.0),
+                Vector3D::new(-0.0, 5.0, 7.25),
+                &white(),
+            )],
+        };
+        let reloaded = Mesh::parse_stl(&exact.to_stl(StlFormat::Ascii), &white()).unwrap();
+        assert_eq!(reloaded.triangles[0].vertices, exact.triangles[0].vertices);
+    }
+
+    #[test]
+    fn binary_round_trips_as_32_bit_floats() {
+        let mesh = Mesh::parse_stl(ASCII.as_bytes(), &white()).unwrap();
+        let bytes = mesh.to_stl(StlFormat::Binary);
+        assert_eq!(bytes.len(), HEADER_LENGTH + 4 + 3 * TRIANGLE_LENGTH);
+
+        let reloaded = Mesh::parse_stl(&bytes, &white()).unwrap();
+        let narrowed: Vec<_> = corners(&mesh)
+            .into_iter()
+            .map(|(vertices, normal)| {
+                let f32 = |v: &Vector3D| {
+                    Vector3D::new(v.x as f32 as f64, v.y as f32 as f64, v.z as f32 as f64)
+                };
+                (vertices.iter().map(f32).collect(), f32(&normal))
+            })
+            .collect();
+        assert_eq!(corners(&reloaded), narrowed);
+        assert_eq!(reloaded.to_stl(StlFormat::Binary), bytes);
+    }
+
+    #[test]
+    fn tells_binary_starting_with_solid_from_ascii() {
+        let mesh = Mesh::parse_stl(ASCII.as_bytes(), &white()).unwrap();
+        let mut bytes = mesh.to_stl(StlFormat::Binary);
+        bytes[..5].copy_from_slice(b"solid");
+        assert_eq!(
+            Mesh::parse_stl(&bytes, &white()).unwrap().triangles.len(),
+            3
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        assert_eq!(
+            malformed_message(b"solid x\n  facet normal 0 0 1\n    vertex 0 0 0\n"),
+            "line 3: expected `outer loop`, found `vertex`"
+        );
+        assert_eq!(
+            malformed_message(b"solid x\n facet normal 0 0 one\n"),
+            "line 2: expected numbers"
+        );
+        assert_eq!(
+            malformed_message(b"solid x\n facet normal 0 0 1\n"),
+            "ascii stl ends before `endsolid`"
+        );
+
+        let mut truncated = vec![0u8; HEADER_LENGTH];
+        truncated.extend_from_slice(&2u32.to_le_bytes());
+        truncated.extend_from_slice(&[0; TRIANGLE_LENGTH + 10]);
+        assert_eq!(
+            malformed_message(&truncated),
+            "binary stl says it has 2 triangles but only has room for 1"
+        );
+        assert_eq!(
+            malformed_message(&[1, 2, 3]),
+            "binary stl is shorter than its header"
+        );
+    }
+
+    #[test]
+    fn weld_joins_close_corners_and_drops_collapsed_triangles() {
+        let mesh = Mesh::parse_stl(ASCII.as_bytes(), &white()).unwrap();
+        let mut nudged = mesh.clone();
+        nudged.triangles[2].vertices[0] = Vector3D::new(1e-9, 0.0, 0.0);
+
+        let welded = nudged.weld(1e-6);
+        assert_eq!(welded.triangles.len(), 2);
+        assert_eq!(
+            welded.triangles[1].vertices[0],
+            Vector3D::new(0.0, 0.0, 0.0)
+        );
+        assert_eq!(nudged.weld(0.0).triangles.len(), 2);
+    }
+}
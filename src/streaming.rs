@@ -0,0 +1,320 @@
+use crate::biome::BiomeTable;
+use crate::linalg::{Vector2D, Vector3D};
+use crate::noise::FractalNoise;
+use crate::renderer::{Color, Mesh};
+use crate::terrain::{GridRegion, Heightmap};
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+
+/// Index of a tile, tile (0, 0) has its corner on the world origin.
+pub type ChunkCoord = (i32, i32);
+
+#[derive(Clone)]
+pub struct StreamingSettings {
+    /// Grid cells along each side of a tile.
+    pub chunk_cells: usize,
+    /// World units between grid points.
+    pub spacing: f64,
+    pub amplitude: f64,
+    /// Height of the terrain's zero level.
+    pub base_height: f64,
+    /// Tiles within this many tiles of the camera's tile are generated.
+    pub load_radius: i32,
+    /// Tiles further than this are dropped. Kept larger than `load_radius` so moving back
+    /// and forth over a tile border doesn't throw away and regenerate the same tiles.
+    pub unload_radius: i32,
+    /// Most tiles queued on the worker at once, so a fast camera doesn't leave it
+    /// working through tiles that are already out of range.
+    pub max_in_flight: usize,
+    pub color: Color,
+    /// Colors tiles by height and slope instead of the flat `color`, heights being
+    /// relative to `base_height`.
+    pub biomes: Option<BiomeTable>,
+}
+
+impl Default for StreamingSettings {
+    fn default() -> StreamingSettings {
+        StreamingSettings {
+            chunk_cells: 16,
+            spacing: 2.0,
+            amplitude: 12.0,
+            base_height: -14.0,
+            load_radius: 3,
+            unload_radius: 5,
+            max_in_flight: 4,
+            color: Color::new(90, 110, 80, 255),
+            biomes: None,
+        }
+    }
+}
+
+impl StreamingSettings {
+    fn chunk_size(&self) -> f64 {
+        self.chunk_cells as f64 * self.spacing
+    }
+}
+
+/// Endless terrain generated tile by tile around the camera on a background thread.
+///
+/// `update` never waits on the worker: tiles that aren't ready yet are simply missing
+/// for a few frames.
+pub struct StreamingTerrain {
+    pub settings: StreamingSettings,
+    // the worker has its own copy
+    noise: FractalNoise,
+    chunks: HashMap<ChunkCoord, Mesh>,
+    pending: HashSet<ChunkCoord>,
+    // None only while dropping, so the worker sees its channel close
+    requests: Option<Sender<ChunkCoord>>,
+    results: Receiver<(ChunkCoord, Mesh)>,
+    worker: Option<JoinHandle<()>>,
+}
+
+// heights for a tile plus a one point border, so normals along its edges match its
+// neighbours'
+fn generate_chunk(noise: &FractalNoise, settings: &StreamingSettings, coord: ChunkCoord) -> Mesh {
+    let size = settings.chunk_size();
+    let corner = Vector2D::new(
+        coord.0 as f64 * size - settings.spacing,
+        coord.1 as f64 * size - settings.spacing,
+    );
+    let points = settings.chunk_cells + 3;
+    let heightmap = Heightmap::from_noise(
+        noise,
+        points,
+        points,
+        settings.spacing,
+        &corner,
+        settings.amplitude,
+    );
+
+    let inner = GridRegion {
+        x: 1,
+        z: 1,
+        cells_x: settings.chunk_cells,
+        cells_z: settings.chunk_cells,
+    };
+    let mut mesh = heightmap.region_mesh(&inner, 1, settings.spacing, &settings.color);
+    if let Some(biomes) = &settings.biomes {
+        mesh = biomes.paint(&mesh);
+    }
+    mesh.translate(&Vector3D::new(corner.x, settings.base_height, corner.y))
+}
+
+// a thread generating every tile sent to it, until its requests close
+fn spawn_worker(
+    noise: &FractalNoise,
+    settings: &StreamingSettings,
+) -> (
+    Sender<ChunkCoord>,
+    Receiver<(ChunkCoord, Mesh)>,
+    JoinHandle<()>,
+) {
+    let (request_tx, request_rx) = channel::<ChunkCoord>();
+    let (result_tx, result_rx) = channel();
+
+    let worker_noise = noise.clone();
+    let worker_settings = settings.clone();
+    let worker = thread::spawn(move || {
+        // ends once the terrain is dropped and its sender with it
+        for coord in request_rx {
+            let mesh = generate_chunk(&worker_noise, &worker_settings, coord);
+            if result_tx.send((coord, mesh)).is_err() {
+                break;
+            }
+        }
+    });
+    (request_tx, result_rx, worker)
+}
+
+impl StreamingTerrain {
+    pub fn new(noise: FractalNoise, settings: StreamingSettings) -> StreamingTerrain {
+        let (requests, results, worker) = spawn_worker(&noise, &settings);
+        StreamingTerrain {
+            settings,
+            noise,
+            chunks: HashMap::new(),
+            pending: HashSet::new(),
+            requests: Some(requests),
+            results,
+            worker: Some(worker),
+        }
+    }
+
+    // a worker that died, panicking on a tile, took every tile queued on it along, so
+    // they're forgotten and a new worker gets them asked again
+    fn restart_worker(&mut self) {
+        let (requests, results, worker) = spawn_worker(&self.noise, &self.settings);
+        // dropping the old sender ends an old worker still waiting on it, so joining can't hang
+        self.requests = Some(requests);
+        self.results = results;
+        if let Some(old) = self.worker.replace(worker) {
+            let _ = old.join();
+        }
+        self.pending.clear();
+    }
+
+    /// The tile containing a world position.
+    pub fn chunk_at(&self, position: &Vector3D) -> ChunkCoord {
+        let size = self.settings.chunk_size();
+        (
+            (position.x / size).floor() as i32,
+            (position.z / size).floor() as i32,
+        )
+    }
+
+    /// Collects finished tiles, drops far ones and queues the nearest missing ones.
+    pub fn update(&mut self, camera_position: &Vector3D) {
+        let center = self.chunk_at(camera_position);
+        let ring_distance =
+            |coord: &ChunkCoord| (coord.0 - center.0).abs().max((coord.1 - center.1).abs());
+
+        loop {
+            match self.results.try_recv() {
+                Ok((coord, mesh)) => {
+                    self.pending.remove(&coord);
+                    if ring_distance(&coord) <= self.settings.unload_radius {
+                        self.chunks.insert(coord, mesh);
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.restart_worker();
+                    break;
+                }
+            }
+        }
+
+        let unload_radius = self.settings.unload_radius;
+        self.chunks
+            .retain(|coord, _| ring_distance(coord) <= unload_radius);
+
+        let radius = self.settings.load_radius;
+        let mut missing = vec![];
+        for z in center.1 - radius..=center.1 + radius {
+            for x in center.0 - radius..=center.0 + radius {
+                let coord = (x, z);
+                if !self.chunks.contains_key(&coord) && !self.pending.contains(&coord) {
+                    missing.push(coord);
+                }
+            }
+        }
+
+        // nearest first, so the ground under the camera shows up before the horizon
+        missing.sort_by_key(|coord| {
+            let (dx, dz) = (coord.0 - center.0, coord.1 - center.1);
+            dx * dx + dz * dz
+        });
+
+        let requests = match &self.requests {
+            Some(requests) => requests,
+            None => return,
+        };
+        for coord in missing {
+            if self.pending.len() >= self.settings.max_in_flight {
+                break;
+            }
+            if requests.send(coord).is_ok() {
+                self.pending.insert(coord);
+            }
+        }
+    }
+
+    /// World height of the terrain at `x`, `z` on the triangles the tiles are built from,
+    /// whether or not that tile has been generated yet.
+    pub fn height_at(&self, x: f64, z: f64) -> f64 {
+        let spacing = self.settings.spacing;
+        let (grid_x, grid_z) = (x / spacing, z / spacing);
+        let (x0, z0) = (grid_x.floor(), grid_z.floor());
+        let (fx, fz) = (grid_x - x0, grid_z - z0);
+
+        let point =
+            |i: f64, j: f64| self.noise.sample(i * spacing, j * spacing) * self.settings.amplitude;
+        let a = point(x0, z0);
+        let b = point(x0 + 1.0, z0);
+        let c = point(x0 + 1.0, z0 + 1.0);
+        let d = point(x0, z0 + 1.0);
+
+        // same a-c diagonal split as the tile meshes
+        let height = if fx >= fz {
+            a + (b - a) * fx + (c - b) * fz
+        } else {
+            a + (d - a) * fz + (c - d) * fx
+        };
+        self.settings.base_height + height
+    }
+
+    pub fn meshes(&self) -> impl Iterator<Item = &Mesh> {
+        self.chunks.values()
+    }
+
+    pub fn loaded_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+impl Drop for StreamingTerrain {
+    fn drop(&mut self) {
+        self.requests = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn terrain() -> StreamingTerrain {
+        let settings = StreamingSettings {
+            chunk_cells: 4,
+            load_radius: 1,
+            unload_radius: 2,
+            max_in_flight: 2,
+            ..StreamingSettings::default()
+        };
+        StreamingTerrain::new(FractalNoise::new(5), settings)
+    }
+
+    // updates until every tile around the origin is loaded
+    fn load_all(terrain: &mut StreamingTerrain) {
+        let origin = Vector3D::new(0.0, 0.0, 0.0);
+        let start = Instant::now();
+        while terrain.loaded_count() < 9 {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "tiles never arrived"
+            );
+            terrain.update(&origin);
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(terrain.pending_count(), 0);
+    }
+
+    #[test]
+    fn loads_the_tiles_around_the_camera() {
+        let mut terrain = terrain();
+        terrain.update(&Vector3D::new(0.0, 0.0, 0.0));
+        assert_eq!(terrain.pending_count(), 2);
+        load_all(&mut terrain);
+    }
+
+    #[test]
+    fn a_dead_worker_is_replaced_and_its_tiles_asked_again() {
+        let mut terrain = terrain();
+        terrain.update(&Vector3D::new(0.0, 0.0, 0.0));
+        assert_eq!(terrain.pending_count(), 2);
+
+        // the worker goes away without sending back the tiles it was given
+        let (_, results) = channel();
+        terrain.results = results;
+        load_all(&mut terrain);
+    }
+}
@@ -0,0 +1,296 @@
+use crate::image::{Image, ImageError};
+use crate::linalg::{Vector2D, Vector3D};
+use crate::noise::FractalNoise;
+use crate::renderer::{Color, Mesh, Triangle};
+use std::path::Path;
+
+/// A rectangle of grid cells starting at grid point (`x`, `z`).
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct GridRegion {
+    pub x: usize,
+    pub z: usize,
+    pub cells_x: usize,
+    pub cells_z: usize,
+}
+
+/// A regular grid of heights, `width` samples along x by `depth` along z.
+#[derive(Clone)]
+pub struct Heightmap {
+    pub width: usize,
+    pub depth: usize,
+    pub heights: Vec<f64>,
+}
+
+impl Heightmap {
+    /// A flat map at height 0. Panics if either size is 0, since there'd be nothing to
+    /// sample.
+    pub fn new(width: usize, depth: usize) -> Heightmap {
+        assert!(
+            width > 0 && depth > 0,
+            "heightmap must have at least one point a side"
+        );
+        Heightmap {
+            width,
+            depth,
+            heights: vec![0.0; width * depth],
+        }
+    }
+
+    /// Samples `noise` at the world position of every grid point, so maps generated with
+    /// neighbouring origins line up exactly along their shared edge.
+    pub fn from_noise(
+        noise: &FractalNoise,
+        width: usize,
+        depth: usize,
+        spacing: f64,
+        origin: &Vector2D,
+        amplitude: f64,
+    ) -> Heightmap {
+        let mut heightmap = Heightmap::new(width, depth);
+
+        for z in 0..depth {
+            for x in 0..width {
+                let world_x = origin.x + x as f64 * spacing;
+                let world_z = origin.y + z as f64 * spacing;
+                heightmap.set(x, z, noise.sample(world_x, world_z) * amplitude);
+            }
+        }
+
+        heightmap
+    }
+
+    /// Heights from an image's brightness, black at 0 and white at `vertical_scale`. Image
+    /// rows run along z, so the top left pixel is grid point (0, 0).
+    pub fn from_image(image: &Image, vertical_scale: f64) -> Heightmap {
+        let mut heightmap = Heightmap::new(image.width, image.height);
+
+        for z in 0..image.height {
+            for x in 0..image.width {
+                heightmap.set(x, z, image.luminance(x, z) * vertical_scale);
+            }
+        }
+
+        heightmap
+    }
+
+    /// Loads a grayscale png or pgm, see `from_image`.
+    pub fn load<P: AsRef<Path>>(path: P, vertical_scale: f64) -> Result<Heightmap, ImageError> {
+        Ok(Heightmap::from_image(&Image::load(path)?, vertical_scale))
+    }
+
+    /// A 16 bit grayscale image stretching the lowest height to black and the highest to
+    /// white. Loading it back with a vertical scale of `max - min` from `min_max` and
+    /// offsetting by `min` gives the heights back to within 1/65535 of the range.
+    pub fn to_image(&self) -> Image {
+        let (min, max) = self.min_max();
+        let range = if max > min { max - min } else { 1.0 };
+        let mut image = Image::new(self.width, self.depth, 1, u16::MAX);
+
+        for (sample, height) in image.samples.iter_mut().zip(&self.heights) {
+            *sample = ((height - min) / range * u16::MAX as f64).round() as u16;
+        }
+
+        image
+    }
+
+    pub fn save_pgm<P: AsRef<Path>>(&self, path: P) -> Result<(), ImageError> {
+        self.to_image().save_pgm(path)
+    }
+
+    pub fn get(&self, x: usize, z: usize) -> f64 {
+        self.heights[z * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, z: usize, height: f64) {
+        self.heights[z * self.width + x] = height;
+    }
+
+    fn get_clamped(&self, x: i64, z: i64) -> f64 {
+        let x = x.clamp(0, self.width as i64 - 1) as usize;
+        let z = z.clamp(0, self.depth as i64 - 1) as usize;
+        self.get(x, z)
+    }
+
+    /// Height at a fractional grid position, on the same triangles `to_mesh` builds.
+    pub fn sample(&self, x: f64, z: f64) -> f64 {
+        let x = x.clamp(0.0, (self.width - 1) as f64);
+        let z = z.clamp(0.0, (self.depth - 1) as f64);
+        let (x0, z0) = (x.floor() as i64, z.floor() as i64);
+        let (fx, fz) = (x - x0 as f64, z - z0 as f64);
+
+        let a = self.get_clamped(x0, z0);
+        let b = self.get_clamped(x0 + 1, z0);
+        let c = self.get_clamped(x0 + 1, z0 + 1);
+        let d = self.get_clamped(x0, z0 + 1);
+
+        // each cell is split along its a-c diagonal
+        if fx >= fz {
+            a + (b - a) * fx + (c - b) * fz
+        } else {
+            a + (d - a) * fz + (c - d) * fx
+        }
+    }
+
+    /// Height at grid point (`x`, `z`) of the surface built from every `step`th grid point,
+    /// as `region_mesh` draws it.
+    pub fn sample_coarse(&self, x: usize, z: usize, step: usize) -> f64 {
+        let step = step.max(1);
+        let (x0, z0) = (x / step * step, z / step * step);
+        let x1 = (x0 + step).min(self.width - 1);
+        let z1 = (z0 + step).min(self.depth - 1);
+        let fx = (x - x0) as f64 / step as f64;
+        let fz = (z - z0) as f64 / step as f64;
+
+        let a = self.get(x0, z0);
+        let b = self.get(x1, z0);
+        let c = self.get(x1, z1);
+        let d = self.get(x0, z1);
+
+        if fx >= fz {
+            a + (b - a) * fx + (c - b) * fz
+        } else {
+            a + (d - a) * fz + (c - d) * fx
+        }
+    }
+
+    /// Smooth surface normal at a grid point from the slope to its neighbours.
+    pub fn normal(&self, x: usize, z: usize, spacing: f64) -> Vector3D {
+        let (x, z) = (x as i64, z as i64);
+        let dx = self.get_clamped(x - 1, z) - self.get_clamped(x + 1, z);
+        let dz = self.get_clamped(x, z - 1) - self.get_clamped(x, z + 1);
+        Vector3D::new(dx, 2.0 * spacing, dz).normalize()
+    }
+
+    pub fn min_max(&self) -> (f64, f64) {
+        self.heights
+            .iter()
+            .fold((f64::MAX, f64::MIN), |(lo, hi), h| (lo.min(*h), hi.max(*h)))
+    }
+
+    /// A mesh with grid point (0, 0) at the origin, `spacing` world units between points,
+    /// smooth normals and uvs stretched once over the whole map.
+    pub fn to_mesh(&self, spacing: f64, color: &Color) -> Mesh {
+        let region = GridRegion {
+            x: 0,
+            z: 0,
+            cells_x: self.width.saturating_sub(1),
+            cells_z: self.depth.saturating_sub(1),
+        };
+        self.region_mesh(&region, 1, spacing, color)
+    }
+
+    /// The cells of `region`, using only every `step`th grid point. Positions are relative
+    /// to grid point (0, 0) like `to_mesh`, so regions of the same map fit together.
+    pub fn region_mesh(
+        &self,
+        region: &GridRegion,
+        step: usize,
+        spacing: f64,
+        color: &Color,
+    ) -> Mesh {
+        let step = step.max(1);
+        let u_scale = 1.0 / (self.width - 1).max(1) as f64;
+        let v_scale = 1.0 / (self.depth - 1).max(1) as f64;
+
+        let point = |x: usize, z: usize| {
+            (
+                Vector3D::new(x as f64 * spacing, self.get(x, z), z as f64 * spacing),
+                self.normal(x, z, spacing),
+                Vector2D::new(x as f64 * u_scale, z as f64 * v_scale),
+            )
+        };
+
+        let (cells_x, cells_z) = (region.cells_x / step, region.cells_z / step);
+        let mut triangles = Vec::with_capacity(cells_x * cells_z * 2);
+        for j in 0..cells_z {
+            for i in 0..cells_x {
+                let (x, z) = (region.x + i * step, region.z + j * step);
+                let a = point(x, z);
+                let b = point(x + step, z);
+                let c = point(x + step, z + step);
+                let d = point(x, z + step);
+
+                // wound so the face normal points up, split along the a-c diagonal
+                for (p, q, r) in [(&a, &c, &b), (&a, &d, &c)] {
+                    triangles.push(
+                        Triangle::new(p.0, q.0, r.0, color)
+                            .with_normals(p.1, q.1, r.1)
+                            .with_uvs(p.2, q.2, r.2),
+                    );
+                }
+            }
+        }
+
+        Mesh { triangles }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::noise::{FractalKind, Noise, NoiseKind};
+
+    fn noise_map(seed: u64, noise: NoiseKind, kind: FractalKind) -> Heightmap {
+        let mut fractal = FractalNoise::new(seed);
+        fractal.noise = Noise::new(seed, noise);
+        fractal.kind = kind;
+        Heightmap::from_noise(&fractal, 33, 33, 2.0, &Vector2D::new(-20.0, 7.0), 10.0)
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_terrain() {
+        for noise in [NoiseKind::Perlin, NoiseKind::Simplex] {
+            for kind in [FractalKind::Fbm, FractalKind::Ridged, FractalKind::Billowed] {
+                let a = noise_map(42, noise, kind);
+                assert_eq!(a.heights, noise_map(42, noise, kind).heights);
+                let (min, max) = a.min_max();
+                assert!(max - min > 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn different_seeds_give_different_terrain() {
+        for noise in [NoiseKind::Perlin, NoiseKind::Simplex] {
+            let maps: Vec<Heightmap> = (0..4)
+                .map(|seed| noise_map(seed, noise, FractalKind::Fbm))
+                .collect();
+            for i in 0..maps.len() {
+                for j in i + 1..maps.len() {
+                    let differing = maps[i]
+                        .heights
+                        .iter()
+                        .zip(&maps[j].heights)
+                        .filter(|(a, b)| (*a - *b).abs() > 1e-6)
+                        .count();
+                    assert!(differing > maps[i].heights.len() / 2);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn a_single_point_map_samples_its_height() {
+        let mut heightmap = Heightmap::new(1, 1);
+        heightmap.set(0, 0, 2.5);
+        assert_eq!(heightmap.sample(-1.0, 3.0), 2.5);
+        assert!(heightmap
+            .to_mesh(1.0, &Color::new(0, 0, 0, 255))
+            .triangles
+            .is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one point a side")]
+    fn empty_maps_are_refused() {
+        Heightmap::new(0, 4);
+    }
+
+    #[test]
+    fn empty_images_dont_load() {
+        let path = std::env::temp_dir().join("atlas_empty_heightmap.pgm");
+        std::fs::write(&path, b"P5 0 0 255\n").unwrap();
+        assert!(Heightmap::load(&path, 1.0).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,53 @@
+use crate::image::{Image, ImageError};
+use crate::linalg::Vector2D;
+
+/// An image sampled by texture coordinates, remembering the file it came from so scenes
+/// can be saved and reloaded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Texture {
+    /// As written in the scene file, None for textures made in code.
+    pub path: Option<String>,
+    pub image: Image,
+}
+
+impl Texture {
+    pub fn new(image: Image) -> Texture {
+        Texture { path: None, image }
+    }
+
+    pub fn load(path: &str) -> Result<Texture, ImageError> {
+        Ok(Texture {
+            path: Some(path.to_string()),
+            image: Image::load(path)?,
+        })
+    }
+
+    /// Bilinearly filtered linear color at `uv`, repeating outside [0, 1]. `v` of 0 is the
+    /// top row.
+    pub fn sample(&self, uv: &Vector2D) -> [f64; 3] {
+        let (width, height) = (self.image.width, self.image.height);
+        if width == 0 || height == 0 {
+            return [1.0; 3];
+        }
+
+        let x = uv.x.rem_euclid(1.0) * width as f64 - 0.5;
+        let y = uv.y.rem_euclid(1.0) * height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let wrap = |v: f64, size: usize| (v as i64).rem_euclid(size as i64) as usize;
+        let (x0, x1) = (wrap(x0, width), wrap(x0 + 1.0, width));
+        let (y0, y1) = (wrap(y0, height), wrap(y0 + 1.0, height));
+
+        let texel = |x: usize, y: usize| {
+            let gray = self.image.channels < 3;
+            [0, 1, 2].map(|c| self.image.sample(x, y, if gray { 0 } else { c }))
+        };
+        let (a, b, c, d) = (texel(x0, y0), texel(x1, y0), texel(x0, y1), texel(x1, y1));
+
+        [0, 1, 2].map(|k| {
+            let top = a[k] + (b[k] - a[k]) * fx;
+            let bottom = c[k] + (d[k] - c[k]) * fx;
+            top + (bottom - top) * fy
+        })
+    }
+}
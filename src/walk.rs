@@ -0,0 +1,315 @@
+use crate::linalg::Vector3D;
+use crate::lod::TerrainLod;
+use crate::renderer::{Camera, Input};
+use crate::streaming::StreamingTerrain;
+
+/// Anything the walking camera can stand on.
+pub trait Ground {
+    /// Height of the surface at world `x`, `z`, None where there is no ground.
+    fn height_at(&self, x: f64, z: f64) -> Option<f64>;
+}
+
+impl Ground for TerrainLod {
+    fn height_at(&self, x: f64, z: f64) -> Option<f64> {
+        TerrainLod::height_at(self, x, z)
+    }
+}
+
+impl Ground for StreamingTerrain {
+    fn height_at(&self, x: f64, z: f64) -> Option<f64> {
+        Some(StreamingTerrain::height_at(self, x, z))
+    }
+}
+
+pub struct WalkSettings {
+    /// Camera height above the ground while standing.
+    pub eye_height: f64,
+    pub walk_speed: f64,
+    /// Downward acceleration in world units per second squared.
+    pub gravity: f64,
+    /// Upward speed at the start of a jump.
+    pub jump_speed: f64,
+    /// Steepest slope in radians that can be walked up.
+    pub max_slope: f64,
+    /// Largest sudden rise the camera steps onto, or drop it stays on the ground over.
+    pub step_height: f64,
+}
+
+impl Default for WalkSettings {
+    fn default() -> WalkSettings {
+        WalkSettings {
+            eye_height: 1.7,
+            walk_speed: 6.0,
+            gravity: 20.0,
+            jump_speed: 7.0,
+            max_slope: 45.0_f64.to_radians(),
+            step_height: 0.4,
+        }
+    }
+}
+
+/// Moves a camera either in free flight or walking on the ground, with gravity and jumps.
+///
+/// In walk mode `Input::up` jumps and `Input::down` does nothing.
+pub struct WalkController {
+    pub settings: WalkSettings,
+    pub walking: bool,
+    vertical_speed: f64,
+    grounded: bool,
+}
+
+impl Default for WalkController {
+    fn default() -> WalkController {
+        WalkController::new()
+    }
+}
+
+impl WalkController {
+    pub fn new() -> WalkController {
+        WalkController {
+            settings: WalkSettings::default(),
+            walking: false,
+            vertical_speed: 0.0,
+            grounded: false,
+        }
+    }
+
+    /// Switches between walking and free flight. Walking starts in the air so the camera
+    /// drops onto whatever is below it.
+    pub fn toggle(&mut self) {
+        self.walking = !self.walking;
+        self.vertical_speed = 0.0;
+        self.grounded = false;
+    }
+
+    pub fn grounded(&self) -> bool {
+        self.walking && self.grounded
+    }
+
+    pub fn update(
+        &mut self,
+        camera: &mut Camera,
+        input: &Input,
+        ground: &dyn Ground,
+        delta_time: f64,
+    ) {
+        if !self.walking {
+            camera.update(input, delta_time);
+            return;
+        }
+
+        camera.look(input);
+
+        let direction = camera.move_direction(input);
+        let step = direction.scale(self.settings.walk_speed * delta_time);
+        if !step.is_zero() {
+            self.move_horizontally(camera, &step, ground);
+        }
+
+        if self.grounded && input.up {
+            self.vertical_speed = self.settings.jump_speed;
+            self.grounded = false;
+        }
+
+        let feet = camera.position.y - self.settings.eye_height;
+        let floor = ground.height_at(camera.position.x, camera.position.z);
+
+        if self.grounded {
+            match floor {
+                // stay on the ground over small drops, fall off bigger ones
+                Some(floor) if feet - floor <= self.settings.step_height => {
+                    camera.position.y = floor + self.settings.eye_height;
+                    return;
+                }
+                _ => self.grounded = false,
+            }
+        }
+
+        self.vertical_speed -= self.settings.gravity * delta_time;
+        let mut new_feet = feet + self.vertical_speed * delta_time;
+
+        if let Some(floor) = floor {
+            if new_feet <= floor {
+                new_feet = floor;
+                self.vertical_speed = 0.0;
+                self.grounded = true;
+            }
+        }
+
+        camera.position.y = new_feet + self.settings.eye_height;
+    }
+
+    // tries the full step, then each axis on its own so walls of rock are slid along
+    fn move_horizontally(&self, camera: &mut Camera, step: &Vector3D, ground: &dyn Ground) {
+        let candidates = [
+            *step,
+            Vector3D::new(step.x, 0.0, 0.0),
+            Vector3D::new(0.0, 0.0, step.z),
+        ];
+
+        for candidate in candidates {
+            if !candidate.is_zero() && self.can_move(camera, &candidate, ground) {
+                camera.position = camera.position.add(&candidate);
+                return;
+            }
+        }
+    }
+
+    fn can_move(&self, camera: &Camera, step: &Vector3D, ground: &dyn Ground) -> bool {
+        let from = &camera.position;
+        let to = from.add(step);
+        let feet = from.y - self.settings.eye_height;
+
+        let floor = match ground.height_at(to.x, to.z) {
+            Some(floor) => floor,
+            // nothing to bump into, walking off the edge is allowed
+            None => return true,
+        };
+
+        // airborne, only blocked by ground above the feet
+        if !self.grounded {
+            return floor <= feet + self.settings.step_height;
+        }
+
+        let rise = floor - feet;
+        if rise > self.settings.step_height {
+            return false;
+        }
+
+        // going downhill or across is always fine, uphill only up to the slope limit
+        rise <= 0.0 || self.slope_ahead(ground, &to, step) <= self.settings.max_slope
+    }
+
+    // steepness of the surface from a point onward in the direction of `step`, measured
+    // past the point so the edge of a step just climbed doesn't count as a slope
+    fn slope_ahead(&self, ground: &dyn Ground, at: &Vector3D, step: &Vector3D) -> f64 {
+        let radius = step.magnitude().max(0.05);
+        let ahead = |along: f64| if along < 0.0 { -radius } else { radius };
+        let (rx, rz) = (ahead(step.x), ahead(step.z));
+        let height = |x: f64, z: f64| ground.height_at(x, z);
+
+        match (
+            height(at.x, at.z),
+            height(at.x + rx, at.z),
+            height(at.x, at.z + rz),
+        ) {
+            (Some(here), Some(beside), Some(beyond)) => {
+                let dx = (beside - here) / radius;
+                let dz = (beyond - here) / radius;
+                (dx * dx + dz * dz).sqrt().atan()
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::CameraSetup;
+
+    const DT: f64 = 1.0 / 60.0;
+
+    // a surface given by a height function of z alone
+    struct Profile(fn(f64) -> f64);
+
+    impl Ground for Profile {
+        fn height_at(&self, _x: f64, z: f64) -> Option<f64> {
+            Some((self.0)(z))
+        }
+    }
+
+    fn input(forward: bool, up: bool) -> Input {
+        Input {
+            forward,
+            backward: false,
+            left: false,
+            right: false,
+            up,
+            down: false,
+            mouse_dx: 0.0,
+            mouse_dy: 0.0,
+        }
+    }
+
+    // a walking camera at `z` looking along +z, dropped from `height`
+    fn walker(z: f64, height: f64) -> (WalkController, Camera) {
+        let camera = CameraSetup {
+            position: Vector3D::new(0.0, height, z),
+            ..CameraSetup::default()
+        }
+        .camera(1.0);
+        let mut controller = WalkController::new();
+        controller.toggle();
+        (controller, camera)
+    }
+
+    fn run(
+        controller: &mut WalkController,
+        camera: &mut Camera,
+        ground: &dyn Ground,
+        forward: bool,
+        seconds: f64,
+    ) {
+        for _ in 0..(seconds / DT).round() as usize {
+            controller.update(camera, &input(forward, false), ground, DT);
+        }
+    }
+
+    #[test]
+    fn falls_onto_flat_ground_and_stays_at_eye_height() {
+        let flat = Profile(|_| 0.0);
+        let (mut controller, mut camera) = walker(0.0, 5.0);
+        let eye = controller.settings.eye_height;
+
+        controller.update(&mut camera, &input(false, false), &flat, DT);
+        assert!(!controller.grounded());
+        assert!(camera.position.y < 5.0);
+
+        run(&mut controller, &mut camera, &flat, false, 2.0);
+        assert!(controller.grounded());
+        assert_eq!(camera.position.y, eye);
+        run(&mut controller, &mut camera, &flat, true, 1.0);
+        assert_eq!(camera.position.y, eye);
+
+        // a jump leaves the ground and comes back down to it
+        controller.update(&mut camera, &input(false, true), &flat, DT);
+        assert!(!controller.grounded());
+        run(&mut controller, &mut camera, &flat, false, 0.2);
+        assert!(camera.position.y > eye + 0.5);
+        run(&mut controller, &mut camera, &flat, false, 2.0);
+        assert!(controller.grounded());
+        assert_eq!(camera.position.y, eye);
+    }
+
+    #[test]
+    fn climbs_gentle_ramps_but_not_steep_ones() {
+        let gentle = Profile(|z| z.max(0.0) * 20.0_f64.to_radians().tan());
+        let (mut controller, mut camera) = walker(-1.0, 1.7);
+        run(&mut controller, &mut camera, &gentle, true, 2.0);
+        let floor = gentle.height_at(0.0, camera.position.z).unwrap();
+        assert!(camera.position.z > 8.0);
+        assert!((camera.position.y - floor - controller.settings.eye_height).abs() < 1e-9);
+
+        let steep = Profile(|z| z.max(0.0) * 60.0_f64.to_radians().tan());
+        let (mut controller, mut camera) = walker(-1.0, 1.7);
+        run(&mut controller, &mut camera, &steep, true, 2.0);
+        assert!(camera.position.z < 0.1);
+        assert!(controller.grounded());
+    }
+
+    #[test]
+    fn steps_up_to_the_step_height_but_no_higher() {
+        let low = Profile(|z| if z < 1.0 { 0.0 } else { 0.35 });
+        let (mut controller, mut camera) = walker(0.0, 1.7);
+        run(&mut controller, &mut camera, &low, true, 1.0);
+        assert!(camera.position.z > 4.0);
+        assert_eq!(camera.position.y, 0.35 + controller.settings.eye_height);
+
+        let high = Profile(|z| if z < 1.0 { 0.0 } else { 0.5 });
+        let (mut controller, mut camera) = walker(0.0, 1.7);
+        run(&mut controller, &mut camera, &high, true, 1.0);
+        assert!(camera.position.z < 1.0);
+        assert_eq!(camera.position.y, controller.settings.eye_height);
+    }
+}
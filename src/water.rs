@@ -0,0 +1,319 @@
+use crate::linalg::{cross, dot, Matrix4D, Plane, Vector2D, Vector3D};
+use crate::renderer::{Color, FrameBuffer, Mesh, RenderMode, Renderer, Triangle, FAR_DEPTH};
+use std::f64::consts::PI;
+
+/// One gerstner wave, a sine whose crests also pinch together horizontally.
+#[derive(Clone, Copy, Debug)]
+pub struct Wave {
+    /// Unit direction the wave travels along in the xz plane.
+    pub direction: Vector2D,
+    pub amplitude: f64,
+    pub wavelength: f64,
+    /// World units per second.
+    pub speed: f64,
+    /// 0 is a plain sine, 1 gives the sharpest crests before they loop over themselves.
+    pub steepness: f64,
+}
+
+impl Wave {
+    pub fn new(angle: f64, amplitude: f64, wavelength: f64, speed: f64, steepness: f64) -> Wave {
+        Wave {
+            direction: Vector2D::new(angle.cos(), angle.sin()),
+            amplitude,
+            wavelength,
+            speed,
+            steepness,
+        }
+    }
+
+    /// A wave with no height or no length moves nothing.
+    pub fn is_flat(&self) -> bool {
+        self.amplitude == 0.0 || self.wavelength <= 0.0
+    }
+}
+
+/// A flat body of water at `level`, animated by summed gerstner waves.
+///
+/// It is colored by blending a planar reflection, rendered from the camera mirrored in
+/// the surface, with the already drawn scene below tinted by how much water it is seen
+/// through. The blend follows the fresnel term, so looking straight down shows mostly
+/// what's under the surface and grazing views mostly the reflection.
+pub struct Water {
+    pub level: f64,
+    /// World xz position of the middle of the surface mesh.
+    pub center: Vector2D,
+    pub size: f64,
+    /// Grid cells along each side of the surface mesh.
+    pub resolution: usize,
+    pub waves: Vec<Wave>,
+    pub color: Color,
+    /// View distance through water after which the scene below is mostly hidden by `color`.
+    pub absorption_depth: f64,
+    /// Depth of water over which the surface fades in from the shoreline.
+    pub shore_fade_depth: f64,
+    /// Reflectance looking straight down, about 0.02 for real water.
+    pub base_reflectance: f64,
+    /// How far in pixels the surface normals shift what is reflected and refracted.
+    pub distortion: f64,
+    time: f64,
+    reflection: FrameBuffer,
+}
+
+impl Water {
+    /// The reflection is rendered at `reflection_width` by `reflection_height`, it can be
+    /// smaller than the screen to save time.
+    pub fn new(level: f64, size: f64, reflection_width: usize, reflection_height: usize) -> Water {
+        Water {
+            level,
+            center: Vector2D::new(0.0, 0.0),
+            size,
+            resolution: 48,
+            waves: vec![
+                Wave::new(0.3, 0.15, 9.0, 2.5, 0.5),
+                Wave::new(1.9, 0.1, 5.5, 1.8, 0.4),
+                Wave::new(-0.8, 0.05, 2.7, 1.2, 0.3),
+            ],
+            color: Color::new(20, 60, 80, 255),
+            absorption_depth: 6.0,
+            shore_fade_depth: 0.6,
+            base_reflectance: 0.02,
+            distortion: 12.0,
+            time: 0.0,
+            reflection: FrameBuffer::new(reflection_width, reflection_height),
+        }
+    }
+
+    pub fn update(&mut self, delta_time: f64) {
+        self.time += delta_time;
+    }
+
+    /// Moves the surface to stay under `position`, in whole grid cells so the waves don't
+    /// slide along with the camera.
+    pub fn follow(&mut self, position: &Vector3D) {
+        let cell = self.size / self.resolution as f64;
+        self.center = Vector2D::new(
+            (position.x / cell).round() * cell,
+            (position.z / cell).round() * cell,
+        );
+    }
+
+    /// World position the surface point resting at `x`, `z` is moved to, and the surface
+    /// normal there.
+    pub fn displace(&self, x: f64, z: f64) -> (Vector3D, Vector3D) {
+        let mut position = Vector3D::new(x, self.level, z);
+        let mut tangent = Vector3D::new(1.0, 0.0, 0.0);
+        let mut bitangent = Vector3D::new(0.0, 0.0, 1.0);
+
+        // flat waves are skipped, they'd divide by zero below
+        let count = self.waves.iter().filter(|w| !w.is_flat()).count();
+        for wave in self.waves.iter().filter(|w| !w.is_flat()) {
+            let k = 2.0 * PI / wave.wavelength;
+            let (dx, dz) = (wave.direction.x, wave.direction.y);
+            let phase = k * (dx * x + dz * z - wave.speed * self.time);
+            let (sin, cos) = phase.sin_cos();
+            // keeps the crests from folding over however many waves are summed
+            let q = wave.steepness / (k * wave.amplitude * count as f64);
+            let qa = q * wave.amplitude;
+
+            position.x += qa * dx * cos;
+            position.z += qa * dz * cos;
+            position.y += wave.amplitude * sin;
+
+            // derivatives of the displaced position along x and z
+            let wa = k * wave.amplitude;
+            tangent.x -= q * dx * dx * wa * sin;
+            tangent.y += dx * wa * cos;
+            tangent.z -= q * dx * dz * wa * sin;
+            bitangent.x -= q * dx * dz * wa * sin;
+            bitangent.y += dz * wa * cos;
+            bitangent.z -= q * dz * dz * wa * sin;
+        }
+
+        (position, cross(&bitangent, &tangent).normalize())
+    }
+
+    /// The displaced surface around `center` at the current time, with smooth normals.
+    pub fn surface_mesh(&self) -> Mesh {
+        let n = self.resolution.max(1);
+        let cell = self.size / n as f64;
+        let start_x = self.center.x - self.size / 2.0;
+        let start_z = self.center.y - self.size / 2.0;
+
+        let points: Vec<(Vector3D, Vector3D)> = (0..=n)
+            .flat_map(|j| (0..=n).map(move |i| (i, j)))
+            .map(|(i, j)| self.displace(start_x + i as f64 * cell, start_z + j as f64 * cell))
+            .collect();
+
+        let mut triangles = Vec::with_capacity(n * n * 2);
+        for j in 0..n {
+            for i in 0..n {
+                let a = &points[j * (n + 1) + i];
+                let b = &points[j * (n + 1) + i + 1];
+                let c = &points[(j + 1) * (n + 1) + i + 1];
+                let d = &points[(j + 1) * (n + 1) + i];
+
+                // wound so the face normal points up, like the terrain
+                for (p, q, r) in [(a, c, b), (a, d, c)] {
+                    triangles.push(
+                        Triangle::new(p.0, q.0, r.0, &self.color).with_normals(p.1, q.1, r.1),
+                    );
+                }
+            }
+        }
+
+        Mesh { triangles }
+    }
+
+    /// Renders the reflection for this frame. `draw_scene` should draw everything that can
+    /// be reflected; it is called with the renderer's camera mirrored in the water and
+    /// everything below the surface clipped away.
+    pub fn render_reflection<F: FnMut(&mut Renderer)>(
+        &mut self,
+        renderer: &mut Renderer,
+        mut draw_scene: F,
+    ) {
+        let saved_position = renderer.camera.position;
+        let saved_pitch = renderer.camera.pitch;
+        let saved_mode = renderer.mode;
+        let saved_clip = renderer.clip_plane;
+
+        // mirroring the camera flips the image upside down, `draw` flips it back
+        renderer.camera.position.y = 2.0 * self.level - saved_position.y;
+        renderer.camera.pitch = -saved_pitch;
+        renderer.mode = RenderMode::Shaded;
+        renderer.clip_plane = Some(Plane::new(Vector3D::new(0.0, 1.0, 0.0), -self.level));
+        self.reflection.clear_color = renderer.framebuffer.clear_color.clone();
+        std::mem::swap(&mut renderer.framebuffer, &mut self.reflection);
+
+        renderer.framebuffer.clear();
+        draw_scene(renderer);
+
+        std::mem::swap(&mut renderer.framebuffer, &mut self.reflection);
+        renderer.camera.position = saved_position;
+        renderer.camera.pitch = saved_pitch;
+        renderer.mode = saved_mode;
+        renderer.clip_plane = saved_clip;
+    }
+
+    fn reflection_at(&self, x: f64, y: f64, screen_width: usize, screen_height: usize) -> Color {
+        let rx = (x / screen_width as f64 * self.reflection.width as f64) as i64;
+        let ry = ((1.0 - y / screen_height as f64) * self.reflection.height as f64) as i64;
+        let rx = rx.clamp(0, self.reflection.width as i64 - 1) as usize;
+        let ry = ry.clamp(0, self.reflection.height as i64 - 1) as usize;
+        Color::from_u32(self.reflection.color_buffer[ry * self.reflection.width + rx])
+    }
+
+    /// Draws the surface over the scene already in the renderer's framebuffer. Call after
+    /// the opaque scene and `render_reflection`, before anything transparent.
+    pub fn draw(&self, renderer: &mut Renderer, proj_mat: &Matrix4D) {
+        if matches!(
+            renderer.mode,
+            RenderMode::Normals | RenderMode::Depth | RenderMode::Overdraw | RenderMode::UvChecker
+        ) {
+            renderer.render_mesh(&self.surface_mesh(), proj_mat);
+            return;
+        }
+
+        let (width, height) = (renderer.framebuffer.width, renderer.framebuffer.height);
+        // the scene as drawn so far, what is seen through the water
+        let scene_color = renderer.framebuffer.color_buffer.clone();
+        let scene_depth = renderer.framebuffer.depth_buffer.clone();
+        let eye = renderer.camera.position;
+
+        let mesh = self.surface_mesh();
+        renderer.stats.triangles_submitted += mesh.triangles.len();
+
+        // the surface always reaches behind the camera, cut it at the near plane rather than
+        // dropping the triangles that cross it
+        let forward = renderer.camera.forward();
+        let near_plane = Plane::from_point_normal(
+            &eye.add(&forward.scale(renderer.camera.near_clip)),
+            &forward,
+        );
+        let triangles: Vec<Triangle> = mesh
+            .triangles
+            .iter()
+            .flat_map(|t| t.clip(&near_plane))
+            .collect();
+
+        for triangle in &triangles {
+            let projected = match renderer.project_triangle(triangle, proj_mat) {
+                Some(projected) => projected,
+                None => continue,
+            };
+            renderer.stats.triangles_drawn += 1;
+
+            renderer.rasterize_triangle(&projected, |fragment| {
+                let b = &fragment.barycentric;
+                let interpolate = |v: &[Vector3D]| {
+                    v[0].scale(b[0])
+                        .add(&v[1].scale(b[1]))
+                        .add(&v[2].scale(b[2]))
+                };
+                let position = interpolate(&triangle.vertices);
+                let normal = interpolate(&triangle.normals).normalize();
+                let to_eye = eye.sub(&position).normalize();
+
+                let i = fragment.y * width + fragment.x;
+                let below_depth = scene_depth[i];
+                let below = Color::from_u32(scene_color[i]);
+
+                // how much water the view ray passes through before hitting the scene
+                let thickness = if below_depth >= FAR_DEPTH {
+                    f64::MAX
+                } else {
+                    (below_depth - fragment.depth).max(0.0)
+                };
+
+                let (offset_x, offset_y) = (normal.x * self.distortion, normal.z * self.distortion);
+                let reflected = self.reflection_at(
+                    fragment.x as f64 + offset_x,
+                    fragment.y as f64 + offset_y,
+                    width,
+                    height,
+                );
+
+                // refraction offset, falling back to straight through if it would sample
+                // something in front of the water
+                let sx =
+                    (fragment.x as f64 + offset_x * 0.5).clamp(0.0, width as f64 - 1.0) as usize;
+                let sy =
+                    (fragment.y as f64 + offset_y * 0.5).clamp(0.0, height as f64 - 1.0) as usize;
+                let j = sy * width + sx;
+                let seen = if scene_depth[j] > fragment.depth {
+                    Color::from_u32(scene_color[j])
+                } else {
+                    below.clone()
+                };
+                let absorbed = 1.0 - (-thickness / self.absorption_depth).exp();
+                let refracted = seen.lerp(&self.color, absorbed);
+
+                // schlick's approximation
+                let cos = dot(&normal, &to_eye).clamp(0.0, 1.0);
+                let fresnel =
+                    self.base_reflectance + (1.0 - self.base_reflectance) * (1.0 - cos).powi(5);
+                let surface = refracted.lerp(&reflected, fresnel);
+
+                let shore = (thickness / self.shore_fade_depth).clamp(0.0, 1.0);
+                below.lerp(&surface, shore)
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_waves_leave_the_surface_alone() {
+        let mut water = Water::new(1.0, 10.0, 4, 4);
+        water.update(0.7);
+        let (position, normal) = water.displace(0.3, -2.0);
+
+        water.waves.push(Wave::new(0.5, 0.0, 4.0, 1.0, 0.8));
+        water.waves.push(Wave::new(0.5, 0.2, 0.0, 1.0, 0.8));
+        assert_eq!(water.displace(0.3, -2.0), (position, normal));
+        assert!(position.x.is_finite() && normal.y > 0.0);
+    }
+}